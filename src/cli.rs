@@ -1,15 +1,36 @@
 use anyhow::Result;
-use clap::{Parser, Subcommand};
-use inquire::{Confirm, Password, Select, Text};
-use std::cmp::Reverse;
-
-use crate::models::Server;
+use clap::{ArgGroup, Parser, Subcommand};
+use inquire::autocompletion::{Autocomplete, Replacement};
+use inquire::{Confirm, CustomUserError, Password, Select, Text};
+use serde::Serialize;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::clipboard;
+use crate::config::Config;
+use crate::crypto::{generate_password, Charset, KdfStrength, MasterKey, DEFAULT_PASSWORD_LENGTH};
+use crate::csv_import::parse_server_csv;
+use crate::debug;
+use crate::models::{
+    best_description_matches, compare_groups, match_servers, parse_age, parse_bind_address,
+    parse_field_assignment, parse_jump_host, parse_port, parse_port_forwards, parse_seconds,
+    parse_ssh_option, parse_tags, parse_tmux_session, same_group, stale_servers, strong_match,
+    tag_counts, validate_host, AuthMethod, ConnectionEvent, HostKeyPolicy, MergeSummary,
+    OnConflict, PortForward, Server, SortOrder, ValidationError, VaultData,
+};
 use crate::ssh;
-use crate::ssh_config::{render_managed_block, upsert_managed_block};
+use crate::ssh_config::{
+    managed_block_line, parse_ssh_config, render_host_block, render_managed_block,
+    upsert_managed_block, validate_ssh_config,
+};
+use crate::theme::{Theme, ThemeName};
 use crate::tui;
-use crate::vault::Vault;
-use fuzzy_matcher::FuzzyMatcher;
+use crate::vault::{
+    write_atomic, ExportFile, StoreLayout, Vault, VaultError, VaultFormat, PASSWORD_ENV,
+};
 use uuid::Uuid;
+use zeroize::Zeroize;
 
 pub fn password_option_from_choice(use_password: bool, password: &str) -> Result<Option<&str>> {
     if use_password && password.is_empty() {
@@ -21,6 +42,430 @@ pub fn password_option_from_choice(use_password: bool, password: &str) -> Result
     Ok(if use_password { Some(password) } else { None })
 }
 
+/// Reads one line for `--password-stdin`, without its line ending.
+pub fn read_password_line(mut input: impl BufRead) -> Result<String> {
+    let mut line = String::new();
+    input
+        .read_line(&mut line)
+        .map_err(|e| anyhow::anyhow!("Failed to read the master password from stdin: {e}"))?;
+    let password = line.trim_end_matches(['\n', '\r']).to_string();
+    line.zeroize();
+    if password.is_empty() {
+        return Err(anyhow::anyhow!("No master password on stdin"));
+    }
+    Ok(password)
+}
+
+// Listening for Ctrl-C through tokio swaps in its own SIGINT handler for good;
+// put the default back so a later Ctrl-C ends the process as usual.
+fn restore_default_sigint() {
+    #[cfg(unix)]
+    // SAFETY: resetting a signal to SIG_DFL installs no handler code of ours.
+    unsafe {
+        libc::signal(libc::SIGINT, libc::SIG_DFL);
+    }
+}
+
+fn editor_command() -> Vec<String> {
+    ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .map(|value| {
+            value
+                .split_whitespace()
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        })
+        .find(|parts| !parts.is_empty())
+        .unwrap_or_else(|| vec!["vi".to_string()])
+}
+
+#[derive(Serialize)]
+struct ServerJson<'a> {
+    id: Uuid,
+    name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alias: Option<&'a str>,
+    host: &'a str,
+    port: u16,
+    username: &'a str,
+    description: Option<&'a str>,
+    tags: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    group: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    password: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sudo_password: Option<&'a str>,
+}
+
+/// Fields given to `add` on the command line.
+struct AddFlags {
+    name: Option<String>,
+    host: Option<String>,
+    port: Option<String>,
+    user: Option<String>,
+    password: Option<String>,
+    description: Option<String>,
+    tags: Option<String>,
+}
+
+impl AddFlags {
+    fn is_empty(&self) -> bool {
+        [
+            &self.name,
+            &self.host,
+            &self.port,
+            &self.user,
+            &self.password,
+            &self.description,
+            &self.tags,
+        ]
+        .iter()
+        .all(|flag| flag.is_none())
+    }
+}
+
+/// Which server `connect` should open.
+enum ConnectTarget {
+    /// Ask with a list of every server.
+    Pick,
+    /// A name or id, or a prefix of either unless `exact` is set.
+    Name { name: String, exact: bool },
+    /// The best fuzzy match on descriptions.
+    Description(String),
+}
+
+/// Narrows `list` down to servers with a tag and/or in a group.
+struct ListFilter {
+    tag: Option<String>,
+    group: Option<String>,
+}
+
+/// Renders servers as the JSON array printed by `list --json` and
+/// `search --json`. Passwords are left out unless `include_passwords` is set.
+pub fn servers_json(servers: &[&Server], include_passwords: bool) -> Result<String> {
+    let entries: Vec<ServerJson> = servers
+        .iter()
+        .map(|server| ServerJson {
+            id: server.id,
+            name: &server.name,
+            alias: server.alias.as_deref(),
+            host: &server.host,
+            port: server.port,
+            username: &server.username,
+            description: server.description.as_deref(),
+            tags: &server.tags,
+            group: server.group.as_deref(),
+            password: include_passwords.then_some(server.password.as_str()),
+            sudo_password: server
+                .sudo_password
+                .as_deref()
+                .filter(|_| include_passwords),
+        })
+        .collect();
+    Ok(serde_json::to_string_pretty(&entries)?)
+}
+
+/// Renders connection events as the CSV written by `history --csv`, with the
+/// columns `server_name,host,timestamp,success` and RFC 3339 UTC timestamps.
+/// Servers no longer in the vault are named "(deleted)" with a blank host.
+pub fn history_csv(events: &[&ConnectionEvent], servers: &[Server]) -> Result<String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(["server_name", "host", "timestamp", "success"])?;
+    for event in events {
+        let server = servers.iter().find(|s| s.id == event.server_id);
+        writer.write_record([
+            server.map_or("(deleted)", |s| s.name.as_str()),
+            server.map_or("", |s| s.host.as_str()),
+            &event.at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            if event.success { "true" } else { "false" },
+        ])?;
+    }
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+/// The line closing `list`: the number of servers and how many carry each
+/// tag, counted like `portkey tags` does, e.g. "3 servers (2 prod, 1 staging)".
+pub fn list_summary(servers: &[&Server]) -> String {
+    let total = match servers.len() {
+        1 => "1 server".to_string(),
+        n => format!("{n} servers"),
+    };
+
+    let tags = tag_counts(servers.iter().copied());
+    if tags.is_empty() {
+        return total;
+    }
+    let tags: Vec<String> = tags
+        .iter()
+        .map(|(tag, count)| format!("{count} {tag}"))
+        .collect();
+    format!("{total} ({})", tags.join(", "))
+}
+
+/// Master password prompts allowed before giving up on unlocking.
+const MAX_PASSWORD_ATTEMPTS: u32 = 3;
+
+fn ssh_config_path() -> Result<PathBuf> {
+    let mut path = dirs::home_dir().ok_or_else(|| anyhow::anyhow!("Home directory not found"))?;
+    path.push(".ssh");
+    std::fs::create_dir_all(&path)?;
+    path.push("config");
+    Ok(path)
+}
+
+// ssh falls back to the local login name when a host has no User
+fn local_user() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "root".to_string())
+}
+
+/// Rewrites `path` with `managed_block` in place of the previous one, so
+/// repeated runs never pile up duplicate entries. Anything outside the
+/// markers is kept; a missing file (and its directory) is created. The new
+/// content is renamed into place, so a crash or a full disk never leaves the
+/// user's own entries truncated.
+fn write_managed_block(path: &Path, managed_block: &str) -> Result<()> {
+    // A config symlinked from a dotfiles repo is updated where it lives
+    let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let existing = match std::fs::read_to_string(&path) {
+        Ok(existing) => existing,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(anyhow::anyhow!("Failed to read {}: {e}", path.display())),
+    };
+    write_atomic(
+        &path,
+        upsert_managed_block(&existing, managed_block).as_bytes(),
+    )
+    .map_err(|e| anyhow::anyhow!("Failed to write {}: {e}", path.display()))
+}
+
+/// Opens `content` in the user's editor via a scratch copy next to `path`. The
+/// real file is only replaced once the editor exits cleanly and the result
+/// still parses, so a crashed or aborted editor never corrupts the config.
+fn edit_ssh_config(path: &Path, content: &str) -> Result<()> {
+    let parent = path
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("SSH config path has no parent directory"))?;
+    let scratch_path = parent.join(format!(".config.portkey-{}.tmp", Uuid::new_v4()));
+    std::fs::write(&scratch_path, content)?;
+
+    let result = (|| -> Result<()> {
+        let editor = editor_command();
+        let mut command = std::process::Command::new(&editor[0]);
+        command.args(&editor[1..]);
+        if let Some(line) = managed_block_line(content) {
+            command.arg(format!("+{line}"));
+        }
+        let status = command
+            .arg(&scratch_path)
+            .status()
+            .map_err(|e| anyhow::anyhow!("Failed to launch editor '{}': {e}", editor[0]))?;
+
+        if !status.success() {
+            return Err(anyhow::anyhow!(
+                "Editor exited with {status}; {} was left unchanged",
+                path.display()
+            ));
+        }
+
+        let edited = std::fs::read_to_string(&scratch_path)?;
+        validate_ssh_config(&edited).map_err(|e| {
+            anyhow::anyhow!(
+                "Edited SSH config is invalid ({e}); {} was left unchanged",
+                path.display()
+            )
+        })?;
+
+        if let Ok(metadata) = std::fs::metadata(path) {
+            std::fs::set_permissions(&scratch_path, metadata.permissions())?;
+        }
+        std::fs::rename(&scratch_path, path)?;
+        Ok(())
+    })();
+
+    let _ = std::fs::remove_file(&scratch_path);
+    result
+}
+
+fn prompt_seconds(message: &str) -> Option<u32> {
+    loop {
+        let input = Text::new(message).prompt().unwrap_or_default();
+        match parse_seconds(&input) {
+            Ok(seconds) => return seconds,
+            Err(e) => println!("{e}"),
+        }
+    }
+}
+
+/// Asks for a server password, offering to generate one instead. A
+/// generated password is printed once so it can be set on the server.
+fn prompt_server_password() -> Result<String> {
+    const TYPE_IT: &str = "Type it";
+    let mut choices = vec![TYPE_IT.to_string()];
+    choices.extend(Charset::ALL.map(|charset| format!("Generate ({charset})")));
+    let choice = Select::new("Password:", choices).raw_prompt()?;
+    if choice.index == 0 {
+        return Ok(Password::new("Password:")
+            .with_display_toggle_enabled()
+            .prompt()?);
+    }
+
+    let charset = Charset::ALL[choice.index - 1];
+    let length = loop {
+        let input = Text::new("Length:")
+            .with_default(&DEFAULT_PASSWORD_LENGTH.to_string())
+            .prompt()?;
+        match input.trim().parse::<usize>() {
+            Ok(length) if (8..=128).contains(&length) => break length,
+            _ => println!("Enter a length from 8 to 128"),
+        }
+    };
+    let password = generate_password(length, charset);
+    println!("Generated password (shown only once): {password}");
+    Ok(password)
+}
+
+/// Completes the tag being typed in a comma-separated list from the tags
+/// already in the vault, so "pr" offers "prod" rather than a new spelling.
+#[derive(Clone)]
+struct TagCompleter {
+    known: Vec<String>,
+}
+
+impl TagCompleter {
+    // The full input with the last, partly typed tag replaced by each
+    // matching known tag that isn't in the list already
+    fn candidates(&self, input: &str) -> Vec<String> {
+        let (done, partial) = match input.rsplit_once(',') {
+            Some((done, partial)) => (Some(done), partial),
+            None => (None, input),
+        };
+        let partial = partial.trim().to_lowercase();
+        let entered = done.map(parse_tags).unwrap_or_default();
+        self.known
+            .iter()
+            .filter(|tag| tag.starts_with(&partial) && !entered.contains(tag))
+            .map(|tag| match done {
+                Some(done) => format!("{}, {tag}", done.trim_end()),
+                None => tag.clone(),
+            })
+            .collect()
+    }
+}
+
+impl Autocomplete for TagCompleter {
+    fn get_suggestions(&mut self, input: &str) -> Result<Vec<String>, CustomUserError> {
+        Ok(self.candidates(input))
+    }
+
+    fn get_completion(
+        &mut self,
+        input: &str,
+        highlighted_suggestion: Option<String>,
+    ) -> Result<Replacement, CustomUserError> {
+        if highlighted_suggestion.is_some() {
+            return Ok(highlighted_suggestion);
+        }
+        let mut candidates = self.candidates(input);
+        Ok(match candidates.len() {
+            1 => candidates.pop(),
+            _ => None,
+        })
+    }
+}
+
+// `connect --sudo`: the sudo password goes on the clipboard before ssh
+// starts. `server` must be unsealed, see `Vault::with_password`.
+fn copy_sudo_password(server: &Server, options: &ssh::ConnectOptions) -> Result<()> {
+    let password = server.sudo_password.as_deref().unwrap_or(&server.password);
+    if password.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Server '{}' has no password to use for sudo",
+            server.name
+        ));
+    }
+    // Without sshpass the clipboard is needed for the login password itself
+    if server.uses_sshpass() && !ssh::will_use_sshpass(server, options) {
+        return Err(anyhow::anyhow!(
+            "--sudo needs sshpass for the login password, since the clipboard holds the sudo password"
+        ));
+    }
+    clipboard::copy_secret(password, clipboard::CLEAR_AFTER)?;
+    println!(
+        "🔑 Sudo password copied to the clipboard (cleared in {}s); paste it at the first sudo prompt.",
+        clipboard::CLEAR_AFTER.as_secs()
+    );
+    Ok(())
+}
+
+// Multi-line notes, indented under a heading, in list and search output.
+fn print_notes(server: &Server) {
+    if let Some(notes) = &server.notes {
+        println!("Notes:");
+        for line in notes.lines() {
+            println!("  {line}");
+        }
+    }
+}
+
+fn host_with_tags(server: &Server) -> String {
+    let host = server.host_port();
+    if server.tags.is_empty() {
+        host
+    } else {
+        format!("{host} {}", server.tag_label())
+    }
+}
+
+/// Returns the only candidate directly, otherwise asks the user to pick one.
+fn choose_server<'a>(prompt: &str, candidates: &[&'a Server]) -> Result<&'a Server> {
+    match candidates {
+        [] => Err(anyhow::anyhow!("No matching servers")),
+        [only] => Ok(only),
+        _ => {
+            let options: Vec<String> = candidates
+                .iter()
+                .map(|s| match &s.description {
+                    Some(desc) => format!("{} ({}) - {desc}", s.name, s.host),
+                    None => format!("{} ({})", s.name, s.host),
+                })
+                .collect();
+            let selection = Select::new(prompt, options).raw_prompt()?;
+            Ok(candidates[selection.index])
+        }
+    }
+}
+
+/// Prints what an import did to each server and returns the totals, e.g.
+/// "Imported 2 server(s), merged 1, skipped 3 duplicate(s)".
+fn report_import(summary: &MergeSummary, verb: &str) -> String {
+    for name in &summary.added {
+        println!("+ {name}");
+    }
+    for name in &summary.merged {
+        println!("~ {name} (merged)");
+    }
+    for name in &summary.replaced {
+        println!("~ {name} (overwritten)");
+    }
+    for name in &summary.skipped {
+        println!("= {name} (already present)");
+    }
+
+    let mut totals = format!("{verb} {} server(s)", summary.added.len());
+    if !summary.merged.is_empty() {
+        totals.push_str(&format!(", merged {}", summary.merged.len()));
+    }
+    if !summary.replaced.is_empty() {
+        totals.push_str(&format!(", overwrote {}", summary.replaced.len()));
+    }
+    totals.push_str(&format!(", skipped {} duplicate(s)", summary.skipped.len()));
+    totals
+}
+
 #[derive(Parser)]
 #[command(name = "portkey")]
 #[command(about = "Secure SSH credential manager")]
@@ -28,23 +473,249 @@ pub fn password_option_from_choice(use_password: bool, password: &str) -> Result
 pub struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
+
+    /// Without a command: connect to the server this clearly names, or open
+    /// the TUI filtered by it. A subcommand of the same name wins.
+    query: Option<String>,
+
+    /// Lock the TUI after this many idle seconds (0 disables)
+    #[arg(long, global = true, value_name = "SECONDS", default_value_t = 300)]
+    lock_timeout: u64,
+
+    /// Use the named vault (<name>.dat in the data directory) instead of the default
+    #[arg(long, global = true, value_name = "NAME")]
+    vault: Option<String>,
+
+    /// Use the vault stored at this path
+    #[arg(long, global = true, value_name = "PATH", conflicts_with = "vault")]
+    vault_file: Option<PathBuf>,
+
+    /// Unlock (or with `init`, create) the vault with this 32-byte key file instead of a password
+    #[arg(long, global = true, value_name = "PATH")]
+    key_file: Option<PathBuf>,
+
+    /// Read the master password from the first line of stdin instead of prompting
+    #[arg(long, global = true, conflicts_with = "key_file")]
+    password_stdin: bool,
+
+    /// Don't restore or save the TUI's last filter, sort and selection
+    #[arg(long, global = true)]
+    no_remember: bool,
+
+    /// Host key checking for this run, overriding each server's policy: strict, accept-new or off
+    #[arg(long, global = true, value_name = "POLICY")]
+    host_key_checking: Option<HostKeyPolicy>,
+
+    /// Same as `--host-key-checking off`
+    #[arg(long, global = true, conflicts_with = "host_key_checking")]
+    no_strict_host_checking: bool,
+
+    /// Browse and connect only: anything that would change the vault fails
+    #[arg(long, global = true)]
+    read_only: bool,
+
+    /// TUI colors: dark or light (NO_COLOR turns colors off)
+    #[arg(long, global = true, value_name = "THEME", default_value = "dark")]
+    theme: ThemeName,
 }
 
 #[derive(Subcommand)]
 pub enum Commands {
     /// Initialize a new vault
-    Init,
+    Init {
+        /// Argon2 cost for the master key: interactive, moderate or sensitive
+        #[arg(long, default_value = "interactive")]
+        kdf: KdfStrength,
+
+        /// Storage format: json, or toml for a hand-editable vault without a
+        /// master password
+        #[arg(long, default_value = "json")]
+        format: VaultFormat,
+
+        /// Layout on disk: single, or split for one file per server plus an
+        /// index, which keeps diffs small when the vault is synced with git
+        #[arg(long, default_value = "single")]
+        store: StoreLayout,
+    },
 
-    /// Add a new server
-    Add,
+    /// Add a new server; with any of the flags below, only missing required
+    /// fields are prompted for
+    Add {
+        /// Server name
+        #[arg(long)]
+        name: Option<String>,
+
+        /// Host name or IP address
+        #[arg(long)]
+        host: Option<String>,
+
+        /// SSH port, 22 when left out
+        #[arg(long)]
+        port: Option<String>,
+
+        /// Login user
+        #[arg(long)]
+        user: Option<String>,
+
+        /// Visible to other local users in the process list; leave it out to
+        /// be asked for it instead
+        #[arg(long)]
+        password: Option<String>,
+
+        /// Free-form description
+        #[arg(long)]
+        description: Option<String>,
+
+        /// Comma-separated tags
+        #[arg(long)]
+        tags: Option<String>,
+    },
 
     /// List all servers
-    List,
+    List {
+        /// Print only the number of servers (as {"count": N} with --json)
+        #[arg(long)]
+        count: bool,
+
+        /// Only show servers carrying this tag (case-insensitive)
+        #[arg(long)]
+        tag: Option<String>,
+
+        /// Only show servers in this group (case-insensitive)
+        #[arg(long)]
+        group: Option<String>,
+
+        /// Print the servers in sections, one per group
+        #[arg(long, conflicts_with_all = ["count", "json"])]
+        by_group: bool,
+
+        /// Order by recent, name, host or port instead of insertion order
+        #[arg(long)]
+        sort: Option<SortOrder>,
+
+        /// Print the servers as a JSON array instead
+        #[arg(long)]
+        json: bool,
+
+        /// Include stored passwords in the JSON output
+        #[arg(long, requires = "json", conflicts_with = "count")]
+        include_passwords: bool,
+    },
+
+    /// List every tag in use with the number of servers carrying it
+    Tags {
+        /// Print the tags as a JSON array of {"tag", "count"} objects instead
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Summarize the vault: server counts by tag, group and auth method,
+    /// and the oldest, newest and last-used servers
+    Stats {
+        /// Print the summary as a JSON object instead
+        #[arg(long)]
+        json: bool,
+    },
 
     /// Connect to a server
     Connect {
         /// Server name or ID
         name: Option<String>,
+
+        /// Attach to (or create) this remote tmux session instead of the stored one
+        #[arg(long)]
+        tmux_session: Option<String>,
+
+        /// Connect to the server whose description best matches this keyword
+        #[arg(long, conflicts_with = "name")]
+        desc: Option<String>,
+
+        /// Only accept an exact server name or full ID, not a prefix
+        #[arg(long, requires = "name")]
+        exact: bool,
+
+        /// Don't use sshpass; copy the password to the clipboard and type it at ssh's prompt
+        #[arg(long)]
+        no_sshpass: bool,
+
+        /// Extra local forward for this connection only, e.g. 5432:localhost:5432
+        #[arg(short = 'L', long = "port-forward", value_name = "LOCAL:HOST:PORT")]
+        port_forwards: Vec<PortForward>,
+
+        /// Run this command instead of a shell and exit with its status
+        #[arg(long, value_name = "COMMAND", conflicts_with = "tmux_session")]
+        exec: Option<String>,
+
+        /// Allocate a terminal for --exec, for commands like top that need one
+        #[arg(long, requires = "exec")]
+        tty: bool,
+
+        /// Log in as this user for this connection only; asks for its password
+        #[arg(long, short = 'u', value_name = "USER")]
+        user: Option<String>,
+
+        /// Ask for the login password instead of using the stored one
+        #[arg(long, conflicts_with = "dry_run")]
+        ask_pass: bool,
+
+        /// Put the sudo password on the clipboard for the first sudo prompt
+        #[arg(long, conflicts_with = "dry_run")]
+        sudo: bool,
+
+        /// Pass -v to ssh for its own diagnostics; repeat for more (-vvv)
+        #[arg(short, long, action = clap::ArgAction::Count)]
+        verbose: u8,
+
+        /// Start ssh again up to this many times when it fails because the
+        /// server's port doesn't answer; failed logins are never retried
+        #[arg(long, value_name = "N", default_value_t = 0)]
+        retries: u32,
+
+        /// Wait before the first retry, doubling for each one after it
+        #[arg(long, value_name = "MS", default_value_t = 1000)]
+        retry_delay: u64,
+
+        /// Print the command that would be run, password redacted, without connecting
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Run a command on several servers at once, output prefixed by server name
+    #[command(group(
+        ArgGroup::new("targets")
+            .args(["query", "tag", "all"])
+            .required(true)
+            .multiple(true)
+    ))]
+    Exec {
+        /// Command to run on each server
+        command: String,
+
+        /// Servers whose name or ID starts with this (an exact name wins)
+        #[arg(long)]
+        query: Option<String>,
+
+        /// Servers carrying this tag (case-insensitive)
+        #[arg(long)]
+        tag: Option<String>,
+
+        /// Every server in the vault
+        #[arg(long, conflicts_with_all = ["query", "tag"])]
+        all: bool,
+
+        /// How many servers to run on at the same time
+        #[arg(long, default_value_t = 4)]
+        parallel: usize,
+    },
+
+    /// Open an SFTP session to a server
+    Sftp {
+        /// Server name or ID
+        name: String,
+
+        /// Don't use sshpass; copy the password to the clipboard and type it at the prompt
+        #[arg(long)]
+        no_sshpass: bool,
     },
 
     /// Remove a server
@@ -53,352 +724,2310 @@ pub enum Commands {
         name: String,
     },
 
+    /// Remove servers not connected to within an age like 90d, 12w or 3m
+    Prune {
+        /// Servers last connected longer ago than this, or never, are removed
+        #[arg(long, value_name = "AGE")]
+        older_than: String,
+
+        /// Only list the servers that would be removed
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Don't ask for confirmation
+        #[arg(long, short, conflicts_with = "dry_run")]
+        yes: bool,
+    },
+
+    /// Change fields of a server without the interactive form
+    Edit {
+        /// Server name or ID
+        name: String,
+
+        /// Field to change, e.g. host=10.0.0.5 or tags=prod,web (repeatable)
+        #[arg(long, value_name = "FIELD=VALUE", required = true)]
+        set: Vec<String>,
+    },
+
+    /// Copy a server under the name "<name> (copy)"
+    Clone {
+        /// Server name or ID
+        name: String,
+    },
+
     /// Interactive server selection and connection
     Quick,
 
     /// Search servers
-    Search { query: String },
+    Search {
+        /// Fuzzy text, optionally with exact tag:, user:, host:, group: and
+        /// port: qualifiers, e.g. "tag:prod user:root web"
+        query: String,
+
+        /// Print only the number of matching servers
+        #[arg(long)]
+        count: bool,
+
+        /// Print the matches as a JSON array instead, best match first
+        #[arg(long, conflicts_with = "count")]
+        json: bool,
+
+        /// Include stored passwords in the JSON output
+        #[arg(long, requires = "json")]
+        include_passwords: bool,
+    },
 
     /// Export SSH config entries for servers
     SshConfig {
+        /// Print just this server's Host block, for sharing
+        #[arg(conflicts_with_all = ["write", "edit", "output"])]
+        name: Option<String>,
+
         /// Actually write to ~/.ssh/config instead of printing
         #[arg(long)]
         write: bool,
+
+        /// Write the entries, then open ~/.ssh/config in $VISUAL/$EDITOR at the managed block
+        #[arg(long, alias = "open-config", conflicts_with = "write")]
+        edit: bool,
+
+        /// Write the entries to this file instead, e.g. one pulled in with an
+        /// `Include ~/.ssh/config.d/*` line
+        #[arg(long, value_name = "PATH", conflicts_with_all = ["write", "edit"])]
+        output: Option<PathBuf>,
     },
 
-    /// Full-screen TUI application
-    Ui,
-}
+    /// Check that a server is reachable without opening a session
+    Test {
+        /// Server name or ID
+        name: String,
 
-pub struct CliHandler {
-    vault: Vault,
-}
+        /// Also log in non-interactively to verify authentication
+        #[arg(long)]
+        auth: bool,
+    },
 
-impl CliHandler {
-    pub fn new() -> Result<Self> {
-        let vault = Vault::new()?;
-        Ok(Self { vault })
+    /// Copy a server's password to the clipboard for 30 seconds
+    Copy {
+        /// Server name or ID
+        name: String,
+
+        /// Copy the sudo password instead (the login password unless one is set)
+        #[arg(long)]
+        sudo: bool,
+    },
+
+    /// Print a server's ssh://user@host:port URI
+    Uri {
+        /// Server name or ID
+        name: String,
+
+        /// Copy it to the clipboard as well
+        #[arg(long, short)]
+        copy: bool,
+    },
+
+    /// Open a server in the desktop file manager over sftp://
+    Open {
+        /// Server name or ID
+        name: String,
+    },
+
+    /// Print a server's password to stdout, for piping into other tools
+    Pass {
+        /// Server name or ID
+        name: String,
+
+        /// Don't ask for confirmation
+        #[arg(long, short)]
+        yes: bool,
+
+        /// Print even when stdout is a terminal
+        #[arg(long)]
+        force: bool,
+
+        /// Print the sudo password instead (the login password unless one is set)
+        #[arg(long)]
+        sudo: bool,
+    },
+
+    /// Restores the clipboard after `copy`; spawned internally
+    #[command(name = clipboard::RESTORE_COMMAND, hide = true)]
+    ClipboardRestore {
+        #[arg(long)]
+        after: u64,
+    },
+
+    /// List the vaults in the data directory
+    Vaults,
+
+    /// Print diagnostics about the selected vault and required binaries
+    #[command(hide = true)]
+    Debug,
+
+    /// Print the vault as JSON for a bug report, secrets redacted
+    Dump {
+        /// Redact passwords and notes (the default)
+        #[arg(long, conflicts_with = "include_secrets")]
+        redacted: bool,
+
+        /// Print real passwords and notes; never paste this anywhere public
+        #[arg(long)]
+        include_secrets: bool,
+
+        /// Replace hosts, also in ssh options, with a hash salted for this dump only
+        #[arg(long)]
+        hash_hosts: bool,
+    },
+
+    /// Show recent connections, newest first
+    History {
+        /// How many events to show
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+
+        /// Only connections within an age like 7d, 12w or 3m
+        #[arg(long, value_name = "AGE")]
+        since: Option<String>,
+
+        /// Write every matching event to this CSV file instead of printing them
+        #[arg(long, value_name = "PATH")]
+        csv: Option<PathBuf>,
+
+        /// Change how many events the vault keeps (default 500)
+        #[arg(long)]
+        keep: Option<usize>,
+    },
+
+    /// Undo the last add, edit or remove
+    Undo,
+
+    /// Change, add or remove the vault master password
+    Passwd,
+
+    /// Wipe the master key and decrypted servers from memory. No command
+    /// keeps the key after it exits, so this only confirms that; inside the
+    /// TUI, L locks the open vault
+    Lock,
+
+    /// Write an encrypted, portable backup of the vault
+    Export {
+        /// Where to write the backup
+        #[arg(long, short)]
+        output: PathBuf,
+
+        /// Write the decrypted vault as plain JSON instead (see `portkey schema`)
+        #[arg(long)]
+        plain_json: bool,
+
+        /// Don't ask before writing passwords in plaintext
+        #[arg(long, short, requires = "plain_json")]
+        yes: bool,
+    },
+
+    /// Print the JSON Schema of the vault data that `export --plain-json` writes
+    Schema,
+
+    /// Merge servers from a backup made with `export`
+    Import {
+        /// Backup file to read
+        file: PathBuf,
+
+        /// Show what would be imported without changing the vault
+        #[arg(long)]
+        dry_run: bool,
+
+        /// For servers already stored with the same host, user and port:
+        /// skip, merge (add tags, forwards and ssh options, take the
+        /// description) or overwrite
+        #[arg(long, value_name = "STRATEGY", default_value = "skip")]
+        on_conflict: OnConflict,
+    },
+
+    /// Add the hosts defined in an OpenSSH client config
+    ImportSshConfig {
+        /// Config file to read (defaults to ~/.ssh/config)
+        #[arg(long)]
+        path: Option<PathBuf>,
+
+        /// For hosts already stored: skip, merge or overwrite
+        #[arg(long, value_name = "STRATEGY", default_value = "skip")]
+        on_conflict: OnConflict,
+    },
+
+    /// Import servers from a CSV file (name,host,port,username,password,description,tags)
+    ImportCsv {
+        /// CSV file to read
+        path: PathBuf,
+
+        /// Same as `--on-conflict overwrite`
+        #[arg(long, conflicts_with = "on_conflict")]
+        overwrite: bool,
+
+        /// For rows already stored with the same host, user and port: skip,
+        /// merge or overwrite
+        #[arg(long, value_name = "STRATEGY", default_value = "skip")]
+        on_conflict: OnConflict,
+    },
+
+    /// Show or change the preferences in config.toml
+    Config {
+        #[command(subcommand)]
+        action: Option<ConfigAction>,
+    },
+
+    /// Full-screen TUI application
+    Ui,
+}
+
+#[derive(Subcommand)]
+pub enum ConfigAction {
+    /// Print the current settings (the default when no action is given)
+    Show,
+
+    /// Change a setting: default_port or default_username
+    Set {
+        key: String,
+        /// A blank username clears it
+        value: String,
+    },
+}
+
+pub struct CliHandler {
+    vault: Vault,
+    key_file: Option<PathBuf>,
+    password_stdin: bool,
+    host_key_policy: Option<HostKeyPolicy>,
+    theme: Theme,
+    config: Config,
+    cli: Option<Cli>,
+}
+
+impl CliHandler {
+    pub fn new() -> Result<Self> {
+        let cli = Cli::parse();
+        let mut vault = match (&cli.vault_file, &cli.vault) {
+            (Some(path), _) => Vault::with_path(path.clone())?,
+            (None, Some(name)) => Vault::named(name)?,
+            (None, None) => Vault::new()?,
+        };
+        vault.set_read_only(cli.read_only);
+        let host_key_policy = if cli.no_strict_host_checking {
+            Some(HostKeyPolicy::Off)
+        } else {
+            cli.host_key_checking
+        };
+        // A broken config shouldn't lock anyone out of their servers
+        let config = match Config::path().and_then(|path| Config::load_from(&path)) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Warning: {e:#}; using the default settings");
+                Config::default()
+            }
+        };
+        Ok(Self {
+            vault,
+            key_file: cli.key_file.clone(),
+            password_stdin: cli.password_stdin,
+            host_key_policy,
+            theme: Theme::from_env(cli.theme),
+            config,
+            cli: Some(cli),
+        })
+    }
+
+    pub async fn run(&mut self) -> Result<()> {
+        let cli = self
+            .cli
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("Command already run"))?;
+        let lock_timeout = (cli.lock_timeout > 0).then(|| Duration::from_secs(cli.lock_timeout));
+        let remember = !cli.no_remember;
+        // Fail before any prompt rather than after the user has filled it in
+        let edits = matches!(
+            cli.command,
+            Some(
+                Commands::Init { .. }
+                    | Commands::Add { .. }
+                    | Commands::Remove { .. }
+                    | Commands::Prune { dry_run: false, .. }
+                    | Commands::Edit { .. }
+                    | Commands::Clone { .. }
+                    | Commands::Undo
+                    | Commands::Passwd
+                    | Commands::Import { dry_run: false, .. }
+                    | Commands::ImportSshConfig { .. }
+                    | Commands::ImportCsv { .. }
+            )
+        );
+        if edits && self.vault.is_read_only() {
+            return Err(VaultError::ReadOnly.into());
+        }
+        if let (Some(query), Some(_)) = (&cli.query, &cli.command) {
+            return Err(anyhow::anyhow!(
+                "'{query}' can't be combined with a command; run `portkey {query}` on its own"
+            ));
+        }
+
+        match cli.command {
+            Some(Commands::Init { kdf, format, store }) => {
+                self.handle_init(kdf, format, store).await?
+            }
+            Some(Commands::Add {
+                name,
+                host,
+                port,
+                user,
+                password,
+                description,
+                tags,
+            }) => {
+                let flags = AddFlags {
+                    name,
+                    host,
+                    port,
+                    user,
+                    password,
+                    description,
+                    tags,
+                };
+                self.handle_add(flags).await?
+            }
+            Some(Commands::List {
+                count,
+                tag,
+                group,
+                by_group,
+                sort,
+                json,
+                include_passwords,
+            }) => {
+                let filter = ListFilter { tag, group };
+                self.handle_list(count, filter, by_group, sort, json, include_passwords)
+                    .await?
+            }
+            Some(Commands::Tags { json }) => self.handle_tags(json).await?,
+            Some(Commands::Stats { json }) => self.handle_stats(json).await?,
+            Some(Commands::Connect {
+                name,
+                tmux_session,
+                desc,
+                exact,
+                no_sshpass,
+                port_forwards,
+                exec,
+                tty,
+                verbose,
+                retries,
+                retry_delay,
+                user,
+                ask_pass,
+                sudo,
+                dry_run,
+            }) => {
+                let target = match (name, desc) {
+                    (_, Some(keyword)) => ConnectTarget::Description(keyword),
+                    (Some(name), None) => ConnectTarget::Name { name, exact },
+                    (None, None) => ConnectTarget::Pick,
+                };
+                let tmux_session = match tmux_session {
+                    Some(session) => parse_tmux_session(&session)?,
+                    None => None,
+                };
+                // Gateways and the host key policy are filled in per server
+                let overrides = ssh::ConnectOptions {
+                    tmux_session,
+                    no_sshpass,
+                    port_forwards,
+                    remote_command: exec.map(|command| ssh::RemoteCommand { command, tty }),
+                    verbosity: verbose,
+                    retry: ssh::Retry {
+                        retries,
+                        delay: Duration::from_millis(retry_delay),
+                    },
+                    ..Default::default()
+                };
+                self.handle_connect(target, overrides, user, ask_pass, sudo, dry_run)
+                    .await?
+            }
+            Some(Commands::Exec {
+                command,
+                query,
+                tag,
+                all: _,
+                parallel,
+            }) => self.handle_exec(command, query, tag, parallel).await?,
+            Some(Commands::Sftp { name, no_sshpass }) => self.handle_sftp(name, no_sshpass).await?,
+            Some(Commands::Remove { name }) => self.handle_remove(name).await?,
+            Some(Commands::Prune {
+                older_than,
+                dry_run,
+                yes,
+            }) => {
+                self.handle_prune(parse_age(&older_than)?, dry_run, yes)
+                    .await?
+            }
+            Some(Commands::Edit { name, set }) => self.handle_edit(name, set).await?,
+            Some(Commands::Clone { name }) => self.handle_clone(name).await?,
+            Some(Commands::Quick) => self.handle_quick(lock_timeout, remember).await?,
+            Some(Commands::Search {
+                query,
+                count,
+                json,
+                include_passwords,
+            }) => {
+                self.handle_search(query, count, json, include_passwords)
+                    .await?
+            }
+            Some(Commands::SshConfig {
+                name: Some(name), ..
+            }) => self.handle_ssh_config_entry(name).await?,
+            Some(Commands::SshConfig {
+                name: None,
+                write,
+                edit,
+                output,
+            }) => self.handle_ssh_config(write, edit, output).await?,
+            Some(Commands::Test { name, auth }) => self.handle_test(name, auth).await?,
+            Some(Commands::Copy { name, sudo }) => self.handle_copy(name, sudo).await?,
+            Some(Commands::Uri { name, copy }) => self.handle_uri(name, copy).await?,
+            Some(Commands::Open { name }) => self.handle_open(name).await?,
+            Some(Commands::Pass {
+                name,
+                yes,
+                force,
+                sudo,
+            }) => self.handle_pass(name, yes, force, sudo).await?,
+            Some(Commands::ClipboardRestore { after }) => {
+                clipboard::restore_after(Duration::from_secs(after), std::io::stdin())?
+            }
+            Some(Commands::Vaults) => self.handle_vaults()?,
+            Some(Commands::Debug) => debug::debug_vault(&self.vault),
+            Some(Commands::Dump {
+                redacted: _,
+                include_secrets,
+                hash_hosts,
+            }) => {
+                self.handle_dump(debug::DumpOptions {
+                    include_secrets,
+                    hash_hosts,
+                })
+                .await?
+            }
+            Some(Commands::History {
+                limit,
+                since,
+                csv,
+                keep,
+            }) => {
+                let since = since.as_deref().map(parse_age).transpose()?;
+                self.handle_history(limit, since, csv, keep).await?
+            }
+            Some(Commands::Undo) => self.handle_undo().await?,
+            Some(Commands::Passwd) => self.handle_passwd().await?,
+            Some(Commands::Lock) => self.handle_lock()?,
+            Some(Commands::Config { action }) => self.handle_config(action)?,
+            Some(Commands::Export {
+                output,
+                plain_json,
+                yes,
+            }) => {
+                if plain_json {
+                    self.handle_export_plain(output, yes).await?
+                } else {
+                    self.handle_export(output).await?
+                }
+            }
+            Some(Commands::Schema) => {
+                println!("{}", serde_json::to_string_pretty(&VaultData::schema())?)
+            }
+            Some(Commands::Import {
+                file,
+                dry_run,
+                on_conflict,
+            }) => self.handle_import(file, dry_run, on_conflict).await?,
+            Some(Commands::ImportSshConfig { path, on_conflict }) => {
+                self.handle_import_ssh_config(path, on_conflict).await?
+            }
+            Some(Commands::ImportCsv {
+                path,
+                overwrite,
+                on_conflict,
+            }) => {
+                let on_conflict = if overwrite {
+                    OnConflict::Overwrite
+                } else {
+                    on_conflict
+                };
+                self.handle_import_csv(path, on_conflict).await?
+            }
+            Some(Commands::Ui) => {
+                self.handle_interactive(lock_timeout, remember, None)
+                    .await?
+            }
+            None => match cli.query {
+                Some(query) => self.handle_jump(query, lock_timeout, remember).await?,
+                None => {
+                    self.handle_interactive(lock_timeout, remember, None)
+                        .await?
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    async fn handle_init(
+        &mut self,
+        kdf: KdfStrength,
+        format: VaultFormat,
+        store: StoreLayout,
+    ) -> Result<()> {
+        if format == VaultFormat::Toml && self.key_file.is_some() {
+            return Err(anyhow::anyhow!(
+                "TOML vaults are unencrypted and can't use a key file"
+            ));
+        }
+        if format == VaultFormat::Toml && store == StoreLayout::Split {
+            return Err(anyhow::anyhow!(
+                "TOML vaults are always a single file and can't be split"
+            ));
+        }
+
+        if self.vault.exists() {
+            let confirmed = Confirm::new("Vault already exists. Do you want to overwrite it?")
+                .with_default(false)
+                .prompt()?;
+
+            if !confirmed {
+                println!("Operation cancelled.");
+                return Ok(());
+            }
+
+            let vault_path = self.vault.vault_path();
+            let file_name = vault_path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let backup_path =
+                vault_path.with_file_name(format!("{file_name}.{}.bak", Uuid::new_v4()));
+            std::fs::rename(self.vault.vault_path(), &backup_path)?;
+            println!("Existing vault backed up to {}", backup_path.display());
+
+            // The new vault would delete server files its index doesn't list
+            let servers_dir = self.vault.servers_dir();
+            if servers_dir.exists() {
+                let backup_dir = backup_path.with_extension("servers");
+                std::fs::rename(&servers_dir, &backup_dir)?;
+                println!("Its server files moved to {}", backup_dir.display());
+            }
+        }
+        self.vault.set_layout(store)?;
+
+        if let Some(key_file) = &self.key_file {
+            self.vault.create_with_keyfile(key_file)?;
+            println!("🔒 Vault created, encrypted with {}", key_file.display());
+            return Ok(());
+        }
+
+        if format == VaultFormat::Toml {
+            self.vault.create_with_format(format)?;
+            println!(
+                "✅ Vault created without password protection, as editable TOML at {}",
+                self.vault.vault_path().display()
+            );
+            return Ok(());
+        }
+
+        let use_password =
+            Confirm::new("Would you like to protect your vault with a master password?")
+                .with_default(true)
+                .prompt()?;
+
+        let password = if use_password {
+            Password::new("Enter master password:")
+                .with_display_toggle_enabled()
+                .prompt()?
+        } else {
+            println!("Creating vault without password protection...");
+            String::new()
+        };
+
+        let password_opt = password_option_from_choice(use_password, password.as_str())?;
+        self.vault.create_with_kdf(password_opt, kdf.params())?;
+
+        if use_password {
+            println!("🔒 Vault created with password protection!");
+        } else {
+            println!("✅ Vault created without password protection!");
+        }
+
+        Ok(())
+    }
+
+    fn handle_config(&mut self, action: Option<ConfigAction>) -> Result<()> {
+        let path = Config::path()?;
+        if let Some(ConfigAction::Set { key, value }) = action {
+            // Re-read so a file that fails to parse is reported, not overwritten
+            let mut config = Config::load_from(&path)?;
+            config.set(&key, &value)?;
+            config.save_to(&path)?;
+            self.config = config;
+            println!("✅ Saved {key} to {}", path.display());
+            return Ok(());
+        }
+
+        println!("# {}", path.display());
+        println!("default_port = {}", self.config.port());
+        println!("default_username = {:?}", self.config.username());
+        Ok(())
+    }
+
+    async fn handle_add(&mut self, flags: AddFlags) -> Result<()> {
+        self.ensure_unlocked().await?;
+
+        if !flags.is_empty() {
+            return self.add_from_flags(flags);
+        }
+
+        let name = Text::new("Server name:").prompt()?;
+        if name.trim().is_empty() {
+            return Err(ValidationError::EmptyName.into());
+        }
+        let host = Text::new("Host/IP:").prompt()?.trim().to_string();
+        validate_host(&host)?;
+        let default_port = self.config.port().to_string();
+        let port = parse_port(&Text::new("Port:").with_default(&default_port).prompt()?)?;
+        let username = Text::new("Username:")
+            .with_initial_value(self.config.username())
+            .prompt()?;
+        if username.trim().is_empty() {
+            return Err(ValidationError::EmptyUsername.into());
+        }
+        let auth_method =
+            Select::new("Authentication method:", AuthMethod::ALL.to_vec()).prompt()?;
+        let password = match auth_method {
+            AuthMethod::Password => prompt_server_password()?,
+            AuthMethod::PublicKey | AuthMethod::Agent => String::new(),
+        };
+        let identity_file = match auth_method {
+            AuthMethod::PublicKey => loop {
+                let input = Text::new("Identity file (e.g. ~/.ssh/id_ed25519):").prompt()?;
+                if !input.trim().is_empty() {
+                    break Some(input.trim().to_string());
+                }
+                println!("An identity file is required for public key authentication");
+            },
+            AuthMethod::Password => Text::new("Identity file (optional, e.g. ~/.ssh/id_ed25519):")
+                .prompt()
+                .ok()
+                .filter(|value| !value.trim().is_empty())
+                .map(|value| value.trim().to_string()),
+            AuthMethod::Agent => None,
+        };
+        let host_key_checking = Select::new("Host key checking:", HostKeyPolicy::ALL.to_vec())
+            .with_starting_cursor(1)
+            .prompt()?;
+        let forward_agent = Confirm::new("Forward SSH agent for this session?")
+            .with_default(false)
+            .prompt()
+            .unwrap_or(false);
+        let quiet_login = Confirm::new("Suppress login banner / MOTD?")
+            .with_default(false)
+            .prompt()
+            .unwrap_or(false);
+        let remote_tmux = loop {
+            let input = Text::new("Remote tmux session to attach on connect (optional):")
+                .prompt()
+                .unwrap_or_default();
+            match parse_tmux_session(&input) {
+                Ok(session) => break session,
+                Err(e) => println!("{e}"),
+            }
+        };
+        let bind_address = loop {
+            let input = Text::new("Local bind address (optional, e.g. 10.0.0.5):")
+                .prompt()
+                .unwrap_or_default();
+            match parse_bind_address(&input) {
+                Ok(address) => break address,
+                Err(e) => println!("{e}"),
+            }
+        };
+        let connect_timeout = prompt_seconds(&format!(
+            "Connect timeout in seconds (blank for {}):",
+            ssh::DEFAULT_CONNECT_TIMEOUT
+        ));
+        let keepalive = prompt_seconds(&format!(
+            "Keepalive interval in seconds (blank for {}):",
+            ssh::DEFAULT_KEEPALIVE
+        ));
+        let mut ssh_options = Vec::new();
+        loop {
+            let input = Text::new("Extra ssh option (Key=Value, blank to finish):")
+                .prompt()
+                .unwrap_or_default();
+            if input.trim().is_empty() {
+                break;
+            }
+            match parse_ssh_option(&input) {
+                Ok(option) => ssh_options.push(option),
+                Err(e) => println!("{e}"),
+            }
+        }
+        let gateway_id = self.prompt_gateway()?;
+        let jump_host = if gateway_id.is_none() {
+            loop {
+                let input = Text::new("Jump host (optional, e.g. admin@bastion:22):")
+                    .prompt()
+                    .unwrap_or_default();
+                match parse_jump_host(&input) {
+                    Ok(jump_host) => break jump_host,
+                    Err(e) => println!("{e}"),
+                }
+            }
+        } else {
+            None
+        };
+        let port_forwards = loop {
+            let input = Text::new("Local port forwards (optional, e.g. 5432:localhost:5432):")
+                .prompt()
+                .unwrap_or_default();
+            match parse_port_forwards(&input) {
+                Ok(forwards) => break forwards,
+                Err(e) => println!("{e}"),
+            }
+        };
+        let known = self.vault.tag_counts()?.into_iter().map(|(tag, _)| tag);
+        let tags = Text::new("Tags (optional, comma-separated):")
+            .with_autocomplete(TagCompleter {
+                known: known.collect(),
+            })
+            .prompt()
+            .unwrap_or_default();
+        let group = Text::new("Group (optional, e.g. a client or environment):")
+            .prompt()
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty());
+        let description = Text::new("Description (optional):")
+            .prompt()
+            .ok()
+            .filter(|value| !value.trim().is_empty());
+
+        let mut server = Server::new(name, host, port, username, password, description);
+        server.identity_file = identity_file;
+        server.auth_method = auth_method;
+        server.forward_agent = forward_agent;
+        server.host_key_checking = host_key_checking;
+        server.gateway_id = gateway_id;
+        server.jump_host = jump_host;
+        server.bind_address = bind_address;
+        server.connect_timeout = connect_timeout;
+        server.keepalive = keepalive;
+        server.ssh_options = ssh_options;
+        server.quiet_login = quiet_login;
+        server.remote_tmux = remote_tmux;
+        server.port_forwards = port_forwards;
+        server.set_tags(tags.split(','));
+        server.group = group;
+
+        self.save_new_server(server)
+    }
+
+    // The one-shot `add`: flags fill in the server, and only a missing name,
+    // host, user or password is asked for. Everything else keeps its default.
+    fn add_from_flags(&mut self, flags: AddFlags) -> Result<()> {
+        let name = match flags.name {
+            Some(name) => name,
+            None => Text::new("Server name:").prompt()?,
+        };
+        if name.trim().is_empty() {
+            return Err(ValidationError::EmptyName.into());
+        }
+        let host = match flags.host {
+            Some(host) => host,
+            None => Text::new("Host/IP:").prompt()?,
+        };
+        let host = host.trim().to_string();
+        validate_host(&host)?;
+        let port = match flags.port {
+            Some(port) => parse_port(&port)?,
+            None => self.config.port(),
+        };
+        let username = match flags.user {
+            Some(user) => user,
+            None => Text::new("Username:")
+                .with_initial_value(self.config.username())
+                .prompt()?,
+        };
+        if username.trim().is_empty() {
+            return Err(ValidationError::EmptyUsername.into());
+        }
+        let password = match flags.password {
+            Some(password) => password,
+            None => Password::new("Password:")
+                .with_display_toggle_enabled()
+                .prompt()?,
+        };
+        let description = flags
+            .description
+            .filter(|description| !description.trim().is_empty());
+
+        let mut server = Server::new(name, host, port, username, password, description);
+        if let Some(tags) = &flags.tags {
+            server.set_tags(tags.split(','));
+        }
+        self.save_new_server(server)
+    }
+
+    // Adds `server`, asking before storing a second entry for the same
+    // endpoint. Without a terminal to ask on, the duplicate is an error.
+    fn save_new_server(&mut self, server: Server) -> Result<()> {
+        if let Err(e) = self.vault.add_server(server.clone()) {
+            let Some(VaultError::DuplicateServer(name)) = e.downcast_ref::<VaultError>() else {
+                return Err(e);
+            };
+            if !std::io::stdin().is_terminal() {
+                return Err(e);
+            }
+            let add_anyway = Confirm::new(&format!(
+                "A server with this host/user/port already exists as '{name}'. Add anyway?"
+            ))
+            .with_default(false)
+            .prompt()?;
+            if !add_anyway {
+                println!("Server not added.");
+                return Ok(());
+            }
+            self.vault.add_server_allowing_duplicate(server)?;
+        }
+        println!("Server added successfully!");
+
+        Ok(())
+    }
+
+    async fn handle_list(
+        &mut self,
+        count: bool,
+        filter: ListFilter,
+        by_group: bool,
+        sort: Option<SortOrder>,
+        json: bool,
+        include_passwords: bool,
+    ) -> Result<()> {
+        self.ensure_unlocked().await?;
+
+        let all_servers = self.vault.list_servers()?;
+        let mut servers: Vec<&Server> = all_servers
+            .iter()
+            .filter(|s| filter.tag.as_deref().is_none_or(|tag| s.has_tag(tag)))
+            .filter(|s| {
+                filter
+                    .group
+                    .as_deref()
+                    .is_none_or(|group| s.in_group(group))
+            })
+            .collect();
+        if let Some(order) = sort {
+            servers.sort_by(|a, b| order.compare(a, b));
+        }
+        if by_group {
+            servers.sort_by(|a, b| compare_groups(a, b));
+        }
+
+        if count {
+            if json {
+                println!("{}", serde_json::json!({ "count": servers.len() }));
+            } else {
+                println!("{}", servers.len());
+            }
+            return Ok(());
+        }
+
+        if servers.is_empty() {
+            let message = match (&filter.tag, &filter.group) {
+                (Some(tag), Some(group)) => {
+                    format!("No servers tagged '{tag}' in group '{group}'.")
+                }
+                (Some(tag), None) => format!("No servers tagged '{tag}'."),
+                (None, Some(group)) => format!("No servers in group '{group}'."),
+                (None, None) => "No servers configured.".to_string(),
+            };
+            if !json {
+                println!("{message}");
+                return Ok(());
+            }
+            eprintln!("{message}");
+        }
+
+        if json {
+            return self.print_servers_json(&servers, include_passwords);
+        }
+
+        println!("\nConfigured servers:");
+        println!("{:-<60}", "");
+
+        let mut previous: Option<&Server> = None;
+        for server in &servers {
+            if by_group && previous.is_none_or(|previous| !same_group(previous, server)) {
+                println!("== {} ==", server.group_label());
+                println!("{:-<60}", "");
+            }
+            previous = Some(server);
+            println!("ID: {}", server.id);
+            println!("Name: {}", server.name);
+            println!("Host: {}", host_with_tags(server));
+            println!("User: {}", server.username);
+            println!("Auth: {}", server.auth_method);
+            if let Some(identity_file) = &server.identity_file {
+                println!("Identity file: {identity_file}");
+            }
+            if server.forward_agent {
+                println!("Forward agent: yes");
+            }
+            if server.quiet_login {
+                println!("Quiet login: yes");
+            }
+            if server.host_key_checking != HostKeyPolicy::default() {
+                println!("Host key checking: {}", server.host_key_checking);
+            }
+            if server.protected {
+                println!("Protected: yes");
+            }
+            if let Some(bind_address) = &server.bind_address {
+                println!("Bind address: {bind_address}");
+            }
+            if let Some(session) = &server.remote_tmux {
+                println!("Remote tmux session: {session}");
+            }
+            if let Some(last) = server.last_connected {
+                println!(
+                    "Last connected: {}",
+                    last.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M")
+                );
+            }
+            if let Some(timeout) = server.connect_timeout {
+                println!("Connect timeout: {timeout}s");
+            }
+            for (key, value) in &server.ssh_options {
+                println!("SSH option: {key}={value}");
+            }
+            if let Some(keepalive) = server.keepalive {
+                println!("Keepalive: {keepalive}s");
+            }
+            if let Some(gateway) = server
+                .gateway_id
+                .and_then(|id| all_servers.iter().find(|s| s.id == id))
+            {
+                println!("Gateway: {}", gateway.name);
+            }
+            if let Some(jump_host) = &server.jump_host {
+                println!("Jump host: {jump_host}");
+            }
+            for forward in &server.port_forwards {
+                println!(
+                    "Port forward: localhost:{} -> {}",
+                    forward.local,
+                    forward.remote()
+                );
+            }
+            if let Some(group) = server.group.as_deref().filter(|_| !by_group) {
+                println!("Group: {group}");
+            }
+            if let Some(desc) = &server.description {
+                println!("Description: {desc}");
+            }
+            print_notes(server);
+            println!("{:-<60}", "");
+        }
+        println!("{}", list_summary(&servers));
+
+        Ok(())
+    }
+
+    async fn handle_connect(
+        &mut self,
+        target: ConnectTarget,
+        overrides: ssh::ConnectOptions,
+        user: Option<String>,
+        ask_pass: bool,
+        sudo: bool,
+        dry_run: bool,
+    ) -> Result<()> {
+        self.ensure_unlocked().await?;
+
+        let server = match target {
+            ConnectTarget::Description(keyword) => self.find_server_by_description(&keyword)?,
+            ConnectTarget::Name { name, exact } => self.find_server_by_prefix(&name, exact)?,
+            ConnectTarget::Pick => {
+                let servers = self.vault.list_servers()?;
+                if servers.is_empty() {
+                    println!("No servers available.");
+                    return Ok(());
+                }
+
+                let options: Vec<String> = servers
+                    .iter()
+                    .map(|s| format!("{} ({})", s.name, s.host))
+                    .collect();
+
+                let selection = Select::new("Select server:", options).prompt()?;
+
+                let index = servers
+                    .iter()
+                    .position(|s| format!("{} ({})", s.name, s.host) == selection)
+                    .unwrap();
+
+                &servers[index]
+            }
+        };
+
+        // Another user gets a copy of the server; the stored entry is untouched
+        let other_user = user.filter(|user| *user != server.username);
+        let ask_pass = ask_pass || other_user.is_some();
+
+        if dry_run {
+            let mut server = server.clone();
+            if let Some(user) = other_user {
+                server.username = user;
+                server.validate()?;
+                // The stored password is for the stored user
+                server.clear_password();
+                eprintln!("(the password for {} would be asked for)", server.username);
+            }
+            let options = self.connect_options_with(&server, overrides)?;
+            // The password stays sealed; the preview only shows it as ***
+            let use_sshpass = ssh::will_use_sshpass(&server, &options);
+            let command = ssh::build_ssh_command(&server, &options, use_sshpass);
+            println!("{}", ssh::command_preview(&command));
+            return Ok(());
+        }
+
+        let mut server = self.vault.with_password(server)?;
+        if let Some(user) = other_user {
+            server.username = user;
+            server.validate()?;
+            server.set_sudo_password(None);
+        }
+        if ask_pass {
+            let password = Password::new(&format!(
+                "Password for {}@{}:",
+                server.username, server.host
+            ))
+            .without_confirmation()
+            .with_help_message("Leave blank to use keys or answer ssh's own prompt")
+            .prompt()?;
+            if !password.is_empty() {
+                server.auth_method = AuthMethod::Password;
+            }
+            server.set_password(password);
+        }
+        let options = self.connect_options_with(&server, overrides)?;
+        if sudo {
+            copy_sudo_password(&server, &options)?;
+        }
+        let result = ssh::connect_with(&server, &options, || self.vault.mark_connected(&server.id));
+        server.password.zeroize();
+        // A remote command that failed still means the login worked
+        let connected = match &result {
+            Ok(()) => true,
+            Err(e) => e.downcast_ref::<ssh::RemoteExit>().is_some(),
+        };
+        if let Err(e) = self.vault.record_connection(&server.id, connected) {
+            eprintln!("Warning: could not record this connection in the history: {e}");
+        }
+        result
+    }
+
+    async fn handle_exec(
+        &mut self,
+        command: String,
+        query: Option<String>,
+        tag: Option<String>,
+        parallel: usize,
+    ) -> Result<()> {
+        if parallel == 0 {
+            return Err(anyhow::anyhow!("--parallel must be at least 1"));
+        }
+        self.ensure_unlocked().await?;
+
+        let servers = self.vault.list_servers()?;
+        let mut targets = match &query {
+            Some(query) => match_servers(servers, query, false),
+            None => servers.iter().collect(),
+        };
+        if let Some(tag) = &tag {
+            targets.retain(|server| server.has_tag(tag));
+        }
+        if targets.is_empty() {
+            return Err(anyhow::anyhow!("No servers match"));
+        }
+        let names: Vec<&str> = targets.iter().map(|server| server.name.as_str()).collect();
+        eprintln!("Running on {}: {}", targets.len(), names.join(", "));
+
+        let mut jobs = Vec::new();
+        for server in targets {
+            let options = self.connect_options(server)?;
+            jobs.push((self.vault.with_password(server)?, options));
+        }
+        let total = jobs.len();
+
+        // At most `parallel` ssh processes at a time, each on a blocking
+        // thread; results are printed in the order they finish
+        let mut pending = jobs.into_iter();
+        let mut running = tokio::task::JoinSet::new();
+        let mut failures = Vec::new();
+        loop {
+            while running.len() < parallel {
+                let Some((server, options)) = pending.next() else {
+                    break;
+                };
+                let command = command.clone();
+                running.spawn_blocking(move || {
+                    let result = ssh::run_batch(&server, &options, &command);
+                    (server, result)
+                });
+            }
+            let Some(joined) = running.join_next().await else {
+                break;
+            };
+            let (server, result) = joined?;
+
+            // Whether ssh logged in, and why the run counts as failed
+            let (connected, failure) = match result {
+                Ok(output) => {
+                    let name = &server.name;
+                    for line in String::from_utf8_lossy(&output.stdout).lines() {
+                        println!("[{name}] {line}");
+                    }
+                    for line in String::from_utf8_lossy(&output.stderr).lines() {
+                        eprintln!("[{name}] {line}");
+                    }
+                    match output.status.code() {
+                        Some(0) => (true, None),
+                        // ssh itself exits with 255; anything else came from the command
+                        Some(255) => (false, Some("ssh failed".to_string())),
+                        Some(code) => (true, Some(format!("exit {code}"))),
+                        None => (true, Some(output.status.to_string())),
+                    }
+                }
+                Err(e) => {
+                    eprintln!("[{}] {e}", server.name);
+                    (false, Some("not run".to_string()))
+                }
+            };
+            if let Err(e) = self.vault.record_connection(&server.id, connected) {
+                eprintln!("Warning: could not record this connection in the history: {e}");
+            }
+            if let Some(failure) = failure {
+                failures.push(format!("{} ({failure})", server.name));
+            }
+        }
+
+        if failures.is_empty() {
+            eprintln!("✅ {total} of {total} succeeded");
+            return Ok(());
+        }
+        eprintln!(
+            "❌ {} of {total} failed: {}",
+            failures.len(),
+            failures.join(", ")
+        );
+        Err(anyhow::anyhow!(
+            "The command failed on {} of {total} servers",
+            failures.len()
+        ))
+    }
+
+    async fn handle_sftp(&mut self, name: String, no_sshpass: bool) -> Result<()> {
+        self.ensure_unlocked().await?;
+
+        let server = self.find_server_by_name_or_id(&name)?;
+        let mut server = self.vault.with_password(server)?;
+        let options = ssh::ConnectOptions {
+            no_sshpass,
+            ..self.connect_options(&server)?
+        };
+        let result = ssh::sftp(&server, &options);
+        server.password.zeroize();
+        result
+    }
+
+    async fn handle_history(
+        &mut self,
+        limit: usize,
+        since: Option<chrono::Duration>,
+        csv: Option<PathBuf>,
+        keep: Option<usize>,
+    ) -> Result<()> {
+        self.ensure_unlocked().await?;
+
+        if let Some(keep) = keep {
+            self.vault.set_history_limit(keep)?;
+            println!("Keeping the last {keep} connection(s).");
+        }
+
+        // Newest first, like the printed list
+        let cutoff = since.map(|age| chrono::Utc::now() - age);
+        let history: Vec<&ConnectionEvent> = self
+            .vault
+            .history()?
+            .iter()
+            .rev()
+            .filter(|event| cutoff.is_none_or(|cutoff| event.at >= cutoff))
+            .collect();
+        let servers = self.vault.list_servers()?;
+
+        if let Some(path) = csv {
+            std::fs::write(&path, history_csv(&history, servers)?)
+                .map_err(|e| anyhow::anyhow!("Failed to write {}: {e}", path.display()))?;
+            println!(
+                "Wrote {} connection(s) to {}",
+                history.len(),
+                path.display()
+            );
+            return Ok(());
+        }
+
+        if history.is_empty() {
+            if since.is_some() {
+                println!("No connections in that time.");
+            } else {
+                println!("No connections recorded yet.");
+            }
+            return Ok(());
+        }
+
+        for event in history.into_iter().take(limit) {
+            let name = servers
+                .iter()
+                .find(|s| s.id == event.server_id)
+                .map_or("(removed server)", |s| s.name.as_str());
+            println!(
+                "{}  {}  {name}",
+                event
+                    .at
+                    .with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M"),
+                if event.success { "✅" } else { "❌" }
+            );
+        }
+        Ok(())
+    }
+
+    async fn handle_edit(&mut self, name: String, assignments: Vec<String>) -> Result<()> {
+        self.ensure_unlocked().await?;
+
+        let mut server = self.find_server_by_name_or_id(&name)?.clone();
+        let mut fields = Vec::new();
+        for assignment in &assignments {
+            let (field, value) = parse_field_assignment(assignment)?;
+            server.set_field(&field, &value)?;
+            fields.push(field);
+        }
+        server.validate_auth()?;
+
+        // All assignments are applied to the copy first, so one bad value
+        // leaves the vault untouched and the rest are saved together
+        let server_name = server.name.clone();
+        self.vault.replace_server(server)?;
+        println!("Updated {} on '{server_name}'.", fields.join(", "));
+        Ok(())
+    }
+
+    async fn handle_clone(&mut self, name: String) -> Result<()> {
+        self.ensure_unlocked().await?;
+
+        let server = self.find_server_by_name_or_id(&name)?;
+        let (id, original) = (server.id, server.name.clone());
+        let new_id = self
+            .vault
+            .clone_server(&id)?
+            .ok_or_else(|| anyhow::anyhow!("Server '{name}' not found"))?;
+        let copy = self.vault.find_server(&new_id)?.map(|s| s.name.clone());
+        println!(
+            "Cloned '{original}' as '{}' ({new_id}).",
+            copy.unwrap_or_default()
+        );
+        Ok(())
+    }
+
+    async fn handle_remove(&mut self, name: String) -> Result<()> {
+        self.ensure_unlocked().await?;
+
+        let server_id = {
+            let server = self.find_server_by_name_or_id(&name)?;
+            server.id
+        };
+
+        let server = self
+            .vault
+            .find_server(&server_id)?
+            .ok_or_else(|| anyhow::anyhow!("Server not found"))?;
+
+        let dependents = self.vault.dependents_of(&server_id)?;
+        if !dependents.is_empty() {
+            let names = dependents
+                .iter()
+                .map(|s| s.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+            println!(
+                "⚠️  '{}' is the gateway for: {names}. They will fail to connect until reassigned.",
+                server.name
+            );
+        }
+
+        let confirmed = if server.protected {
+            let typed = Text::new(&format!(
+                "'{}' is protected. Type its name to remove it:",
+                server.name
+            ))
+            .prompt()?;
+            server.confirms_removal(&typed)
+        } else {
+            Confirm::new(&format!(
+                "Remove server '{}' ({})?",
+                server.name, server.host
+            ))
+            .with_default(false)
+            .prompt()?
+        };
+
+        if confirmed {
+            self.vault.remove_server(&server_id)?;
+            println!("Server removed successfully!");
+        } else {
+            println!("Operation cancelled.");
+        }
+
+        Ok(())
+    }
+
+    async fn handle_prune(
+        &mut self,
+        older_than: chrono::Duration,
+        dry_run: bool,
+        yes: bool,
+    ) -> Result<()> {
+        self.ensure_unlocked().await?;
+
+        let cutoff = chrono::Utc::now() - older_than;
+        let servers = self.vault.list_servers()?;
+        let stale = stale_servers(servers, cutoff);
+        let protected = servers
+            .iter()
+            .filter(|server| server.protected)
+            .filter(|server| server.last_connected.is_none_or(|at| at < cutoff))
+            .count();
+        if protected > 0 {
+            println!("Keeping {protected} protected server(s).");
+        }
+        if stale.is_empty() {
+            println!("No servers to prune.");
+            return Ok(());
+        }
+
+        for server in &stale {
+            let last = server.last_connected.map_or_else(
+                || "never connected".to_string(),
+                |at| format!("last connected {}", at.format("%Y-%m-%d")),
+            );
+            println!(
+                "- {} ({}@{}, {last})",
+                server.name, server.username, server.host
+            );
+        }
+        if dry_run {
+            println!("Would remove {} server(s).", stale.len());
+            return Ok(());
+        }
+
+        let ids: Vec<Uuid> = stale.iter().map(|server| server.id).collect();
+        let confirmed = yes
+            || Confirm::new(&format!("Remove these {} server(s)?", ids.len()))
+                .with_default(false)
+                .prompt()?;
+        if !confirmed {
+            println!("Operation cancelled.");
+            return Ok(());
+        }
+
+        let size_before = self.vault.disk_size()?;
+        let removed = self.vault.remove_servers(&ids)?;
+        let size_after = self.vault.disk_size()?;
+        println!(
+            "🧹 Removed {} server(s); the vault shrank by {} bytes ({size_before} -> {size_after}). 'portkey undo' brings them back.",
+            removed.len(),
+            size_before.saturating_sub(size_after)
+        );
+        Ok(())
+    }
+
+    async fn handle_dump(&mut self, options: debug::DumpOptions) -> Result<()> {
+        self.ensure_unlocked().await?;
+
+        let data = self.vault.data()?;
+        let dump = if options.include_secrets {
+            eprintln!("⚠️  Including real passwords and notes; don't share this output.");
+            let mut unsealed = data.clone();
+            for server in &mut unsealed.servers {
+                *server = self.vault.with_password(server)?;
+            }
+            debug::dump_vault(&unsealed, options)?
+        } else {
+            debug::dump_vault(data, options)?
+        };
+        let mut json = serde_json::to_string_pretty(&dump)?;
+        println!("{json}");
+        json.zeroize();
+        Ok(())
+    }
+
+    async fn handle_quick(&mut self, lock_timeout: Option<Duration>, remember: bool) -> Result<()> {
+        // Quick now just launches the full TUI
+        self.handle_interactive(lock_timeout, remember, None).await
+    }
+
+    async fn handle_tags(&mut self, json: bool) -> Result<()> {
+        self.ensure_unlocked().await?;
+
+        let tags = self.vault.tag_counts()?;
+        if json {
+            let entries: Vec<serde_json::Value> = tags
+                .iter()
+                .map(|(tag, count)| serde_json::json!({ "tag": tag, "count": count }))
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&entries)?);
+            return Ok(());
+        }
+
+        if tags.is_empty() {
+            println!("No tags yet. Add some with 'portkey edit <name> --set tags=prod,web'.");
+            return Ok(());
+        }
+        let width = tags.iter().map(|(tag, _)| tag.chars().count()).max();
+        for (tag, count) in &tags {
+            println!("{tag:<width$}  {count}", width = width.unwrap_or(0));
+        }
+        Ok(())
+    }
+
+    async fn handle_stats(&mut self, json: bool) -> Result<()> {
+        self.ensure_unlocked().await?;
+
+        let stats = self.vault.stats()?;
+        if json {
+            let counts = |entries: &[(String, usize)], key: &str| -> Vec<serde_json::Value> {
+                entries
+                    .iter()
+                    .map(|(name, count)| serde_json::json!({ key: name, "count": count }))
+                    .collect()
+            };
+            let dated = |entry: &Option<(String, chrono::DateTime<chrono::Utc>)>, key: &str| {
+                entry.as_ref().map_or(
+                    serde_json::Value::Null,
+                    |(name, at)| serde_json::json!({ "name": name, key: at }),
+                )
+            };
+            let mut auth_methods = serde_json::Map::new();
+            for (method, count) in &stats.auth_methods {
+                if let serde_json::Value::String(key) = serde_json::to_value(method)? {
+                    auth_methods.insert(key, serde_json::json!(count));
+                }
+            }
+            let summary = serde_json::json!({
+                "servers": stats.servers,
+                "tags": counts(&stats.tags, "tag"),
+                "groups": counts(&stats.groups, "group"),
+                "auth_methods": auth_methods,
+                "with_description": stats.with_description,
+                "oldest": dated(&stats.oldest, "created_at"),
+                "newest": dated(&stats.newest, "created_at"),
+                "last_connected": dated(&stats.last_connected, "at"),
+            });
+            println!("{}", serde_json::to_string_pretty(&summary)?);
+            return Ok(());
+        }
+
+        let dated = |entry: &Option<(String, chrono::DateTime<chrono::Utc>)>| {
+            entry.as_ref().map_or_else(
+                || "-".to_string(),
+                |(name, at)| {
+                    let at = at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M");
+                    format!("{name} ({at})")
+                },
+            )
+        };
+        let auth_methods: Vec<String> = stats
+            .auth_methods
+            .iter()
+            .map(|(method, count)| format!("{method} {count}"))
+            .collect();
+        println!("Servers:           {}", stats.servers);
+        println!("With description:  {}", stats.with_description);
+        println!("Auth methods:      {}", auth_methods.join(", "));
+        println!("Oldest:            {}", dated(&stats.oldest));
+        println!("Newest:            {}", dated(&stats.newest));
+        println!("Last connected:    {}", dated(&stats.last_connected));
+        for (heading, entries) in [("Tags", &stats.tags), ("Groups", &stats.groups)] {
+            if entries.is_empty() {
+                continue;
+            }
+            println!("{heading}:");
+            let width = entries.iter().map(|(name, _)| name.chars().count()).max();
+            for (name, count) in entries {
+                println!("  {name:<width$}  {count}", width = width.unwrap_or(0));
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle_search(
+        &mut self,
+        query: String,
+        count: bool,
+        json: bool,
+        include_passwords: bool,
+    ) -> Result<()> {
+        self.ensure_unlocked().await?;
+
+        let matches = self.vault.search(&query)?;
+
+        if count {
+            println!("{}", matches.len());
+            return Ok(());
+        }
+
+        if matches.is_empty() {
+            if !json {
+                println!("No servers match your search.");
+                return Ok(());
+            }
+            eprintln!("No servers match your search.");
+        }
+
+        if json {
+            let servers: Vec<&Server> = matches.iter().map(|(_, server)| *server).collect();
+            return self.print_servers_json(&servers, include_passwords);
+        }
+
+        println!("Search results:");
+        println!("{:-<60}", "");
+
+        for (_, server) in matches {
+            println!("Name: {}", server.name);
+            println!("Host: {}", host_with_tags(server));
+            println!("User: {}", server.username);
+            println!("Auth: {}", server.auth_method);
+            if let Some(identity_file) = &server.identity_file {
+                println!("Identity file: {identity_file}");
+            }
+            if server.forward_agent {
+                println!("Forward agent: yes");
+            }
+            if let Some(group) = &server.group {
+                println!("Group: {group}");
+            }
+            if let Some(desc) = &server.description {
+                println!("Description: {desc}");
+            }
+            print_notes(server);
+            println!("{:-<60}", "");
+        }
+
+        Ok(())
+    }
+
+    async fn handle_ssh_config(
+        &mut self,
+        write: bool,
+        edit: bool,
+        output: Option<PathBuf>,
+    ) -> Result<()> {
+        self.ensure_unlocked().await?;
+        let servers = self.vault.list_servers()?;
+
+        let managed_block = render_managed_block(servers)?;
+
+        if edit {
+            let path = ssh_config_path()?;
+            let existing = std::fs::read_to_string(&path).unwrap_or_default();
+            let updated = upsert_managed_block(&existing, &managed_block);
+            edit_ssh_config(&path, &updated)?;
+            println!("Updated SSH config at {}", path.display());
+        } else if write || output.is_some() {
+            let path = match output {
+                Some(path) => path,
+                None => ssh_config_path()?,
+            };
+            write_managed_block(&path, &managed_block)?;
+            println!("Written SSH config entries to {}", path.display());
+        } else {
+            println!("# Preview: add these to ~/.ssh/config\n{managed_block}");
+        }
+
+        println!("Note: SSH config does not store passwords. Consider setting up SSH keys.");
+        Ok(())
+    }
+
+    async fn handle_ssh_config_entry(&mut self, name: String) -> Result<()> {
+        self.ensure_unlocked().await?;
+        let server = self.find_server_by_name_or_id(&name)?;
+        let servers = self.vault.list_servers()?;
+
+        print!("{}", render_host_block(server, servers)?);
+        if server.jump_host.is_none() {
+            if let Some(gateway) = server
+                .gateway_id
+                .and_then(|id| servers.iter().find(|s| s.id == id))
+            {
+                eprintln!(
+                    "Note: ProxyJump names another entry; share it too with 'portkey ssh-config {}'.",
+                    gateway.name
+                );
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle_test(&mut self, name: String, auth: bool) -> Result<()> {
+        self.ensure_unlocked().await?;
+
+        let server = self.find_server_by_name_or_id(&name)?;
+        let target = format!("{} ({})", server.name, server.host_port());
+
+        if server.gateway_id.is_some() || server.jump_host.is_some() {
+            println!("Note: the TCP check goes direct, not through the jump host.");
+        }
+        match ssh::probe_tcp(server, ssh::PROBE_TIMEOUT) {
+            Ok(latency) => println!("✅ {target} reachable in {} ms", latency.as_millis()),
+            Err(e) if !auth => {
+                println!("❌ {target}: {e}");
+                return Err(anyhow::anyhow!("'{}' is unreachable", server.name));
+            }
+            Err(e) => println!("⚠️  {target}: {e}"),
+        }
+
+        if auth {
+            let options = self.connect_options(server)?;
+            let mut server = self.vault.with_password(server)?;
+            let result = ssh::check_login(&server, &options);
+            server.password.zeroize();
+            match result {
+                Ok(()) => println!("✅ Authentication as {} succeeded", server.username),
+                Err(e) => {
+                    println!("❌ {e}");
+                    return Err(anyhow::anyhow!("Authentication check failed"));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn handle_copy(&mut self, name: String, sudo: bool) -> Result<()> {
+        self.ensure_unlocked().await?;
+
+        let server = self.find_server_by_name_or_id(&name)?;
+        let mut password = self.stored_password(server, sudo)?;
+        let copied = clipboard::copy_secret(&password, clipboard::CLEAR_AFTER);
+        password.zeroize();
+        copied?;
+        println!(
+            "{} for '{}' copied (clears in {}s)",
+            if sudo { "Sudo password" } else { "Password" },
+            server.name,
+            clipboard::CLEAR_AFTER.as_secs()
+        );
+        Ok(())
+    }
+
+    async fn handle_uri(&mut self, name: String, copy: bool) -> Result<()> {
+        self.ensure_unlocked().await?;
+
+        let uri = self.find_server_by_name_or_id(&name)?.ssh_uri();
+        println!("{uri}");
+        if copy {
+            clipboard::copy(&uri)?;
+            eprintln!("Copied to the clipboard");
+        }
+        Ok(())
+    }
+
+    async fn handle_open(&mut self, name: String) -> Result<()> {
+        self.ensure_unlocked().await?;
+
+        let server = self.find_server_by_name_or_id(&name)?;
+        ssh::open_in_file_manager(server)?;
+        println!("Opened {}", server.sftp_uri());
+        Ok(())
+    }
+
+    // The login password, or with `sudo` the password sudo expects; an error
+    // when there is none to hand out. Zeroize the result after use.
+    fn stored_password(&self, server: &Server, sudo: bool) -> Result<String> {
+        if sudo && server.has_sudo_password() {
+            return self.vault.decrypt_sudo_password(server);
+        }
+        if !server.has_password() {
+            return Err(anyhow::anyhow!(
+                "Server '{}' has no stored password",
+                server.name
+            ));
+        }
+        self.vault.decrypt_password(server)
+    }
+
+    // Only the password goes to stdout; everything else, prompts included,
+    // is on stderr so the output can be piped as-is.
+    async fn handle_pass(
+        &mut self,
+        name: String,
+        yes: bool,
+        force: bool,
+        sudo: bool,
+    ) -> Result<()> {
+        if std::io::stdout().is_terminal() && !force {
+            return Err(anyhow::anyhow!(
+                "Refusing to print a password to the terminal; pipe the output or pass --force"
+            ));
+        }
+
+        self.ensure_unlocked().await?;
+
+        let server = self.find_server_by_name_or_id(&name)?;
+        let has_password = server.has_password() || (sudo && server.has_sudo_password());
+        if !has_password {
+            return Err(anyhow::anyhow!(
+                "Server '{}' has no stored password",
+                server.name
+            ));
+        }
+
+        if !yes {
+            if !std::io::stdin().is_terminal() {
+                return Err(anyhow::anyhow!(
+                    "Confirmation needed; pass --yes when running without a terminal"
+                ));
+            }
+            let confirmed = Confirm::new(&format!("Print the password for '{}'?", server.name))
+                .with_default(false)
+                .prompt()?;
+            if !confirmed {
+                eprintln!("Cancelled.");
+                return Ok(());
+            }
+        }
+
+        let mut password = self.stored_password(server, sudo)?;
+        let mut stdout = std::io::stdout().lock();
+        let written = stdout
+            .write_all(password.as_bytes())
+            .and_then(|_| stdout.flush());
+        password.zeroize();
+        Ok(written?)
+    }
+
+    fn handle_vaults(&self) -> Result<()> {
+        let vaults = Vault::list_vaults()?;
+        if vaults.is_empty() {
+            println!("No vaults found in {}.", Vault::data_dir()?.display());
+            return Ok(());
+        }
+
+        for path in vaults {
+            let name = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let marker = if &path == self.vault.vault_path() {
+                "*"
+            } else {
+                " "
+            };
+            println!("{marker} {name:<20} {}", path.display());
+        }
+        Ok(())
     }
 
-    pub async fn run(&mut self) -> Result<()> {
-        let cli = Cli::parse();
+    async fn handle_undo(&mut self) -> Result<()> {
+        self.ensure_unlocked().await?;
 
-        match cli.command {
-            Some(Commands::Init) => self.handle_init().await?,
-            Some(Commands::Add) => self.handle_add().await?,
-            Some(Commands::List) => self.handle_list().await?,
-            Some(Commands::Connect { name }) => self.handle_connect(name).await?,
-            Some(Commands::Remove { name }) => self.handle_remove(name).await?,
-            Some(Commands::Quick) => self.handle_quick().await?,
-            Some(Commands::Search { query }) => self.handle_search(query).await?,
-            Some(Commands::SshConfig { write }) => self.handle_ssh_config(write).await?,
-            Some(Commands::Ui) => self.handle_interactive().await?,
-            None => self.handle_interactive().await?,
+        match self.vault.undo()? {
+            Some(label) => println!("Undid: {label}"),
+            None => println!("Nothing to undo."),
         }
 
         Ok(())
     }
 
-    async fn handle_init(&mut self) -> Result<()> {
-        if self.vault.exists() {
-            let confirmed = Confirm::new("Vault already exists. Do you want to overwrite it?")
-                .with_default(false)
-                .prompt()?;
+    async fn handle_passwd(&mut self) -> Result<()> {
+        if !self.vault.exists() {
+            return Err(anyhow::anyhow!(
+                "No vault found. Run 'portkey init' to create one."
+            ));
+        }
 
-            if !confirmed {
-                println!("Operation cancelled.");
-                return Ok(());
+        let old_password = match self.vault.unlock(None) {
+            Ok(_) => None,
+            Err(e) if !matches!(e.downcast_ref(), Some(VaultError::PasswordRequired)) => {
+                return Err(e);
             }
+            Err(_) => Some(
+                Password::new("Current master password:")
+                    .without_confirmation()
+                    .with_display_toggle_enabled()
+                    .prompt()?,
+            ),
+        };
 
-            let backup_path = self
-                .vault
-                .vault_path()
-                .with_file_name(format!("vault.dat.{}.bak", Uuid::new_v4()));
-            std::fs::rename(self.vault.vault_path(), &backup_path)?;
-            println!("Existing vault backed up to {}", backup_path.display());
+        let use_password = Confirm::new("Protect the vault with a master password?")
+            .with_default(true)
+            .prompt()?;
+        if old_password.is_none() && !use_password {
+            println!("Vault is already unprotected; nothing changed.");
+            return Ok(());
         }
-
-        let use_password =
-            Confirm::new("Would you like to protect your vault with a master password?")
-                .with_default(true)
-                .prompt()?;
-
-        let password = if use_password {
-            Password::new("Enter master password:")
+        let new_password = if use_password {
+            Password::new("New master password:")
                 .with_display_toggle_enabled()
                 .prompt()?
         } else {
-            println!("Creating vault without password protection...");
             String::new()
         };
 
-        let password_opt = password_option_from_choice(use_password, password.as_str())?;
-        self.vault.create(password_opt)?;
+        let new_opt = password_option_from_choice(use_password, new_password.as_str())?;
+        self.vault
+            .change_password(old_password.as_deref(), new_opt)?;
 
         if use_password {
-            println!("🔒 Vault created with password protection!");
+            println!("🔒 Master password updated.");
         } else {
-            println!("✅ Vault created without password protection!");
+            println!("🔓 Password protection removed.");
+        }
+        Ok(())
+    }
+
+    fn handle_lock(&mut self) -> Result<()> {
+        if !self.vault.exists() {
+            return Err(anyhow::anyhow!(
+                "No vault found. Run 'portkey init' to create one."
+            ));
         }
 
+        self.vault.lock();
+        if self.vault.is_unlocked() {
+            return Err(anyhow::anyhow!("Vault is still unlocked"));
+        }
+        println!("🔒 Vault locked.");
+        eprintln!("portkey keeps no key between commands; the next one asks for the master password again.");
         Ok(())
     }
 
-    async fn handle_add(&mut self) -> Result<()> {
+    async fn handle_export(&mut self, output: PathBuf) -> Result<()> {
         self.ensure_unlocked().await?;
 
-        let name = Text::new("Server name:").prompt()?;
-        let host = Text::new("Host/IP:").prompt()?;
-        let port_input = Text::new("Port:").with_default("22").prompt()?;
-        let port = port_input
-            .parse::<u16>()
-            .map_err(|_| anyhow::anyhow!("Invalid port '{}'", port_input))?;
-        let username = Text::new("Username:").prompt()?;
-        let password = Password::new("Password:")
-            .with_display_toggle_enabled()
+        let use_password = Confirm::new("Protect the backup with a password?")
+            .with_default(true)
             .prompt()?;
-        let identity_file = Text::new("Identity file (optional, e.g. ~/.ssh/id_ed25519):")
-            .prompt()
-            .ok()
-            .and_then(|value| {
-                let trimmed = value.trim().to_string();
-                if trimmed.is_empty() {
-                    None
-                } else {
-                    Some(trimmed)
-                }
-            });
-        let forward_agent = Confirm::new("Forward SSH agent for this session?")
-            .with_default(false)
-            .prompt()
-            .unwrap_or(false);
-        let description = Text::new("Description (optional):").prompt().ok();
+        let password = if use_password {
+            Password::new("Backup password:")
+                .with_display_toggle_enabled()
+                .prompt()?
+        } else {
+            println!("Writing backup without password protection...");
+            String::new()
+        };
 
-        let mut server = Server::new(name, host, port, username, password, description);
-        server.identity_file = identity_file;
-        server.forward_agent = forward_agent;
+        let password_opt = password_option_from_choice(use_password, password.as_str())?;
+        self.vault.export(&output, password_opt)?;
+        println!(
+            "✅ Exported {} server(s) to {}",
+            self.vault.list_servers()?.len(),
+            output.display()
+        );
+        Ok(())
+    }
 
-        self.vault.add_server(server)?;
-        println!("Server added successfully!");
+    async fn handle_export_plain(&mut self, output: PathBuf, yes: bool) -> Result<()> {
+        self.ensure_unlocked().await?;
+
+        if !yes {
+            let confirmed = Confirm::new(&format!(
+                "⚠️  This writes EVERY PASSWORD IN PLAINTEXT to {}. Continue?",
+                output.display()
+            ))
+            .with_default(false)
+            .prompt()?;
+            if !confirmed {
+                println!("Cancelled.");
+                return Ok(());
+            }
+        }
 
+        self.vault.export_plain(&output)?;
+        println!(
+            "⚠️  Wrote {} server(s) with cleartext passwords to {}; delete it when you are done.",
+            self.vault.list_servers()?.len(),
+            output.display()
+        );
         Ok(())
     }
 
-    async fn handle_list(&mut self) -> Result<()> {
+    async fn handle_import(
+        &mut self,
+        file: PathBuf,
+        dry_run: bool,
+        on_conflict: OnConflict,
+    ) -> Result<()> {
+        let export = ExportFile::read(&file)?;
         self.ensure_unlocked().await?;
 
-        let servers = self.vault.list_servers()?;
+        let password = if export.encrypted {
+            Some(
+                Password::new("Backup password:")
+                    .without_confirmation()
+                    .with_display_toggle_enabled()
+                    .prompt()?,
+            )
+        } else {
+            None
+        };
+        let data = export.open(password.as_deref())?;
 
-        if servers.is_empty() {
-            println!("No servers configured.");
-            return Ok(());
-        }
+        let summary = self
+            .vault
+            .import_servers_with(data.servers, dry_run, on_conflict)?;
+        let verb = if dry_run { "Would import" } else { "Imported" };
+        println!("{}.", report_import(&summary, verb));
+        Ok(())
+    }
 
-        println!("\nConfigured servers:");
-        println!("{:-<60}", "");
+    async fn handle_import_ssh_config(
+        &mut self,
+        path: Option<PathBuf>,
+        on_conflict: OnConflict,
+    ) -> Result<()> {
+        let path = match path {
+            Some(path) => path,
+            None => ssh_config_path()?,
+        };
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| anyhow::anyhow!("Failed to read {}: {e}", path.display()))?;
+        self.ensure_unlocked().await?;
 
-        for server in servers {
-            println!("ID: {}", server.id);
-            println!("Name: {}", server.name);
-            println!("Host: {}:{}", server.host, server.port);
-            println!("User: {}", server.username);
-            if let Some(identity_file) = &server.identity_file {
-                println!("Identity file: {identity_file}");
-            }
-            if server.forward_agent {
-                println!("Forward agent: yes");
-            }
-            if let Some(desc) = &server.description {
-                println!("Description: {desc}");
-            }
-            println!("{:-<60}", "");
+        let parsed = parse_ssh_config(&content, &local_user());
+        for warning in &parsed.warnings {
+            println!("⚠️  {warning}");
         }
 
+        let summary = self
+            .vault
+            .import_servers_with(parsed.servers, false, on_conflict)?;
+        println!("{}.", report_import(&summary, "Imported"));
         Ok(())
     }
 
-    async fn handle_connect(&mut self, name: Option<String>) -> Result<()> {
+    async fn handle_import_csv(&mut self, path: PathBuf, on_conflict: OnConflict) -> Result<()> {
+        let file = std::fs::File::open(&path)
+            .map_err(|e| anyhow::anyhow!("Failed to read {}: {e}", path.display()))?;
+        let parsed = parse_server_csv(file)?;
         self.ensure_unlocked().await?;
 
-        let server = match name {
-            Some(name) => self.find_server_by_name_or_id(&name)?,
-            None => {
-                let servers = self.vault.list_servers()?;
-                if servers.is_empty() {
-                    println!("No servers available.");
-                    return Ok(());
-                }
+        for warning in &parsed.warnings {
+            println!("⚠️  {warning}");
+        }
 
-                let options: Vec<String> = servers
-                    .iter()
-                    .map(|s| format!("{} ({})", s.name, s.host))
-                    .collect();
+        let summary = self
+            .vault
+            .import_servers_with(parsed.servers, false, on_conflict)?;
+        println!(
+            "{} and {} invalid row(s).",
+            report_import(&summary, "Imported"),
+            parsed.warnings.len()
+        );
+        Ok(())
+    }
 
-                let selection = Select::new("Select server:", options).prompt()?;
+    // `portkey <query>`: straight to the server when the query is a clear
+    // match, otherwise the TUI with the query as its filter.
+    async fn handle_jump(
+        &mut self,
+        query: String,
+        lock_timeout: Option<Duration>,
+        remember: bool,
+    ) -> Result<()> {
+        if !self.vault.exists() {
+            println!("No vault found. Run 'portkey init' to create one.");
+            return Ok(());
+        }
 
-                let index = servers
-                    .iter()
-                    .position(|s| format!("{} ({})", s.name, s.host) == selection)
-                    .unwrap();
+        self.ensure_unlocked().await?;
+        let id = strong_match(self.vault.list_servers()?, &query).map(|server| server.id);
+        match id {
+            Some(id) => {
+                let target = ConnectTarget::Name {
+                    name: id.to_string(),
+                    exact: true,
+                };
+                self.handle_connect(
+                    target,
+                    ssh::ConnectOptions::default(),
+                    None,
+                    false,
+                    false,
+                    false,
+                )
+                .await
+            }
+            None => {
+                self.handle_interactive(lock_timeout, remember, Some(query))
+                    .await
+            }
+        }
+    }
 
-                &servers[index]
+    async fn handle_interactive(
+        &mut self,
+        lock_timeout: Option<Duration>,
+        remember: bool,
+        filter: Option<String>,
+    ) -> Result<()> {
+        if !self.vault.exists() {
+            let interactive = std::io::stdin().is_terminal() && !self.vault.is_read_only();
+            if !interactive || !self.handle_first_run().await? {
+                println!("No vault found. Run 'portkey init' to create one.");
+                return Ok(());
             }
-        };
+        }
 
-        self.connect_to_server(server).await
+        // Unlock before entering raw mode
+        self.ensure_unlocked().await?;
+        tui::run_full_ui(
+            &mut self.vault,
+            self.key_file.as_deref(),
+            lock_timeout,
+            remember,
+            self.host_key_policy,
+            self.theme,
+            &self.config,
+            filter,
+        )
+        .map_err(|e| anyhow::anyhow!(e))
     }
 
-    async fn handle_remove(&mut self, name: String) -> Result<()> {
-        self.ensure_unlocked().await?;
+    // Offered instead of the TUI when there is no vault yet: creates one the
+    // way `init` does, then offers to import the hosts in ~/.ssh/config.
+    // Returns whether a vault was created.
+    async fn handle_first_run(&mut self) -> Result<bool> {
+        println!("👋 Welcome to Portkey! There's no vault here yet.");
+        let create = Confirm::new("Create one now?")
+            .with_default(true)
+            .prompt()?;
+        if !create {
+            return Ok(false);
+        }
 
-        let server_id = {
-            let server = self.find_server_by_name_or_id(&name)?;
-            server.id
-        };
+        self.handle_init(
+            KdfStrength::default(),
+            VaultFormat::default(),
+            StoreLayout::default(),
+        )
+        .await?;
+        if !self.vault.exists() {
+            return Ok(false);
+        }
 
-        let server = self
-            .vault
-            .find_server(&server_id)?
-            .ok_or_else(|| anyhow::anyhow!("Server not found"))?;
+        // Only looked up, unlike `import-ssh-config`, so ~/.ssh isn't created
+        let Some(path) = dirs::home_dir().map(|home| home.join(".ssh").join("config")) else {
+            return Ok(true);
+        };
+        let Ok(content) = std::fs::read_to_string(&path) else {
+            return Ok(true);
+        };
+        let parsed = parse_ssh_config(&content, &local_user());
+        if parsed.servers.is_empty() {
+            return Ok(true);
+        }
 
-        let confirmed = Confirm::new(&format!(
-            "Remove server '{}' ({})?",
-            server.name, server.host
+        let import = Confirm::new(&format!(
+            "Import the {} host(s) in {}?",
+            parsed.servers.len(),
+            path.display()
         ))
-        .with_default(false)
+        .with_default(true)
         .prompt()?;
-
-        if confirmed {
-            self.vault.remove_server(&server_id)?;
-            println!("Server removed successfully!");
+        if import {
+            for warning in &parsed.warnings {
+                println!("⚠️  {warning}");
+            }
+            let summary =
+                self.vault
+                    .import_servers_with(parsed.servers, false, OnConflict::Skip)?;
+            println!("{}.", report_import(&summary, "Imported"));
         } else {
-            println!("Operation cancelled.");
+            println!("Skipped. Run 'portkey import-ssh-config' any time to import them.");
         }
-
-        Ok(())
+        Ok(true)
     }
 
-    async fn handle_quick(&mut self) -> Result<()> {
-        // Quick now just launches the full TUI
-        self.handle_interactive().await
-    }
-
-    async fn handle_search(&mut self, query: String) -> Result<()> {
-        self.ensure_unlocked().await?;
-
-        let servers = self.vault.list_servers()?;
-        let matcher = fuzzy_matcher::skim::SkimMatcherV2::default();
-        let mut matches: Vec<(&Server, i64)> = servers
-            .iter()
-            .filter_map(|s| {
-                let hay = format!(
-                    "{} {} {} {} {}",
-                    s.name,
-                    s.host,
-                    s.username,
-                    s.port,
-                    s.description.as_deref().unwrap_or("")
-                );
-                matcher.fuzzy_match(&hay, &query).map(|score| (s, score))
-            })
-            .collect();
-        matches.sort_by_key(|match_result| Reverse(match_result.1));
+    async fn ensure_unlocked(&mut self) -> Result<()> {
+        if !self.vault.exists() {
+            return Err(anyhow::anyhow!(
+                "No vault found. Run 'portkey init' to create one."
+            ));
+        }
 
-        if matches.is_empty() {
-            println!("No servers match your search.");
+        if self.vault.is_unlocked() {
             return Ok(());
         }
 
-        println!("Search results:");
-        println!("{:-<60}", "");
+        if let Some(key_file) = &self.key_file {
+            self.vault.unlock_with_keyfile(key_file)?;
+            eprintln!("Vault unlocked with key file!");
+            return Ok(());
+        }
 
-        for (server, _) in matches {
-            println!("Name: {}", server.name);
-            println!("Host: {}:{}", server.host, server.port);
-            println!("User: {}", server.username);
-            if let Some(identity_file) = &server.identity_file {
-                println!("Identity file: {identity_file}");
-            }
-            if server.forward_agent {
-                println!("Forward agent: yes");
+        // Try to unlock with no password first (for unencrypted vaults)
+        match self.vault.unlock(None) {
+            Ok(_) => {
+                eprintln!("Vault unlocked (no password required)!");
+                return Ok(());
             }
-            if let Some(desc) = &server.description {
-                println!("Description: {desc}");
+            Err(e) if !matches!(e.downcast_ref(), Some(VaultError::PasswordRequired)) => {
+                return Err(e);
             }
-            println!("{:-<60}", "");
+            Err(_) => {}
         }
 
-        Ok(())
-    }
+        // A scripted password gets a single try; there is nobody to retype it
+        if let Some(mut password) = self.scripted_password()? {
+            let result = self.unlock_with_password(&password).await;
+            password.zeroize();
+            return result;
+        }
 
-    async fn handle_ssh_config(&mut self, write: bool) -> Result<()> {
-        self.ensure_unlocked().await?;
-        let servers = self.vault.list_servers()?;
+        // Encrypted vault - prompt for password, allowing for typos
+        for attempt in 1..=MAX_PASSWORD_ATTEMPTS {
+            let password = Password::new("Enter master password:")
+                .with_display_toggle_enabled()
+                .prompt()?;
 
-        let managed_block = render_managed_block(servers)?;
+            match self.unlock_with_password(&password).await {
+                Ok(()) => {
+                    eprintln!("Vault unlocked!");
+                    return Ok(());
+                }
+                Err(e) if matches!(e.downcast_ref(), Some(VaultError::WrongPassword)) => {
+                    if attempt < MAX_PASSWORD_ATTEMPTS {
+                        eprintln!("❌ Wrong master password, try again.");
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
 
-        if write {
-            let mut path =
-                dirs::home_dir().ok_or_else(|| anyhow::anyhow!("Home directory not found"))?;
-            path.push(".ssh");
-            std::fs::create_dir_all(&path)?;
-            path.push("config");
+        Err(VaultError::WrongPassword.into())
+    }
 
-            use std::io::Write;
-            let existing = std::fs::read_to_string(&path).unwrap_or_default();
-            let updated = upsert_managed_block(&existing, &managed_block);
-            let mut file = std::fs::OpenOptions::new()
-                .create(true)
-                .write(true)
-                .truncate(true)
-                .open(&path)?;
-            write!(file, "{updated}")?;
-            println!("Written SSH config entries to {}", path.display());
-        } else {
-            println!("# Preview: add these to ~/.ssh/config\n{managed_block}");
+    // Argon2 at the moderate or sensitive cost takes seconds, so the key is
+    // derived on a blocking thread while a spinner runs on stderr. Ctrl-C exits
+    // at once rather than waiting for the thread, taking the half-derived key
+    // with the process; once the key is in, Ctrl-C gets its default meaning back.
+    async fn unlock_with_password(&mut self, password: &str) -> Result<()> {
+        const SPINNER: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+        let (salt, kdf) = self.vault.key_derivation()?;
+        let mut password = password.to_string();
+        let mut derivation = tokio::task::spawn_blocking(move || {
+            let master_key = MasterKey::from_password(&password, &salt, kdf);
+            password.zeroize();
+            master_key
+        });
+
+        let spinner = std::io::stderr().is_terminal();
+        let mut ticks = tokio::time::interval(Duration::from_millis(100));
+        let cancel = tokio::signal::ctrl_c();
+        tokio::pin!(cancel);
+        let mut frame = 0;
+        let derived = loop {
+            tokio::select! {
+                derived = &mut derivation => break derived,
+                _ = &mut cancel => {
+                    if spinner {
+                        eprint!("\r\x1b[2K");
+                    }
+                    eprintln!("Unlock cancelled");
+                    std::process::exit(130);
+                }
+                _ = ticks.tick(), if spinner => {
+                    eprint!("\r{} Deriving key…", SPINNER[frame % SPINNER.len()]);
+                    let _ = std::io::stderr().flush();
+                    frame += 1;
+                }
+            }
+        };
+        restore_default_sigint();
+        if spinner {
+            eprint!("\r\x1b[2K");
         }
 
-        println!("Note: SSH config does not store passwords. Consider setting up SSH keys.");
-        Ok(())
+        let master_key = derived.map_err(|e| anyhow::anyhow!("Key derivation failed: {e}"))??;
+        self.vault.unlock_with_key(master_key)
     }
 
-    async fn handle_interactive(&mut self) -> Result<()> {
-        if !self.vault.exists() {
-            println!("No vault found. Run 'portkey init' to create one.");
-            return Ok(());
+    /// The master password from `--password-stdin` or `PORTKEY_PASSWORD`, if
+    /// either was given.
+    fn scripted_password(&self) -> Result<Option<String>> {
+        if self.password_stdin {
+            return read_password_line(std::io::stdin().lock()).map(Some);
         }
-
-        // Unlock before entering raw mode
-        self.ensure_unlocked().await?;
-        tui::run_full_ui(&mut self.vault).map_err(|e| anyhow::anyhow!(e))
+        Ok(std::env::var(PASSWORD_ENV)
+            .ok()
+            .filter(|password| !password.is_empty()))
     }
 
-    async fn ensure_unlocked(&mut self) -> Result<()> {
-        if !self.vault.exists() {
-            return Err(anyhow::anyhow!(
-                "No vault found. Run 'portkey init' to create one."
-            ));
+    fn prompt_gateway(&self) -> Result<Option<Uuid>> {
+        const NO_GATEWAY: &str = "(none - connect directly)";
+
+        let servers = self.vault.list_servers()?;
+        if servers.is_empty() {
+            return Ok(None);
         }
 
-        if !self.vault.is_unlocked() {
-            // Try to unlock with no password first (for unencrypted vaults)
-            match self.vault.unlock(None) {
-                Ok(_) => {
-                    println!("Vault unlocked (no password required)!");
-                }
-                Err(_) => {
-                    // Encrypted vault - prompt for password
-                    let password = Password::new("Enter master password:")
-                        .with_display_toggle_enabled()
-                        .prompt()?;
-
-                    self.vault.unlock(Some(&password))?;
-                    println!("Vault unlocked!");
-                }
-            }
+        let mut options = vec![NO_GATEWAY.to_string()];
+        options.extend(servers.iter().map(|s| format!("{} ({})", s.name, s.host)));
+
+        // Index 0 is NO_GATEWAY, so servers are offset by one
+        let selection = Select::new("Route through gateway:", options).raw_prompt()?;
+        Ok(selection
+            .index
+            .checked_sub(1)
+            .map(|index| servers[index].id))
+    }
+
+    fn find_server_by_description(&self, keyword: &str) -> Result<&Server> {
+        let ties = best_description_matches(self.vault.list_servers()?, keyword);
+        if ties.is_empty() {
+            return Err(anyhow::anyhow!("No server description matches '{keyword}'"));
         }
 
-        Ok(())
+        choose_server(
+            &format!("Several descriptions match '{keyword}' equally well:"),
+            &ties,
+        )
+    }
+
+    /// Like [`Self::find_server_by_name_or_id`], but a name prefix works too
+    /// and the user picks when several servers match equally well.
+    fn find_server_by_prefix(&self, query: &str, exact: bool) -> Result<&Server> {
+        let candidates = match_servers(self.vault.list_servers()?, query, exact);
+        if candidates.is_empty() {
+            return Err(anyhow::anyhow!("Server '{query}' not found"));
+        }
+        choose_server(&format!("'{query}' matches several servers:"), &candidates)
     }
 
     fn find_server_by_name_or_id(&self, name_or_id: &str) -> Result<&Server> {
@@ -407,12 +3036,46 @@ impl CliHandler {
         servers
             .iter()
             .find(|s| {
-                s.name.eq_ignore_ascii_case(name_or_id) || s.id.to_string().starts_with(name_or_id)
+                s.name.eq_ignore_ascii_case(name_or_id)
+                    || s.ssh_alias().eq_ignore_ascii_case(name_or_id)
+                    || s.id.to_string().starts_with(name_or_id)
             })
             .ok_or_else(|| anyhow::anyhow!("Server '{}' not found", name_or_id))
     }
 
-    async fn connect_to_server(&self, server: &Server) -> Result<()> {
-        ssh::connect(server)
+    // `servers_json` with sealed passwords opened first when they are wanted
+    fn print_servers_json(&self, servers: &[&Server], include_passwords: bool) -> Result<()> {
+        if !include_passwords {
+            println!("{}", servers_json(servers, false)?);
+            return Ok(());
+        }
+
+        let mut unsealed = servers
+            .iter()
+            .map(|server| self.vault.with_password(server))
+            .collect::<Result<Vec<Server>>>()?;
+        let json = servers_json(&unsealed.iter().collect::<Vec<_>>(), true);
+        for server in &mut unsealed {
+            server.password.zeroize();
+        }
+        println!("{}", json?);
+        Ok(())
+    }
+
+    fn connect_options(&self, server: &Server) -> Result<ssh::ConnectOptions> {
+        self.connect_options_with(server, ssh::ConnectOptions::default())
+    }
+
+    // `overrides` with the server's gateway chain and this run's host key policy
+    fn connect_options_with(
+        &self,
+        server: &Server,
+        overrides: ssh::ConnectOptions,
+    ) -> Result<ssh::ConnectOptions> {
+        Ok(ssh::ConnectOptions {
+            gateways: self.vault.gateway_chain(server)?,
+            host_key_policy: self.host_key_policy,
+            ..overrides
+        })
     }
 }
@@ -0,0 +1,176 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use zeroize::Zeroize;
+
+use crate::ssh::find_in_path;
+
+/// How long a copied password stays on the clipboard.
+pub const CLEAR_AFTER: Duration = Duration::from_secs(30);
+
+/// Hidden subcommand the detached restore helper runs as.
+pub const RESTORE_COMMAND: &str = "__clipboard-restore";
+
+struct Backend {
+    copy: (&'static str, Vec<&'static str>),
+    paste: (&'static str, Vec<&'static str>),
+}
+
+// Candidate clipboard tools, in preference order. Each entry is only used
+// when its binary exists and, for the Linux tools, a display server is running.
+fn clipboard_backends() -> Vec<Backend> {
+    let mut backends = Vec::new();
+
+    if cfg!(target_os = "macos") {
+        backends.push(Backend {
+            copy: ("pbcopy", vec![]),
+            paste: ("pbpaste", vec![]),
+        });
+    }
+    if cfg!(windows) {
+        backends.push(Backend {
+            copy: ("clip.exe", vec![]),
+            paste: (
+                "powershell.exe",
+                vec!["-NoProfile", "-Command", "Get-Clipboard"],
+            ),
+        });
+    }
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        backends.push(Backend {
+            copy: ("wl-copy", vec![]),
+            paste: ("wl-paste", vec!["--no-newline"]),
+        });
+    }
+    if std::env::var_os("DISPLAY").is_some() {
+        backends.push(Backend {
+            copy: ("xclip", vec!["-selection", "clipboard"]),
+            paste: ("xclip", vec!["-selection", "clipboard", "-o"]),
+        });
+        backends.push(Backend {
+            copy: ("xsel", vec!["--clipboard", "--input"]),
+            paste: ("xsel", vec!["--clipboard", "--output"]),
+        });
+    }
+
+    backends
+}
+
+fn backend() -> Result<Backend> {
+    clipboard_backends()
+        .into_iter()
+        .find(|backend| find_in_path(backend.copy.0).is_some())
+        .ok_or_else(|| {
+            anyhow!("No clipboard available (install pbcopy, wl-copy, xclip or xsel, or run inside a desktop session)")
+        })
+}
+
+pub fn copy(text: &str) -> Result<()> {
+    let (program, args) = backend()?.copy;
+
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| anyhow!("Failed to launch '{program}': {e}"))?;
+
+    child
+        .stdin
+        .take()
+        .ok_or_else(|| anyhow!("Failed to open stdin for '{program}'"))?
+        .write_all(text.as_bytes())?;
+
+    let status = child.wait()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("'{program}' exited with {status}"))
+    }
+}
+
+pub fn paste() -> Result<String> {
+    let (program, args) = backend()?.paste;
+
+    let output = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|e| anyhow!("Failed to launch '{program}': {e}"))?;
+
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(anyhow!("'{program}' exited with {}", output.status))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct RestorePayload {
+    secret: String,
+    previous: Option<String>,
+}
+
+/// Copies `secret` and leaves a detached helper process behind that puts the
+/// previous clipboard contents back after `clear_after`, so the secret is
+/// cleared even if portkey exits first.
+pub fn copy_secret(secret: &str, clear_after: Duration) -> Result<()> {
+    let previous = paste().ok().filter(|previous| previous != secret);
+    copy(secret)?;
+
+    let exe = std::env::current_exe().context("Failed to locate the portkey binary")?;
+    let mut child = Command::new(exe)
+        .arg(RESTORE_COMMAND)
+        .arg("--after")
+        .arg(clear_after.as_secs().to_string())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .context("Failed to schedule clipboard clearing")?;
+
+    let mut payload = serde_json::to_vec(&RestorePayload {
+        secret: secret.to_string(),
+        previous,
+    })?;
+    let written = child
+        .stdin
+        .take()
+        .ok_or_else(|| anyhow!("Failed to open stdin for the clipboard helper"))
+        .and_then(|mut stdin| Ok(stdin.write_all(&payload)?));
+    payload.zeroize();
+    written
+}
+
+/// Body of the restore helper: waits, then restores the previous contents if
+/// the clipboard still holds the secret (anything copied since is left alone).
+pub fn restore_after(delay: Duration, mut input: impl Read) -> Result<()> {
+    let mut raw = Vec::new();
+    input.read_to_end(&mut raw)?;
+    let parsed = serde_json::from_slice::<RestorePayload>(&raw);
+    raw.zeroize();
+    let mut payload = parsed.context("Invalid clipboard restore payload")?;
+
+    std::thread::sleep(delay);
+
+    let result = match paste() {
+        Ok(mut current) => {
+            let still_secret = current == payload.secret;
+            current.zeroize();
+            if still_secret {
+                copy(payload.previous.as_deref().unwrap_or(""))
+            } else {
+                Ok(())
+            }
+        }
+        // Can't tell what is on the clipboard; clear it to be safe
+        Err(_) => copy(""),
+    };
+
+    payload.secret.zeroize();
+    result
+}
@@ -0,0 +1,73 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::models::parse_port;
+use crate::vault::{write_atomic, Vault};
+
+pub const CONFIG_FILE: &str = "config.toml";
+
+/// Keys `portkey config set` accepts.
+pub const CONFIG_KEYS: [&str; 2] = ["default_port", "default_username"];
+
+/// Non-secret preferences from `config.toml` in the data directory. Every
+/// setting is optional; a missing file is the same as an empty one.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Config {
+    /// Port the add forms start with, 22 when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_port: Option<u16>,
+    /// Username the add forms start with, blank when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_username: Option<String>,
+}
+
+impl Config {
+    /// `config.toml` in the data directory.
+    pub fn path() -> Result<PathBuf> {
+        Ok(Vault::data_dir()?.join(CONFIG_FILE))
+    }
+
+    /// Reads the config at `path`; a missing file gives the defaults, but
+    /// one that doesn't parse is an error rather than silently ignored.
+    pub fn load_from(path: &Path) -> Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+        toml::from_str(&text).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    pub fn save_to(&self, path: &Path) -> Result<()> {
+        write_atomic(path, toml::to_string_pretty(self)?.as_bytes())
+    }
+
+    pub fn port(&self) -> u16 {
+        self.default_port.unwrap_or(22)
+    }
+
+    pub fn username(&self) -> &str {
+        self.default_username.as_deref().unwrap_or("")
+    }
+
+    /// Sets one of [`CONFIG_KEYS`] from its text form. A blank username
+    /// clears it.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "default_port" => self.default_port = Some(parse_port(value)?),
+            "default_username" => {
+                let value = value.trim();
+                self.default_username = (!value.is_empty()).then(|| value.to_string());
+            }
+            _ => {
+                return Err(anyhow!(
+                    "Unknown config key '{key}' (expected {})",
+                    CONFIG_KEYS.join(" or ")
+                ))
+            }
+        }
+        Ok(())
+    }
+}
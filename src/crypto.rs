@@ -1,26 +1,229 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
 use sodiumoxide::crypto::pwhash::argon2id13;
 use sodiumoxide::crypto::secretbox;
+use sodiumoxide::randombytes;
+use sodiumoxide::utils;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
 use zeroize::Zeroize;
 
+static MEMORY_LOCKING: AtomicBool = AtomicBool::new(false);
+static MEMORY_LOCK_WARNING: Once = Once::new();
+
+/// Disables core dumps for the process and, when `lock_memory` is set, pins key
+/// material in RAM so it can't be swapped to disk. Anything the environment
+/// refuses is reported as a warning rather than an error.
+pub fn harden_process(lock_memory: bool) {
+    #[cfg(unix)]
+    {
+        let no_core = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        // SAFETY: setrlimit only reads the fully initialised struct passed by reference.
+        if unsafe { libc::setrlimit(libc::RLIMIT_CORE, &no_core) } != 0 {
+            eprintln!(
+                "⚠️  Could not disable core dumps: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+
+    MEMORY_LOCKING.store(lock_memory, Ordering::Relaxed);
+}
+
+/// Locks `bytes` into RAM if memory locking is enabled, returning whether the
+/// region is now locked and must be released with [`unlock_memory`].
+pub fn lock_memory(bytes: &mut [u8]) -> bool {
+    if !MEMORY_LOCKING.load(Ordering::Relaxed) || bytes.is_empty() {
+        return false;
+    }
+
+    let locked = utils::mlock(bytes).is_ok();
+    if !locked {
+        MEMORY_LOCK_WARNING.call_once(|| {
+            eprintln!(
+                "⚠️  Could not lock secrets in memory (mlock refused); they may be swapped to disk. \
+                 Set PORTKEY_NO_MLOCK=1 to silence this warning."
+            );
+        });
+    }
+    locked
+}
+
+/// Zeroes and unlocks a region previously locked with [`lock_memory`].
+pub fn unlock_memory(bytes: &mut [u8]) {
+    let _ = utils::munlock(bytes);
+}
+
+/// Argon2id cost presets offered by `portkey init --kdf`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KdfStrength {
+    #[default]
+    Interactive,
+    Moderate,
+    Sensitive,
+}
+
+impl KdfStrength {
+    pub fn params(self) -> KdfParams {
+        let (ops, mem) = match self {
+            KdfStrength::Interactive => (
+                argon2id13::OPSLIMIT_INTERACTIVE,
+                argon2id13::MEMLIMIT_INTERACTIVE,
+            ),
+            KdfStrength::Moderate => (argon2id13::OPSLIMIT_MODERATE, argon2id13::MEMLIMIT_MODERATE),
+            KdfStrength::Sensitive => (
+                argon2id13::OPSLIMIT_SENSITIVE,
+                argon2id13::MEMLIMIT_SENSITIVE,
+            ),
+        };
+        KdfParams {
+            opslimit: ops.0,
+            memlimit: mem.0,
+        }
+    }
+}
+
+impl FromStr for KdfStrength {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "interactive" => Ok(KdfStrength::Interactive),
+            "moderate" => Ok(KdfStrength::Moderate),
+            "sensitive" => Ok(KdfStrength::Sensitive),
+            _ => Err(format!(
+                "unknown KDF strength '{value}' (expected interactive, moderate or sensitive)"
+            )),
+        }
+    }
+}
+
+/// Argon2id cost a master key is derived with. Stored next to the salt so a
+/// vault is always unlocked with the parameters it was created with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub opslimit: usize,
+    pub memlimit: usize,
+}
+
+impl KdfParams {
+    pub fn is_interactive(&self) -> bool {
+        *self == KdfStrength::Interactive.params()
+    }
+}
+
+impl Default for KdfParams {
+    // Vaults written before the cost was configurable all used these
+    fn default() -> Self {
+        KdfStrength::Interactive.params()
+    }
+}
+
+/// Where a vault's master key comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeySource {
+    /// Derived from the master password with Argon2id.
+    #[default]
+    Password,
+    /// Read verbatim from a 32-byte key file, for headless use.
+    KeyFile,
+}
+
+impl KeySource {
+    pub fn is_password(&self) -> bool {
+        *self == KeySource::Password
+    }
+}
+
 pub struct MasterKey {
-    key: secretbox::Key,
+    // Boxed so the key keeps a stable address for mlock as the struct moves around
+    key: Box<secretbox::Key>,
+    locked: bool,
+    kdf: KdfParams,
+    source: KeySource,
 }
 
 impl MasterKey {
-    pub fn from_password(password: &str, salt: &argon2id13::Salt) -> Result<Self> {
-        let mut key = secretbox::Key([0; secretbox::KEYBYTES]);
+    pub fn from_password(password: &str, salt: &argon2id13::Salt, kdf: KdfParams) -> Result<Self> {
+        let mut key = Box::new(secretbox::Key([0; secretbox::KEYBYTES]));
+        let locked = lock_memory(&mut key.0);
 
         argon2id13::derive_key(
             &mut key.0,
             password.as_bytes(),
             salt,
-            argon2id13::OPSLIMIT_INTERACTIVE,
-            argon2id13::MEMLIMIT_INTERACTIVE,
+            argon2id13::OpsLimit(kdf.opslimit),
+            argon2id13::MemLimit(kdf.memlimit),
         )
         .map_err(|_| anyhow::anyhow!("Failed to derive key from password"))?;
 
-        Ok(Self { key })
+        Ok(Self {
+            key,
+            locked,
+            kdf,
+            source: KeySource::Password,
+        })
+    }
+
+    /// Reads a raw key from `path`, which must hold exactly 32 bytes and be
+    /// accessible only by its owner (mode 0600).
+    pub fn from_key_file(path: &Path) -> Result<Self> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = fs::metadata(path)
+                .with_context(|| format!("Failed to read key file {}", path.display()))?
+                .permissions()
+                .mode();
+            if mode & 0o077 != 0 {
+                return Err(anyhow::anyhow!(
+                    "Key file {} has mode {:o}; restrict it with 'chmod 600'",
+                    path.display(),
+                    mode & 0o777
+                ));
+            }
+        }
+
+        let mut bytes = fs::read(path)
+            .with_context(|| format!("Failed to read key file {}", path.display()))?;
+        if bytes.len() != secretbox::KEYBYTES {
+            let len = bytes.len();
+            bytes.zeroize();
+            return Err(anyhow::anyhow!(
+                "Key file {} must contain exactly {} bytes, found {len}",
+                path.display(),
+                secretbox::KEYBYTES
+            ));
+        }
+
+        let mut key = Box::new(secretbox::Key([0; secretbox::KEYBYTES]));
+        let locked = lock_memory(&mut key.0);
+        key.0.copy_from_slice(&bytes);
+        bytes.zeroize();
+
+        Ok(Self {
+            key,
+            locked,
+            kdf: KdfParams::default(),
+            source: KeySource::KeyFile,
+        })
+    }
+
+    /// The Argon2id cost this key was derived with.
+    pub fn kdf(&self) -> KdfParams {
+        self.kdf
+    }
+
+    pub fn source(&self) -> KeySource {
+        self.source
     }
 
     pub fn encrypt(&self, data: &[u8]) -> (secretbox::Nonce, Vec<u8>) {
@@ -39,9 +242,64 @@ impl MasterKey {
 impl Drop for MasterKey {
     fn drop(&mut self) {
         self.key.0.zeroize();
+        if self.locked {
+            unlock_memory(&mut self.key.0);
+        }
     }
 }
 
 pub fn generate_salt() -> argon2id13::Salt {
     argon2id13::gen_salt()
 }
+
+/// Length of passwords generated when adding a server.
+pub const DEFAULT_PASSWORD_LENGTH: usize = 20;
+
+const LETTERS_AND_DIGITS: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+// Printable ASCII punctuation minus quotes, backslash and backtick, which
+// tend to need escaping when the password is pasted into a shell or config
+const SYMBOLS: &str = "!#$%&()*+,-./:;<=>?@[]^_{|}~";
+
+/// Characters a generated password is drawn from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Charset {
+    Alphanumeric,
+    #[default]
+    AlphanumericSymbols,
+}
+
+impl Charset {
+    pub const ALL: [Charset; 2] = [Charset::AlphanumericSymbols, Charset::Alphanumeric];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Charset::Alphanumeric => "letters and digits",
+            Charset::AlphanumericSymbols => "letters, digits and symbols",
+        }
+    }
+
+    fn chars(self) -> Vec<char> {
+        match self {
+            Charset::Alphanumeric => LETTERS_AND_DIGITS.chars().collect(),
+            Charset::AlphanumericSymbols => {
+                LETTERS_AND_DIGITS.chars().chain(SYMBOLS.chars()).collect()
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Charset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+/// Random password of `len` characters from `charset`. Each character comes
+/// from libsodium's `randombytes_uniform`, which reads the OS CSPRNG and
+/// avoids modulo bias.
+pub fn generate_password(len: usize, charset: Charset) -> String {
+    let chars = charset.chars();
+    (0..len)
+        .map(|_| chars[randombytes::randombytes_uniform(chars.len() as u32) as usize])
+        .collect()
+}
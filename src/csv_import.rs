@@ -0,0 +1,105 @@
+use anyhow::{anyhow, Result};
+use std::io::Read;
+
+use crate::models::{parse_port, Server};
+
+/// Columns read from the header row; only the first three are required.
+const REQUIRED_COLUMNS: [&str; 3] = ["name", "host", "username"];
+
+/// Servers read from a CSV file, plus the rows that were skipped and why.
+#[derive(Debug, Default)]
+pub struct ParsedCsv {
+    pub servers: Vec<Server>,
+    pub warnings: Vec<String>,
+}
+
+/// Reads servers from CSV with the header
+/// `name,host,port,username,password,description,tags`, in any column order.
+/// Tags are separated by `;` and a blank port means 22. Rows that are missing
+/// a required field or don't describe a valid server are reported by line
+/// number and skipped; only an unusable header fails the whole import.
+pub fn parse_server_csv(input: impl Read) -> Result<ParsedCsv> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(input);
+
+    let headers: Vec<String> = reader
+        .headers()
+        .map_err(|e| anyhow!("Failed to read the CSV header: {e}"))?
+        .iter()
+        .map(str::to_ascii_lowercase)
+        .collect();
+    let column = |name: &str| headers.iter().position(|header| header == name);
+    let missing: Vec<&str> = REQUIRED_COLUMNS
+        .into_iter()
+        .filter(|name| column(name).is_none())
+        .collect();
+    if !missing.is_empty() {
+        return Err(anyhow!(
+            "CSV header is missing the {} column(s)",
+            missing.join(", ")
+        ));
+    }
+
+    let mut parsed = ParsedCsv::default();
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                let line = e.position().map_or(0, |position| position.line());
+                parsed.warnings.push(format!("line {line}: {e}"));
+                continue;
+            }
+        };
+        let line = record.position().map_or(0, |position| position.line());
+        let field = |name: &str| {
+            column(name)
+                .and_then(|index| record.get(index))
+                .unwrap_or("")
+                .to_string()
+        };
+
+        let missing: Vec<&str> = REQUIRED_COLUMNS
+            .into_iter()
+            .filter(|name| field(name).is_empty())
+            .collect();
+        if !missing.is_empty() {
+            parsed
+                .warnings
+                .push(format!("line {line}: missing {}", missing.join(", ")));
+            continue;
+        }
+
+        let port = field("port");
+        let port = if port.is_empty() {
+            22
+        } else {
+            match parse_port(&port) {
+                Ok(port) => port,
+                Err(e) => {
+                    parsed.warnings.push(format!("line {line}: {e}"));
+                    continue;
+                }
+            }
+        };
+
+        let description = Some(field("description")).filter(|d| !d.is_empty());
+        let mut server = Server::new(
+            field("name"),
+            field("host"),
+            port,
+            field("username"),
+            field("password"),
+            description,
+        );
+        server.set_tags(field("tags").split(';'));
+        if let Err(e) = server.validate() {
+            parsed.warnings.push(format!("line {line}: {e}"));
+            continue;
+        }
+        parsed.servers.push(server);
+    }
+
+    Ok(parsed)
+}
@@ -1,46 +1,197 @@
+use anyhow::Result;
+use serde_json::{json, Map, Value};
+use sodiumoxide::crypto::hash::sha256;
+use sodiumoxide::randombytes;
+use std::collections::BTreeMap;
 use std::fs;
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 
+use crate::models::VaultData;
+use crate::ssh::find_in_path;
 use crate::vault::Vault;
 
-pub fn debug_vault() {
-    println!("🔍 Vault Debug Information");
-    println!("==========================");
+/// Stands in for every secret in a redacted dump.
+pub const REDACTED: &str = "<redacted>";
 
-    match Vault::new() {
-        Ok(vault) => {
-            // Get path via debug method
-            let vault_path = vault.vault_path().clone();
-            println!("Vault path: {}", vault_path.display());
+// Server fields every entry has, left out of the populated-field counts
+const ALWAYS_SET: [&str; 7] = [
+    "id",
+    "name",
+    "host",
+    "port",
+    "username",
+    "created_at",
+    "updated_at",
+];
+// `ssh_options` whose values name hosts, matched ignoring case
+const HOST_OPTIONS: [&str; 7] = [
+    "hostname",
+    "hostkeyalias",
+    "proxyjump",
+    "proxycommand",
+    "bindaddress",
+    "localforward",
+    "remoteforward",
+];
+// Sealed copies of the passwords; never dumped, sealed or not
+const SEALED_FIELDS: [&str; 4] = [
+    "password_enc",
+    "password_nonce",
+    "sudo_password_enc",
+    "sudo_password_nonce",
+];
 
-            let exists = vault.exists();
-            println!("Vault exists: {exists}");
+/// How much of the vault `portkey dump` gives away.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DumpOptions {
+    /// Print passwords and notes as they are instead of [`REDACTED`]. The
+    /// data must have been unsealed for that, see `Vault::with_password`.
+    pub include_secrets: bool,
+    /// Replace host names and addresses, including those in `ssh_options`,
+    /// with a hash salted afresh for each dump, so equal hosts still look
+    /// equal within it but can't be looked up or matched across dumps.
+    pub hash_hosts: bool,
+}
+
+/// The vault as JSON for a bug report: its version, the server count, how
+/// many servers fill in each optional field, and the data itself with
+/// passwords and notes (free text that may hold anything) redacted unless
+/// `options` asks for them.
+pub fn dump_vault(data: &VaultData, options: DumpOptions) -> Result<Value> {
+    let mut populated: BTreeMap<String, usize> = BTreeMap::new();
+    let salt = randombytes::randombytes(16);
+    let mut servers = Vec::with_capacity(data.servers.len());
+    for server in &data.servers {
+        let Value::Object(mut fields) = serde_json::to_value(server)? else {
+            unreachable!("a server serializes to an object");
+        };
+        for field in SEALED_FIELDS {
+            fields.remove(field);
+        }
+        if !options.include_secrets {
+            // Sealed passwords count too, though they serialize as blank
+            let secrets = [
+                ("password", server.has_password()),
+                ("sudo_password", server.has_sudo_password()),
+                ("notes", server.notes.is_some()),
+            ];
+            for (field, set) in secrets {
+                if set {
+                    fields.insert(field.to_string(), json!(REDACTED));
+                }
+            }
+        }
+        if options.hash_hosts {
+            hash_hosts(&mut fields, &salt);
+        }
 
-            if exists {
-                if let Ok(metadata) = fs::metadata(&vault_path) {
-                    println!("File size: {} bytes", metadata.len());
+        for (field, value) in &fields {
+            if !ALWAYS_SET.contains(&field.as_str()) && is_populated(value) {
+                *populated.entry(field.clone()).or_default() += 1;
+            }
+        }
+        servers.push(Value::Object(fields));
+    }
 
-                    #[cfg(unix)]
-                    {
-                        println!("Permissions: {:o}", metadata.permissions().mode());
-                    }
+    let mut dumped = serde_json::to_value(data)?;
+    dumped["servers"] = Value::Array(servers);
+    Ok(json!({
+        "version": data.version,
+        "server_count": data.servers.len(),
+        "populated_fields": populated,
+        "secrets": if options.include_secrets { "included" } else { "redacted" },
+        "data": dumped,
+    }))
+}
 
-                    if let Ok(modified) = metadata.modified() {
-                        println!("Modified: {modified:?}");
-                    }
-                }
+// Something set beyond the field's default: not null, false, empty or zero
+fn is_populated(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(set) => *set,
+        Value::String(text) => !text.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(fields) => !fields.is_empty(),
+        Value::Number(_) => true,
+    }
+}
 
-                if let Ok(content) = fs::read(&vault_path) {
-                    println!("File readable: ✅");
-                    println!("Content size: {} bytes", content.len());
-                } else {
-                    println!("File readable: ❌");
+fn hash_hosts(fields: &mut Map<String, Value>, salt: &[u8]) {
+    let hashed = |host: &str| {
+        let mut salted = salt.to_vec();
+        salted.extend_from_slice(host.to_lowercase().as_bytes());
+        let digest = sha256::hash(&salted);
+        let hex: String = digest.0[..6].iter().map(|b| format!("{b:02x}")).collect();
+        format!("host-{hex}")
+    };
+    for field in ["host", "jump_host", "bind_address"] {
+        if let Some(Value::String(host)) = fields.get_mut(field) {
+            *host = hashed(host);
+        }
+    }
+    if let Some(Value::Array(forwards)) = fields.get_mut("port_forwards") {
+        for forward in forwards {
+            if let Some(Value::String(host)) = forward.get_mut("remote_host") {
+                *host = hashed(host);
+            }
+        }
+    }
+    // Each option serializes as a `[key, value]` pair
+    if let Some(Value::Array(ssh_options)) = fields.get_mut("ssh_options") {
+        for option in ssh_options {
+            if let Some([Value::String(key), Value::String(value)]) =
+                option.as_array_mut().map(Vec::as_mut_slice)
+            {
+                if HOST_OPTIONS.contains(&key.to_lowercase().as_str()) {
+                    *value = hashed(value);
                 }
             }
         }
-        Err(e) => {
-            println!("❌ Failed to determine vault path: {e}");
+    }
+}
+
+pub fn debug_vault(vault: &Vault) {
+    println!("🔍 Vault Debug Information");
+    println!("==========================");
+
+    let vault_path = vault.vault_path();
+    println!("Vault path: {}", vault_path.display());
+
+    let exists = vault.exists();
+    println!("Vault exists: {exists}");
+
+    if exists {
+        if let Ok(metadata) = fs::metadata(vault_path) {
+            println!("File size: {} bytes", metadata.len());
+
+            #[cfg(unix)]
+            {
+                println!("Permissions: {:o}", metadata.permissions().mode());
+            }
+            #[cfg(not(unix))]
+            {
+                // No mode bits here; access comes from the folder's ACL
+                println!("Read-only: {}", metadata.permissions().readonly());
+            }
+
+            if let Ok(modified) = metadata.modified() {
+                println!("Modified: {modified:?}");
+            }
+        }
+
+        if let Ok(content) = fs::read(vault_path) {
+            println!("File readable: ✅");
+            println!("Content size: {} bytes", content.len());
+        } else {
+            println!("File readable: ❌");
+        }
+    }
+
+    for binary in ["ssh", "sshpass"] {
+        match find_in_path(binary) {
+            Some(path) => println!("{binary}: {}", path.display()),
+            None => println!("{binary}: ❌ not found in PATH"),
         }
     }
 }
@@ -0,0 +1,150 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::models::Server;
+use crate::ssh;
+
+/// How long a result is trusted before the server is probed again.
+pub const HEALTH_TTL: Duration = Duration::from_secs(60);
+/// Probes allowed to run at the same time.
+pub const MAX_CONCURRENT_PROBES: usize = 8;
+
+/// Reachability of a server's ssh port as last seen by a background probe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Health {
+    /// Not probed yet, still being probed, or reached through a jump host,
+    /// which a direct TCP connect can't speak for.
+    Unknown,
+    Up,
+    Down,
+}
+
+struct Probe {
+    id: Uuid,
+    host: String,
+    port: u16,
+}
+
+#[derive(Default)]
+struct Queue {
+    probes: VecDeque<Probe>,
+    workers: usize,
+}
+
+/// TCP-connect checks run on worker threads so the TUI never waits on the
+/// network. Results come back over a channel and are cached for
+/// [`HEALTH_TTL`].
+pub struct HealthMonitor {
+    results: HashMap<Uuid, (Health, Instant)>,
+    pending: HashSet<Uuid>,
+    queue: Arc<Mutex<Queue>>,
+    sender: Sender<(Uuid, Health)>,
+    receiver: Receiver<(Uuid, Health)>,
+    timeout: Duration,
+}
+
+impl Default for HealthMonitor {
+    fn default() -> Self {
+        Self::new(ssh::PROBE_TIMEOUT)
+    }
+}
+
+impl HealthMonitor {
+    pub fn new(timeout: Duration) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            results: HashMap::new(),
+            pending: HashSet::new(),
+            queue: Arc::default(),
+            sender,
+            receiver,
+            timeout,
+        }
+    }
+
+    /// The last known result, even if it is older than the TTL.
+    pub fn status(&self, server: &Server) -> Health {
+        self.results
+            .get(&server.id)
+            .map_or(Health::Unknown, |(health, _)| *health)
+    }
+
+    /// Queues a probe for each of `servers` without a fresh result; `force`
+    /// re-probes them regardless. Servers already being probed are skipped.
+    pub fn check<'a>(&mut self, servers: impl IntoIterator<Item = &'a Server>, force: bool) {
+        let mut queue = self.queue.lock().unwrap();
+        for server in servers {
+            if self.pending.contains(&server.id) {
+                continue;
+            }
+            let fresh = self
+                .results
+                .get(&server.id)
+                .is_some_and(|(_, checked)| checked.elapsed() < HEALTH_TTL);
+            if fresh && !force {
+                continue;
+            }
+            if server.gateway_id.is_some() || server.jump_host.is_some() {
+                self.results
+                    .insert(server.id, (Health::Unknown, Instant::now()));
+                continue;
+            }
+
+            self.pending.insert(server.id);
+            queue.probes.push_back(Probe {
+                id: server.id,
+                host: server.host.clone(),
+                port: server.port,
+            });
+        }
+
+        while queue.workers < MAX_CONCURRENT_PROBES && queue.workers < queue.probes.len() {
+            queue.workers += 1;
+            let queue = Arc::clone(&self.queue);
+            let sender = self.sender.clone();
+            let timeout = self.timeout;
+            std::thread::spawn(move || probe_worker(&queue, &sender, timeout));
+        }
+    }
+
+    /// Takes in any results that have arrived; returns whether there were any.
+    pub fn poll(&mut self) -> bool {
+        let mut updated = false;
+        while let Ok((id, health)) = self.receiver.try_recv() {
+            self.pending.remove(&id);
+            self.results.insert(id, (health, Instant::now()));
+            updated = true;
+        }
+        updated
+    }
+}
+
+// Probes queued servers until the queue is empty. The worker count is only
+// decremented under the same lock that found the queue empty, so a probe
+// queued meanwhile always has a worker to run it.
+fn probe_worker(queue: &Mutex<Queue>, sender: &Sender<(Uuid, Health)>, timeout: Duration) {
+    loop {
+        let probe = {
+            let mut queue = queue.lock().unwrap();
+            match queue.probes.pop_front() {
+                Some(probe) => probe,
+                None => {
+                    queue.workers -= 1;
+                    return;
+                }
+            }
+        };
+        let health = match ssh::probe_address(&probe.host, probe.port, timeout) {
+            Ok(_) => Health::Up,
+            Err(_) => Health::Down,
+        };
+        // The TUI has exited when nobody is listening
+        if sender.send((probe.id, health)).is_err() {
+            queue.lock().unwrap().workers -= 1;
+            return;
+        }
+    }
+}
@@ -1,10 +1,17 @@
 pub mod cli;
+pub mod clipboard;
+pub mod config;
 pub mod crypto;
+pub mod csv_import;
 pub mod debug;
+pub mod health;
+pub mod migrations;
 pub mod models;
 pub mod ssh;
 pub mod ssh_config;
+pub mod theme;
 pub mod tui;
+pub mod ui_state;
 pub mod vault;
 
 // Re-export commonly used types for tests and external use
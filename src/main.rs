@@ -1,18 +1,27 @@
 use anyhow::Result;
-use portkey::{cli, debug};
+use portkey::{cli, crypto, ssh};
 use std::env;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     sodiumoxide::init().expect("Failed to initialize sodiumoxide");
 
-    // Check for debug flag
-    let args: Vec<String> = env::args().collect();
-    if args.len() > 1 && args[1] == "debug" {
-        debug::debug_vault();
-        return Ok(());
-    }
+    // Keep secrets out of core dumps and swap; PORTKEY_NO_MLOCK=1 skips mlock
+    // on systems where locked memory is restricted (e.g. some containers)
+    crypto::harden_process(env::var_os("PORTKEY_NO_MLOCK").is_none());
 
     let mut handler = cli::CliHandler::new()?;
-    handler.run().await
+    let result = handler.run().await;
+
+    // `connect --exec` passes the remote command's exit code on as its own
+    let remote_exit = result
+        .as_ref()
+        .err()
+        .and_then(|e| e.downcast_ref::<ssh::RemoteExit>())
+        .map(|exit| exit.0);
+    if let Some(code) = remote_exit {
+        drop(handler);
+        std::process::exit(code);
+    }
+    result
 }
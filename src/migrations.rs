@@ -0,0 +1,91 @@
+use anyhow::{anyhow, Result};
+
+use crate::models::{AuthMethod, VaultData};
+
+pub const CURRENT_VERSION: &str = "1.1.0";
+
+type Step = fn(&mut VaultData);
+
+/// Stands in for the version of vaults written before `version` was stored.
+pub const LEGACY_VERSION: &str = "0.0.0";
+
+// Ordered upgrade steps as (from, to, step). Each step only has to handle
+// vaults written by exactly `from` ("0.x" covers every 0.* release);
+// `migrate` chains them up to CURRENT_VERSION.
+const STEPS: &[(&str, &str, Step)] = &[
+    ("0.x", "1.0.0", backfill_auth_methods),
+    ("1.0.0", "1.1.0", normalize_optional_fields),
+];
+
+/// Upgrades `data` from `from_version` to [`CURRENT_VERSION`], returning
+/// whether anything had to be migrated.
+pub fn migrate(data: &mut VaultData, from_version: &str) -> Result<bool> {
+    let mut version = from_version.to_string();
+
+    while version != CURRENT_VERSION {
+        let Some((_, to, step)) = STEPS.iter().find(|(from, _, _)| applies(from, &version)) else {
+            return Err(if is_newer(&version, CURRENT_VERSION) {
+                anyhow!(
+                    "Vault version {version} was written by a newer portkey (this build supports up to {CURRENT_VERSION}); please upgrade"
+                )
+            } else {
+                anyhow!("Unsupported vault version '{version}'")
+            });
+        };
+
+        step(data);
+        version = to.to_string();
+    }
+
+    let migrated = data.version != version;
+    data.version = version;
+    Ok(migrated)
+}
+
+fn applies(from: &str, version: &str) -> bool {
+    match from.strip_suffix(".x") {
+        Some(major) => version.split('.').next() == Some(major),
+        None => from == version,
+    }
+}
+
+fn is_newer(version: &str, than: &str) -> bool {
+    let parse = |v: &str| -> Vec<u64> { v.split('.').map(|n| n.parse().unwrap_or(0)).collect() };
+    parse(version) > parse(than)
+}
+
+/// 0.x -> 1.0.0: there was no `auth_method`, so it reads as the password
+/// default. A server with an identity file and no password really logged in
+/// with its key, and is marked as such.
+fn backfill_auth_methods(data: &mut VaultData) {
+    for server in &mut data.servers {
+        let has_identity = server
+            .identity_file
+            .as_deref()
+            .is_some_and(|path| !path.trim().is_empty());
+        if has_identity && !server.has_password() {
+            server.auth_method = AuthMethod::PublicKey;
+        }
+    }
+}
+
+/// 1.0.0 -> 1.1.0: the CLI stored skipped optional prompts as empty strings
+/// and tags were free-form. Blank optionals become `None`; tags are trimmed,
+/// lowercased and de-duplicated.
+fn normalize_optional_fields(data: &mut VaultData) {
+    let blank_to_none = |value: &mut Option<String>| {
+        if value.as_deref().is_some_and(|v| v.trim().is_empty()) {
+            *value = None;
+        }
+    };
+
+    for server in &mut data.servers {
+        blank_to_none(&mut server.description);
+        blank_to_none(&mut server.identity_file);
+        blank_to_none(&mut server.bind_address);
+        blank_to_none(&mut server.remote_tmux);
+
+        let tags = std::mem::take(&mut server.tags);
+        server.set_tags(tags);
+    }
+}
@@ -1,24 +1,253 @@
+use anyhow::{anyhow, Result};
 use chrono::{DateTime, Utc};
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{RootSchema, Schema};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use sodiumoxide::crypto::secretbox;
+use std::cmp::{Ordering, Reverse};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr};
+use std::str::FromStr;
 use uuid::Uuid;
+use zeroize::Zeroize;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::crypto::MasterKey;
+use crate::migrations::{self, CURRENT_VERSION, LEGACY_VERSION};
+
+/// How `ssh::connect` authenticates against a server.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMethod {
+    /// Stored password fed through `sshpass`, or ssh's own prompt when empty.
+    #[default]
+    Password,
+    /// Private key passed with `ssh -i`.
+    PublicKey,
+    /// Whatever keys the running ssh-agent offers.
+    Agent,
+}
+
+impl AuthMethod {
+    pub const ALL: [AuthMethod; 3] = [
+        AuthMethod::Password,
+        AuthMethod::PublicKey,
+        AuthMethod::Agent,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            AuthMethod::Password => "password",
+            AuthMethod::PublicKey => "public key",
+            AuthMethod::Agent => "ssh agent",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            AuthMethod::Password => AuthMethod::PublicKey,
+            AuthMethod::PublicKey => AuthMethod::Agent,
+            AuthMethod::Agent => AuthMethod::Password,
+        }
+    }
+}
+
+impl std::fmt::Display for AuthMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+impl FromStr for AuthMethod {
+    type Err = String;
+
+    /// Accepts the labels as well as `public-key`, `key` and `agent`.
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        let normalized = value.trim().to_lowercase().replace(['-', '_'], " ");
+        match normalized.as_str() {
+            "password" => Ok(AuthMethod::Password),
+            "public key" | "publickey" | "key" => Ok(AuthMethod::PublicKey),
+            "ssh agent" | "agent" => Ok(AuthMethod::Agent),
+            _ => Err(format!(
+                "Invalid auth method '{value}': expected password, public-key or agent"
+            )),
+        }
+    }
+}
+
+/// How ssh treats the server's host key, passed as `StrictHostKeyChecking`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HostKeyPolicy {
+    /// Only connect to hosts already in `known_hosts`.
+    Strict,
+    /// Trust a host on first contact, refuse if its key later changes.
+    #[default]
+    AcceptNew,
+    /// Never verify the host key; for throwaway machines only.
+    Off,
+}
+
+impl HostKeyPolicy {
+    pub const ALL: [HostKeyPolicy; 3] = [
+        HostKeyPolicy::Strict,
+        HostKeyPolicy::AcceptNew,
+        HostKeyPolicy::Off,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            HostKeyPolicy::Strict => "strict",
+            HostKeyPolicy::AcceptNew => "accept-new",
+            HostKeyPolicy::Off => "off",
+        }
+    }
+
+    /// The `StrictHostKeyChecking` value ssh expects.
+    pub fn ssh_value(self) -> &'static str {
+        match self {
+            HostKeyPolicy::Strict => "yes",
+            HostKeyPolicy::AcceptNew => "accept-new",
+            HostKeyPolicy::Off => "no",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            HostKeyPolicy::Strict => HostKeyPolicy::AcceptNew,
+            HostKeyPolicy::AcceptNew => HostKeyPolicy::Off,
+            HostKeyPolicy::Off => HostKeyPolicy::Strict,
+        }
+    }
+}
+
+impl std::fmt::Display for HostKeyPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+impl FromStr for HostKeyPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        HostKeyPolicy::ALL
+            .into_iter()
+            .find(|policy| policy.label().eq_ignore_ascii_case(value.trim()))
+            .ok_or_else(|| {
+                format!("Invalid host key policy '{value}': expected strict, accept-new or off")
+            })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Server {
     pub id: Uuid,
     pub name: String,
+    /// `Host` token for `ssh-config` and another name to connect by; see
+    /// [`Server::ssh_alias`] for the fallback when unset.
+    #[serde(default)]
+    pub alias: Option<String>,
     pub host: String,
     pub port: u16,
     pub username: String,
+    /// Cleartext password, empty while it is sealed in `password_enc`. Wiped
+    /// when the server is dropped, so copies of a server don't leave it
+    /// behind in freed memory.
+    #[schemars(schema_with = "secret_schema::<String>")]
     pub password: String,
     pub description: Option<String>,
     #[serde(default)]
     pub identity_file: Option<String>,
     #[serde(default)]
+    pub auth_method: AuthMethod,
+    #[serde(default)]
     pub forward_agent: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     #[serde(default)]
     pub tags: Vec<String>,
+    /// Folder the server is listed under, e.g. a client or an environment.
+    #[serde(default)]
+    pub group: Option<String>,
+    /// Free-form, multi-line notes such as setup steps or runbook links;
+    /// `description` stays the one-line summary.
+    #[serde(default)]
+    pub notes: Option<String>,
+    /// Server to route through as a jump host, stored by id so renames carry over.
+    #[serde(default)]
+    pub gateway_id: Option<Uuid>,
+    /// Local source address passed to `ssh -b` on multi-homed machines.
+    #[serde(default)]
+    pub bind_address: Option<String>,
+    /// Suppress login banners and the MOTD (`ssh -q -o LogLevel=QUIET`).
+    #[serde(default)]
+    pub quiet_login: bool,
+    /// Remote tmux session to attach to (or create) on connect.
+    #[serde(default)]
+    pub remote_tmux: Option<String>,
+    /// `ConnectTimeout` in seconds; `ssh::DEFAULT_CONNECT_TIMEOUT` when unset.
+    #[serde(default)]
+    pub connect_timeout: Option<u32>,
+    /// `ServerAliveInterval` in seconds; `ssh::DEFAULT_KEEPALIVE` when unset.
+    #[serde(default)]
+    pub keepalive: Option<u32>,
+    /// When a connection to this server was last launched.
+    #[serde(default)]
+    pub last_connected: Option<DateTime<Utc>>,
+    /// Extra `-o Key=Value` options, in the order they are passed to ssh.
+    #[serde(default)]
+    pub ssh_options: Vec<(String, String)>,
+    /// Free-form `-J` jump host(s) as `user@host:port`; takes precedence over
+    /// `gateway_id` for hosts that aren't stored in the vault.
+    #[serde(default)]
+    pub jump_host: Option<String>,
+    /// Removal requires typing the server name instead of a plain yes.
+    #[serde(default)]
+    pub protected: bool,
+    /// Local tunnels opened with every interactive session.
+    #[serde(default)]
+    pub port_forwards: Vec<PortForward>,
+    #[serde(default)]
+    pub host_key_checking: HostKeyPolicy,
+    /// `password` sealed with the vault's master key, so encrypted vaults
+    /// don't keep cleartext passwords around while unlocked. `password` is
+    /// empty whenever this is set.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub password_enc: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<Vec<u8>>")]
+    pub password_nonce: Option<secretbox::Nonce>,
+    /// Password for sudo when it differs from the login password; None
+    /// reuses `password`. Sealed into `sudo_password_enc` just like it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(schema_with = "secret_schema::<Option<String>>")]
+    pub sudo_password: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sudo_password_enc: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<Vec<u8>>")]
+    pub sudo_password_nonce: Option<secretbox::Nonce>,
+}
+
+// Flags a cleartext password field as `writeOnly` and `x-secret` so tools
+// reading the schema know not to log or display it.
+fn secret_schema<T: JsonSchema>(gen: &mut SchemaGenerator) -> Schema {
+    let mut schema = T::json_schema(gen).into_object();
+    schema.metadata().write_only = true;
+    schema
+        .extensions
+        .insert("x-secret".to_string(), serde_json::Value::Bool(true));
+    Schema::Object(schema)
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.password.zeroize();
+        self.sudo_password.zeroize();
+    }
 }
 
 impl Server {
@@ -34,21 +263,342 @@ impl Server {
         Self {
             id: Uuid::new_v4(),
             name,
+            alias: None,
             host,
             port,
             username,
             password,
             description,
             identity_file: None,
+            auth_method: AuthMethod::Password,
             forward_agent: false,
             created_at: now,
             updated_at: now,
             tags: Vec::new(),
+            group: None,
+            notes: None,
+            gateway_id: None,
+            bind_address: None,
+            quiet_login: false,
+            remote_tmux: None,
+            connect_timeout: None,
+            keepalive: None,
+            last_connected: None,
+            ssh_options: Vec::new(),
+            jump_host: None,
+            protected: false,
+            port_forwards: Vec::new(),
+            host_key_checking: HostKeyPolicy::default(),
+            password_enc: Vec::new(),
+            password_nonce: None,
+            sudo_password: None,
+            sudo_password_enc: Vec::new(),
+            sudo_password_nonce: None,
+        }
+    }
+
+    /// Checks the fields every server needs: a name, a user, a real host
+    /// and a non-zero port.
+    pub fn validate(&self) -> std::result::Result<(), ValidationError> {
+        validate_name(&self.name)?;
+        validate_username(&self.username)?;
+        validate_host(&self.host)?;
+        if self.port == 0 {
+            return Err(ValidationError::InvalidPort(self.port.to_string()));
+        }
+        if let Some(alias) = &self.alias {
+            validate_alias(alias)?;
+        }
+        Ok(())
+    }
+
+    /// The alias, or the name with anything ssh wouldn't take in a `Host`
+    /// line turned into '-', so "Web Box (EU)" becomes "Web-Box-EU".
+    pub fn ssh_alias(&self) -> String {
+        if let Some(alias) = &self.alias {
+            return alias.clone();
+        }
+        let mut alias = String::new();
+        for c in self.name.chars() {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-') {
+                alias.push(c);
+            } else if !alias.ends_with('-') {
+                alias.push('-');
+            }
+        }
+        let alias = alias.trim_matches('-');
+        if alias.is_empty() {
+            format!("server-{}", &self.id.simple().to_string()[..8])
+        } else {
+            alias.to_string()
+        }
+    }
+
+    /// Checks that the fields the chosen auth method relies on are present.
+    pub fn validate_auth(&self) -> Result<()> {
+        let has_identity = self
+            .identity_file
+            .as_deref()
+            .is_some_and(|path| !path.trim().is_empty());
+        if self.auth_method == AuthMethod::PublicKey && !has_identity {
+            return Err(anyhow!(
+                "Server '{}' uses public key authentication but has no identity file",
+                self.name
+            ));
+        }
+        Ok(())
+    }
+
+    /// Fuzzy relevance of this server for `query`: each field is matched on
+    /// its own and the scores are weighted (name ×3; host, user, tags and
+    /// group ×2; port and description ×1) and summed, so a name hit beats the
+    /// same hit in a description. Queries that only match across fields, like
+    /// "web deploy", fall back to a plain score over all of them.
+    pub fn search_score(&self, matcher: &impl FuzzyMatcher, query: &str) -> Option<i64> {
+        let tags = self.tags.join(" ");
+        let port = self.port.to_string();
+        let description = self.description.as_deref().unwrap_or("");
+        let fields = [
+            (self.name.as_str(), 3),
+            (self.host.as_str(), 2),
+            (self.username.as_str(), 2),
+            (tags.as_str(), 2),
+            (self.group.as_deref().unwrap_or(""), 2),
+            (port.as_str(), 1),
+            (description, 1),
+        ];
+
+        let scores: Vec<i64> = fields
+            .iter()
+            .filter_map(|(field, weight)| {
+                matcher
+                    .fuzzy_match(field, query)
+                    .map(|score| score * weight)
+            })
+            .collect();
+        if !scores.is_empty() {
+            return Some(scores.iter().sum());
+        }
+
+        let haystack = fields.map(|(field, _)| field).join(" ");
+        matcher.fuzzy_match(&haystack, query)
+    }
+
+    /// Case-insensitive match against the server's group.
+    pub fn in_group(&self, group: &str) -> bool {
+        self.group
+            .as_deref()
+            .is_some_and(|own| own.eq_ignore_ascii_case(group.trim()))
+    }
+
+    /// The group heading the server is shown under.
+    pub fn group_label(&self) -> &str {
+        self.group.as_deref().unwrap_or(NO_GROUP)
+    }
+
+    /// Exact, case-insensitive tag match.
+    pub fn has_tag(&self, tag: &str) -> bool {
+        let tag = tag.trim();
+        self.tags.iter().any(|t| t.eq_ignore_ascii_case(tag))
+    }
+
+    /// Tags as shown next to the host, e.g. `[prod,web]`; empty when untagged.
+    pub fn tag_label(&self) -> String {
+        if self.tags.is_empty() {
+            String::new()
+        } else {
+            format!("[{}]", self.tags.join(","))
+        }
+    }
+
+    pub fn validate_ssh_options(&self) -> Result<()> {
+        for (key, value) in &self.ssh_options {
+            validate_ssh_option(key, value)?;
+        }
+        Ok(())
+    }
+
+    /// Whether connecting should go through `sshpass` with the stored password.
+    pub fn uses_sshpass(&self) -> bool {
+        self.auth_method == AuthMethod::Password && self.has_password()
+    }
+
+    /// Whether a password is stored, in cleartext or sealed.
+    pub fn has_password(&self) -> bool {
+        !self.password.is_empty() || !self.password_enc.is_empty()
+    }
+
+    pub fn is_password_sealed(&self) -> bool {
+        !self.password_enc.is_empty()
+    }
+
+    /// Replaces the stored password, dropping any sealed copy.
+    pub fn set_password(&mut self, password: String) {
+        self.password.zeroize();
+        self.password = password;
+        self.password_enc.clear();
+        self.password_nonce = None;
+    }
+
+    pub fn clear_password(&mut self) {
+        self.set_password(String::new());
+    }
+
+    /// Zeroizes the cleartext login and sudo passwords and drops their
+    /// sealed copies, as [`crate::vault::Vault::lock`] does to every server.
+    pub fn wipe_secrets(&mut self) {
+        self.clear_password();
+        self.set_sudo_password(None);
+    }
+
+    /// Whether sudo has a password of its own rather than reusing the login one.
+    pub fn has_sudo_password(&self) -> bool {
+        self.sudo_password.is_some() || !self.sudo_password_enc.is_empty()
+    }
+
+    /// Replaces the separate sudo password (None to reuse the login
+    /// password), dropping any sealed copy.
+    pub fn set_sudo_password(&mut self, password: Option<String>) {
+        self.sudo_password.zeroize();
+        self.sudo_password = password;
+        self.sudo_password_enc.clear();
+        self.sudo_password_nonce = None;
+    }
+
+    /// Moves the cleartext passwords into `password_enc` and
+    /// `sudo_password_enc`, sealed with `key`. Returns whether there was
+    /// anything to seal.
+    pub fn seal_password(&mut self, key: &MasterKey) -> bool {
+        let mut sealed = false;
+        if !self.password.is_empty() {
+            let (nonce, ciphertext) = key.encrypt(self.password.as_bytes());
+            self.password.zeroize();
+            self.password_enc = ciphertext;
+            self.password_nonce = Some(nonce);
+            sealed = true;
+        }
+        if let Some(mut sudo_password) = self.sudo_password.take() {
+            let (nonce, ciphertext) = key.encrypt(sudo_password.as_bytes());
+            sudo_password.zeroize();
+            self.sudo_password_enc = ciphertext;
+            self.sudo_password_nonce = Some(nonce);
+            sealed = true;
+        }
+        sealed
+    }
+
+    /// The cleartext password, opening the sealed copy with `key` if needed.
+    /// Callers should zeroize the result once they are done with it.
+    pub fn decrypt_password(&self, key: &MasterKey) -> Result<String> {
+        if !self.is_password_sealed() {
+            return Ok(self.password.clone());
+        }
+        self.open_secret(
+            key,
+            &self.password_enc,
+            self.password_nonce.as_ref(),
+            "password",
+        )
+    }
+
+    /// The separate sudo password, if there is one, opened like
+    /// [`Server::decrypt_password`].
+    pub fn decrypt_sudo_password(&self, key: &MasterKey) -> Result<Option<String>> {
+        if self.sudo_password_enc.is_empty() {
+            return Ok(self.sudo_password.clone());
         }
+        let nonce = self.sudo_password_nonce.as_ref();
+        self.open_secret(key, &self.sudo_password_enc, nonce, "sudo password")
+            .map(Some)
+    }
+
+    fn open_secret(
+        &self,
+        key: &MasterKey,
+        ciphertext: &[u8],
+        nonce: Option<&secretbox::Nonce>,
+        what: &str,
+    ) -> Result<String> {
+        let nonce =
+            nonce.ok_or_else(|| anyhow!("Sealed {what} for '{}' has no nonce", self.name))?;
+        let plaintext = key
+            .decrypt(ciphertext, nonce)
+            .map_err(|_| anyhow!("Failed to decrypt the {what} for '{}'", self.name))?;
+        String::from_utf8(plaintext).map_err(|e| {
+            let mut bytes = e.into_bytes();
+            bytes.zeroize();
+            anyhow!("Stored {what} for '{}' is not valid UTF-8", self.name)
+        })
+    }
+
+    /// Whether `typed` confirms removing this server when it's protected: its
+    /// exact name, ignoring surrounding whitespace.
+    pub fn confirms_removal(&self, typed: &str) -> bool {
+        typed.trim() == self.name
+    }
+
+    /// Whether both entries point at the same account: host and username
+    /// (ignoring case) and port.
+    pub fn same_endpoint(&self, other: &Server) -> bool {
+        self.matches_endpoint(&other.host, other.port, &other.username)
+    }
+
+    pub fn matches_endpoint(&self, host: &str, port: u16, username: &str) -> bool {
+        self.host.eq_ignore_ascii_case(host)
+            && self.port == port
+            && self.username.eq_ignore_ascii_case(username)
+    }
+
+    /// The host for display, with an IPv6 literal in brackets so a following
+    /// `:port` can't be read as part of the address.
+    pub fn display_host(&self) -> String {
+        if is_ipv6_literal(&self.host) {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        }
+    }
+
+    /// `host:port`, bracketing IPv6 hosts.
+    pub fn host_port(&self) -> String {
+        format!("{}:{}", self.display_host(), self.port)
+    }
+
+    pub fn jump_spec(&self) -> String {
+        format!("{}@{}", self.username, self.host_port())
     }
 
     pub fn ssh_command(&self) -> String {
-        format!("ssh {}@{} -p {}", self.username, self.host, self.port)
+        format!(
+            "ssh {}@{} -p {}",
+            self.username,
+            self.display_host(),
+            self.port
+        )
+    }
+
+    /// `ssh://user@host:port` (RFC 3986 style), leaving out the default port
+    /// 22. IPv6 hosts are bracketed and the username is percent-encoded.
+    pub fn ssh_uri(&self) -> String {
+        self.uri("ssh")
+    }
+
+    /// Like [`Server::ssh_uri`] with the `sftp` scheme, which file managers
+    /// open as a remote folder.
+    pub fn sftp_uri(&self) -> String {
+        self.uri("sftp")
+    }
+
+    fn uri(&self, scheme: &str) -> String {
+        let mut uri = format!(
+            "{scheme}://{}@{}",
+            percent_encode_userinfo(&self.username),
+            self.display_host()
+        );
+        if self.port != 22 {
+            uri.push_str(&format!(":{}", self.port));
+        }
+        uri
     }
 
     pub fn update_fields(
@@ -57,23 +607,961 @@ impl Server {
         host: String,
         port: u16,
         username: String,
-        password: String,
+        password: Option<String>,
         description: Option<String>,
     ) {
         self.name = name;
         self.host = host;
         self.port = port;
         self.username = username;
-        self.password = password;
+        // None keeps the current password, which may be sealed
+        if let Some(password) = password {
+            self.set_password(password);
+        }
         self.description = description;
         self.updated_at = Utc::now();
     }
+
+    /// Replaces the tags, trimmed, lowercased and without repeats, so "Prod"
+    /// and " prod" end up as the same tag.
+    pub fn set_tags<S: AsRef<str>>(&mut self, tags: impl IntoIterator<Item = S>) {
+        self.tags = normalize_tags(tags);
+    }
+
+    /// Folds `incoming`, an imported record of the same endpoint, into this
+    /// server: its description replaces ours when it has one, and its tags,
+    /// port forwards and ssh options are added to ours, an option set on
+    /// both taking its value. The id, name, passwords, timestamps and every
+    /// other setting stay. Returns whether anything changed.
+    pub fn merge_from(&mut self, incoming: &mut Server) -> bool {
+        let before = (
+            self.description.clone(),
+            self.tags.clone(),
+            self.port_forwards.clone(),
+            self.ssh_options.clone(),
+        );
+
+        if let Some(description) = incoming.description.take() {
+            self.description = Some(description);
+        }
+        let tags = std::mem::take(&mut self.tags);
+        self.set_tags(tags.iter().chain(&incoming.tags));
+        for forward in incoming.port_forwards.drain(..) {
+            if !self.port_forwards.contains(&forward) {
+                self.port_forwards.push(forward);
+            }
+        }
+        for (key, value) in incoming.ssh_options.drain(..) {
+            match self
+                .ssh_options
+                .iter_mut()
+                .find(|(existing, _)| existing.eq_ignore_ascii_case(&key))
+            {
+                Some(option) => option.1 = value,
+                None => self.ssh_options.push((key, value)),
+            }
+        }
+
+        let changed = before
+            != (
+                self.description.clone(),
+                self.tags.clone(),
+                self.port_forwards.clone(),
+                self.ssh_options.clone(),
+            );
+        if changed {
+            self.updated_at = Utc::now();
+        }
+        changed
+    }
+
+    /// Sets one field from its text form, as used by `portkey edit --set`.
+    /// Blank values clear optional fields; the result isn't validated here.
+    pub fn set_field(&mut self, field: &str, value: &str) -> Result<()> {
+        let optional = |value: &str| Some(value.trim().to_string()).filter(|v| !v.is_empty());
+        match field.trim().to_lowercase().replace('-', "_").as_str() {
+            "name" => self.name = value.trim().to_string(),
+            "alias" => self.alias = optional(value),
+            "host" => self.host = value.trim().to_string(),
+            "port" => self.port = parse_port(value)?,
+            "username" | "user" => self.username = value.trim().to_string(),
+            "password" => self.set_password(value.to_string()),
+            // Blank goes back to reusing the login password
+            "sudo_password" => {
+                self.set_sudo_password(Some(value.to_string()).filter(|v| !v.is_empty()))
+            }
+            "description" => self.description = optional(value),
+            "identity_file" => self.identity_file = optional(value),
+            "auth_method" => self.auth_method = value.parse().map_err(|e: String| anyhow!(e))?,
+            "forward_agent" => self.forward_agent = parse_bool(value)?,
+            "tags" => self.set_tags(value.split(',')),
+            "group" => self.group = optional(value),
+            "notes" => self.notes = Some(value.trim_end().to_string()).filter(|v| !v.is_empty()),
+            "bind_address" => self.bind_address = parse_bind_address(value)?,
+            "quiet_login" => self.quiet_login = parse_bool(value)?,
+            "remote_tmux" => self.remote_tmux = parse_tmux_session(value)?,
+            "connect_timeout" => self.connect_timeout = parse_seconds(value)?,
+            "keepalive" => self.keepalive = parse_seconds(value)?,
+            "ssh_options" => self.ssh_options = parse_ssh_options(value)?,
+            "jump_host" => self.jump_host = parse_jump_host(value)?,
+            "protected" => self.protected = parse_bool(value)?,
+            "port_forwards" => self.port_forwards = parse_port_forwards(value)?,
+            "host_key_checking" => {
+                self.host_key_checking = value.parse().map_err(|e: String| anyhow!(e))?
+            }
+            _ => {
+                return Err(anyhow!(
+                    "Unknown field '{field}'. Valid fields: {}",
+                    EDITABLE_FIELDS.join(", ")
+                ))
+            }
+        }
+        self.updated_at = Utc::now();
+        Ok(())
+    }
+}
+
+/// Fields accepted by [`Server::set_field`].
+pub const EDITABLE_FIELDS: [&str; 24] = [
+    "name",
+    "alias",
+    "host",
+    "port",
+    "username",
+    "password",
+    "sudo_password",
+    "description",
+    "identity_file",
+    "auth_method",
+    "forward_agent",
+    "tags",
+    "group",
+    "notes",
+    "bind_address",
+    "quiet_login",
+    "remote_tmux",
+    "connect_timeout",
+    "keepalive",
+    "ssh_options",
+    "jump_host",
+    "protected",
+    "port_forwards",
+    "host_key_checking",
+];
+
+/// Splits a `field=value` assignment; the value may be empty or contain `=`.
+pub fn parse_field_assignment(value: &str) -> Result<(String, String)> {
+    let (field, field_value) = value
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Invalid assignment '{value}': expected field=value"))?;
+    if field.trim().is_empty() {
+        return Err(anyhow!("Invalid assignment '{value}': missing field name"));
+    }
+    Ok((field.trim().to_string(), field_value.to_string()))
+}
+
+/// Parses yes/no style flags: true/false, yes/no, on/off or 1/0.
+pub fn parse_bool(value: &str) -> Result<bool> {
+    match value.trim().to_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" => Ok(false),
+        _ => Err(anyhow!("Invalid flag '{value}': expected true or false")),
+    }
+}
+
+/// A server field that failed [`Server::validate`].
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ValidationError {
+    #[error("Server name cannot be empty")]
+    EmptyName,
+    #[error("Username cannot be empty")]
+    EmptyUsername,
+    #[error("Invalid host '{0}': expected a hostname or an IP address")]
+    InvalidHost(String),
+    #[error("Invalid port '{0}': must be a number from 1 to 65535")]
+    InvalidPort(String),
+    #[error("Invalid alias '{0}': no whitespace or ssh pattern characters (*?!,#)")]
+    InvalidAlias(String),
+}
+
+/// An alias is a single `Host` token: not empty, with no whitespace and none
+/// of the characters ssh reads as patterns or comments.
+pub fn validate_alias(alias: &str) -> std::result::Result<(), ValidationError> {
+    let valid = !alias.is_empty()
+        && !alias
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || "*?!,#\"".contains(c));
+    if valid {
+        Ok(())
+    } else {
+        Err(ValidationError::InvalidAlias(alias.to_string()))
+    }
+}
+
+pub fn validate_name(name: &str) -> std::result::Result<(), ValidationError> {
+    if name.trim().is_empty() {
+        return Err(ValidationError::EmptyName);
+    }
+    Ok(())
+}
+
+pub fn validate_username(username: &str) -> std::result::Result<(), ValidationError> {
+    if username.trim().is_empty() {
+        return Err(ValidationError::EmptyUsername);
+    }
+    Ok(())
+}
+
+/// Accepts an IPv4/IPv6 address or a DNS-style hostname: dot-separated
+/// labels of letters, digits, '-' and '_' that don't start or end with '-'.
+pub fn validate_host(host: &str) -> std::result::Result<(), ValidationError> {
+    let invalid = || ValidationError::InvalidHost(host.to_string());
+    if host.parse::<IpAddr>().is_ok() {
+        return Ok(());
+    }
+    if host.is_empty() || host.len() > 253 {
+        return Err(invalid());
+    }
+
+    let valid_label = |label: &str| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    };
+    // A single trailing dot marks a fully qualified name
+    let name = host.strip_suffix('.').unwrap_or(host);
+    if name.split('.').all(valid_label) {
+        Ok(())
+    } else {
+        Err(invalid())
+    }
+}
+
+// Leaves the characters a URI's userinfo allows as they are and escapes the
+// rest, so a username with '@' or ':' can't be mistaken for the host.
+fn percent_encode_userinfo(value: &str) -> String {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~!$&'()*+,;=".contains(&byte) {
+            encoded.push(char::from(byte));
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
+
+/// Whether `host` is an IPv6 address literal. ssh takes those bare as its
+/// destination, but they need brackets anywhere a port or path follows.
+pub fn is_ipv6_literal(host: &str) -> bool {
+    host.contains(':') && host.parse::<Ipv6Addr>().is_ok()
+}
+
+/// Parses a port typed by the user, rejecting 0 and anything above 65535.
+pub fn parse_port(value: &str) -> std::result::Result<u16, ValidationError> {
+    let value = value.trim();
+    match value.parse::<u16>() {
+        Ok(port) if port != 0 => Ok(port),
+        _ => Err(ValidationError::InvalidPort(value.to_string())),
+    }
+}
+
+pub fn parse_bind_address(value: &str) -> Result<Option<String>> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+
+    value
+        .parse::<IpAddr>()
+        .map(|addr| Some(addr.to_string()))
+        .map_err(|_| anyhow!("Invalid bind address '{value}': expected an IPv4 or IPv6 address"))
+}
+
+/// A `key:value` term in a search query, matched exactly (ignoring case)
+/// rather than fuzzily.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SearchQualifier {
+    Tag(String),
+    User(String),
+    Host(String),
+    Group(String),
+    Port(u16),
+}
+
+impl SearchQualifier {
+    /// The keys a qualifier can start with.
+    pub const KEYS: [&'static str; 5] = ["tag", "user", "host", "group", "port"];
+
+    /// None for anything that isn't a known key followed by a usable value,
+    /// so `http://x` or a half-typed `tag:` stay free text.
+    pub fn parse(token: &str) -> Option<Self> {
+        let (key, value) = token.split_once(':')?;
+        if value.is_empty() {
+            return None;
+        }
+        let value = value.to_string();
+        match key.to_lowercase().as_str() {
+            "tag" => Some(SearchQualifier::Tag(value)),
+            "user" => Some(SearchQualifier::User(value)),
+            "host" => Some(SearchQualifier::Host(value)),
+            "group" => Some(SearchQualifier::Group(value)),
+            "port" => value.parse().ok().map(SearchQualifier::Port),
+            _ => None,
+        }
+    }
+
+    pub fn matches(&self, server: &Server) -> bool {
+        match self {
+            SearchQualifier::Tag(tag) => server.has_tag(tag),
+            SearchQualifier::User(user) => server.username.eq_ignore_ascii_case(user),
+            SearchQualifier::Host(host) => server.host.eq_ignore_ascii_case(host),
+            SearchQualifier::Group(group) => server.in_group(group),
+            SearchQualifier::Port(port) => server.port == *port,
+        }
+    }
+}
+
+/// Splits a query into its [`SearchQualifier`]s and the remaining free text,
+/// e.g. `tag:prod user:root web` into the tag and user terms and `web`.
+pub fn parse_search_query(query: &str) -> (Vec<SearchQualifier>, String) {
+    let mut qualifiers = Vec::new();
+    let mut text = Vec::new();
+    for token in query.split_whitespace() {
+        match SearchQualifier::parse(token) {
+            Some(qualifier) => qualifiers.push(qualifier),
+            None => text.push(token),
+        }
+    }
+    (qualifiers, text.join(" "))
+}
+
+/// Search used by both `portkey search` and the TUI filter: the indices of
+/// servers matching `query` with their [`Server::search_score`], highest
+/// first and in list order among equal scores. Every [`SearchQualifier`] in
+/// the query must match; the rest is matched fuzzily. Servers matched by
+/// qualifiers alone, and every server for an empty query, score 0.
+pub fn search_servers(servers: &[Server], query: &str) -> Vec<(i64, usize)> {
+    let (qualifiers, text) = parse_search_query(query);
+    let matcher = SkimMatcherV2::default();
+    let mut scored: Vec<(i64, usize)> = servers
+        .iter()
+        .enumerate()
+        .filter(|(_, s)| qualifiers.iter().all(|qualifier| qualifier.matches(s)))
+        .filter_map(|(index, s)| {
+            if text.is_empty() {
+                Some((0, index))
+            } else {
+                s.search_score(&matcher, &text).map(|score| (score, index))
+            }
+        })
+        .collect();
+    scored.sort_by_key(|(score, _)| Reverse(*score));
+    scored
+}
+
+/// Servers that `query` names, from the best tier that has any: exact names
+/// or ssh aliases (ignoring case) or full ids, then name prefixes, then id
+/// prefixes. With `exact` only the first tier counts.
+pub fn match_servers<'a>(servers: &'a [Server], query: &str, exact: bool) -> Vec<&'a Server> {
+    let lowered = query.to_lowercase();
+    let tiers: [&dyn Fn(&Server) -> bool; 3] = [
+        &|s| {
+            s.name.eq_ignore_ascii_case(query)
+                || s.ssh_alias().eq_ignore_ascii_case(query)
+                || s.id.to_string() == lowered
+        },
+        &|s| s.name.to_lowercase().starts_with(&lowered),
+        &|s| s.id.to_string().starts_with(&lowered),
+    ];
+    let tiers = if exact { &tiers[..1] } else { &tiers[..] };
+
+    tiers
+        .iter()
+        .map(|matches| servers.iter().filter(|s| matches(s)).collect::<Vec<_>>())
+        .find(|candidates| !candidates.is_empty())
+        .unwrap_or_default()
+}
+
+/// The one server `query` clearly means, for `portkey <query>`: the only
+/// [`match_servers`] hit, or when nothing matches by name or id, a fuzzy
+/// match scoring at least twice the runner-up. None when it's ambiguous.
+pub fn strong_match<'a>(servers: &'a [Server], query: &str) -> Option<&'a Server> {
+    let named = match_servers(servers, query, false);
+    if !named.is_empty() {
+        return (named.len() == 1).then(|| named[0]);
+    }
+
+    match search_servers(servers, query)[..] {
+        [(_, best)] => Some(&servers[best]),
+        [(best_score, best), (runner_up, _), ..]
+            if best_score > 0 && best_score >= runner_up.saturating_mul(2) =>
+        {
+            Some(&servers[best])
+        }
+        _ => None,
+    }
+}
+
+/// Heading for servers without a group.
+pub const NO_GROUP: &str = "(no group)";
+
+/// Orders servers by group name, case-insensitively, with ungrouped servers
+/// last. Servers in the same group compare equal, so a stable sort keeps
+/// their existing order.
+pub fn compare_groups(a: &Server, b: &Server) -> Ordering {
+    match (&a.group, &b.group) {
+        (Some(a), Some(b)) => a.to_lowercase().cmp(&b.to_lowercase()),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+/// Whether `a` and `b` go under the same group heading. Like
+/// [`compare_groups`] this ignores case, so "Backend" and "backend" share one.
+pub fn same_group(a: &Server, b: &Server) -> bool {
+    compare_groups(a, b).is_eq()
+}
+
+/// Orderings offered by `list --sort` and the TUI sort keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Name,
+    Host,
+    Port,
+    /// Most recently connected first; never-connected servers last.
+    Recent,
+}
+
+impl SortOrder {
+    pub fn label(self) -> &'static str {
+        match self {
+            SortOrder::Name => "name",
+            SortOrder::Host => "host",
+            SortOrder::Port => "port",
+            SortOrder::Recent => "recent",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            SortOrder::Name => SortOrder::Host,
+            SortOrder::Host => SortOrder::Port,
+            SortOrder::Port => SortOrder::Recent,
+            SortOrder::Recent => SortOrder::Name,
+        }
+    }
+
+    pub fn compare(self, a: &Server, b: &Server) -> Ordering {
+        let by_name = || a.name.to_lowercase().cmp(&b.name.to_lowercase());
+        match self {
+            SortOrder::Name => by_name(),
+            SortOrder::Host => a
+                .host
+                .to_lowercase()
+                .cmp(&b.host.to_lowercase())
+                .then(a.port.cmp(&b.port))
+                .then_with(by_name),
+            SortOrder::Port => a.port.cmp(&b.port).then_with(by_name),
+            SortOrder::Recent => b.last_connected.cmp(&a.last_connected).then_with(by_name),
+        }
+    }
+}
+
+/// A [`SortOrder`] and its direction, as picked with the TUI's sort keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListSort {
+    pub order: SortOrder,
+    #[serde(default)]
+    pub descending: bool,
+}
+
+impl ListSort {
+    pub fn ascending(order: SortOrder) -> Self {
+        Self {
+            order,
+            descending: false,
+        }
+    }
+
+    /// The same order in the other direction.
+    pub fn reversed(self) -> Self {
+        Self {
+            descending: !self.descending,
+            ..self
+        }
+    }
+
+    /// Shown in the TUI, e.g. "▲name" or "▼recent".
+    pub fn label(self) -> String {
+        let arrow = if self.descending { '▼' } else { '▲' };
+        format!("{arrow}{}", self.order.label())
+    }
+
+    pub fn compare(self, a: &Server, b: &Server) -> Ordering {
+        let ordering = self.order.compare(a, b);
+        if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+}
+
+impl FromStr for SortOrder {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "name" => Ok(SortOrder::Name),
+            "host" => Ok(SortOrder::Host),
+            "port" => Ok(SortOrder::Port),
+            "recent" => Ok(SortOrder::Recent),
+            _ => Err(format!(
+                "unknown sort order '{value}' (expected recent, name, host or port)"
+            )),
+        }
+    }
+}
+
+/// Parses an optional whole number of seconds; blank input means "use the default".
+pub fn parse_seconds(value: &str) -> Result<Option<u32>> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+
+    value
+        .parse::<u32>()
+        .map(Some)
+        .map_err(|_| anyhow!("Invalid number of seconds '{value}'"))
+}
+
+/// A local port forward, `ssh -L local:remote_host:remote_port`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct PortForward {
+    pub local: u16,
+    pub remote_host: String,
+    pub remote_port: u16,
+}
+
+impl PortForward {
+    /// `remote_host:remote_port`, bracketing IPv6 addresses as ssh expects.
+    pub fn remote(&self) -> String {
+        if self.remote_host.contains(':') {
+            format!("[{}]:{}", self.remote_host, self.remote_port)
+        } else {
+            format!("{}:{}", self.remote_host, self.remote_port)
+        }
+    }
+}
+
+impl std::fmt::Display for PortForward {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.local, self.remote())
+    }
+}
+
+impl FromStr for PortForward {
+    type Err = String;
+
+    /// Parses `local:remote_host:remote_port`; IPv6 remote hosts go in brackets.
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        let value = value.trim();
+        let invalid = || format!("Invalid port forward '{value}': expected local:host:port");
+
+        let (local, rest) = value.split_once(':').ok_or_else(invalid)?;
+        let (remote_host, remote_port) = match rest.strip_prefix('[') {
+            Some(bracketed) => {
+                let (host, port) = bracketed.split_once("]:").ok_or_else(invalid)?;
+                (host, port)
+            }
+            None => rest.rsplit_once(':').ok_or_else(invalid)?,
+        };
+
+        Ok(PortForward {
+            local: parse_port(local).map_err(|e| e.to_string())?,
+            remote_host: validate_host(remote_host)
+                .map(|_| remote_host.to_string())
+                .map_err(|e| e.to_string())?,
+            remote_port: parse_port(remote_port).map_err(|e| e.to_string())?,
+        })
+    }
+}
+
+/// Parses a comma-separated list of port forwards; blank means none.
+pub fn parse_port_forwards(value: &str) -> Result<Vec<PortForward>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+        .map(|spec| spec.parse::<PortForward>().map_err(|e| anyhow!(e)))
+        .collect()
+}
+
+/// Parses a `-J` jump spec: one or more comma-separated `[user@]host[:port]`
+/// hops.
+pub fn parse_jump_host(value: &str) -> Result<Option<String>> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+
+    for hop in value.split(',') {
+        let invalid = || anyhow!("Invalid jump host '{hop}': expected user@host:port");
+        let (user, host_port) = match hop.split_once('@') {
+            Some((user, rest)) => (Some(user), rest),
+            None => (None, hop),
+        };
+        let (host, port) = match host_port.rsplit_once(':') {
+            Some((host, port)) if !host.contains(':') => (host, Some(port)),
+            _ => (host_port, None),
+        };
+
+        let word = |s: &str| {
+            !s.is_empty()
+                && !s.starts_with('-')
+                && s.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+        };
+        if user.is_some_and(|user| !word(user)) || !word(host) {
+            return Err(invalid());
+        }
+        if port.is_some_and(|port| port.parse::<u16>().is_err()) {
+            return Err(invalid());
+        }
+    }
+
+    Ok(Some(value.to_string()))
+}
+
+/// Checks an ssh `-o` option: keys must look like an ssh_config keyword
+/// (`[A-Za-z][A-Za-z0-9]*`) and values must be a single non-empty line, so an
+/// option can never smuggle in extra arguments or config lines.
+pub fn validate_ssh_option(key: &str, value: &str) -> Result<()> {
+    let mut chars = key.chars();
+    let valid_key = chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric());
+    if !valid_key {
+        return Err(anyhow!(
+            "Invalid ssh option name '{key}': use letters and digits, starting with a letter"
+        ));
+    }
+
+    if value.trim().is_empty() || value.chars().any(char::is_control) {
+        return Err(anyhow!(
+            "Invalid value for ssh option '{key}': must be a single non-empty line"
+        ));
+    }
+
+    Ok(())
+}
+
+/// Parses one `Key=Value` (or `Key Value`) ssh option.
+pub fn parse_ssh_option(value: &str) -> Result<(String, String)> {
+    let value = value.trim();
+    let (key, option_value) = value
+        .split_once(|c: char| c == '=' || c.is_whitespace())
+        .ok_or_else(|| anyhow!("Invalid ssh option '{value}': expected Key=Value"))?;
+    let (key, option_value) = (key.trim(), option_value.trim());
+
+    validate_ssh_option(key, option_value)?;
+    Ok((key.to_string(), option_value.to_string()))
+}
+
+/// Parses `;`-separated `Key=Value` options, skipping empty entries.
+pub fn parse_ssh_options(value: &str) -> Result<Vec<(String, String)>> {
+    value
+        .split(';')
+        .filter(|entry| !entry.trim().is_empty())
+        .map(parse_ssh_option)
+        .collect()
+}
+
+/// Splits comma-separated input into trimmed, lowercased, de-duplicated tags.
+pub fn parse_tags(value: &str) -> Vec<String> {
+    normalize_tags(value.split(','))
+}
+
+/// Trims and lowercases each tag, dropping blanks and repeats while keeping
+/// the order the tags were first given in.
+pub fn normalize_tags<S: AsRef<str>>(tags: impl IntoIterator<Item = S>) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.as_ref().trim().to_lowercase();
+        if !tag.is_empty() && !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    normalized
+}
+
+/// Every distinct tag on `servers` with the number of servers carrying it,
+/// most common first and alphabetical among equals.
+pub fn tag_counts<'a>(servers: impl IntoIterator<Item = &'a Server>) -> Vec<(String, usize)> {
+    let mut counts: Vec<(String, usize)> = Vec::new();
+    for server in servers {
+        // Hand-edited vaults may hold tags that were never normalized
+        for tag in normalize_tags(&server.tags) {
+            match counts.iter_mut().find(|(seen, _)| *seen == tag) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((tag, 1)),
+            }
+        }
+    }
+    counts.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then_with(|| a.cmp(b)));
+    counts
+}
+
+/// A summary of a vault for `portkey stats`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VaultStats {
+    pub servers: usize,
+    /// Same order as [`tag_counts`].
+    pub tags: Vec<(String, usize)>,
+    /// Servers per group, most common first; ungrouped servers aren't counted.
+    pub groups: Vec<(String, usize)>,
+    /// Every auth method, including the ones no server uses.
+    pub auth_methods: Vec<(AuthMethod, usize)>,
+    pub with_description: usize,
+    /// Name and time of the server created first.
+    pub oldest: Option<(String, DateTime<Utc>)>,
+    pub newest: Option<(String, DateTime<Utc>)>,
+    pub last_connected: Option<(String, DateTime<Utc>)>,
+}
+
+impl VaultStats {
+    pub fn of(servers: &[Server]) -> Self {
+        let mut groups: Vec<(String, usize)> = Vec::new();
+        for group in servers.iter().filter_map(|s| s.group.as_deref()) {
+            match groups
+                .iter_mut()
+                .find(|(seen, _)| seen.eq_ignore_ascii_case(group))
+            {
+                Some((_, count)) => *count += 1,
+                None => groups.push((group.to_string(), 1)),
+            }
+        }
+        groups.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then_with(|| a.cmp(b)));
+
+        let auth_methods = AuthMethod::ALL
+            .into_iter()
+            .map(|method| {
+                let count = servers.iter().filter(|s| s.auth_method == method).count();
+                (method, count)
+            })
+            .collect();
+        let named = |server: &Server, at: DateTime<Utc>| (server.name.clone(), at);
+
+        Self {
+            servers: servers.len(),
+            tags: tag_counts(servers),
+            groups,
+            auth_methods,
+            with_description: servers
+                .iter()
+                .filter(|s| {
+                    s.description
+                        .as_deref()
+                        .is_some_and(|d| !d.trim().is_empty())
+                })
+                .count(),
+            oldest: servers
+                .iter()
+                .min_by_key(|s| s.created_at)
+                .map(|s| named(s, s.created_at)),
+            newest: servers
+                .iter()
+                .max_by_key(|s| s.created_at)
+                .map(|s| named(s, s.created_at)),
+            last_connected: servers
+                .iter()
+                .filter_map(|s| s.last_connected.map(|at| (s, at)))
+                .max_by_key(|(_, at)| *at)
+                .map(|(s, at)| named(s, at)),
+        }
+    }
+}
+
+/// Parses an age like `90d`, `12w` or `3m` (a month being 30 days).
+pub fn parse_age(value: &str) -> Result<chrono::Duration> {
+    let value = value.trim();
+    let invalid = || {
+        anyhow!("Invalid age '{value}' (expected a number of days, weeks or months, e.g. 90d, 12w or 3m)")
+    };
+    let split = value.len().checked_sub(1).ok_or_else(invalid)?;
+    if !value.is_char_boundary(split) {
+        return Err(invalid());
+    }
+    let (count, unit) = value.split_at(split);
+    let count: i64 = count.parse().map_err(|_| invalid())?;
+    let days_per_unit = match unit.to_ascii_lowercase().as_str() {
+        "d" => 1,
+        "w" => 7,
+        "m" => 30,
+        _ => return Err(invalid()),
+    };
+    count
+        .checked_mul(days_per_unit)
+        .filter(|days| (1..=36_500).contains(days))
+        .map(chrono::Duration::days)
+        .ok_or_else(invalid)
+}
+
+/// Servers not connected to since `cutoff`, or never, that `portkey prune`
+/// may remove. Protected servers are never included, and neither is a
+/// gateway still used by a server that stays.
+pub fn stale_servers(servers: &[Server], cutoff: DateTime<Utc>) -> Vec<&Server> {
+    let mut stale: Vec<&Server> = servers
+        .iter()
+        .filter(|server| !server.protected)
+        .filter(|server| server.last_connected.is_none_or(|at| at < cutoff))
+        .collect();
+    // Sparing one gateway can make its own gateway needed, so repeat until settled
+    loop {
+        let needed: Vec<Uuid> = stale
+            .iter()
+            .filter(|candidate| {
+                servers.iter().any(|server| {
+                    server.gateway_id == Some(candidate.id)
+                        && !stale.iter().any(|stale| stale.id == server.id)
+                })
+            })
+            .map(|candidate| candidate.id)
+            .collect();
+        if needed.is_empty() {
+            return stale;
+        }
+        stale.retain(|server| !needed.contains(&server.id));
+    }
+}
+
+pub fn parse_tmux_session(value: &str) -> Result<Option<String>> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+
+    if !value
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(anyhow!(
+            "Invalid tmux session name '{value}': use letters, digits, '-' or '_'"
+        ));
+    }
+
+    Ok(Some(value.to_string()))
+}
+
+/// Servers whose description fuzzy-matches `keyword` with the best score;
+/// several are returned only when they tie.
+pub fn best_description_matches<'a>(servers: &'a [Server], keyword: &str) -> Vec<&'a Server> {
+    let matcher = SkimMatcherV2::default();
+
+    let scored: Vec<(i64, &Server)> = servers
+        .iter()
+        .filter_map(|s| {
+            let description = s.description.as_deref()?;
+            matcher
+                .fuzzy_match(description, keyword)
+                .map(|score| (score, s))
+        })
+        .collect();
+
+    let Some(best) = scored.iter().map(|(score, _)| *score).max() else {
+        return Vec::new();
+    };
+    scored
+        .into_iter()
+        .filter(|(score, _)| *score == best)
+        .map(|(_, s)| s)
+        .collect()
+}
+
+/// Names of the servers added, skipped, merged and overwritten by
+/// [`VaultData::merge_servers_with`].
+#[derive(Debug, Default)]
+pub struct MergeSummary {
+    pub added: Vec<String>,
+    /// Already present, or merged without anything to change.
+    pub skipped: Vec<String>,
+    pub merged: Vec<String>,
+    pub replaced: Vec<String>,
+}
+
+impl MergeSummary {
+    /// Whether the vault changed at all.
+    pub fn changed(&self) -> bool {
+        !self.added.is_empty() || !self.merged.is_empty() || !self.replaced.is_empty()
+    }
+}
+
+/// What an import does with a server whose host, port and user are already
+/// in the vault.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnConflict {
+    /// Keep the existing server as it is.
+    #[default]
+    Skip,
+    /// Fold the incoming description, tags, port forwards and ssh options
+    /// in; see [`Server::merge_from`].
+    Merge,
+    /// Replace the name, password, description and tags.
+    Overwrite,
+}
+
+impl FromStr for OnConflict {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "skip" => Ok(OnConflict::Skip),
+            "merge" => Ok(OnConflict::Merge),
+            "overwrite" => Ok(OnConflict::Overwrite),
+            _ => Err(format!(
+                "unknown conflict strategy '{value}' (expected skip, merge or overwrite)"
+            )),
+        }
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Connection events kept in the vault unless `history_limit` says otherwise.
+pub const DEFAULT_HISTORY_LIMIT: usize = 500;
+
+fn default_history_limit() -> usize {
+    DEFAULT_HISTORY_LIMIT
+}
+
+/// One connection attempt, recorded when ssh returns.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ConnectionEvent {
+    pub server_id: Uuid,
+    pub at: DateTime<Utc>,
+    pub success: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct VaultData {
     pub servers: Vec<Server>,
+    /// Schema version, brought up to date by [`VaultData::migrate`].
+    #[serde(default = "legacy_version")]
     pub version: String,
+    /// Oldest first, trimmed to `history_limit` entries.
+    #[serde(default)]
+    pub history: Vec<ConnectionEvent>,
+    #[serde(default = "default_history_limit")]
+    pub history_limit: usize,
+}
+
+fn legacy_version() -> String {
+    LEGACY_VERSION.to_string()
 }
 
 impl Default for VaultData {
@@ -83,17 +1571,54 @@ impl Default for VaultData {
 }
 
 impl VaultData {
+    /// JSON Schema of the decrypted vault, generated from these structs so it
+    /// always matches what `export --plain-json` writes.
+    pub fn schema() -> RootSchema {
+        schemars::schema_for!(VaultData)
+    }
+
     pub fn new() -> Self {
         Self {
             servers: Vec::new(),
-            version: "1.0.0".to_string(),
+            version: CURRENT_VERSION.to_string(),
+            history: Vec::new(),
+            history_limit: DEFAULT_HISTORY_LIMIT,
         }
     }
 
+    /// Appends `event`, dropping the oldest entries beyond `history_limit`.
+    pub fn record_connection(&mut self, event: ConnectionEvent) {
+        self.history.push(event);
+        self.trim_history();
+    }
+
+    pub fn trim_history(&mut self) {
+        let excess = self.history.len().saturating_sub(self.history_limit);
+        self.history.drain(..excess);
+    }
+
     pub fn add_server(&mut self, server: Server) {
         self.servers.push(server);
     }
 
+    /// Appends a copy of server `id` under a new id, named "<name> (copy)"
+    /// and with fresh timestamps, returning the new id.
+    pub fn clone_server(&mut self, id: &Uuid) -> Option<Uuid> {
+        let mut copy = self.find_server(id)?.clone();
+        let now = Utc::now();
+        copy.id = Uuid::new_v4();
+        copy.name = format!("{} (copy)", copy.name);
+        // Aliases must stay unique, so the copy falls back to its new name
+        copy.alias = None;
+        copy.created_at = now;
+        copy.updated_at = now;
+        copy.last_connected = None;
+
+        let new_id = copy.id;
+        self.servers.push(copy);
+        Some(new_id)
+    }
+
     pub fn remove_server(&mut self, id: &Uuid) -> bool {
         let len = self.servers.len();
         self.servers.retain(|s| &s.id != id);
@@ -104,6 +1629,169 @@ impl VaultData {
         self.servers.iter().find(|s| &s.id == id)
     }
 
+    pub fn find_duplicate(&self, host: &str, port: u16, username: &str) -> Option<&Server> {
+        self.servers
+            .iter()
+            .find(|s| s.matches_endpoint(host, port, username))
+    }
+
+    /// Another server whose [`Server::ssh_alias`] equals `server`'s, ignoring
+    /// case, so the generated ssh config never has two `Host` entries for it.
+    pub fn find_alias_clash(&self, server: &Server) -> Option<&Server> {
+        let alias = server.ssh_alias();
+        self.servers
+            .iter()
+            .find(|s| s.id != server.id && s.ssh_alias().eq_ignore_ascii_case(&alias))
+    }
+
+    /// Resolves the gateway hops for `server`, outermost first, ready to be
+    /// joined into a `-J` spec. Fails on dangling references and cycles.
+    pub fn gateway_chain(&self, server: &Server) -> Result<Vec<&Server>> {
+        let mut chain = Vec::new();
+        let mut current = server;
+
+        while let Some(gateway_id) = current.gateway_id {
+            let gateway = self.find_server(&gateway_id).ok_or_else(|| {
+                anyhow!(
+                    "Gateway for '{}' no longer exists; edit the server to pick another",
+                    current.name
+                )
+            })?;
+
+            if gateway.id == server.id || chain.iter().any(|s: &&Server| s.id == gateway.id) {
+                return Err(anyhow!(
+                    "Gateway chain for '{}' loops back through '{}'",
+                    server.name,
+                    gateway.name
+                ));
+            }
+
+            chain.push(gateway);
+            current = gateway;
+        }
+
+        chain.reverse();
+        Ok(chain)
+    }
+
+    /// Distinct tags with how many servers carry each; see [`tag_counts`].
+    pub fn tag_counts(&self) -> Vec<(String, usize)> {
+        tag_counts(&self.servers)
+    }
+
+    pub fn stats(&self) -> VaultStats {
+        VaultStats::of(&self.servers)
+    }
+
+    /// Runs the upgrade steps from `version` up to the current one; see
+    /// [`migrations::migrate`]. Returns whether anything changed.
+    pub fn migrate(&mut self) -> Result<bool> {
+        let from_version = self.version.clone();
+        migrations::migrate(self, &from_version)
+    }
+
+    /// Servers matching `query`, best first; see [`search_servers`].
+    pub fn search(&self, query: &str) -> Vec<(i64, &Server)> {
+        search_servers(&self.servers, query)
+            .into_iter()
+            .map(|(score, index)| (score, &self.servers[index]))
+            .collect()
+    }
+
+    pub fn dependents_of(&self, id: &Uuid) -> Vec<&Server> {
+        self.servers
+            .iter()
+            .filter(|s| s.gateway_id.as_ref() == Some(id))
+            .collect()
+    }
+
+    /// Adds servers from another vault, skipping any whose endpoint already
+    /// exists here. Colliding ids are regenerated and gateway references are
+    /// remapped onto the local copies, or dropped when the gateway is missing.
+    pub fn merge_servers(&mut self, incoming: Vec<Server>) -> MergeSummary {
+        self.merge_servers_with(incoming, OnConflict::Skip)
+    }
+
+    /// Like [`merge_servers`](Self::merge_servers), but a server matching an
+    /// existing entry's endpoint is handled as `on_conflict` says. With
+    /// [`OnConflict::Overwrite`] it refreshes that entry's name, password,
+    /// sudo password and description where the record has them, and its
+    /// tags; with
+    /// [`OnConflict::Merge`] see [`Server::merge_from`]. Either way the
+    /// entry keeps its id, and so its history, and every other setting.
+    pub fn merge_servers_with(
+        &mut self,
+        incoming: Vec<Server>,
+        on_conflict: OnConflict,
+    ) -> MergeSummary {
+        let mut summary = MergeSummary::default();
+        let mut id_map: HashMap<Uuid, Uuid> = HashMap::new();
+        let mut added: Vec<Server> = Vec::new();
+
+        for mut server in incoming {
+            if on_conflict != OnConflict::Skip && !added.iter().any(|s| s.same_endpoint(&server)) {
+                if let Some(existing) = self.servers.iter_mut().find(|s| s.same_endpoint(&server)) {
+                    id_map.insert(server.id, existing.id);
+                    if on_conflict == OnConflict::Merge {
+                        let name = std::mem::take(&mut server.name);
+                        if existing.merge_from(&mut server) {
+                            summary.merged.push(name);
+                        } else {
+                            summary.skipped.push(name);
+                        }
+                        continue;
+                    }
+                    // Taken rather than moved: `Server` wipes its password on drop.
+                    // Records without a password or description (ssh config
+                    // entries, blank CSV cells) keep the stored ones.
+                    let password = Some(std::mem::take(&mut server.password))
+                        .filter(|password| !password.is_empty());
+                    let description = server.description.take().or(existing.description.take());
+                    existing.update_fields(
+                        server.name.clone(),
+                        std::mem::take(&mut server.host),
+                        server.port,
+                        std::mem::take(&mut server.username),
+                        password,
+                        description,
+                    );
+                    existing.tags = std::mem::take(&mut server.tags);
+                    if server.sudo_password.is_some() {
+                        existing.set_sudo_password(server.sudo_password.take());
+                    }
+                    summary.replaced.push(std::mem::take(&mut server.name));
+                    continue;
+                }
+            }
+
+            let existing_id = self
+                .servers
+                .iter()
+                .chain(&added)
+                .find(|s| s.same_endpoint(&server))
+                .map(|s| s.id);
+            if let Some(existing_id) = existing_id {
+                id_map.insert(server.id, existing_id);
+                summary.skipped.push(std::mem::take(&mut server.name));
+                continue;
+            }
+
+            let original_id = server.id;
+            if self.find_server(&server.id).is_some() || added.iter().any(|s| s.id == server.id) {
+                server.id = Uuid::new_v4();
+            }
+            id_map.insert(original_id, server.id);
+            summary.added.push(server.name.clone());
+            added.push(server);
+        }
+
+        for server in &mut added {
+            server.gateway_id = server.gateway_id.and_then(|id| id_map.get(&id).copied());
+        }
+        self.servers.extend(added);
+        summary
+    }
+
     pub fn replace_server(&mut self, server: Server) -> bool {
         if let Some(pos) = self.servers.iter().position(|s| s.id == server.id) {
             self.servers[pos] = server;
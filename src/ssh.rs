@@ -1,35 +1,414 @@
 use anyhow::{anyhow, Result};
-use std::process::Command;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::time::{Duration, Instant};
 
-use crate::models::Server;
+use crate::clipboard;
+use crate::models::{AuthMethod, HostKeyPolicy, PortForward, Server};
+
+/// The files [`find_in_path`] tries for `path`: the path itself and, when it
+/// has no extension, the path with each extension in `pathext` (Windows'
+/// `PATHEXT`, e.g. `.COM;.EXE`).
+pub fn path_candidates(path: &Path, pathext: Option<&str>) -> Vec<PathBuf> {
+    let mut candidates = vec![path.to_path_buf()];
+    if let Some(pathext) = pathext.filter(|_| path.extension().is_none()) {
+        candidates.extend(
+            pathext
+                .split(';')
+                .filter(|ext| !ext.is_empty())
+                .map(|ext| path.with_extension(ext.trim_start_matches('.'))),
+        );
+    }
+    candidates
+}
+
+/// Looks `command` up on `PATH` without shelling out to `which`, which is
+/// itself missing on many minimal containers. On Windows the extensions in
+/// `PATHEXT` are tried too, so `ssh` finds `ssh.exe`.
+pub fn find_in_path(command: &str) -> Option<PathBuf> {
+    let is_executable = |path: &Path| {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            path.metadata()
+                .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+                .unwrap_or(false)
+        }
+        #[cfg(not(unix))]
+        {
+            path.is_file()
+        }
+    };
+
+    let pathext = cfg!(windows)
+        .then(|| std::env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".into()));
+    let with_extensions = |path: PathBuf| {
+        path_candidates(&path, pathext.as_deref())
+            .into_iter()
+            .find(|candidate| is_executable(candidate))
+    };
+
+    if command.contains(std::path::MAIN_SEPARATOR) {
+        return with_extensions(PathBuf::from(command));
+    }
+
+    std::env::var_os("PATH").and_then(|paths| {
+        std::env::split_paths(&paths).find_map(|dir| with_extensions(dir.join(command)))
+    })
+}
 
 fn command_exists(command: &str) -> bool {
-    Command::new("which")
-        .arg(command)
-        .output()
-        .map(|output| output.status.success())
-        .unwrap_or(false)
+    find_in_path(command).is_some()
+}
+
+fn spawn_error(binary: &str, error: std::io::Error) -> anyhow::Error {
+    if error.kind() == std::io::ErrorKind::NotFound {
+        anyhow!("'{binary}' not found in PATH")
+    } else {
+        anyhow!("Failed to launch '{binary}': {error}")
+    }
+}
+
+/// Seconds to wait for the TCP connection when a server sets no `connect_timeout`.
+pub const DEFAULT_CONNECT_TIMEOUT: u32 = 10;
+/// `ServerAliveInterval` when a server sets no `keepalive`.
+pub const DEFAULT_KEEPALIVE: u32 = 30;
+/// Unanswered keepalives before ssh drops the session.
+pub const KEEPALIVE_COUNT_MAX: u32 = 3;
+
+/// Settings resolved at connect time rather than stored on the server itself.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectOptions {
+    /// Gateway hops from `Server::gateway_id`, outermost first.
+    pub gateways: Vec<Server>,
+    /// Overrides `Server::remote_tmux` for this connection only.
+    pub tmux_session: Option<String>,
+    /// Run plain ssh for password servers even when sshpass is installed,
+    /// leaving the password on the clipboard for ssh's own prompt.
+    pub no_sshpass: bool,
+    /// One-off `-L` forwards added on top of `Server::port_forwards`.
+    pub port_forwards: Vec<PortForward>,
+    /// Overrides `Server::host_key_checking` (and any `StrictHostKeyChecking`
+    /// in its ssh options) for this invocation.
+    pub host_key_policy: Option<HostKeyPolicy>,
+    /// Runs this instead of an interactive shell (or the remote tmux session).
+    pub remote_command: Option<RemoteCommand>,
+    /// How many `-v` flags to give ssh, at most three. Any verbosity also
+    /// turns off `Server::quiet_login` so the diagnostics aren't silenced.
+    pub verbosity: u8,
+    /// How often [`connect_with`] starts ssh again after a connection failure.
+    pub retry: Retry,
+}
+
+/// Extra ssh attempts for `connect --retries`, waiting `delay` before the
+/// first and twice as long before each one after that.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Retry {
+    pub retries: u32,
+    pub delay: Duration,
+}
+
+/// ssh's own exit code for a failure on its side, as opposed to one passed
+/// on from the remote command.
+pub const SSH_FAILURE: i32 = 255;
+
+/// Whether an ssh run ended in ssh's own failure rather than the remote
+/// side's exit. sshpass reports a rejected password as 5 and an unknown host
+/// key as 6, so neither counts. ssh also exits 255 when it rejects a key or
+/// a session drops, so this alone doesn't make a run worth retrying; see
+/// [`with_retries`].
+pub fn is_connection_failure(status: &ExitStatus) -> bool {
+    status.code() == Some(SSH_FAILURE)
 }
 
-pub fn build_ssh_args(server: &Server) -> Vec<String> {
-    let mut args = vec!["-tt".to_string()];
+/// Runs `attempt` again, at most `retry.retries` extra times, while it ends
+/// in a connection failure and `unreachable` confirms ssh never got as far
+/// as logging in. An authentication failure or a session that ran and
+/// dropped had a listening server, so it is never retried. Errors starting
+/// the client are returned straight away.
+pub fn with_retries(
+    retry: Retry,
+    mut attempt: impl FnMut() -> Result<ExitStatus>,
+    mut unreachable: impl FnMut() -> bool,
+) -> Result<ExitStatus> {
+    let mut delay = retry.delay;
+    for tried in 1..=retry.retries {
+        let status = attempt()?;
+        if !is_connection_failure(&status) || !unreachable() {
+            return Ok(status);
+        }
+        eprintln!("Retrying ({}/{})…", tried + 1, retry.retries + 1);
+        std::thread::sleep(delay);
+        delay = delay.saturating_mul(2);
+    }
+    attempt()
+}
+
+/// Host and port of the first connection ssh opens for `server`: the first
+/// jump hop or the server itself. None when its ssh options reroute the
+/// connection, since then there is no telling where ssh went.
+pub fn first_hop(server: &Server, options: &ConnectOptions) -> Option<(String, u16)> {
+    let rerouted = server.ssh_options.iter().any(|(key, _)| {
+        ["hostname", "port", "proxyjump", "proxycommand"].contains(&key.to_lowercase().as_str())
+    });
+    if rerouted {
+        return None;
+    }
+
+    let Some(jumps) = jump_spec(server, options) else {
+        return Some((server.host.clone(), server.port));
+    };
+    let hop = jumps.split(',').next()?;
+    let host_port = hop.rsplit_once('@').map_or(hop, |(_, rest)| rest);
+    // Gateways are written `user@host:port`, IPv6 hosts in brackets
+    let (host, port) = match host_port.strip_prefix('[') {
+        Some(rest) => {
+            let (host, port) = rest.split_once(']')?;
+            (host, port.strip_prefix(':'))
+        }
+        None => match host_port.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (host_port, None),
+        },
+    };
+    let port = port.map_or(Some(22), |port| port.parse().ok())?;
+    Some((host.to_string(), port))
+}
+
+/// Whether [`first_hop`] doesn't answer on its port, meaning a failed ssh
+/// run never reached the login. False when the hop isn't known.
+pub fn first_hop_unreachable(server: &Server, options: &ConnectOptions) -> bool {
+    first_hop(server, options)
+        .is_some_and(|(host, port)| probe_address(&host, port, PROBE_TIMEOUT).is_err())
+}
+
+/// A one-off command for `connect --exec`.
+#[derive(Debug, Clone)]
+pub struct RemoteCommand {
+    pub command: String,
+    /// Keep `-tt` for commands that need a terminal, like `top`.
+    pub tty: bool,
+}
+
+/// The exit code of a `connect --exec` command that failed, passed on as
+/// portkey's own exit code.
+#[derive(Debug, thiserror::Error)]
+#[error("Remote command exited with status {0}")]
+pub struct RemoteExit(pub i32);
+
+/// The server's stored forwards followed by the one-off ones, duplicates dropped.
+pub fn active_forwards(server: &Server, options: &ConnectOptions) -> Vec<PortForward> {
+    let mut forwards: Vec<PortForward> = Vec::new();
+    for forward in server.port_forwards.iter().chain(&options.port_forwards) {
+        if !forwards.contains(forward) {
+            forwards.push(forward.clone());
+        }
+    }
+    forwards
+}
+
+/// The `-J` value for `server`: its own `jump_host` if set, otherwise the
+/// resolved gateway chain.
+pub fn jump_spec(server: &Server, options: &ConnectOptions) -> Option<String> {
+    if let Some(jump_host) = server.jump_host.as_deref().filter(|j| !j.is_empty()) {
+        return Some(jump_host.to_string());
+    }
+
+    if options.gateways.is_empty() {
+        return None;
+    }
+    Some(
+        options
+            .gateways
+            .iter()
+            .map(Server::jump_spec)
+            .collect::<Vec<_>>()
+            .join(","),
+    )
+}
+
+pub fn build_ssh_args(server: &Server, options: &ConnectOptions) -> Vec<String> {
+    let mut args = Vec::new();
+    if options
+        .remote_command
+        .as_ref()
+        .is_none_or(|remote| remote.tty)
+    {
+        args.push("-tt".to_string());
+    }
+    for forward in active_forwards(server, options) {
+        args.push("-L".to_string());
+        args.push(forward.to_string());
+    }
+    args.extend(destination_args(server, options));
+
+    if let Some(remote) = &options.remote_command {
+        args.push(remote.command.clone());
+    } else if let Some(session) = options
+        .tmux_session
+        .as_deref()
+        .or(server.remote_tmux.as_deref())
+        .filter(|session| !session.is_empty())
+    {
+        args.push(format!("tmux new -A -s {session}"));
+    }
+
+    args
+}
+
+/// Arguments for a non-interactive `ssh ... true` that only proves the login
+/// works; see [`build_batch_args`].
+pub fn build_check_args(server: &Server, options: &ConnectOptions) -> Vec<String> {
+    build_batch_args(server, options, "true")
+}
+
+/// Arguments for running `command` without a terminal or any chance of a
+/// prompt. Password servers keep a single prompt for sshpass to answer; all
+/// others run in batch mode so ssh never waits for input.
+pub fn build_batch_args(server: &Server, options: &ConnectOptions, command: &str) -> Vec<String> {
+    let mode = if server.uses_sshpass() {
+        "NumberOfPasswordPrompts=1"
+    } else {
+        "BatchMode=yes"
+    };
+    let mut args = vec!["-o".to_string(), mode.to_string()];
+    args.extend(destination_args(server, options));
+    args.push(command.to_string());
+    args
+}
+
+// The client a set of shared arguments is built for. sftp and scp take the
+// port as `-P` and use `-b` and `-A` for other things, so they get those
+// settings as `-o` options instead.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Client {
+    Ssh,
+    Transfer,
+}
+
+// Everything from the identity file through `user@host`, shared by the
+// interactive and check invocations.
+fn destination_args(server: &Server, options: &ConnectOptions) -> Vec<String> {
+    let mut args = client_args(server, options, Client::Ssh);
+    args.push(format!("{}@{}", server.username, server.host));
+    args
+}
+
+// Everything from the identity file through the port, for any client.
+fn client_args(server: &Server, options: &ConnectOptions, client: Client) -> Vec<String> {
+    let mut args = Vec::new();
+    let ssh = client == Client::Ssh;
+
+    if options.verbosity > 0 {
+        args.push(format!("-{}", "v".repeat(options.verbosity.min(3).into())));
+    }
 
     if let Some(identity_file) = server
         .identity_file
         .as_deref()
-        .filter(|path| !path.is_empty())
+        .filter(|path| !path.is_empty() && server.auth_method != AuthMethod::Agent)
     {
         args.push("-i".to_string());
         args.push(identity_file.to_string());
     }
 
     if server.forward_agent {
-        args.push("-A".to_string());
+        if ssh {
+            args.push("-A".to_string());
+        } else {
+            args.push("-o".to_string());
+            args.push("ForwardAgent=yes".to_string());
+        }
+    }
+
+    if server.quiet_login && options.verbosity == 0 {
+        if ssh {
+            args.push("-q".to_string());
+        }
+        args.push("-o".to_string());
+        args.push("LogLevel=QUIET".to_string());
+    }
+
+    if let Some(bind_address) = server
+        .bind_address
+        .as_deref()
+        .filter(|addr| !addr.is_empty())
+    {
+        if ssh {
+            args.push("-b".to_string());
+            args.push(bind_address.to_string());
+        } else {
+            args.push("-o".to_string());
+            args.push(format!("BindAddress={bind_address}"));
+        }
+    }
+
+    if let Some(policy) = options.host_key_policy {
+        args.push("-o".to_string());
+        args.push(format!("StrictHostKeyChecking={}", policy.ssh_value()));
     }
 
-    args.push("-p".to_string());
+    // ssh keeps the first value it sees for an option, so per-server options
+    // go ahead of the defaults below and can override them
+    for (key, value) in &server.ssh_options {
+        args.push("-o".to_string());
+        args.push(format!("{key}={value}"));
+    }
+
+    let connect_timeout = server.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT);
+    let keepalive = server.keepalive.unwrap_or(DEFAULT_KEEPALIVE);
+    for option in [
+        format!(
+            "StrictHostKeyChecking={}",
+            server.host_key_checking.ssh_value()
+        ),
+        format!("ConnectTimeout={connect_timeout}"),
+        format!("ServerAliveInterval={keepalive}"),
+        format!("ServerAliveCountMax={KEEPALIVE_COUNT_MAX}"),
+    ] {
+        args.push("-o".to_string());
+        args.push(option);
+    }
+
+    if let Some(jumps) = jump_spec(server, options) {
+        if ssh {
+            args.push("-J".to_string());
+            args.push(jumps);
+        } else {
+            args.push("-o".to_string());
+            args.push(format!("ProxyJump={jumps}"));
+        }
+    }
+
+    args.push(if ssh { "-p" } else { "-P" }.to_string());
     args.push(server.port.to_string());
-    args.push(format!("{}@{}", server.username, server.host));
+    args
+}
+
+// `user@host` for sftp and scp, which read a colon as the start of a path,
+// so IPv6 addresses need brackets.
+fn transfer_destination(server: &Server) -> String {
+    format!("{}@{}", server.username, server.display_host())
+}
+
+pub fn build_sftp_args(server: &Server, options: &ConnectOptions) -> Vec<String> {
+    let mut args = client_args(server, options, Client::Transfer);
+    args.push(transfer_destination(server));
+    args
+}
+
+/// Arguments for copying `local` to `remote` on the server.
+pub fn build_scp_args(
+    server: &Server,
+    options: &ConnectOptions,
+    local: &str,
+    remote: &str,
+) -> Vec<String> {
+    let mut args = client_args(server, options, Client::Transfer);
+    args.push("--".to_string());
+    args.push(local.to_string());
+    args.push(format!("{}:{remote}", transfer_destination(server)));
     args
 }
 
@@ -44,8 +423,58 @@ fn shell_quote(arg: &str) -> String {
     }
 }
 
-fn ssh_command_line(server: &Server) -> String {
-    let args = build_ssh_args(server)
+/// Whether a connection would feed the stored password through sshpass:
+/// a password server, sshpass not turned off, and the binary installed.
+pub fn will_use_sshpass(server: &Server, options: &ConnectOptions) -> bool {
+    server.uses_sshpass() && !options.no_sshpass && command_exists("sshpass")
+}
+
+/// The command an OpenSSH client is started with: `program args...`, or
+/// `sshpass -e program args...` with the password in `SSHPASS`.
+pub fn build_client_command(
+    program: &str,
+    args: &[String],
+    server: &Server,
+    use_sshpass: bool,
+) -> Command {
+    let mut command = if use_sshpass {
+        let mut command = Command::new("sshpass");
+        command
+            .env("SSHPASS", &server.password)
+            .arg("-e")
+            .arg(program);
+        command
+    } else {
+        Command::new(program)
+    };
+    command.args(args);
+    command
+}
+
+/// The interactive ssh command [`connect`] runs for `server`.
+pub fn build_ssh_command(server: &Server, options: &ConnectOptions, use_sshpass: bool) -> Command {
+    build_client_command("ssh", &build_ssh_args(server, options), server, use_sshpass)
+}
+
+/// Renders `command` as a shell line for `connect --dry-run`, showing a
+/// password passed in the environment as `SSHPASS=***`.
+pub fn command_preview(command: &Command) -> String {
+    let mut parts: Vec<String> = command
+        .get_envs()
+        .filter(|(key, value)| *key == "SSHPASS" && value.is_some())
+        .map(|_| "SSHPASS=***".to_string())
+        .collect();
+    parts.push(shell_quote(&command.get_program().to_string_lossy()));
+    parts.extend(
+        command
+            .get_args()
+            .map(|arg| shell_quote(&arg.to_string_lossy())),
+    );
+    parts.join(" ")
+}
+
+pub fn ssh_command_line(server: &Server, options: &ConnectOptions) -> String {
+    let args = build_ssh_args(server, options)
         .iter()
         .map(|arg| shell_quote(arg))
         .collect::<Vec<_>>()
@@ -53,67 +482,319 @@ fn ssh_command_line(server: &Server) -> String {
     format!("ssh {args}")
 }
 
-pub fn manual_connection_help(server: &Server) -> String {
+pub fn manual_connection_help(server: &Server, options: &ConnectOptions) -> String {
     format!(
         "Connect manually with:\n  {}\nPassword is stored in Portkey and will not be printed.",
-        ssh_command_line(server)
+        ssh_command_line(server, options)
     )
 }
 
-pub fn connect(server: &Server) -> Result<()> {
-    println!(
-        "Connecting to {}@{}:{}...",
-        server.username, server.host, server.port
-    );
+// Last resort when neither sshpass nor a clipboard can supply the password.
+fn sshpass_missing(server: &Server, options: &ConnectOptions) -> anyhow::Error {
+    eprintln!("❌ sshpass is not installed or not in PATH.");
+    eprintln!();
+    eprintln!("Install sshpass to use password authentication:");
+    eprintln!("  macOS: brew install hudochenkov/sshpass/sshpass");
+    eprintln!("  Ubuntu/Debian: sudo apt-get install sshpass");
+    eprintln!("  CentOS/RHEL: sudo yum install sshpass");
+    eprintln!("  Arch: sudo pacman -S sshpass");
+    eprintln!();
+    eprintln!("{}", manual_connection_help(server, options));
+    anyhow!("sshpass is required for stored password authentication")
+}
 
-    if !command_exists("ssh") {
-        return Err(anyhow!("ssh is not installed or not in PATH"));
-    }
-
-    let ssh_args = build_ssh_args(server);
-    let has_password = !server.password.is_empty();
-
-    let status = if has_password {
-        if !command_exists("sshpass") {
-            eprintln!("❌ sshpass is not installed or not in PATH.");
-            eprintln!();
-            eprintln!("Install sshpass to use password authentication:");
-            eprintln!("  macOS: brew install hudochenkov/sshpass/sshpass");
-            eprintln!("  Ubuntu/Debian: sudo apt-get install sshpass");
-            eprintln!("  CentOS/RHEL: sudo yum install sshpass");
-            eprintln!("  Arch: sudo pacman -S sshpass");
-            eprintln!();
-            eprintln!("{}", manual_connection_help(server));
-            return Err(anyhow!(
-                "sshpass is required for stored password authentication"
-            ));
+pub fn connect(server: &Server, options: &ConnectOptions) -> Result<()> {
+    connect_with(server, options, || Ok(()))
+}
+
+/// Like [`connect`], calling `on_launch` just before ssh starts so the caller
+/// can record the connection. A failing callback is reported but does not
+/// stop the connection.
+pub fn connect_with(
+    server: &Server,
+    options: &ConnectOptions,
+    on_launch: impl FnOnce() -> Result<()>,
+) -> Result<()> {
+    // With --exec, stdout belongs to the remote command
+    let exec = options.remote_command.is_some();
+    let banner = format!(
+        "Connecting to {}@{}...",
+        server.username,
+        server.host_port()
+    );
+    let mut notices = vec![banner];
+    for forward in active_forwards(server, options) {
+        notices.push(format!(
+            "Forwarding localhost:{} -> {}",
+            forward.local,
+            forward.remote()
+        ));
+    }
+    for notice in notices {
+        if exec {
+            eprintln!("{notice}");
+        } else {
+            println!("{notice}");
         }
+    }
 
-        Command::new("sshpass")
-            .env("SSHPASS", &server.password)
-            .env(
-                "TERM",
-                std::env::var("TERM").unwrap_or_else(|_| "xterm-256color".to_string()),
-            )
-            .arg("-e")
-            .arg("ssh")
-            .args(&ssh_args)
-            .status()?
+    let ssh_args = build_ssh_args(server, options);
+    let mut on_launch = Some(on_launch);
+    let mut used_sshpass = false;
+    let status = with_retries(
+        options.retry,
+        || {
+            // Only the first attempt counts as a launch
+            let on_launch = on_launch.take();
+            let (status, sshpass) = run_client("ssh", &ssh_args, server, options, || {
+                on_launch.map_or(Ok(()), |on_launch| on_launch())
+            })?;
+            used_sshpass = sshpass;
+            Ok(status)
+        },
+        || first_hop_unreachable(server, options),
+    )?;
+
+    if status.success() {
+        Ok(())
+    } else if let Some(code) = status.code().filter(|&code| exec && code != SSH_FAILURE) {
+        // ssh itself exits with 255; anything else came from the command
+        Err(RemoteExit(code).into())
+    } else if used_sshpass && jump_spec(server, options).is_some() {
+        Err(anyhow!(
+            "SSH connection failed ({status}). Note: sshpass only answers the password prompt of the final host, not of the jump host; set up SSH keys or an agent for the jump host"
+        ))
+    } else if options.verbosity == 0 {
+        Err(anyhow!(
+            "SSH connection failed ({status}); run again with -v to see ssh's own diagnostics"
+        ))
     } else {
-        Command::new("ssh")
-            .env(
-                "TERM",
-                std::env::var("TERM").unwrap_or_else(|_| "xterm-256color".to_string()),
-            )
-            .args(&ssh_args)
-            .status()?
-    };
+        Err(anyhow!("SSH connection failed ({status})"))
+    }
+}
 
+/// The program that hands a URI to the desktop's default handler.
+pub fn uri_opener() -> &'static str {
+    if cfg!(target_os = "macos") {
+        "open"
+    } else if cfg!(windows) {
+        "explorer.exe"
+    } else {
+        "xdg-open"
+    }
+}
+
+/// Opens the server's `sftp://` URI with [`uri_opener`], so a file manager
+/// such as GNOME Files or Finder shows it as a remote folder. The password is
+/// never passed along; the file manager asks for it itself.
+pub fn open_in_file_manager(server: &Server) -> Result<()> {
+    let opener = uri_opener();
+    if find_in_path(opener).is_none() {
+        return Err(anyhow!(
+            "{opener} is not installed or not in PATH, so there is no file manager to open {} with",
+            server.sftp_uri()
+        ));
+    }
+
+    let status = Command::new(opener)
+        .arg(server.sftp_uri())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map_err(|e| anyhow!("Failed to run {opener}: {e}"))?;
     if status.success() {
         Ok(())
     } else {
         Err(anyhow!(
-            "SSH connection failed. Possible causes: server unreachable, invalid credentials, SSH service not running, or port blocked by firewall"
+            "{opener} could not open {} ({status})",
+            server.sftp_uri()
         ))
     }
 }
+
+/// Opens an interactive sftp session with the same credentials and routing
+/// as [`connect`].
+pub fn sftp(server: &Server, options: &ConnectOptions) -> Result<()> {
+    println!(
+        "Opening SFTP session to {}@{}...",
+        server.username,
+        server.host_port()
+    );
+
+    let args = build_sftp_args(server, options);
+    let (status, _) = run_client("sftp", &args, server, options, || Ok(()))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("SFTP session failed ({status})"))
+    }
+}
+
+/// Copies `local` to `remote` on the server with scp.
+pub fn scp(server: &Server, options: &ConnectOptions, local: &str, remote: &str) -> Result<()> {
+    let args = build_scp_args(server, options, local, remote);
+    let (status, _) = run_client("scp", &args, server, options, || Ok(()))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("scp to '{}' failed ({status})", server.name))
+    }
+}
+
+// Clients can only be given a cleartext password; see `Vault::with_password`.
+fn ensure_unsealed(server: &Server) -> Result<()> {
+    if server.is_password_sealed() {
+        return Err(anyhow!(
+            "The password for '{}' is still sealed with the vault key",
+            server.name
+        ));
+    }
+    Ok(())
+}
+
+// Runs an OpenSSH client (`ssh`, `sftp` or `scp`) with the server's stored
+// credentials: through sshpass when it applies, otherwise with the password
+// on the clipboard for the client's own prompt. Returns the exit status and
+// whether sshpass was used.
+fn run_client(
+    program: &str,
+    args: &[String],
+    server: &Server,
+    options: &ConnectOptions,
+    on_launch: impl FnOnce() -> Result<()>,
+) -> Result<(ExitStatus, bool)> {
+    if !command_exists(program) {
+        return Err(anyhow!(
+            "'{program}' not found in PATH; install OpenSSH (e.g. openssh-client) and try again"
+        ));
+    }
+
+    server.validate_auth()?;
+    server.validate_ssh_options()?;
+    ensure_unsealed(server)?;
+
+    let use_sshpass = will_use_sshpass(server, options);
+    if server.uses_sshpass() && !use_sshpass {
+        // Let the client prompt for the password itself, with it ready to paste
+        match clipboard::copy_secret(&server.password, clipboard::CLEAR_AFTER) {
+            Ok(()) => {
+                if !options.no_sshpass {
+                    println!("sshpass not found; falling back to {program}'s own password prompt.");
+                }
+                println!(
+                    "🔑 Password copied to the clipboard (cleared in {}s); paste it at the {program} prompt.",
+                    clipboard::CLEAR_AFTER.as_secs()
+                );
+            }
+            Err(e) if options.no_sshpass => {
+                eprintln!("Warning: could not copy the password to the clipboard: {e}");
+            }
+            Err(_) => return Err(sshpass_missing(server, options)),
+        }
+    }
+
+    if let Err(e) = on_launch() {
+        eprintln!("Warning: could not record this connection: {e}");
+    }
+
+    let term = std::env::var("TERM").unwrap_or_else(|_| "xterm-256color".to_string());
+    let binary = if use_sshpass { "sshpass" } else { program };
+    let status = build_client_command(program, args, server, use_sshpass)
+        .env("TERM", term)
+        .status()
+        .map_err(|e| spawn_error(binary, e))?;
+
+    Ok((status, use_sshpass))
+}
+
+/// How long `probe_tcp` waits for each resolved address.
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Opens and immediately closes a TCP connection to the server's ssh port,
+/// returning how long the handshake took. Never starts an ssh session.
+pub fn probe_tcp(server: &Server, timeout: Duration) -> Result<Duration> {
+    probe_address(&server.host, server.port, timeout).map_err(|e| match e {
+        ProbeError::Unresolved(e) => anyhow!("Could not resolve '{}': {e}", server.host),
+        ProbeError::Unreachable(e) => anyhow!("{} is unreachable: {e}", server.host_port()),
+        ProbeError::NoAddress => anyhow!("'{}' did not resolve to any address", server.host),
+    })
+}
+
+/// Why [`probe_address`] failed.
+#[derive(Debug)]
+pub enum ProbeError {
+    Unresolved(std::io::Error),
+    Unreachable(std::io::Error),
+    NoAddress,
+}
+
+/// [`probe_tcp`] for a bare host and port, for callers that shouldn't hold
+/// on to a whole `Server` (like the TUI's background health checks).
+pub fn probe_address(host: &str, port: u16, timeout: Duration) -> Result<Duration, ProbeError> {
+    let addrs = (host, port)
+        .to_socket_addrs()
+        .map_err(ProbeError::Unresolved)?;
+
+    let mut last_error = None;
+    for addr in addrs {
+        let started = Instant::now();
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(_) => return Ok(started.elapsed()),
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(match last_error {
+        Some(e) => ProbeError::Unreachable(e),
+        None => ProbeError::NoAddress,
+    })
+}
+
+/// Runs `command` on the server with [`build_batch_args`] and collects its
+/// output. Nothing can prompt, so password servers need sshpass installed.
+pub fn run_batch(server: &Server, options: &ConnectOptions, command: &str) -> Result<Output> {
+    if !command_exists("ssh") {
+        return Err(anyhow!(
+            "'ssh' not found in PATH; install OpenSSH (e.g. openssh-client) and try again"
+        ));
+    }
+    server.validate_auth()?;
+    server.validate_ssh_options()?;
+    ensure_unsealed(server)?;
+
+    if server.uses_sshpass() && !command_exists("sshpass") {
+        return Err(anyhow!(
+            "sshpass is required to use the stored password without a prompt"
+        ));
+    }
+
+    let args = build_batch_args(server, options, command);
+    let binary = if server.uses_sshpass() {
+        "sshpass"
+    } else {
+        "ssh"
+    };
+    build_client_command("ssh", &args, server, server.uses_sshpass())
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| spawn_error(binary, e))
+}
+
+/// Logs in with `ssh ... true` to verify authentication, without a terminal
+/// or an interactive shell.
+pub fn check_login(server: &Server, options: &ConnectOptions) -> Result<()> {
+    let output = run_batch(server, options, "true")?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr
+            .lines()
+            .rev()
+            .find(|line| !line.trim().is_empty())
+            .unwrap_or("ssh exited with an error");
+        Err(anyhow!("Login check failed: {}", reason.trim()))
+    }
+}
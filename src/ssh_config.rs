@@ -1,6 +1,8 @@
 use anyhow::{anyhow, Result};
 
-use crate::models::Server;
+use crate::models::{
+    parse_bind_address, parse_jump_host, parse_tmux_session, validate_host, AuthMethod, Server,
+};
 
 pub const BEGIN_MARKER: &str = "# BEGIN Portkey managed entries";
 pub const END_MARKER: &str = "# END Portkey managed entries";
@@ -28,7 +30,7 @@ fn validate_host_alias(alias: &str) -> Result<()> {
 }
 
 fn validate_server(server: &Server) -> Result<()> {
-    validate_host_alias(&server.name)?;
+    validate_host_alias(&server.ssh_alias())?;
     validate_non_empty_single_line("HostName", &server.host)?;
     validate_non_empty_single_line("User", &server.username)?;
 
@@ -38,32 +40,114 @@ fn validate_server(server: &Server) -> Result<()> {
         }
     }
 
+    if let Some(bind_address) = server.bind_address.as_deref() {
+        parse_bind_address(bind_address)?;
+    }
+
+    if let Some(session) = server.remote_tmux.as_deref() {
+        parse_tmux_session(session)?;
+    }
+
+    if let Some(jump_host) = server.jump_host.as_deref() {
+        parse_jump_host(jump_host)?;
+    }
+
+    for forward in &server.port_forwards {
+        validate_host(&forward.remote_host)?;
+    }
+
+    server.validate_ssh_options()?;
+
     Ok(())
 }
 
 pub fn render_ssh_config(servers: &[Server]) -> Result<String> {
     let mut output = String::new();
-
     for server in servers {
-        validate_server(server)?;
+        output.push_str(&render_host_block(server, servers)?);
+        output.push('\n');
+    }
+    Ok(output)
+}
+
+/// The `Host` block for one server, as [`render_ssh_config`] writes it.
+/// `servers` is where its gateway is looked up, for `ProxyJump`.
+pub fn render_host_block(server: &Server, servers: &[Server]) -> Result<String> {
+    validate_server(server)?;
+    let mut output = format!(
+        "Host {}\n  HostName {}\n  User {}\n  Port {}\n",
+        server.ssh_alias(),
+        server.host,
+        server.username,
+        server.port
+    );
+
+    // First value wins in ssh_config too, matching the `-o` order in ssh.rs
+    for (key, value) in &server.ssh_options {
+        output.push_str(&format!("  {key} {value}\n"));
+    }
+    output.push_str(&format!(
+        "  StrictHostKeyChecking {}\n",
+        server.host_key_checking.ssh_value()
+    ));
+
+    if let Some(identity_file) = server
+        .identity_file
+        .as_deref()
+        .filter(|path| !path.is_empty())
+    {
+        output.push_str(&format!("  IdentityFile {identity_file}\n"));
+    }
+
+    if server.forward_agent {
+        output.push_str("  ForwardAgent yes\n");
+    }
+
+    if server.quiet_login {
+        output.push_str("  LogLevel QUIET\n");
+    }
+
+    if let Some(bind_address) = server
+        .bind_address
+        .as_deref()
+        .filter(|addr| !addr.is_empty())
+    {
+        output.push_str(&format!("  BindAddress {bind_address}\n"));
+    }
+
+    if let Some(timeout) = server.connect_timeout {
+        output.push_str(&format!("  ConnectTimeout {timeout}\n"));
+    }
+
+    if let Some(keepalive) = server.keepalive {
+        output.push_str(&format!("  ServerAliveInterval {keepalive}\n"));
+    }
+
+    for forward in &server.port_forwards {
         output.push_str(&format!(
-            "Host {}\n  HostName {}\n  User {}\n  Port {}\n",
-            server.name, server.host, server.username, server.port
+            "  LocalForward {} {}\n",
+            forward.local,
+            forward.remote()
         ));
+    }
 
-        if let Some(identity_file) = server
-            .identity_file
-            .as_deref()
-            .filter(|path| !path.is_empty())
-        {
-            output.push_str(&format!("  IdentityFile {identity_file}\n"));
-        }
-
-        if server.forward_agent {
-            output.push_str("  ForwardAgent yes\n");
-        }
+    if let Some(session) = server
+        .remote_tmux
+        .as_deref()
+        .filter(|session| !session.is_empty())
+    {
+        output.push_str(&format!(
+            "  RequestTTY yes\n  RemoteCommand tmux new -A -s {session}\n"
+        ));
+    }
 
-        output.push('\n');
+    if let Some(jump_host) = server.jump_host.as_deref().filter(|j| !j.is_empty()) {
+        output.push_str(&format!("  ProxyJump {jump_host}\n"));
+    } else if let Some(gateway) = server
+        .gateway_id
+        .and_then(|id| servers.iter().find(|s| s.id == id))
+    {
+        output.push_str(&format!("  ProxyJump {}\n", gateway.ssh_alias()));
     }
 
     Ok(output)
@@ -74,26 +158,244 @@ pub fn render_managed_block(servers: &[Server]) -> Result<String> {
     Ok(format!("{BEGIN_MARKER}\n{config}{END_MARKER}\n"))
 }
 
+/// Puts `managed_block` where the previous managed block was, or at the end
+/// when there is none. Every earlier block is dropped, including duplicates
+/// left by older versions that appended on each run, and everything outside
+/// the markers is kept byte for byte.
 pub fn upsert_managed_block(existing: &str, managed_block: &str) -> String {
-    if let Some(begin) = existing.find(BEGIN_MARKER) {
-        if let Some(relative_end) = existing[begin..].find(END_MARKER) {
-            let end = begin + relative_end + END_MARKER.len();
-            let before = existing[..begin].trim_end();
-            let after = existing[end..].trim_start_matches(['\r', '\n']);
-
-            return match (before.is_empty(), after.is_empty()) {
-                (true, true) => managed_block.to_string(),
-                (true, false) => format!("{managed_block}\n{after}"),
-                (false, true) => format!("{before}\n\n{managed_block}"),
-                (false, false) => format!("{before}\n\n{managed_block}\n{after}"),
-            };
+    let mut output = String::with_capacity(existing.len() + managed_block.len());
+    let mut rest = existing;
+    let mut inserted = false;
+    while let Some(begin) = rest.find(BEGIN_MARKER) {
+        let Some(relative_end) = rest[begin..].find(END_MARKER) else {
+            break;
+        };
+        let end = begin + relative_end + END_MARKER.len();
+        output.push_str(&rest[..begin]);
+        if !inserted {
+            output.push_str(managed_block);
+            inserted = true;
         }
+        // The new block brings its own line ending
+        let after = &rest[end..];
+        rest = after
+            .strip_prefix("\r\n")
+            .or_else(|| after.strip_prefix('\n'))
+            .unwrap_or(after);
+    }
+    output.push_str(rest);
+    if inserted {
+        return output;
+    }
+
+    if output.trim().is_empty() {
+        return managed_block.to_string();
     }
+    if !output.ends_with('\n') {
+        output.push('\n');
+    }
+    if !output.ends_with("\n\n") {
+        output.push('\n');
+    }
+    output.push_str(managed_block);
+    output
+}
 
-    let existing = existing.trim_end();
-    if existing.is_empty() {
-        managed_block.to_string()
-    } else {
-        format!("{existing}\n\n{managed_block}")
+pub fn managed_block_line(content: &str) -> Option<usize> {
+    content
+        .lines()
+        .position(|line| line.trim() == BEGIN_MARKER)
+        .map(|index| index + 1)
+}
+
+pub fn validate_ssh_config(content: &str) -> Result<()> {
+    let mut inside_block = false;
+    let mut blocks = 0;
+
+    for (index, raw_line) in content.lines().enumerate() {
+        let line_number = index + 1;
+        let line = raw_line.trim();
+
+        if line == BEGIN_MARKER {
+            if inside_block {
+                return Err(anyhow!(
+                    "line {line_number}: nested '{BEGIN_MARKER}' marker"
+                ));
+            }
+            inside_block = true;
+            blocks += 1;
+            continue;
+        }
+
+        if line == END_MARKER {
+            if !inside_block {
+                return Err(anyhow!(
+                    "line {line_number}: '{END_MARKER}' without matching begin marker"
+                ));
+            }
+            inside_block = false;
+            continue;
+        }
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (keyword, value) = line
+            .split_once(|c: char| c.is_whitespace() || c == '=')
+            .ok_or_else(|| anyhow!("line {line_number}: '{line}' is missing a value"))?;
+
+        if keyword.is_empty() || !keyword.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(anyhow!("line {line_number}: invalid keyword '{keyword}'"));
+        }
+
+        if value.trim_start_matches(['=', ' ', '\t']).trim().is_empty() {
+            return Err(anyhow!(
+                "line {line_number}: '{keyword}' is missing a value"
+            ));
+        }
+    }
+
+    if inside_block {
+        return Err(anyhow!("'{BEGIN_MARKER}' is missing its end marker"));
+    }
+
+    if blocks > 1 {
+        return Err(anyhow!(
+            "found {blocks} Portkey managed blocks, expected one"
+        ));
+    }
+
+    Ok(())
+}
+
+/// Servers found in an existing ssh config, plus notes about entries that
+/// could not be turned into a server.
+#[derive(Debug, Default)]
+pub struct ParsedSshConfig {
+    pub servers: Vec<Server>,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Default)]
+struct HostBlock {
+    aliases: Vec<String>,
+    host_name: Option<String>,
+    user: Option<String>,
+    port: Option<String>,
+    identity_file: Option<String>,
+}
+
+fn is_host_pattern(alias: &str) -> bool {
+    alias.starts_with('!') || alias.contains(['*', '?'])
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+/// Turns the concrete `Host` blocks of an OpenSSH client config into servers,
+/// reading `HostName`, `User`, `Port` and `IdentityFile`. Wildcard patterns,
+/// `Match` blocks and settings outside a `Host` block are not imported.
+/// Hosts without a `User` get `default_user`, as ssh itself would.
+pub fn parse_ssh_config(content: &str, default_user: &str) -> ParsedSshConfig {
+    let mut parsed = ParsedSshConfig::default();
+    let mut block: Option<HostBlock> = None;
+
+    for (index, raw_line) in content.lines().enumerate() {
+        let line_number = index + 1;
+        let line = raw_line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (keyword, value) = line
+            .split_once(|c: char| c.is_whitespace() || c == '=')
+            .unwrap_or((line, ""));
+        let value = unquote(value.trim_start_matches(['=', ' ', '\t']).trim());
+
+        match keyword.to_ascii_lowercase().as_str() {
+            "host" => {
+                finish_host_block(block.take(), default_user, &mut parsed);
+                let mut aliases = Vec::new();
+                for alias in value.split_whitespace() {
+                    if is_host_pattern(alias) {
+                        parsed
+                            .warnings
+                            .push(format!("Skipping 'Host {alias}': not a concrete host"));
+                    } else {
+                        aliases.push(alias.to_string());
+                    }
+                }
+                block = Some(HostBlock {
+                    aliases,
+                    ..HostBlock::default()
+                });
+            }
+            "match" => {
+                finish_host_block(block.take(), default_user, &mut parsed);
+                parsed.warnings.push(format!(
+                    "line {line_number}: skipping 'Match' block, only Host blocks are imported"
+                ));
+            }
+            "include" => parsed.warnings.push(format!(
+                "line {line_number}: 'Include {value}' is not followed"
+            )),
+            keyword => {
+                let Some(block) = block.as_mut() else {
+                    continue;
+                };
+                // First value wins, as in ssh itself
+                let slot = match keyword {
+                    "hostname" => &mut block.host_name,
+                    "user" => &mut block.user,
+                    "port" => &mut block.port,
+                    "identityfile" => &mut block.identity_file,
+                    _ => continue,
+                };
+                if slot.is_none() && !value.is_empty() {
+                    *slot = Some(value.to_string());
+                }
+            }
+        }
+    }
+    finish_host_block(block, default_user, &mut parsed);
+
+    parsed
+}
+
+fn finish_host_block(block: Option<HostBlock>, default_user: &str, parsed: &mut ParsedSshConfig) {
+    let Some(block) = block else {
+        return;
+    };
+
+    let port = match block.port.as_deref().map(str::parse::<u16>) {
+        None => 22,
+        Some(Ok(port)) => port,
+        Some(Err(_)) => {
+            parsed.warnings.push(format!(
+                "Skipping '{}': invalid port '{}'",
+                block.aliases.join(" "),
+                block.port.unwrap_or_default()
+            ));
+            return;
+        }
+    };
+
+    for alias in block.aliases {
+        let host = block.host_name.clone().unwrap_or_else(|| alias.clone());
+        let user = block
+            .user
+            .clone()
+            .unwrap_or_else(|| default_user.to_string());
+        let mut server = Server::new(alias, host, port, user, String::new(), None);
+        if let Some(identity_file) = &block.identity_file {
+            server.identity_file = Some(identity_file.clone());
+            server.auth_method = AuthMethod::PublicKey;
+        }
+        parsed.servers.push(server);
     }
 }
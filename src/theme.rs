@@ -0,0 +1,160 @@
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Span;
+use std::str::FromStr;
+
+use crate::health::Health;
+
+/// Built-in color schemes for `--theme`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ThemeName {
+    /// For dark terminal backgrounds.
+    #[default]
+    Dark,
+    /// For light terminal backgrounds.
+    Light,
+}
+
+impl FromStr for ThemeName {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "dark" => Ok(ThemeName::Dark),
+            "light" => Ok(ThemeName::Light),
+            _ => Err(format!("unknown theme '{value}' (expected dark or light)")),
+        }
+    }
+}
+
+/// Every style the TUI draws with, so a theme is changed in one place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    pub header: Style,
+    pub footer: Style,
+    pub status_bar: Style,
+    /// The user, port and other secondary text in the server list.
+    pub muted: Style,
+    pub tags: Style,
+    pub group_heading: Style,
+    pub selected: Style,
+    /// Field labels and hints in the info pane.
+    pub label: Style,
+    pub title: Style,
+    /// Validation hints under a form field.
+    pub error: Style,
+    health_up: Style,
+    health_down: Style,
+    health_unknown: Style,
+    // Without colors, up and down need different dots to tell them apart
+    colored: bool,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::dark()
+    }
+}
+
+impl Theme {
+    pub fn dark() -> Self {
+        Self {
+            header: Style::default()
+                .fg(Color::Cyan)
+                .add_modifier(Modifier::BOLD),
+            footer: Style::default().fg(Color::Gray),
+            status_bar: Style::default().fg(Color::Black).bg(Color::Cyan),
+            muted: Style::default().fg(Color::DarkGray),
+            tags: Style::default().fg(Color::Yellow),
+            group_heading: Style::default()
+                .fg(Color::Magenta)
+                .add_modifier(Modifier::BOLD),
+            selected: Style::default()
+                .fg(Color::Black)
+                .bg(Color::Cyan)
+                .add_modifier(Modifier::BOLD),
+            label: Style::default().fg(Color::DarkGray),
+            title: Style::default().add_modifier(Modifier::BOLD),
+            error: Style::default().fg(Color::Red),
+            health_up: Style::default().fg(Color::Green),
+            health_down: Style::default().fg(Color::Red),
+            health_unknown: Style::default().fg(Color::DarkGray),
+            colored: true,
+        }
+    }
+
+    pub fn light() -> Self {
+        Self {
+            header: Style::default()
+                .fg(Color::Blue)
+                .add_modifier(Modifier::BOLD),
+            footer: Style::default().fg(Color::DarkGray),
+            status_bar: Style::default().fg(Color::White).bg(Color::Blue),
+            muted: Style::default().fg(Color::Gray),
+            tags: Style::default().fg(Color::Magenta),
+            group_heading: Style::default()
+                .fg(Color::Blue)
+                .add_modifier(Modifier::BOLD),
+            selected: Style::default()
+                .fg(Color::White)
+                .bg(Color::Blue)
+                .add_modifier(Modifier::BOLD),
+            label: Style::default().fg(Color::Gray),
+            title: Style::default().add_modifier(Modifier::BOLD),
+            error: Style::default().fg(Color::Red),
+            health_up: Style::default().fg(Color::Green),
+            health_down: Style::default().fg(Color::Red),
+            health_unknown: Style::default().fg(Color::Gray),
+            colored: true,
+        }
+    }
+
+    /// Bold and reverse video only, for `NO_COLOR`.
+    pub fn plain() -> Self {
+        Self {
+            header: Style::default(),
+            footer: Style::default(),
+            status_bar: Style::default().add_modifier(Modifier::REVERSED),
+            muted: Style::default(),
+            tags: Style::default(),
+            group_heading: Style::default().add_modifier(Modifier::BOLD),
+            selected: Style::default().add_modifier(Modifier::BOLD | Modifier::REVERSED),
+            label: Style::default(),
+            title: Style::default().add_modifier(Modifier::BOLD),
+            error: Style::default().add_modifier(Modifier::BOLD),
+            health_up: Style::default(),
+            health_down: Style::default(),
+            health_unknown: Style::default(),
+            colored: false,
+        }
+    }
+
+    pub fn named(name: ThemeName) -> Self {
+        match name {
+            ThemeName::Dark => Self::dark(),
+            ThemeName::Light => Self::light(),
+        }
+    }
+
+    /// The named theme, or [`Theme::plain`] when `NO_COLOR` is set to
+    /// anything but an empty string (see <https://no-color.org>).
+    pub fn from_env(name: ThemeName) -> Self {
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+        if no_color {
+            Self::plain()
+        } else {
+            Self::named(name)
+        }
+    }
+
+    /// The dot in front of each server showing whether its port answers.
+    pub fn health_dot(&self, health: Health) -> Span<'static> {
+        let (dot, style) = match health {
+            Health::Up => ("● ", self.health_up),
+            Health::Down if self.colored => ("● ", self.health_down),
+            Health::Down => ("✕ ", self.health_down),
+            Health::Unknown if self.colored => ("● ", self.health_unknown),
+            Health::Unknown => ("· ", self.health_unknown),
+        };
+        Span::styled(dot, style)
+    }
+}
@@ -1,25 +1,162 @@
 #![allow(clippy::collapsible_match)]
 
-use std::cmp::Reverse;
+use std::collections::HashSet;
 use std::io;
+use std::path::Path;
 use std::time::{Duration, Instant};
 
-use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers, MouseEventKind};
+use crossterm::event::{
+    self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseEventKind,
+};
 use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
 use ratatui::backend::CrosstermBackend;
-use ratatui::layout::{Constraint, Direction, Layout};
-use ratatui::style::{Modifier, Style};
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
 use ratatui::text::{Line, Span};
-use ratatui::widgets::{Block, Borders, List, ListItem, Paragraph};
+use ratatui::widgets::{Block, Borders, Clear, List, ListItem, Paragraph, Wrap};
 use ratatui::Terminal;
 
-use fuzzy_matcher::skim::SkimMatcherV2;
-use fuzzy_matcher::FuzzyMatcher;
 use uuid::Uuid;
+use zeroize::Zeroize;
 
-use crate::models::Server;
+use crate::clipboard;
+use crate::config::Config;
+use crate::crypto::{generate_password, Charset, DEFAULT_PASSWORD_LENGTH};
+use crate::health::HealthMonitor;
+use crate::models::{
+    compare_groups, parse_bind_address, parse_jump_host, parse_port, parse_port_forwards,
+    parse_seconds, parse_ssh_options, parse_tmux_session, same_group, search_servers,
+    validate_host, validate_name, validate_username, AuthMethod, HostKeyPolicy, ListSort,
+    PortForward, Server, SortOrder,
+};
 use crate::ssh;
-use crate::vault::Vault;
+use crate::theme::Theme;
+use crate::ui_state::UiState;
+use crate::vault::{Vault, VaultError};
+
+type Term = Terminal<CrosstermBackend<io::Stdout>>;
+
+/// How long `p` shows the selected server's password.
+pub const REVEAL_FOR: Duration = Duration::from_secs(10);
+
+/// Whole seconds, rounded up, until a password revealed `elapsed` ago hides
+/// again, or `None` once [`REVEAL_FOR`] has passed.
+pub fn reveal_seconds_left(elapsed: Duration) -> Option<u64> {
+    seconds_left(REVEAL_FOR, elapsed)
+}
+
+/// How long a first `q` or Esc waits for the second one that quits.
+pub const CONFIRM_QUIT_FOR: Duration = Duration::from_secs(3);
+
+/// Whole seconds, rounded up, that a quit prompt shown `elapsed` ago keeps
+/// waiting, or `None` once [`CONFIRM_QUIT_FOR`] has passed.
+pub fn confirm_quit_seconds_left(elapsed: Duration) -> Option<u64> {
+    seconds_left(CONFIRM_QUIT_FOR, elapsed)
+}
+
+/// Whether `code`, pressed at the quit prompt, quits rather than staying.
+pub fn confirms_quit(code: KeyCode) -> bool {
+    matches!(code, KeyCode::Char('q' | 'Q' | 'y') | KeyCode::Esc)
+}
+
+// Countdown shown for a prompt that lasts `window`
+fn seconds_left(window: Duration, elapsed: Duration) -> Option<u64> {
+    let left = window.checked_sub(elapsed).filter(|left| !left.is_zero())?;
+    Some(left.as_secs() + u64::from(left.subsec_nanos() > 0))
+}
+
+/// Every key the server list answers to. Both the footer hints and the `?`
+/// overlay are built from this, so neither can fall behind the other.
+pub const KEYBINDINGS: [(&str, &str); 31] = [
+    ("?", "help"),
+    ("Enter", "connect"),
+    ("j/k", "navigate"),
+    ("PgUp/PgDn", "scroll"),
+    ("Home/End", "jump"),
+    ("/", "filter"),
+    ("a", "add"),
+    ("e", "edit"),
+    ("d/x", "delete"),
+    ("space", "mark"),
+    ("X", "delete marked"),
+    ("u", "undo"),
+    ("c", "clone"),
+    ("r", "recheck"),
+    ("i", "info"),
+    ("N", "notes"),
+    ("P", "protect"),
+    ("L", "lock"),
+    ("g", "groups"),
+    ("s", "sort"),
+    ("1-4", "sort by name, host, port, recent"),
+    ("t", "sftp"),
+    ("o", "open in file manager"),
+    ("T", "test"),
+    ("p", "show password"),
+    ("y", "copy password"),
+    ("Y", "copy sudo password"),
+    ("C", "copy ssh cmd"),
+    ("q/Esc", "quit"),
+    ("Q", "quit without asking"),
+    ("Ctrl+C", "force quit"),
+];
+
+/// The [`KEYBINDINGS`] that change the vault, so they are refused and left
+/// out of the hints when it is read-only.
+const EDITING_KEYS: [&str; 8] = ["a", "e", "d/x", "X", "u", "c", "N", "P"];
+
+/// Whether pressing `c` triggers one of the [`EDITING_KEYS`].
+pub fn is_editing_key(c: char) -> bool {
+    EDITING_KEYS
+        .iter()
+        .flat_map(|keys| keys.split('/'))
+        .any(|key| key.chars().eq([c]))
+}
+
+/// The [`KEYBINDINGS`] offered for a vault, without the [`EDITING_KEYS`]
+/// when it is `read_only`.
+pub fn keybindings(read_only: bool) -> Vec<(&'static str, &'static str)> {
+    KEYBINDINGS
+        .into_iter()
+        .filter(|(key, _)| !(read_only && EDITING_KEYS.contains(key)))
+        .collect()
+}
+
+/// The server list footer: every one of [`keybindings`] on one line.
+pub fn footer_hints(read_only: bool) -> String {
+    keybindings(read_only)
+        .iter()
+        .map(|(key, description)| format!("{key} {description}"))
+        .collect::<Vec<_>>()
+        .join(" | ")
+}
+
+fn setup_terminal(inside_tmux: bool) -> io::Result<Term> {
+    enable_raw_mode()?;
+    let mut stdout = io::stdout();
+    crossterm::execute!(stdout, crossterm::terminal::EnterAlternateScreen)?;
+    if !inside_tmux {
+        crossterm::execute!(stdout, crossterm::event::EnableMouseCapture)?;
+    }
+    Terminal::new(CrosstermBackend::new(stdout))
+}
+
+// Hands the terminal to a foreground child (ssh, sftp) for the duration of
+// `run`, then rebuilds the UI from scratch.
+fn run_outside_ui<T>(
+    terminal: Term,
+    inside_tmux: bool,
+    run: impl FnOnce() -> T,
+) -> io::Result<(Term, T)> {
+    cleanup_terminal(inside_tmux)?;
+    // Drop old terminal to release stdout handle
+    drop(terminal);
+
+    let result = run();
+
+    let mut terminal = setup_terminal(inside_tmux)?;
+    terminal.clear()?;
+    Ok((terminal, result))
+}
 
 fn cleanup_terminal(inside_tmux: bool) -> io::Result<()> {
     disable_raw_mode()?;
@@ -31,29 +168,711 @@ fn cleanup_terminal(inside_tmux: bool) -> io::Result<()> {
     Ok(())
 }
 
-// Full TUI application replacing interactive prompts
-pub fn run_full_ui(vault: &mut Vault) -> anyhow::Result<()> {
-    let inside_tmux = std::env::var("TMUX").is_ok();
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FormField {
+    Name,
+    Host,
+    Port,
+    Username,
+    AuthMethod,
+    Password,
+    SudoPassword,
+    IdentityFile,
+    ForwardAgent,
+    QuietLogin,
+    HostKeyChecking,
+    RemoteTmux,
+    BindAddress,
+    ConnectTimeout,
+    Keepalive,
+    SshOptions,
+    Gateway,
+    JumpHost,
+    PortForwards,
+    Tags,
+    Group,
+    Description,
+}
 
-    enable_raw_mode()?;
-    let mut stdout = io::stdout();
-    crossterm::execute!(stdout, crossterm::terminal::EnterAlternateScreen)?;
-    if !inside_tmux {
-        crossterm::execute!(stdout, crossterm::event::EnableMouseCapture)?;
+const FORM_FIELDS: [FormField; 22] = [
+    FormField::Name,
+    FormField::Host,
+    FormField::Port,
+    FormField::Username,
+    FormField::AuthMethod,
+    FormField::Password,
+    FormField::SudoPassword,
+    FormField::IdentityFile,
+    FormField::ForwardAgent,
+    FormField::QuietLogin,
+    FormField::HostKeyChecking,
+    FormField::RemoteTmux,
+    FormField::BindAddress,
+    FormField::ConnectTimeout,
+    FormField::Keepalive,
+    FormField::SshOptions,
+    FormField::Gateway,
+    FormField::JumpHost,
+    FormField::PortForwards,
+    FormField::Tags,
+    FormField::Group,
+    FormField::Description,
+];
+
+enum FormEvent {
+    Pending,
+    Cancel,
+    Submit,
+}
+
+// Shared by the Add and Edit modes; `id` is set when editing an existing server.
+#[derive(Default, Clone)]
+struct ServerForm {
+    id: Option<Uuid>,
+    name: String,
+    host: String,
+    port: String,
+    username: String,
+    auth_method: AuthMethod,
+    host_key_checking: HostKeyPolicy,
+    password: String,
+    /// Set while a password generated with Ctrl+G is shown in the clear;
+    /// cleared as soon as the field is edited or left.
+    generated: Option<Charset>,
+    sudo_password: String,
+    /// Editing a server that already has a separate sudo password.
+    has_sudo_password: bool,
+    identity_file: String,
+    forward_agent: bool,
+    quiet_login: bool,
+    remote_tmux: String,
+    bind_address: String,
+    connect_timeout: String,
+    keepalive: String,
+    ssh_options: String,
+    gateway: String,
+    jump_host: String,
+    port_forwards: String,
+    tags: String,
+    group: String,
+    description: String,
+    step: usize,
+}
+
+impl ServerForm {
+    /// A blank form for a new server, starting from the configured defaults.
+    fn new(config: &Config) -> Self {
+        Self {
+            port: config.port().to_string(),
+            username: config.username().to_string(),
+            ..Self::default()
+        }
+    }
+
+    fn for_server(server: &Server, servers: &[Server]) -> Self {
+        Self {
+            id: Some(server.id),
+            name: server.name.clone(),
+            host: server.host.clone(),
+            port: server.port.to_string(),
+            username: server.username.clone(),
+            auth_method: server.auth_method,
+            host_key_checking: server.host_key_checking,
+            password: String::new(),
+            generated: None,
+            sudo_password: String::new(),
+            has_sudo_password: server.has_sudo_password(),
+            identity_file: server.identity_file.clone().unwrap_or_default(),
+            forward_agent: server.forward_agent,
+            quiet_login: server.quiet_login,
+            remote_tmux: server.remote_tmux.clone().unwrap_or_default(),
+            bind_address: server.bind_address.clone().unwrap_or_default(),
+            connect_timeout: server
+                .connect_timeout
+                .map(|s| s.to_string())
+                .unwrap_or_default(),
+            keepalive: server.keepalive.map(|s| s.to_string()).unwrap_or_default(),
+            ssh_options: server
+                .ssh_options
+                .iter()
+                .map(|(key, value)| format!("{key}={value}"))
+                .collect::<Vec<_>>()
+                .join("; "),
+            gateway: server
+                .gateway_id
+                .and_then(|id| servers.iter().find(|s| s.id == id))
+                .map(|s| s.name.clone())
+                .unwrap_or_default(),
+            jump_host: server.jump_host.clone().unwrap_or_default(),
+            port_forwards: server
+                .port_forwards
+                .iter()
+                .map(PortForward::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+            tags: server.tags.join(", "),
+            group: server.group.clone().unwrap_or_default(),
+            description: server.description.clone().unwrap_or_default(),
+            step: 0,
+        }
+    }
+
+    fn field(&self) -> FormField {
+        FORM_FIELDS[self.step.min(FORM_FIELDS.len() - 1)]
+    }
+
+    /// Why the current field can't be left as it is, checked with the same
+    /// rules as [`Server::validate`] so a bad value is caught as it is typed
+    /// rather than after the last field.
+    fn field_error(&self) -> Option<String> {
+        let result = match self.field() {
+            FormField::Name => validate_name(&self.name),
+            FormField::Host => validate_host(&self.host),
+            FormField::Port => parse_port(&self.port).map(|_| ()),
+            FormField::Username => validate_username(&self.username),
+            _ => Ok(()),
+        };
+        result.err().map(|e| e.to_string())
+    }
+
+    fn label(&self) -> &'static str {
+        match self.field() {
+            FormField::Name => "Name",
+            FormField::Host => "Host",
+            FormField::Port => "Port",
+            FormField::Username => "Username",
+            FormField::AuthMethod => "Auth method (space to cycle)",
+            FormField::Password if self.generated.is_some() => {
+                "Password (generated, shown once; Ctrl+G again to change the charset)"
+            }
+            FormField::Password if self.id.is_some() => {
+                "Password (blank keeps existing, Ctrl+G generates)"
+            }
+            FormField::Password => "Password (Ctrl+G generates)",
+            FormField::SudoPassword if self.has_sudo_password => {
+                "Sudo password (blank keeps existing)"
+            }
+            FormField::SudoPassword => "Sudo password (blank reuses the login password)",
+            FormField::IdentityFile if self.auth_method == AuthMethod::PublicKey => {
+                "Identity file (required)"
+            }
+            FormField::IdentityFile => "Identity file",
+            FormField::ForwardAgent => "Forward agent (y/n)",
+            FormField::QuietLogin => "Suppress login banner / MOTD (y/n)",
+            FormField::HostKeyChecking => "Host key checking (space to cycle)",
+            FormField::RemoteTmux => "Remote tmux session (blank for plain shell)",
+            FormField::BindAddress => "Local bind address (blank for default)",
+            FormField::ConnectTimeout => "Connect timeout in seconds (blank for default)",
+            FormField::Keepalive => "Keepalive interval in seconds (blank for default)",
+            FormField::SshOptions => "Extra ssh options (Key=Value; separated by ';')",
+            FormField::Gateway => "Gateway server name (blank for direct)",
+            FormField::JumpHost => "Jump host user@host:port (overrides gateway)",
+            FormField::PortForwards => "Port forwards local:host:port (comma-separated)",
+            FormField::Tags => "Tags (comma-separated)",
+            FormField::Group => "Group (blank for none)",
+            FormField::Description => "Description",
+        }
+    }
+
+    fn display_value(&self) -> String {
+        match self.field() {
+            FormField::AuthMethod => self.auth_method.label().to_string(),
+            FormField::HostKeyChecking => self.host_key_checking.label().to_string(),
+            FormField::Password => match self.generated {
+                Some(charset) => format!("{} ({charset})", self.password),
+                None => "*".repeat(self.password.chars().count()),
+            },
+            FormField::SudoPassword => "*".repeat(self.sudo_password.chars().count()),
+            FormField::ForwardAgent => yes_no(self.forward_agent),
+            FormField::QuietLogin => yes_no(self.quiet_login),
+            field => self.text(field).cloned().unwrap_or_default(),
+        }
+    }
+
+    fn text(&self, field: FormField) -> Option<&String> {
+        match field {
+            FormField::Name => Some(&self.name),
+            FormField::Host => Some(&self.host),
+            FormField::Port => Some(&self.port),
+            FormField::Username => Some(&self.username),
+            FormField::Password => Some(&self.password),
+            FormField::SudoPassword => Some(&self.sudo_password),
+            FormField::IdentityFile => Some(&self.identity_file),
+            FormField::RemoteTmux => Some(&self.remote_tmux),
+            FormField::BindAddress => Some(&self.bind_address),
+            FormField::ConnectTimeout => Some(&self.connect_timeout),
+            FormField::Keepalive => Some(&self.keepalive),
+            FormField::SshOptions => Some(&self.ssh_options),
+            FormField::Gateway => Some(&self.gateway),
+            FormField::JumpHost => Some(&self.jump_host),
+            FormField::PortForwards => Some(&self.port_forwards),
+            FormField::Tags => Some(&self.tags),
+            FormField::Group => Some(&self.group),
+            FormField::Description => Some(&self.description),
+            FormField::AuthMethod
+            | FormField::HostKeyChecking
+            | FormField::ForwardAgent
+            | FormField::QuietLogin => None,
+        }
+    }
+
+    fn text_mut(&mut self, field: FormField) -> Option<&mut String> {
+        match field {
+            FormField::Name => Some(&mut self.name),
+            FormField::Host => Some(&mut self.host),
+            FormField::Port => Some(&mut self.port),
+            FormField::Username => Some(&mut self.username),
+            FormField::Password => Some(&mut self.password),
+            FormField::SudoPassword => Some(&mut self.sudo_password),
+            FormField::IdentityFile => Some(&mut self.identity_file),
+            FormField::RemoteTmux => Some(&mut self.remote_tmux),
+            FormField::BindAddress => Some(&mut self.bind_address),
+            FormField::ConnectTimeout => Some(&mut self.connect_timeout),
+            FormField::Keepalive => Some(&mut self.keepalive),
+            FormField::SshOptions => Some(&mut self.ssh_options),
+            FormField::Gateway => Some(&mut self.gateway),
+            FormField::JumpHost => Some(&mut self.jump_host),
+            FormField::PortForwards => Some(&mut self.port_forwards),
+            FormField::Tags => Some(&mut self.tags),
+            FormField::Group => Some(&mut self.group),
+            FormField::Description => Some(&mut self.description),
+            FormField::AuthMethod
+            | FormField::HostKeyChecking
+            | FormField::ForwardAgent
+            | FormField::QuietLogin => None,
+        }
+    }
+
+    fn toggle_mut(&mut self, field: FormField) -> Option<&mut bool> {
+        match field {
+            FormField::ForwardAgent => Some(&mut self.forward_agent),
+            FormField::QuietLogin => Some(&mut self.quiet_login),
+            _ => None,
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> FormEvent {
+        let field = self.field();
+        if field == FormField::Password
+            && key.code == KeyCode::Char('g')
+            && key.modifiers.contains(KeyModifiers::CONTROL)
+        {
+            let charset = match self.generated {
+                Some(Charset::AlphanumericSymbols) => Charset::Alphanumeric,
+                _ => Charset::AlphanumericSymbols,
+            };
+            self.password.zeroize();
+            self.password = generate_password(DEFAULT_PASSWORD_LENGTH, charset);
+            self.generated = Some(charset);
+            return FormEvent::Pending;
+        }
+        // Any other key hides a generated password again
+        self.generated = None;
+
+        match key.code {
+            KeyCode::Esc => return FormEvent::Cancel,
+            KeyCode::BackTab => {
+                self.step = self.step.saturating_sub(1);
+            }
+            // Moving on is refused while the hint under the field is showing
+            KeyCode::Tab | KeyCode::Enter if self.field_error().is_some() => {}
+            KeyCode::Tab => {
+                self.step = (self.step + 1).min(FORM_FIELDS.len() - 1);
+            }
+            KeyCode::Enter => {
+                self.step += 1;
+                if self.step >= FORM_FIELDS.len() {
+                    return FormEvent::Submit;
+                }
+            }
+            KeyCode::Backspace | KeyCode::Delete if field == FormField::AuthMethod => {
+                self.auth_method = AuthMethod::Password;
+            }
+            KeyCode::Char(' ') if field == FormField::AuthMethod => {
+                self.auth_method = self.auth_method.next();
+            }
+            KeyCode::Backspace | KeyCode::Delete if field == FormField::HostKeyChecking => {
+                self.host_key_checking = HostKeyPolicy::default();
+            }
+            KeyCode::Char(' ') if field == FormField::HostKeyChecking => {
+                self.host_key_checking = self.host_key_checking.next();
+            }
+            KeyCode::Backspace => {
+                if let Some(toggle) = self.toggle_mut(field) {
+                    *toggle = false;
+                } else if let Some(text) = self.text_mut(field) {
+                    text.pop();
+                }
+            }
+            KeyCode::Delete => {
+                if let Some(toggle) = self.toggle_mut(field) {
+                    *toggle = false;
+                } else if let Some(text) = self.text_mut(field) {
+                    text.clear();
+                }
+            }
+            KeyCode::Char(c) => {
+                if let Some(toggle) = self.toggle_mut(field) {
+                    match c {
+                        ' ' => *toggle = !*toggle,
+                        'y' | 'Y' | 't' | 'T' | '1' => *toggle = true,
+                        'n' | 'N' | 'f' | 'F' | '0' => *toggle = false,
+                        _ => {}
+                    }
+                } else if let Some(text) = self.text_mut(field) {
+                    text.push(c);
+                }
+            }
+            _ => {}
+        }
+        FormEvent::Pending
+    }
+
+    /// Builds the server described by the form. When editing, `servers` must
+    /// contain the original entry, whose id and timestamps are kept.
+    fn build(&self, servers: &[Server]) -> Result<Server, String> {
+        let port = parse_port(&self.port).map_err(|e| e.to_string())?;
+        let description = non_empty(&self.description);
+        let bind_address = parse_bind_address(&self.bind_address).map_err(|e| e.to_string())?;
+        let connect_timeout = parse_seconds(&self.connect_timeout).map_err(|e| e.to_string())?;
+        let keepalive = parse_seconds(&self.keepalive).map_err(|e| e.to_string())?;
+        let ssh_options = parse_ssh_options(&self.ssh_options).map_err(|e| e.to_string())?;
+        let jump_host = parse_jump_host(&self.jump_host).map_err(|e| e.to_string())?;
+        let remote_tmux = parse_tmux_session(&self.remote_tmux).map_err(|e| e.to_string())?;
+        let port_forwards = parse_port_forwards(&self.port_forwards).map_err(|e| e.to_string())?;
+
+        let mut server = match self.id {
+            Some(id) => {
+                let mut server = servers
+                    .iter()
+                    .find(|s| s.id == id)
+                    .cloned()
+                    .ok_or_else(|| "Server not found".to_string())?;
+                // Blank keeps the stored (sealed) password
+                let password = non_empty(&self.password);
+                server.update_fields(
+                    self.name.clone(),
+                    self.host.clone(),
+                    port,
+                    self.username.clone(),
+                    password,
+                    description,
+                );
+                server
+            }
+            None => Server::new(
+                self.name.clone(),
+                self.host.clone(),
+                port,
+                self.username.clone(),
+                self.password.clone(),
+                description,
+            ),
+        };
+
+        server.identity_file = non_empty(&self.identity_file);
+        server.auth_method = self.auth_method;
+        server.host_key_checking = self.host_key_checking;
+        if server.auth_method != AuthMethod::Password {
+            server.clear_password();
+        }
+        // Blank keeps a stored sudo password, or reuses the login one
+        if let Some(sudo_password) = non_empty(&self.sudo_password) {
+            server.set_sudo_password(Some(sudo_password));
+        }
+        server.validate().map_err(|e| e.to_string())?;
+        server.validate_auth().map_err(|e| e.to_string())?;
+        server.forward_agent = self.forward_agent;
+        server.quiet_login = self.quiet_login;
+        server.remote_tmux = remote_tmux;
+        server.bind_address = bind_address;
+        server.connect_timeout = connect_timeout;
+        server.keepalive = keepalive;
+        server.ssh_options = ssh_options;
+        server.jump_host = jump_host;
+        server.port_forwards = port_forwards;
+        server.set_tags(self.tags.split(','));
+        server.group = non_empty(self.group.trim());
+        server.gateway_id = match non_empty(&self.gateway) {
+            Some(name) => Some(
+                servers
+                    .iter()
+                    .find(|s| s.name.eq_ignore_ascii_case(&name))
+                    .map(|s| s.id)
+                    .ok_or_else(|| format!("Unknown gateway '{name}'"))?,
+            ),
+            None => None,
+        };
+
+        Ok(server)
     }
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend)?;
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_string())
+    }
+}
 
-    let matcher = SkimMatcherV2::default();
-    let mut input = String::new();
+/// Multi-line editor for a server's notes: Enter starts a new line and
+/// Ctrl+S saves, since Enter can't double as submit here.
+struct NotesEditor {
+    id: Uuid,
+    name: String,
+    text: String,
+}
+
+impl NotesEditor {
+    fn new(server: &Server) -> Self {
+        Self {
+            id: server.id,
+            name: server.name.clone(),
+            text: server.notes.clone().unwrap_or_default(),
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> FormEvent {
+        match key.code {
+            KeyCode::Char('s') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return FormEvent::Submit;
+            }
+            KeyCode::Esc => return FormEvent::Cancel,
+            KeyCode::Enter => self.text.push('\n'),
+            KeyCode::Tab => self.text.push_str("    "),
+            KeyCode::Backspace => {
+                self.text.pop();
+            }
+            KeyCode::Char(c) => self.text.push(c),
+            _ => {}
+        }
+        FormEvent::Pending
+    }
+
+    /// The notes to store; only whitespace clears them.
+    fn notes(&self) -> Option<String> {
+        Some(self.text.trim_end().to_string()).filter(|notes| !notes.is_empty())
+    }
+}
+
+/// Every stored field of `server` as label/value pairs, as the `i` info pane
+/// lists them. The password shows as dots unless `revealed` carries it.
+pub fn detail_fields(
+    server: &Server,
+    servers: &[Server],
+    revealed: Option<&str>,
+) -> Vec<(&'static str, String)> {
+    let local_time = |time: chrono::DateTime<chrono::Utc>| {
+        time.with_timezone(&chrono::Local)
+            .format("%Y-%m-%d %H:%M")
+            .to_string()
+    };
+    let mut fields: Vec<(&str, String)> = vec![
+        ("ID", server.id.to_string()),
+        ("Alias", server.ssh_alias()),
+        ("Host", server.host_port()),
+        ("User", server.username.clone()),
+        ("Auth", server.auth_method.to_string()),
+    ];
+    let password = match revealed {
+        Some(password) => password.to_string(),
+        None if server.has_password() => "•••••••• (p to reveal)".to_string(),
+        None => "(none)".to_string(),
+    };
+    fields.push(("Password", password));
+    if server.has_sudo_password() {
+        fields.push(("Sudo password", "•••••••• (Y to copy)".to_string()));
+    }
+    if let Some(identity_file) = &server.identity_file {
+        fields.push(("Identity file", identity_file.clone()));
+    }
+    if server.forward_agent {
+        fields.push(("Forward agent", "yes".to_string()));
+    }
+    if server.quiet_login {
+        fields.push(("Quiet login", "yes".to_string()));
+    }
+    if server.host_key_checking != HostKeyPolicy::default() {
+        fields.push(("Host key checking", server.host_key_checking.to_string()));
+    }
+    if let Some(bind_address) = &server.bind_address {
+        fields.push(("Bind address", bind_address.clone()));
+    }
+    if let Some(session) = &server.remote_tmux {
+        fields.push(("Remote tmux session", session.clone()));
+    }
+    if let Some(timeout) = server.connect_timeout {
+        fields.push(("Connect timeout", format!("{timeout}s")));
+    }
+    if let Some(keepalive) = server.keepalive {
+        fields.push(("Keepalive", format!("{keepalive}s")));
+    }
+    for (key, value) in &server.ssh_options {
+        fields.push(("SSH option", format!("{key}={value}")));
+    }
+    if let Some(group) = &server.group {
+        fields.push(("Group", group.clone()));
+    }
+    if !server.tags.is_empty() {
+        fields.push(("Tags", server.tags.join(", ")));
+    }
+    if let Some(description) = &server.description {
+        fields.push(("Description", description.clone()));
+    }
+    if let Some(gateway) = server
+        .gateway_id
+        .and_then(|id| servers.iter().find(|s| s.id == id))
+    {
+        fields.push(("Gateway", gateway.name.clone()));
+    }
+    if let Some(jump_host) = &server.jump_host {
+        fields.push(("Jump host", jump_host.clone()));
+    }
+    for forward in &server.port_forwards {
+        fields.push((
+            "Forward",
+            format!("localhost:{} -> {}", forward.local, forward.remote()),
+        ));
+    }
+    if server.protected {
+        fields.push(("Protected", "yes".to_string()));
+    }
+    fields.push(("Created", local_time(server.created_at)));
+    fields.push(("Updated", local_time(server.updated_at)));
+    fields.push((
+        "Last connected",
+        server
+            .last_connected
+            .map_or_else(|| "never".to_string(), local_time),
+    ));
+    fields
+}
+
+// Body of the `i` info pane: the server name, its fields and its notes.
+fn server_details(
+    server: &Server,
+    servers: &[Server],
+    revealed: Option<&str>,
+    theme: &Theme,
+) -> Vec<Line<'static>> {
+    let fields = detail_fields(server, servers, revealed);
+    let mut lines = vec![Line::from(Span::styled(server.name.clone(), theme.title))];
+    lines.extend(fields.into_iter().map(|(label, value)| {
+        Line::from(vec![
+            Span::styled(format!("{label}: "), theme.label),
+            Span::raw(value),
+        ])
+    }));
+    lines.push(Line::from(""));
+    match &server.notes {
+        Some(notes) => lines.extend(notes.lines().map(|line| Line::from(line.to_string()))),
+        None => lines.push(Line::from(Span::styled(
+            "No notes -- press N to add some",
+            theme.label,
+        ))),
+    }
+    lines
+}
+
+/// A `width` x `height` box in the middle of `area`, shrunk to fit it.
+pub fn centered(area: Rect, width: u16, height: u16) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+    Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    )
+}
+
+fn yes_no(value: bool) -> String {
+    if value {
+        "yes".to_string()
+    } else {
+        "no".to_string()
+    }
+}
+
+/// Unlocks `vault` again after an idle or `L` lock: from `key_file` when the
+/// session was opened with one, since a key-file vault has no password.
+pub fn unlock_again(
+    vault: &mut Vault,
+    password: &str,
+    key_file: Option<&Path>,
+) -> anyhow::Result<()> {
+    match key_file {
+        Some(path) => vault.unlock_with_keyfile(path),
+        None => vault.unlock(Some(password)),
+    }
+}
+
+/// Rows a bordered list `height` cells tall shows, never fewer than one.
+pub fn visible_rows(height: u16) -> usize {
+    usize::from(height.saturating_sub(2)).max(1)
+}
+
+/// Selection after paging down `rows` from `selected`, stopping at the last
+/// of `len` entries.
+pub fn page_down(selected: usize, rows: usize, len: usize) -> usize {
+    (selected + rows).min(len.saturating_sub(1))
+}
+
+// Full TUI application replacing interactive prompts
+/// Runs the full-screen UI. With `lock_timeout` set, an encrypted vault is
+/// locked after that long without input and must be unlocked again. With
+/// `remember`, the filter, sort and selection are restored from and saved to
+/// [`UiState::path`]. `host_key_policy` overrides every server's own policy,
+/// and `theme` gives every color and highlight. A vault opened with
+/// `key_file` is unlocked from it again instead of asking for a password.
+#[allow(clippy::too_many_arguments)]
+pub fn run_full_ui(
+    vault: &mut Vault,
+    key_file: Option<&Path>,
+    lock_timeout: Option<Duration>,
+    remember: bool,
+    host_key_policy: Option<HostKeyPolicy>,
+    theme: Theme,
+    config: &Config,
+    filter: Option<String>,
+) -> anyhow::Result<()> {
+    let ui_state_path = if remember { UiState::path().ok() } else { None };
+    let ui_state = ui_state_path
+        .as_deref()
+        .map(UiState::load_from)
+        .unwrap_or_default();
+    let save_ui_state = |filter: &str, selected: Option<Uuid>, sort: Option<ListSort>| {
+        if let Some(path) = &ui_state_path {
+            let state = UiState {
+                filter: filter.to_string(),
+                selected,
+                sort,
+            };
+            if let Err(e) = state.save_to(path) {
+                eprintln!("Warning: could not save the UI state: {e}");
+            }
+        }
+    };
+
+    let inside_tmux = std::env::var("TMUX").is_ok();
+    let mut terminal = setup_terminal(inside_tmux)?;
+
+    // A filter given on the command line replaces the remembered one
+    let mut input = filter.unwrap_or(ui_state.filter);
     let mut selected_idx: usize = 0;
     // 200ms tick rate: provides responsive UI updates while being long enough
     // for crossterm to assemble multi-byte escape sequences from tmux.
     let tick_rate = Duration::from_millis(200);
     let mut last_tick = Instant::now();
+    let mut last_activity = Instant::now();
+    let mut lock_now = false;
+    let mut unlock_pending = false;
 
     // Persistent list state so scroll offset is preserved across frames
     let mut list_state = ratatui::widgets::ListState::default();
+    // Rows the list showed in the last frame; PageUp/PageDown move by this much
+    let mut page_size: usize = 10;
 
     let clamp_selection = |idx: &mut usize, len: usize| {
         if len == 0 {
@@ -67,93 +886,170 @@ pub fn run_full_ui(vault: &mut Vault) -> anyhow::Result<()> {
     enum Mode {
         Browse,
         Filter,
-        Add(AddForm),
-        Edit(EditForm),
-        ConfirmDelete(Uuid),
+        Add(ServerForm),
+        Edit(ServerForm),
+        // Name typed so far when the server is protected
+        ConfirmDelete(Uuid, Option<String>),
+        // Marked servers to remove, and how many protected ones are kept
+        ConfirmBatchDelete(Vec<Uuid>, usize),
         Message(String, Instant),
-    }
-    #[derive(Default, Clone)]
-    struct AddForm {
-        name: String,
-        host: String,
-        port: String,
-        username: String,
-        password: String,
-        identity_file: String,
-        forward_agent: bool,
-        description: String,
-        step: usize,
-    }
-    #[derive(Clone)]
-    struct EditForm {
-        id: Uuid,
-        name: String,
-        host: String,
-        port: String,
-        username: String,
-        password: String,
-        identity_file: String,
-        forward_agent: bool,
-        description: String,
-        step: usize,
+        // Server name and its revealed password, hidden again after REVEAL_FOR
+        Reveal(String, String, Instant),
+        // Password being typed and the error from the last attempt
+        Locked(String, Option<String>),
+        Notes(NotesEditor),
+        // First `q` or Esc; a second one within CONFIRM_QUIT_FOR quits
+        ConfirmQuit(Instant),
+        // The `?` overlay, scrolled down this many lines
+        Help(u16),
     }
     let mut mode = Mode::Browse;
+    let read_only = vault.is_read_only();
+    let keybindings = keybindings(read_only);
+    let footer_hints = footer_hints(read_only);
 
     let mut servers: Vec<Server> = vault.list_servers()?.clone();
-    let make_filtered = |query: &str, servers_src: &[Server]| -> Vec<(i64, usize)> {
-        if query.is_empty() {
-            servers_src
-                .iter()
-                .enumerate()
-                .map(|(i, _)| (0, i))
-                .collect()
-        } else {
-            let mut scored: Vec<(i64, usize)> = servers_src
-                .iter()
-                .enumerate()
-                .filter_map(|(i, s)| {
-                    let hay = format!(
-                        "{} {} {} {} {}",
-                        s.name,
-                        s.host,
-                        s.username,
-                        s.port,
-                        s.description.as_deref().unwrap_or("")
-                    );
-                    matcher.fuzzy_match(&hay, query).map(|score| (score, i))
-                })
-                .collect();
-            scored.sort_by_key(|score| Reverse(score.0));
-            scored
+    // None keeps the vault's own order until `s` or a 1-4 sort key is pressed
+    let mut sort: Option<ListSort> = ui_state.sort;
+    // `g` lists the servers under a header row per group
+    let mut group_view = false;
+    // Servers marked with space for a batch action
+    let mut marked: HashSet<Uuid> = HashSet::new();
+    // Reachability dots, probed in the background for the rows on screen
+    let mut health = HealthMonitor::default();
+    // `i` shows the selected server's details and notes beside the list
+    let mut show_info = false;
+    let make_filtered = |query: &str,
+                         servers_src: &[Server],
+                         sort: Option<ListSort>,
+                         grouped: bool|
+     -> Vec<(i64, usize)> {
+        let mut filtered = search_servers(servers_src, query);
+        // The chosen order only breaks ties, so a query still ranks by score
+        if let Some(sort) = sort {
+            filtered.sort_by(|a, b| {
+                b.0.cmp(&a.0)
+                    .then_with(|| sort.compare(&servers_src[a.1], &servers_src[b.1]))
+            });
+        }
+        // Groups stay contiguous, each keeping the order (or ranking) above
+        if grouped {
+            filtered.sort_by(|a, b| compare_groups(&servers_src[a.1], &servers_src[b.1]));
         }
+        filtered
     };
-    let mut filtered: Vec<(i64, usize)> = make_filtered("", &servers);
+    let mut filtered: Vec<(i64, usize)> = make_filtered(&input, &servers, sort, group_view);
+    // A remembered server that has since been removed leaves the top selected
+    if let Some(pos) = ui_state
+        .selected
+        .and_then(|id| filtered.iter().position(|(_, idx)| servers[*idx].id == id))
+    {
+        selected_idx = pos;
+    }
     clamp_selection(&mut selected_idx, filtered.len());
 
     loop {
+        // Pick up servers added or edited by another portkey process
+        if vault.changed_on_disk() {
+            let selected_id = filtered.get(selected_idx).map(|(_, idx)| servers[*idx].id);
+            let reloaded = vault.reload().and_then(|_| vault.list_servers().cloned());
+            match reloaded {
+                Ok(latest) => {
+                    servers = latest;
+                    filtered = make_filtered(&input, &servers, sort, group_view);
+                    if let Some(pos) = selected_id
+                        .and_then(|id| filtered.iter().position(|(_, idx)| servers[*idx].id == id))
+                    {
+                        selected_idx = pos;
+                    }
+                    clamp_selection(&mut selected_idx, filtered.len());
+                    if matches!(mode, Mode::Browse | Mode::Message(..)) {
+                        mode = Mode::Message(
+                            "Vault reloaded (changed externally)".to_string(),
+                            Instant::now(),
+                        );
+                    }
+                }
+                Err(e) => {
+                    if !matches!(mode, Mode::Add(_) | Mode::Edit(_) | Mode::Notes(_)) {
+                        mode = Mode::Message(
+                            format!("Vault changed on disk but could not be reloaded: {e}"),
+                            Instant::now(),
+                        );
+                    }
+                }
+            }
+        }
+
+        // Group headers take up rows of their own, so in group view the
+        // highlighted row is the selection plus the headers above it
+        let headers_above = if group_view {
+            let mut headers = 0;
+            for (pos, (_, idx)) in filtered.iter().enumerate().take(selected_idx + 1) {
+                if pos == 0 || !same_group(&servers[filtered[pos - 1].1], &servers[*idx]) {
+                    headers += 1;
+                }
+            }
+            headers
+        } else {
+            0
+        };
+        // Servers on screen as of the last frame, headers skipped
+        let visible: Vec<usize> = {
+            let first_row = list_state.offset();
+            let mut row = 0;
+            let mut visible = Vec::new();
+            for (pos, (_, idx)) in filtered.iter().enumerate() {
+                if group_view
+                    && (pos == 0 || !same_group(&servers[filtered[pos - 1].1], &servers[*idx]))
+                {
+                    row += 1;
+                }
+                if row >= first_row && row < first_row + page_size {
+                    visible.push(*idx);
+                }
+                row += 1;
+            }
+            visible
+        };
+        health.poll();
+        if matches!(mode, Mode::Browse) {
+            health.check(visible.iter().map(|idx| &servers[*idx]), false);
+        }
+
         // Sync selection to persistent list_state before drawing
         list_state.select(if filtered.is_empty() {
             None
         } else {
-            Some(selected_idx)
+            Some(selected_idx + headers_above)
         });
 
+        let form_error = match &mode {
+            Mode::Add(form) | Mode::Edit(form) => form.field_error(),
+            _ => None,
+        };
+
         terminal.draw(|f| {
             let size = f.size();
             let chunks = Layout::default()
                 .direction(Direction::Vertical)
                 .constraints([
                     Constraint::Length(1), // header
-                    Constraint::Length(3), // filter/input
+                    // filter/input, plus a line for a form field's hint
+                    Constraint::Length(if form_error.is_some() { 4 } else { 3 }),
                     Constraint::Min(1),    // list
+                    Constraint::Length(1), // status bar
                     Constraint::Length(1), // footer
                 ])
                 .split(size);
 
             // Header
-            let header = Paragraph::new(
-                "Portkey -- / filter | a add | e edit | Enter connect | j/k navigate | q quit",
-            )
+            let header = Paragraph::new(if read_only {
+                "Portkey (read-only) -- ? help | / filter | Enter connect | j/k navigate | q quit (Q without asking)"
+            } else {
+                "Portkey -- ? help | / filter | a add | e edit | Enter connect | j/k navigate | q quit (Q without asking)"
+            })
+            .style(theme.header)
             .block(Block::default().borders(Borders::NONE));
             f.render_widget(header, chunks[0]);
 
@@ -163,155 +1059,627 @@ pub fn run_full_ui(vault: &mut Vault) -> anyhow::Result<()> {
                     "Filter (type text, Enter to apply)".to_string(),
                     input.clone(),
                 ),
-                Mode::Add(form) => {
-                    let label = match form.step {
-                        0 => "Name",
-                        1 => "Host",
-                        2 => "Port",
-                        3 => "Username",
-                        4 => "Password",
-                        5 => "Identity file",
-                        6 => "Forward agent (y/n)",
-                        7 => "Description",
-                        _ => "",
-                    };
-                    let current = match form.step {
-                        0 => form.name.clone(),
-                        1 => form.host.clone(),
-                        2 => form.port.clone(),
-                        3 => form.username.clone(),
-                        4 => "*".repeat(form.password.chars().count()),
-                        5 => form.identity_file.clone(),
-                        6 => {
-                            if form.forward_agent {
-                                "yes".to_string()
-                            } else {
-                                "no".to_string()
-                            }
-                        }
-                        7 => form.description.clone(),
-                        _ => form.name.clone(),
-                    };
-                    (
-                        format!("Add server -- {label} (Shift+Tab to go back):"),
-                        current,
-                    )
+                Mode::Add(form) => (
+                    format!("Add server -- {} (Shift+Tab to go back):", form.label()),
+                    form.display_value(),
+                ),
+                Mode::Edit(form) => (
+                    format!("Edit server -- {} (Shift+Tab to go back):", form.label()),
+                    form.display_value(),
+                ),
+                Mode::Message(msg, _) => ("Message".to_string(), msg.clone()),
+                Mode::ConfirmQuit(since) => {
+                    let mut prompt = format!(
+                        "Press q or Esc again to quit, any other key to stay ({}s)",
+                        confirm_quit_seconds_left(since.elapsed()).unwrap_or(0)
+                    );
+                    if !marked.is_empty() {
+                        prompt = format!("{} marked server(s) will be unmarked -- {prompt}", marked.len());
+                    }
+                    ("Quit?".to_string(), prompt)
                 }
-                Mode::Edit(form) => {
-                    let label = match form.step {
-                        0 => "Name",
-                        1 => "Host",
-                        2 => "Port",
-                        3 => "Username",
-                        4 => "Password (blank keeps existing)",
-                        5 => "Identity file",
-                        6 => "Forward agent (y/n)",
-                        7 => "Description",
-                        _ => "",
-                    };
-                    let current = match form.step {
-                        0 => form.name.clone(),
-                        1 => form.host.clone(),
-                        2 => form.port.clone(),
-                        3 => form.username.clone(),
-                        4 => "*".repeat(form.password.chars().count()),
-                        5 => form.identity_file.clone(),
-                        6 => {
-                            if form.forward_agent {
-                                "yes".to_string()
-                            } else {
-                                "no".to_string()
-                            }
-                        }
-                        7 => form.description.clone(),
-                        _ => form.name.clone(),
+                Mode::Reveal(name, password, since) => (
+                    format!(
+                        "Password for '{name}' -- hides in {}s or on any key",
+                        reveal_seconds_left(since.elapsed()).unwrap_or(0)
+                    ),
+                    password.clone(),
+                ),
+                Mode::Locked(password, error) => (
+                    match error {
+                        Some(error) => format!("Vault locked -- {error}"),
+                        None => match key_file {
+                            Some(path) => format!(
+                                "Vault locked -- Enter unlocks with {} (Esc to quit)",
+                                path.display()
+                            ),
+                            None => "Vault locked -- enter master password (Esc to quit)"
+                                .to_string(),
+                        },
+                    },
+                    "*".repeat(password.chars().count()),
+                ),
+                Mode::ConfirmDelete(id, typed) => {
+                    let dependents = servers
+                        .iter()
+                        .filter(|s| s.gateway_id.as_ref() == Some(id))
+                        .map(|s| s.name.as_str())
+                        .collect::<Vec<_>>();
+                    let (title, mut prompt) = match typed {
+                        Some(typed) => (
+                            "Confirm Delete (protected)",
+                            format!("Type the server name, Enter to delete, Esc to cancel: {typed}"),
+                        ),
+                        None => (
+                            "Confirm Delete",
+                            "Press 'y' to confirm, 'n' or Esc to cancel".to_string(),
+                        ),
                     };
-                    (
-                        format!("Edit server -- {label} (Shift+Tab to go back):"),
-                        current,
-                    )
+                    if !dependents.is_empty() {
+                        prompt = format!("Gateway for {} -- {prompt}", dependents.join(", "));
+                    }
+                    (title.to_string(), prompt)
                 }
-                Mode::Message(msg, _) => ("Message".to_string(), msg.clone()),
-                Mode::ConfirmDelete(_) => (
-                    "Confirm Delete".to_string(),
-                    "Press 'y' to confirm, 'n' or Esc to cancel".to_string(),
+                Mode::Notes(editor) => (
+                    format!("Notes for '{}'", editor.name),
+                    "Enter new line | Ctrl+S save | Esc cancel".to_string(),
                 ),
+                Mode::ConfirmBatchDelete(ids, protected) => {
+                    let mut prompt = format!(
+                        "Delete {} marked server(s)? Press 'y' to confirm, 'n' or Esc to cancel",
+                        ids.len()
+                    );
+                    if *protected > 0 {
+                        prompt.push_str(&format!(" ({protected} protected kept)"));
+                    }
+                    ("Confirm Delete".to_string(), prompt)
+                }
                 _ => ("Filter (press / to edit)".to_string(), input.clone()),
             };
+            let mut lines = vec![Line::from(text)];
+            if let Some(error) = &form_error {
+                lines.push(Line::from(Span::styled(error.clone(), theme.error)));
+            }
             let input_widget =
-                Paragraph::new(text).block(Block::default().borders(Borders::ALL).title(title));
+                Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title));
             f.render_widget(input_widget, chunks[1]);
 
             // List
-            let items: Vec<ListItem> = if filtered.is_empty() {
+            let items: Vec<ListItem> = if matches!(mode, Mode::Locked(..)) {
+                vec![ListItem::new(Line::from(vec![Span::raw(
+                    "Locked after inactivity",
+                )]))]
+            } else if filtered.is_empty() {
                 vec![ListItem::new(Line::from(vec![Span::raw("No matches")]))]
             } else {
-                filtered
-                    .iter()
-                    .map(|(_, idx)| {
-                        let s = &servers[*idx];
-                        let line = format!("{} | {}@{}:{}", s.name, s.username, s.host, s.port);
-                        ListItem::new(Line::from(vec![Span::raw(line)]))
-                    })
-                    .collect()
+                let mut items = Vec::new();
+                let mut previous: Option<&Server> = None;
+                for (_, idx) in &filtered {
+                    let s = &servers[*idx];
+                    if group_view && previous.is_none_or(|previous| !same_group(previous, s)) {
+                        items.push(ListItem::new(Line::from(vec![Span::styled(
+                            format!("── {} ──", s.group_label()),
+                            theme.group_heading,
+                        )])));
+                    }
+                    previous = Some(s);
+                    let mut prefix = String::new();
+                    if group_view {
+                        prefix.push_str("  ");
+                    }
+                    if !marked.is_empty() {
+                        prefix.push_str(if marked.contains(&s.id) { "* " } else { "  " });
+                    }
+                    let mut spans = vec![
+                        Span::raw(prefix),
+                        theme.health_dot(health.status(s)),
+                        Span::raw(s.name.clone()),
+                        Span::styled(format!(" | {}@", s.username), theme.muted),
+                        Span::raw(s.display_host()),
+                        Span::styled(format!(":{}", s.port), theme.muted),
+                    ];
+                    if !s.tags.is_empty() {
+                        spans.push(Span::styled(format!(" {}", s.tag_label()), theme.tags));
+                    }
+                    if s.protected {
+                        spans.push(Span::styled(" (protected)", theme.muted));
+                    }
+                    items.push(ListItem::new(Line::from(spans)));
+                }
+                items
             };
+            let position = if filtered.is_empty() || matches!(mode, Mode::Locked(..)) {
+                None
+            } else {
+                Some(format!("{}/{}", selected_idx + 1, filtered.len()))
+            };
+            let details: Vec<String> = position
+                .into_iter()
+                .chain(group_view.then(|| "grouped".to_string()))
+                .chain((!marked.is_empty()).then(|| format!("{} marked", marked.len())))
+                .collect();
+            let mut title = "Servers".to_string();
+            if let Some(sort) = sort {
+                title.push(' ');
+                title.push_str(&sort.label());
+            }
+            if !details.is_empty() {
+                title.push_str(&format!(" ({})", details.join(", ")));
+            }
+            // Details or the notes editor take the right half of the list area
+            let selected_server = filtered
+                .get(selected_idx)
+                .filter(|_| !matches!(mode, Mode::Locked(..)))
+                .map(|(_, idx)| &servers[*idx]);
+            let side_pane = match &mode {
+                Mode::Notes(editor) => Some(
+                    Paragraph::new(format!("{}█", editor.text)).block(
+                        Block::default()
+                            .borders(Borders::ALL)
+                            .title(format!("Notes: {} (Ctrl+S to save)", editor.name)),
+                    ),
+                ),
+                _ if show_info => Some(
+                    Paragraph::new(
+                        selected_server
+                            .map(|server| {
+                                let revealed = match &mode {
+                                    Mode::Reveal(name, password, _) if *name == server.name => {
+                                        Some(password.as_str())
+                                    }
+                                    _ => None,
+                                };
+                                server_details(server, &servers, revealed, &theme)
+                            })
+                            .unwrap_or_default(),
+                    )
+                        .block(Block::default().borders(Borders::ALL).title("Info")),
+                ),
+                _ => None,
+            };
+            let list_area = match side_pane {
+                Some(pane) => {
+                    let halves = Layout::default()
+                        .direction(Direction::Horizontal)
+                        .constraints([Constraint::Percentage(55), Constraint::Percentage(45)])
+                        .split(chunks[2]);
+                    f.render_widget(pane.wrap(Wrap { trim: false }), halves[1]);
+                    halves[0]
+                }
+                None => chunks[2],
+            };
+
+            // Inside the borders
+            page_size = visible_rows(list_area.height);
             let list = List::new(items)
-                .block(Block::default().borders(Borders::ALL).title("Servers"))
-                .highlight_style(
-                    Style::default().add_modifier(Modifier::BOLD | Modifier::REVERSED),
-                );
-            f.render_stateful_widget(list, chunks[2], &mut list_state);
+                .block(Block::default().borders(Borders::ALL).title(title))
+                .highlight_style(theme.selected);
+            f.render_stateful_widget(list, list_area, &mut list_state);
+
+            // Status bar: the active sort and filter
+            let sort_status = match sort {
+                Some(sort) => format!("Sort: {}", sort.label()),
+                None => "Sort: vault order".to_string(),
+            };
+            let filter_status = if input.is_empty() {
+                "Filter: none".to_string()
+            } else {
+                format!("Filter: '{input}' ({} of {})", filtered.len(), servers.len())
+            };
+            let status = Paragraph::new(format!(
+                "{sort_status} | {filter_status} | 1 name 2 host 3 port 4 recent (again to reverse)"
+            ))
+            .style(theme.status_bar);
+            f.render_widget(status, chunks[3]);
 
             // Footer
             let footer_text = match &mode {
-                Mode::ConfirmDelete(_) => "y=YES | n=NO (or Esc to cancel)",
-                _ => "d delete | PgUp/PgDn scroll | Home/End jump | Ctrl+C force quit",
+                Mode::ConfirmDelete(_, Some(_)) => "Enter=delete if the name matches | Esc=cancel",
+                Mode::ConfirmDelete(_, None) | Mode::ConfirmBatchDelete(..) => {
+                    "y=YES | n=NO (or Esc to cancel)"
+                }
+                Mode::ConfirmQuit(_) => "q/Esc=quit | any other key=stay | Q quits without asking",
+                Mode::Help(_) => "j/k scroll | any other key=close",
+                _ => footer_hints.as_str(),
             };
-            let footer = Paragraph::new(footer_text).block(Block::default().borders(Borders::NONE));
-            f.render_widget(footer, chunks[3]);
+            let footer = Paragraph::new(footer_text)
+                .style(theme.footer)
+                .block(Block::default().borders(Borders::NONE));
+            f.render_widget(footer, chunks[4]);
+
+            if let Mode::Help(scroll) = mode {
+                let key_width = keybindings.iter().map(|(key, _)| key.len()).max().unwrap_or(0);
+                let lines: Vec<Line> = keybindings
+                    .iter()
+                    .map(|(key, description)| {
+                        Line::from(vec![
+                            Span::styled(format!("{key:>key_width$}  "), theme.title),
+                            Span::raw(*description),
+                        ])
+                    })
+                    .collect();
+                let width = keybindings
+                    .iter()
+                    .map(|(_, description)| key_width + 2 + description.len())
+                    .max()
+                    .unwrap_or(0)
+                    .max(16);
+                let area = centered(size, width as u16 + 4, keybindings.len() as u16 + 2);
+                let help = Paragraph::new(lines)
+                    .scroll((scroll, 0))
+                    .block(Block::default().borders(Borders::ALL).title("Keys"));
+                f.render_widget(Clear, area);
+                f.render_widget(help, area);
+            }
         })?;
 
+        // Deriving the key can take seconds; the frame just drawn says so
+        if unlock_pending {
+            unlock_pending = false;
+            if let Mode::Locked(password, error) = &mut mode {
+                let unlocked = unlock_again(vault, password, key_file)
+                    .and_then(|_| vault.list_servers().cloned());
+                password.zeroize();
+                match unlocked {
+                    Ok(latest) => {
+                        servers = latest;
+                        filtered = make_filtered(&input, &servers, sort, group_view);
+                        clamp_selection(&mut selected_idx, filtered.len());
+                        mode = Mode::Browse;
+                    }
+                    Err(e) => {
+                        *error = Some(match e.downcast_ref::<VaultError>() {
+                            Some(VaultError::WrongPassword) => {
+                                "wrong password, try again".to_string()
+                            }
+                            _ => e.to_string(),
+                        });
+                    }
+                }
+            }
+            last_activity = Instant::now();
+            continue;
+        }
+
         let timeout = tick_rate.saturating_sub(last_tick.elapsed());
         if crossterm::event::poll(timeout)? {
             match event::read()? {
                 Event::Key(key) if key.kind == KeyEventKind::Press => {
+                    last_activity = Instant::now();
+
                     // Global Ctrl+C: emergency exit from any mode
                     if key.code == KeyCode::Char('c')
                         && key.modifiers.contains(KeyModifiers::CONTROL)
                     {
                         cleanup_terminal(inside_tmux)?;
+                        // Nothing is listed while locked; keep what was saved before
+                        if !matches!(mode, Mode::Locked(..)) {
+                            save_ui_state(
+                                &input,
+                                filtered.get(selected_idx).map(|(_, idx)| servers[*idx].id),
+                                sort,
+                            );
+                        }
                         return Ok(());
                     }
 
                     match &mut mode {
                         Mode::Browse => match key.code {
+                            KeyCode::Char(c) if read_only && is_editing_key(c) => {
+                                mode = Mode::Message(
+                                    "Read-only vault: run without --read-only to make changes"
+                                        .to_string(),
+                                    Instant::now(),
+                                );
+                            }
+                            KeyCode::Char('?') => {
+                                mode = Mode::Help(0);
+                            }
                             KeyCode::Char('/') => {
                                 mode = Mode::Filter;
                             }
                             KeyCode::Char('a') => {
-                                mode = Mode::Add(AddForm::default());
+                                mode = Mode::Add(ServerForm::new(config));
+                            }
+                            KeyCode::Char('s') => {
+                                let selected_id =
+                                    filtered.get(selected_idx).map(|(_, idx)| servers[*idx].id);
+                                let order = sort.map_or(SortOrder::Name, |sort| sort.order.next());
+                                sort = Some(ListSort::ascending(order));
+                                filtered = make_filtered(&input, &servers, sort, group_view);
+                                selected_idx = selected_id
+                                    .and_then(|id| {
+                                        filtered.iter().position(|(_, idx)| servers[*idx].id == id)
+                                    })
+                                    .unwrap_or(0);
+                            }
+                            KeyCode::Char(key @ '1'..='4') => {
+                                let order = match key {
+                                    '1' => SortOrder::Name,
+                                    '2' => SortOrder::Host,
+                                    '3' => SortOrder::Port,
+                                    _ => SortOrder::Recent,
+                                };
+                                let selected_id =
+                                    filtered.get(selected_idx).map(|(_, idx)| servers[*idx].id);
+                                // The active key again flips the direction
+                                sort = Some(match sort {
+                                    Some(current) if current.order == order => current.reversed(),
+                                    _ => ListSort::ascending(order),
+                                });
+                                filtered = make_filtered(&input, &servers, sort, group_view);
+                                selected_idx = selected_id
+                                    .and_then(|id| {
+                                        filtered.iter().position(|(_, idx)| servers[*idx].id == id)
+                                    })
+                                    .unwrap_or(0);
+                            }
+                            KeyCode::Char('g') => {
+                                let selected_id =
+                                    filtered.get(selected_idx).map(|(_, idx)| servers[*idx].id);
+                                group_view = !group_view;
+                                filtered = make_filtered(&input, &servers, sort, group_view);
+                                selected_idx = selected_id
+                                    .and_then(|id| {
+                                        filtered.iter().position(|(_, idx)| servers[*idx].id == id)
+                                    })
+                                    .unwrap_or(0);
                             }
                             KeyCode::Char('e') => {
                                 if let Some((_, idx)) = filtered.get(selected_idx) {
-                                    let s = &servers[*idx];
-                                    let form = EditForm {
-                                        id: s.id,
-                                        name: s.name.clone(),
-                                        host: s.host.clone(),
-                                        port: s.port.to_string(),
-                                        username: s.username.clone(),
-                                        password: String::new(),
-                                        identity_file: s.identity_file.clone().unwrap_or_default(),
-                                        forward_agent: s.forward_agent,
-                                        description: s.description.clone().unwrap_or_default(),
-                                        step: 0,
+                                    mode = Mode::Edit(ServerForm::for_server(
+                                        &servers[*idx],
+                                        &servers,
+                                    ));
+                                }
+                            }
+                            KeyCode::Char('u') => {
+                                let selected_id =
+                                    filtered.get(selected_idx).map(|(_, idx)| servers[*idx].id);
+                                let message = match vault.undo() {
+                                    Ok(Some(label)) => {
+                                        servers = vault.list_servers()?.clone();
+                                        filtered =
+                                            make_filtered(&input, &servers, sort, group_view);
+                                        if let Some(pos) = selected_id.and_then(|id| {
+                                            filtered
+                                                .iter()
+                                                .position(|(_, idx)| servers[*idx].id == id)
+                                        }) {
+                                            selected_idx = pos;
+                                        }
+                                        clamp_selection(&mut selected_idx, filtered.len());
+                                        format!("Undid: {label}")
+                                    }
+                                    Ok(None) => "Nothing to undo".to_string(),
+                                    Err(e) => format!("Undo failed: {e}"),
+                                };
+                                mode = Mode::Message(message, Instant::now());
+                            }
+                            KeyCode::Char('c') => {
+                                if let Some((_, idx)) = filtered.get(selected_idx) {
+                                    let id = servers[*idx].id;
+                                    match vault.clone_server(&id) {
+                                        Ok(Some(new_id)) => {
+                                            servers = vault.list_servers()?.clone();
+                                            filtered =
+                                                make_filtered(&input, &servers, sort, group_view);
+                                            if let Some(pos) = filtered
+                                                .iter()
+                                                .position(|(_, idx)| servers[*idx].id == new_id)
+                                            {
+                                                selected_idx = pos;
+                                            }
+                                            clamp_selection(&mut selected_idx, filtered.len());
+                                            if let Some(copy) =
+                                                servers.iter().find(|s| s.id == new_id)
+                                            {
+                                                mode = Mode::Edit(ServerForm::for_server(
+                                                    copy, &servers,
+                                                ));
+                                            }
+                                        }
+                                        Ok(None) => {}
+                                        Err(e) => {
+                                            mode = Mode::Message(
+                                                format!("Clone failed: {e}"),
+                                                Instant::now(),
+                                            );
+                                        }
+                                    }
+                                }
+                            }
+                            KeyCode::Char('C') => {
+                                if let Some((_, idx)) = filtered.get(selected_idx) {
+                                    let server = &servers[*idx];
+                                    let message = vault
+                                        .gateway_chain(server)
+                                        .map(|gateways| {
+                                            ssh::ssh_command_line(
+                                                server,
+                                                &ssh::ConnectOptions {
+                                                    gateways,
+                                                    host_key_policy,
+                                                    ..Default::default()
+                                                },
+                                            )
+                                        })
+                                        .and_then(|command| clipboard::copy(&command));
+                                    let message = match message {
+                                        Ok(()) => "SSH command copied to clipboard".to_string(),
+                                        Err(e) => format!("Copy failed: {e}"),
+                                    };
+                                    mode = Mode::Message(message, Instant::now());
+                                }
+                            }
+                            KeyCode::Char('r') => {
+                                health.check(visible.iter().map(|idx| &servers[*idx]), true);
+                                mode = Mode::Message(
+                                    format!("Checking {} server(s)...", visible.len()),
+                                    Instant::now(),
+                                );
+                            }
+                            KeyCode::Char('T') => {
+                                if let Some((_, idx)) = filtered.get(selected_idx) {
+                                    let server = &servers[*idx];
+                                    let message = match ssh::probe_tcp(server, ssh::PROBE_TIMEOUT) {
+                                        Ok(latency) => format!(
+                                            "{} reachable ({} ms)",
+                                            server.name,
+                                            latency.as_millis()
+                                        ),
+                                        Err(e) => format!("{}: {e}", server.name),
+                                    };
+                                    mode = Mode::Message(message, Instant::now());
+                                }
+                            }
+                            KeyCode::Char('y') => {
+                                if let Some((_, idx)) = filtered.get(selected_idx) {
+                                    let server = &servers[*idx];
+                                    let message = if !server.has_password() {
+                                        format!("No password stored for '{}'", server.name)
+                                    } else {
+                                        let copied = vault.decrypt_password(server).and_then(
+                                            |mut password| {
+                                                let copied = clipboard::copy_secret(
+                                                    &password,
+                                                    clipboard::CLEAR_AFTER,
+                                                );
+                                                password.zeroize();
+                                                copied
+                                            },
+                                        );
+                                        match copied {
+                                            Ok(()) => format!(
+                                                "Password copied (clears in {}s)",
+                                                clipboard::CLEAR_AFTER.as_secs()
+                                            ),
+                                            Err(e) => format!("Copy failed: {e}"),
+                                        }
+                                    };
+                                    mode = Mode::Message(message, Instant::now());
+                                }
+                            }
+                            KeyCode::Char('Y') => {
+                                if let Some((_, idx)) = filtered.get(selected_idx) {
+                                    let server = &servers[*idx];
+                                    let message =
+                                        if !server.has_password() && !server.has_sudo_password() {
+                                            format!("No password stored for '{}'", server.name)
+                                        } else {
+                                            let copied = vault
+                                                .decrypt_sudo_password(server)
+                                                .and_then(|mut password| {
+                                                    let copied = clipboard::copy_secret(
+                                                        &password,
+                                                        clipboard::CLEAR_AFTER,
+                                                    );
+                                                    password.zeroize();
+                                                    copied
+                                                });
+                                            match copied {
+                                                Ok(()) => format!(
+                                                    "Sudo password copied (clears in {}s)",
+                                                    clipboard::CLEAR_AFTER.as_secs()
+                                                ),
+                                                Err(e) => format!("Copy failed: {e}"),
+                                            }
+                                        };
+                                    mode = Mode::Message(message, Instant::now());
+                                }
+                            }
+                            KeyCode::Char('p') => {
+                                if let Some((_, idx)) = filtered.get(selected_idx) {
+                                    let server = &servers[*idx];
+                                    mode = if !server.has_password() {
+                                        Mode::Message(
+                                            format!("No password stored for '{}'", server.name),
+                                            Instant::now(),
+                                        )
+                                    } else {
+                                        match vault.decrypt_password(server) {
+                                            Ok(password) => Mode::Reveal(
+                                                server.name.clone(),
+                                                password,
+                                                Instant::now(),
+                                            ),
+                                            Err(e) => Mode::Message(
+                                                format!("Could not reveal the password: {e}"),
+                                                Instant::now(),
+                                            ),
+                                        }
                                     };
-                                    mode = Mode::Edit(form);
                                 }
                             }
                             KeyCode::Char('x') | KeyCode::Char('d') => {
                                 if let Some((_, idx)) = filtered.get(selected_idx) {
-                                    mode = Mode::ConfirmDelete(servers[*idx].id);
+                                    let server = &servers[*idx];
+                                    mode = Mode::ConfirmDelete(
+                                        server.id,
+                                        server.protected.then(String::new),
+                                    );
+                                }
+                            }
+                            KeyCode::Char(' ') => {
+                                if let Some((_, idx)) = filtered.get(selected_idx) {
+                                    let id = servers[*idx].id;
+                                    if !marked.remove(&id) {
+                                        marked.insert(id);
+                                    }
+                                    if selected_idx + 1 < filtered.len() {
+                                        selected_idx += 1;
+                                    }
+                                }
+                            }
+                            KeyCode::Char('X') => {
+                                let (protected, deletable): (Vec<&Server>, Vec<&Server>) = servers
+                                    .iter()
+                                    .filter(|s| marked.contains(&s.id))
+                                    .partition(|s| s.protected);
+                                mode = if marked.is_empty() {
+                                    Mode::Message(
+                                        "Mark servers with space first".to_string(),
+                                        Instant::now(),
+                                    )
+                                } else if deletable.is_empty() {
+                                    Mode::Message(
+                                        "All marked servers are protected; nothing to delete"
+                                            .to_string(),
+                                        Instant::now(),
+                                    )
+                                } else {
+                                    Mode::ConfirmBatchDelete(
+                                        deletable.iter().map(|s| s.id).collect(),
+                                        protected.len(),
+                                    )
+                                };
+                            }
+                            KeyCode::Char('i') => {
+                                show_info = !show_info;
+                            }
+                            KeyCode::Char('N') => {
+                                if let Some((_, idx)) = filtered.get(selected_idx) {
+                                    mode = Mode::Notes(NotesEditor::new(&servers[*idx]));
+                                }
+                            }
+                            KeyCode::Char('P') => {
+                                if let Some((_, idx)) = filtered.get(selected_idx) {
+                                    let mut server = servers[*idx].clone();
+                                    server.protected = !server.protected;
+                                    let message = match vault.replace_server(server.clone()) {
+                                        Ok(_) => {
+                                            servers = vault.list_servers()?.clone();
+                                            filtered =
+                                                make_filtered(&input, &servers, sort, group_view);
+                                            if server.protected {
+                                                format!("'{}' is now protected", server.name)
+                                            } else {
+                                                format!("'{}' is no longer protected", server.name)
+                                            }
+                                        }
+                                        Err(e) => format!("Update failed: {e}"),
+                                    };
+                                    mode = Mode::Message(message, Instant::now());
                                 }
                             }
                             // Arrow key navigation
@@ -329,13 +1697,13 @@ pub fn run_full_ui(vault: &mut Vault) -> anyhow::Result<()> {
                             // Page navigation
                             KeyCode::PageUp => {
                                 if !filtered.is_empty() {
-                                    selected_idx = selected_idx.saturating_sub(10);
+                                    selected_idx = selected_idx.saturating_sub(page_size);
                                 }
                             }
                             KeyCode::PageDown => {
                                 if !filtered.is_empty() {
                                     selected_idx =
-                                        (selected_idx + 10).min(filtered.len().saturating_sub(1));
+                                        page_down(selected_idx, page_size, filtered.len());
                                 }
                             }
                             KeyCode::Home => {
@@ -349,36 +1717,54 @@ pub fn run_full_ui(vault: &mut Vault) -> anyhow::Result<()> {
                             KeyCode::Enter => {
                                 if let Some((_, idx)) = filtered.get(selected_idx) {
                                     // Clone server data before tearing down terminal
-                                    let server = servers[*idx].clone();
-
-                                    // Fully clean up terminal state
-                                    cleanup_terminal(inside_tmux)?;
-                                    // Drop old terminal to release stdout handle
-                                    drop(terminal);
+                                    let prepared =
+                                        vault.with_password(&servers[*idx]).and_then(|server| {
+                                            let gateways = vault.gateway_chain(&server)?;
+                                            Ok((server, gateways))
+                                        });
+                                    let (mut server, options) = match prepared {
+                                        Ok((server, gateways)) => (
+                                            server,
+                                            ssh::ConnectOptions {
+                                                gateways,
+                                                host_key_policy,
+                                                ..Default::default()
+                                            },
+                                        ),
+                                        Err(e) => {
+                                            mode = Mode::Message(
+                                                format!("Connection failed: {e}"),
+                                                Instant::now(),
+                                            );
+                                            continue;
+                                        }
+                                    };
 
                                     // Run SSH (blocking, inherits stdio)
-                                    let connection_result = ssh::connect(&server);
-
-                                    // Rebuild terminal from scratch
-                                    enable_raw_mode()?;
-                                    let mut stdout = io::stdout();
-                                    crossterm::execute!(
-                                        stdout,
-                                        crossterm::terminal::EnterAlternateScreen
+                                    let (new_terminal, connection_result) = run_outside_ui(
+                                        terminal,
+                                        inside_tmux,
+                                        || {
+                                            let result =
+                                                ssh::connect_with(&server, &options, || {
+                                                    vault.mark_connected(&server.id)
+                                                });
+                                            if let Err(e) =
+                                                vault.record_connection(&server.id, result.is_ok())
+                                            {
+                                                eprintln!(
+                                                    "Warning: could not record this connection in the history: {e}"
+                                                );
+                                            }
+                                            result
+                                        },
                                     )?;
-                                    if !inside_tmux {
-                                        crossterm::execute!(
-                                            stdout,
-                                            crossterm::event::EnableMouseCapture
-                                        )?;
-                                    }
-                                    let backend = CrosstermBackend::new(stdout);
-                                    terminal = Terminal::new(backend)?;
-                                    terminal.clear()?;
+                                    terminal = new_terminal;
+                                    server.password.zeroize();
 
                                     // Reload servers in case vault changed externally
                                     servers = vault.list_servers()?.clone();
-                                    filtered = make_filtered(&input, &servers);
+                                    filtered = make_filtered(&input, &servers, sort, group_view);
                                     clamp_selection(&mut selected_idx, filtered.len());
                                     if let Err(e) = connection_result {
                                         mode = Mode::Message(
@@ -388,30 +1774,130 @@ pub fn run_full_ui(vault: &mut Vault) -> anyhow::Result<()> {
                                     }
                                 }
                             }
+                            KeyCode::Char('t') => {
+                                if let Some((_, idx)) = filtered.get(selected_idx) {
+                                    let prepared =
+                                        vault.with_password(&servers[*idx]).and_then(|server| {
+                                            let gateways = vault.gateway_chain(&server)?;
+                                            Ok((server, gateways))
+                                        });
+                                    let (mut server, options) = match prepared {
+                                        Ok((server, gateways)) => (
+                                            server,
+                                            ssh::ConnectOptions {
+                                                gateways,
+                                                host_key_policy,
+                                                ..Default::default()
+                                            },
+                                        ),
+                                        Err(e) => {
+                                            mode = Mode::Message(
+                                                format!("SFTP failed: {e}"),
+                                                Instant::now(),
+                                            );
+                                            continue;
+                                        }
+                                    };
+
+                                    let (new_terminal, sftp_result) =
+                                        run_outside_ui(terminal, inside_tmux, || {
+                                            ssh::sftp(&server, &options)
+                                        })?;
+                                    terminal = new_terminal;
+                                    server.password.zeroize();
+                                    if let Err(e) = sftp_result {
+                                        mode = Mode::Message(
+                                            format!("SFTP failed: {e}"),
+                                            Instant::now(),
+                                        );
+                                    }
+                                }
+                            }
+                            KeyCode::Char('o') => {
+                                if let Some((_, idx)) = filtered.get(selected_idx) {
+                                    let server = &servers[*idx];
+                                    let message = match ssh::open_in_file_manager(server) {
+                                        Ok(()) => format!("Opened {}", server.sftp_uri()),
+                                        Err(e) => format!("Open failed: {e}"),
+                                    };
+                                    mode = Mode::Message(message, Instant::now());
+                                }
+                            }
                             KeyCode::Char('q') | KeyCode::Esc => {
+                                mode = Mode::ConfirmQuit(Instant::now());
+                            }
+                            KeyCode::Char('L') => {
+                                if vault.is_encrypted() {
+                                    lock_now = true;
+                                } else {
+                                    mode = Mode::Message(
+                                        "This vault has no master password to lock".to_string(),
+                                        Instant::now(),
+                                    );
+                                }
+                            }
+                            // Shift+Q quits without asking
+                            KeyCode::Char('Q') => {
                                 cleanup_terminal(inside_tmux)?;
+                                save_ui_state(
+                                    &input,
+                                    filtered.get(selected_idx).map(|(_, idx)| servers[*idx].id),
+                                    sort,
+                                );
                                 return Ok(());
                             }
                             _ => {}
                         },
+                        Mode::Help(scroll) => match key.code {
+                            KeyCode::Up | KeyCode::Char('k') => {
+                                *scroll = scroll.saturating_sub(1);
+                            }
+                            KeyCode::Down | KeyCode::Char('j') => {
+                                *scroll = (*scroll + 1).min(keybindings.len() as u16 - 1);
+                            }
+                            KeyCode::PageUp => {
+                                *scroll = scroll.saturating_sub(10);
+                            }
+                            KeyCode::PageDown => {
+                                *scroll = (*scroll + 10).min(keybindings.len() as u16 - 1);
+                            }
+                            _ => {
+                                mode = Mode::Browse;
+                            }
+                        },
+                        Mode::ConfirmQuit(_) => match key.code {
+                            code if confirms_quit(code) => {
+                                cleanup_terminal(inside_tmux)?;
+                                save_ui_state(
+                                    &input,
+                                    filtered.get(selected_idx).map(|(_, idx)| servers[*idx].id),
+                                    sort,
+                                );
+                                return Ok(());
+                            }
+                            // Anything else stays, without acting on the key
+                            _ => {
+                                mode = Mode::Browse;
+                            }
+                        },
                         Mode::Filter => match key.code {
                             KeyCode::Enter => {
                                 mode = Mode::Browse;
                             }
                             KeyCode::Esc => {
                                 input.clear();
-                                filtered = make_filtered("", &servers);
+                                filtered = make_filtered("", &servers, sort, group_view);
                                 clamp_selection(&mut selected_idx, filtered.len());
                                 mode = Mode::Browse;
                             }
                             KeyCode::Backspace => {
                                 input.pop();
-                                filtered = make_filtered(&input, &servers);
+                                filtered = make_filtered(&input, &servers, sort, group_view);
                                 clamp_selection(&mut selected_idx, filtered.len());
                             }
                             KeyCode::Delete => {
                                 input.clear();
-                                filtered = make_filtered("", &servers);
+                                filtered = make_filtered("", &servers, sort, group_view);
                                 clamp_selection(&mut selected_idx, filtered.len());
                             }
                             KeyCode::Up => {
@@ -427,13 +1913,13 @@ pub fn run_full_ui(vault: &mut Vault) -> anyhow::Result<()> {
                             }
                             KeyCode::PageUp => {
                                 if !filtered.is_empty() {
-                                    selected_idx = selected_idx.saturating_sub(10);
+                                    selected_idx = selected_idx.saturating_sub(page_size);
                                 }
                             }
                             KeyCode::PageDown => {
                                 if !filtered.is_empty() {
                                     selected_idx =
-                                        (selected_idx + 10).min(filtered.len().saturating_sub(1));
+                                        page_down(selected_idx, page_size, filtered.len());
                                 }
                             }
                             KeyCode::Home => {
@@ -446,213 +1932,98 @@ pub fn run_full_ui(vault: &mut Vault) -> anyhow::Result<()> {
                             }
                             KeyCode::Char(c) => {
                                 input.push(c);
-                                filtered = make_filtered(&input, &servers);
+                                filtered = make_filtered(&input, &servers, sort, group_view);
                                 clamp_selection(&mut selected_idx, filtered.len());
                             }
                             _ => {}
                         },
-                        Mode::Add(form) => match key.code {
-                            KeyCode::Esc => {
+                        Mode::Add(form) | Mode::Edit(form) => match form.handle_key(key) {
+                            FormEvent::Pending => {}
+                            FormEvent::Cancel => {
                                 mode = Mode::Browse;
                             }
-                            KeyCode::BackTab => {
-                                if form.step > 0 {
-                                    form.step -= 1;
-                                }
-                            }
-                            KeyCode::Tab => {
-                                form.step = (form.step + 1).min(7);
-                            }
-                            KeyCode::Enter => {
-                                form.step += 1;
-                                if form.step > 7 {
-                                    // finalize and add
-                                    match form.port.parse::<u16>() {
-                                        Ok(port) => {
-                                            let mut server = Server::new(
-                                                form.name.clone(),
-                                                form.host.clone(),
-                                                port,
-                                                form.username.clone(),
-                                                form.password.clone(),
-                                                if form.description.is_empty() {
-                                                    None
-                                                } else {
-                                                    Some(form.description.clone())
-                                                },
-                                            );
-                                            server.identity_file = if form.identity_file.is_empty()
-                                            {
-                                                None
-                                            } else {
-                                                Some(form.identity_file.clone())
-                                            };
-                                            server.forward_agent = form.forward_agent;
-                                            if let Err(e) = vault.add_server(server) {
-                                                mode = Mode::Message(
-                                                    format!("Add failed: {e}"),
-                                                    Instant::now(),
-                                                );
-                                            } else {
-                                                servers = vault.list_servers()?.clone();
-                                                filtered = make_filtered(&input, &servers);
-                                                clamp_selection(&mut selected_idx, filtered.len());
-                                                mode = Mode::Message(
-                                                    "Server added".to_string(),
-                                                    Instant::now(),
-                                                );
-                                            }
-                                        }
-                                        Err(_) => {
-                                            mode = Mode::Message(
-                                                "Invalid port".to_string(),
-                                                Instant::now(),
-                                            );
+                            FormEvent::Submit => {
+                                let editing = form.id.is_some();
+                                let result = form.build(&servers).and_then(|server| {
+                                    if editing {
+                                        match vault.replace_server(server) {
+                                            Ok(true) => Ok("Server updated".to_string()),
+                                            Ok(false) => Err("Server not found".to_string()),
+                                            Err(e) => Err(format!("Update failed: {e}")),
                                         }
+                                    } else {
+                                        vault
+                                            .add_server(server)
+                                            .map(|_| "Server added".to_string())
+                                            .map_err(|e| match e.downcast_ref::<VaultError>() {
+                                                Some(
+                                                    duplicate @ VaultError::DuplicateServer(_),
+                                                ) => format!("{duplicate}; not added"),
+                                                _ => format!("Add failed: {e}"),
+                                            })
                                     }
-                                }
-                            }
-                            KeyCode::Backspace => {
-                                if form.step == 6 {
-                                    form.forward_agent = false;
-                                } else {
-                                    let target = match form.step {
-                                        0 => &mut form.name,
-                                        1 => &mut form.host,
-                                        2 => &mut form.port,
-                                        3 => &mut form.username,
-                                        4 => &mut form.password,
-                                        5 => &mut form.identity_file,
-                                        7 => &mut form.description,
-                                        _ => &mut form.name,
-                                    };
-                                    target.pop();
-                                }
-                            }
-                            KeyCode::Delete => {
-                                if form.step == 6 {
-                                    form.forward_agent = false;
-                                } else {
-                                    let target = match form.step {
-                                        0 => &mut form.name,
-                                        1 => &mut form.host,
-                                        2 => &mut form.port,
-                                        3 => &mut form.username,
-                                        4 => &mut form.password,
-                                        5 => &mut form.identity_file,
-                                        7 => &mut form.description,
-                                        _ => &mut form.name,
-                                    };
-                                    target.clear();
-                                }
-                            }
-                            KeyCode::Char(c) => {
-                                if form.step == 6 {
-                                    match c {
-                                        ' ' => form.forward_agent = !form.forward_agent,
-                                        'y' | 'Y' | 't' | 'T' | '1' => form.forward_agent = true,
-                                        'n' | 'N' | 'f' | 'F' | '0' => form.forward_agent = false,
-                                        _ => {}
+                                });
+                                let message = match result {
+                                    Ok(message) => {
+                                        servers = vault.list_servers()?.clone();
+                                        filtered =
+                                            make_filtered(&input, &servers, sort, group_view);
+                                        clamp_selection(&mut selected_idx, filtered.len());
+                                        message
                                     }
-                                } else {
-                                    let target = match form.step {
-                                        0 => &mut form.name,
-                                        1 => &mut form.host,
-                                        2 => &mut form.port,
-                                        3 => &mut form.username,
-                                        4 => &mut form.password,
-                                        5 => &mut form.identity_file,
-                                        7 => &mut form.description,
-                                        _ => &mut form.name,
-                                    };
-                                    target.push(c);
-                                }
+                                    Err(message) => message,
+                                };
+                                mode = Mode::Message(message, Instant::now());
                             }
-                            _ => {}
                         },
-                        Mode::Edit(form) => match key.code {
-                            KeyCode::Esc => {
+                        Mode::Notes(editor) => match editor.handle_key(key) {
+                            FormEvent::Pending => {}
+                            FormEvent::Cancel => {
                                 mode = Mode::Browse;
                             }
-                            KeyCode::BackTab => {
-                                if form.step > 0 {
-                                    form.step -= 1;
-                                }
-                            }
-                            KeyCode::Tab => {
-                                form.step = (form.step + 1).min(7);
+                            FormEvent::Submit => {
+                                let updated = servers.iter().find(|s| s.id == editor.id).map(|s| {
+                                    let mut server = s.clone();
+                                    server.notes = editor.notes();
+                                    server.updated_at = chrono::Utc::now();
+                                    server
+                                });
+                                let message =
+                                    match updated.map(|server| vault.replace_server(server)) {
+                                        Some(Ok(true)) => {
+                                            servers = vault.list_servers()?.clone();
+                                            filtered =
+                                                make_filtered(&input, &servers, sort, group_view);
+                                            format!("Notes saved for '{}'", editor.name)
+                                        }
+                                        Some(Ok(false)) | None => "Server not found".to_string(),
+                                        Some(Err(e)) => format!("Update failed: {e}"),
+                                    };
+                                mode = Mode::Message(message, Instant::now());
                             }
+                        },
+                        Mode::ConfirmDelete(id, Some(typed)) => match key.code {
                             KeyCode::Enter => {
-                                form.step += 1;
-                                if form.step > 7 {
-                                    // finalize and update
-                                    match form.port.parse::<u16>() {
-                                        Ok(port) => {
-                                            // find original
-                                            if let Some(pos) =
-                                                servers.iter().position(|s| s.id == form.id)
-                                            {
-                                                let mut updated = servers[pos].clone();
-                                                let password = if form.password.is_empty() {
-                                                    updated.password.clone()
-                                                } else {
-                                                    form.password.clone()
-                                                };
-                                                updated.update_fields(
-                                                    form.name.clone(),
-                                                    form.host.clone(),
-                                                    port,
-                                                    form.username.clone(),
-                                                    password,
-                                                    if form.description.is_empty() {
-                                                        None
-                                                    } else {
-                                                        Some(form.description.clone())
-                                                    },
-                                                );
-                                                updated.identity_file =
-                                                    if form.identity_file.is_empty() {
-                                                        None
-                                                    } else {
-                                                        Some(form.identity_file.clone())
-                                                    };
-                                                updated.forward_agent = form.forward_agent;
-                                                match vault.replace_server(updated) {
-                                                    Ok(true) => {
-                                                        servers = vault.list_servers()?.clone();
-                                                        filtered = make_filtered(&input, &servers);
-                                                        clamp_selection(
-                                                            &mut selected_idx,
-                                                            filtered.len(),
-                                                        );
-                                                        mode = Mode::Message(
-                                                            "Server updated".to_string(),
-                                                            Instant::now(),
-                                                        );
-                                                    }
-                                                    Ok(false) => {
-                                                        mode = Mode::Message(
-                                                            "Server not found".to_string(),
-                                                            Instant::now(),
-                                                        );
-                                                    }
-                                                    Err(e) => {
-                                                        mode = Mode::Message(
-                                                            format!("Update failed: {e}"),
-                                                            Instant::now(),
-                                                        );
-                                                    }
-                                                }
-                                            } else {
-                                                mode = Mode::Message(
-                                                    "Server not found".to_string(),
-                                                    Instant::now(),
-                                                );
-                                            }
+                                let matches = servers
+                                    .iter()
+                                    .any(|s| &s.id == id && s.confirms_removal(typed));
+                                if !matches {
+                                    mode = Mode::Message(
+                                        "Name did not match; nothing deleted".to_string(),
+                                        Instant::now(),
+                                    );
+                                } else {
+                                    match vault.remove_server(id) {
+                                        Ok(_) => {
+                                            servers = vault.list_servers()?.clone();
+                                            filtered =
+                                                make_filtered(&input, &servers, sort, group_view);
+                                            clamp_selection(&mut selected_idx, filtered.len());
+                                            mode = Mode::Browse;
                                         }
-                                        Err(_) => {
+                                        Err(e) => {
                                             mode = Mode::Message(
-                                                "Invalid port".to_string(),
+                                                format!("Delete failed: {e}"),
                                                 Instant::now(),
                                             );
                                         }
@@ -660,68 +2031,21 @@ pub fn run_full_ui(vault: &mut Vault) -> anyhow::Result<()> {
                                 }
                             }
                             KeyCode::Backspace => {
-                                if form.step == 6 {
-                                    form.forward_agent = false;
-                                } else {
-                                    let target = match form.step {
-                                        0 => &mut form.name,
-                                        1 => &mut form.host,
-                                        2 => &mut form.port,
-                                        3 => &mut form.username,
-                                        4 => &mut form.password,
-                                        5 => &mut form.identity_file,
-                                        7 => &mut form.description,
-                                        _ => &mut form.name,
-                                    };
-                                    target.pop();
-                                }
-                            }
-                            KeyCode::Delete => {
-                                if form.step == 6 {
-                                    form.forward_agent = false;
-                                } else {
-                                    let target = match form.step {
-                                        0 => &mut form.name,
-                                        1 => &mut form.host,
-                                        2 => &mut form.port,
-                                        3 => &mut form.username,
-                                        4 => &mut form.password,
-                                        5 => &mut form.identity_file,
-                                        7 => &mut form.description,
-                                        _ => &mut form.name,
-                                    };
-                                    target.clear();
-                                }
+                                typed.pop();
                             }
                             KeyCode::Char(c) => {
-                                if form.step == 6 {
-                                    match c {
-                                        ' ' => form.forward_agent = !form.forward_agent,
-                                        'y' | 'Y' | 't' | 'T' | '1' => form.forward_agent = true,
-                                        'n' | 'N' | 'f' | 'F' | '0' => form.forward_agent = false,
-                                        _ => {}
-                                    }
-                                } else {
-                                    let target = match form.step {
-                                        0 => &mut form.name,
-                                        1 => &mut form.host,
-                                        2 => &mut form.port,
-                                        3 => &mut form.username,
-                                        4 => &mut form.password,
-                                        5 => &mut form.identity_file,
-                                        7 => &mut form.description,
-                                        _ => &mut form.name,
-                                    };
-                                    target.push(c);
-                                }
+                                typed.push(c);
+                            }
+                            KeyCode::Esc => {
+                                mode = Mode::Browse;
                             }
                             _ => {}
                         },
-                        Mode::ConfirmDelete(id) => match key.code {
+                        Mode::ConfirmDelete(id, None) => match key.code {
                             KeyCode::Char('y') => match vault.remove_server(id) {
                                 Ok(_) => {
                                     servers = vault.list_servers()?.clone();
-                                    filtered = make_filtered(&input, &servers);
+                                    filtered = make_filtered(&input, &servers, sort, group_view);
                                     clamp_selection(&mut selected_idx, filtered.len());
                                     mode = Mode::Browse;
                                 }
@@ -737,26 +2061,74 @@ pub fn run_full_ui(vault: &mut Vault) -> anyhow::Result<()> {
                             }
                             _ => {}
                         },
+                        Mode::ConfirmBatchDelete(ids, _) => match key.code {
+                            KeyCode::Char('y') => {
+                                let message = match vault.remove_servers(ids) {
+                                    Ok(removed) => {
+                                        marked.clear();
+                                        servers = vault.list_servers()?.clone();
+                                        filtered =
+                                            make_filtered(&input, &servers, sort, group_view);
+                                        clamp_selection(&mut selected_idx, filtered.len());
+                                        format!("Deleted {} server(s)", removed.len())
+                                    }
+                                    Err(e) => format!("Delete failed: {e}"),
+                                };
+                                mode = Mode::Message(message, Instant::now());
+                            }
+                            KeyCode::Char('n') | KeyCode::Esc => {
+                                mode = Mode::Browse;
+                            }
+                            _ => {}
+                        },
                         Mode::Message(_, since) => {
                             // any key returns to browse
                             *since = Instant::now();
                             mode = Mode::Browse;
                         }
+                        Mode::Reveal(_, password, _) => {
+                            // any key (including `p` again) hides it
+                            password.zeroize();
+                            mode = Mode::Browse;
+                        }
+                        Mode::Locked(password, error) => match key.code {
+                            // Unlocked after the next frame has shown this
+                            KeyCode::Enter if !unlock_pending => {
+                                *error = Some("deriving key…".to_string());
+                                unlock_pending = true;
+                            }
+                            _ if unlock_pending => {}
+                            KeyCode::Backspace => {
+                                password.pop();
+                            }
+                            KeyCode::Char(c) if key_file.is_none() => {
+                                password.push(c);
+                            }
+                            KeyCode::Esc => {
+                                cleanup_terminal(inside_tmux)?;
+                                return Ok(());
+                            }
+                            _ => {}
+                        },
                     }
                 }
-                Event::Mouse(mouse_event) => match mouse_event.kind {
-                    MouseEventKind::ScrollUp => {
-                        if !filtered.is_empty() {
-                            selected_idx = selected_idx.saturating_sub(3);
+                Event::Mouse(mouse_event) => {
+                    last_activity = Instant::now();
+                    match mouse_event.kind {
+                        MouseEventKind::ScrollUp => {
+                            if !filtered.is_empty() {
+                                selected_idx = selected_idx.saturating_sub(3);
+                            }
                         }
-                    }
-                    MouseEventKind::ScrollDown => {
-                        if !filtered.is_empty() {
-                            selected_idx = (selected_idx + 3).min(filtered.len().saturating_sub(1));
+                        MouseEventKind::ScrollDown => {
+                            if !filtered.is_empty() {
+                                selected_idx =
+                                    (selected_idx + 3).min(filtered.len().saturating_sub(1));
+                            }
                         }
+                        _ => {}
                     }
-                    _ => {}
-                },
+                }
                 Event::Resize(_width, _height) => {
                     // Force a full clear so the next draw() picks up the new dimensions
                     // without leftover artifacts from the old size.
@@ -766,12 +2138,47 @@ pub fn run_full_ui(vault: &mut Vault) -> anyhow::Result<()> {
             }
         }
 
+        // Drop the key and every decrypted copy once the session sits idle
+        // or the user asked for it with `L`
+        let idle = lock_timeout.is_some_and(|timeout| last_activity.elapsed() >= timeout);
+        if (idle || lock_now) && vault.is_encrypted() && !matches!(mode, Mode::Locked(..)) {
+            if let Mode::Add(form) | Mode::Edit(form) = &mut mode {
+                form.password.zeroize();
+                form.sudo_password.zeroize();
+            }
+            if let Mode::Reveal(_, password, _) = &mut mode {
+                password.zeroize();
+            }
+            if let Mode::Notes(editor) = &mut mode {
+                editor.text.zeroize();
+            }
+            for server in &mut servers {
+                server.password.zeroize();
+            }
+            servers.clear();
+            filtered.clear();
+            vault.lock();
+            mode = Mode::Locked(String::new(), None);
+        }
+        lock_now = false;
+
         // auto-clear transient messages
         if let Mode::Message(_, t) = &mode {
             if t.elapsed() > Duration::from_secs(2) {
                 mode = Mode::Browse;
             }
         }
+        if let Mode::ConfirmQuit(t) = &mode {
+            if confirm_quit_seconds_left(t.elapsed()).is_none() {
+                mode = Mode::Browse;
+            }
+        }
+        if let Mode::Reveal(_, password, t) = &mut mode {
+            if reveal_seconds_left(t.elapsed()).is_none() {
+                password.zeroize();
+                mode = Mode::Browse;
+            }
+        }
 
         if last_tick.elapsed() >= tick_rate {
             last_tick = Instant::now();
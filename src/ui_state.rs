@@ -0,0 +1,47 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::models::ListSort;
+use crate::vault::Vault;
+
+pub const UI_STATE_FILE: &str = "ui_state.json";
+
+/// Non-secret TUI state carried between runs: the filter text, the list
+/// order and the selected server. Never holds anything from inside the vault
+/// beyond an id.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UiState {
+    #[serde(default)]
+    pub filter: String,
+    #[serde(default)]
+    pub selected: Option<Uuid>,
+    /// None keeps the vault's own order.
+    #[serde(default)]
+    pub sort: Option<ListSort>,
+}
+
+impl UiState {
+    /// `ui_state.json` in the data directory.
+    pub fn path() -> Result<PathBuf> {
+        Ok(Vault::data_dir()?.join(UI_STATE_FILE))
+    }
+
+    /// Reads the state at `path`; a missing or unreadable file means a fresh start.
+    pub fn load_from(path: &Path) -> Self {
+        fs::read(path)
+            .ok()
+            .and_then(|raw| serde_json::from_slice(&raw).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save_to(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+}
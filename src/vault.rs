@@ -1,16 +1,19 @@
 use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use sodiumoxide::crypto::pwhash::argon2id13;
 use sodiumoxide::crypto::secretbox;
 use std::fs;
 use std::io::Write;
-use std::os::unix::fs::PermissionsExt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
 use uuid::Uuid;
+use zeroize::Zeroize;
 
-use crate::crypto::{generate_salt, MasterKey};
-use crate::models::{Server, VaultData};
+use crate::crypto::{generate_salt, lock_memory, unlock_memory, KdfParams, KeySource, MasterKey};
+use crate::models::{ConnectionEvent, MergeSummary, OnConflict, Server, VaultData, VaultStats};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct VaultFile {
@@ -19,70 +22,493 @@ pub struct VaultFile {
     pub ciphertext: Vec<u8>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    /// Omitted for the interactive defaults, which is what every vault written
+    /// before the cost was configurable used.
+    #[serde(default, skip_serializing_if = "KdfParams::is_interactive")]
+    pub kdf: KdfParams,
+    /// Omitted for password-derived keys, the only kind older vaults have.
+    #[serde(default, skip_serializing_if = "KeySource::is_password")]
+    pub key_source: KeySource,
+    /// Set on the index of a [`StoreLayout::Split`] vault: the ids of the
+    /// server files, in list order. The sealed data then has no servers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub split: Option<Vec<Uuid>>,
+}
+
+/// One server of a split vault, sealed like the vault file itself:
+/// encrypted with the master key, or plain JSON when there is none.
+#[derive(Serialize, Deserialize)]
+struct ServerFile {
+    nonce: secretbox::Nonce,
+    ciphertext: Vec<u8>,
+}
+
+/// Vault errors callers may want to handle rather than just report.
+#[derive(Debug, thiserror::Error)]
+pub enum VaultError {
+    #[error("A server with this host/user/port already exists as '{0}'")]
+    DuplicateServer(String),
+    #[error("The ssh alias '{0}' is already used by '{1}'")]
+    DuplicateAlias(String, String),
+    #[error("Vault does not exist")]
+    NotFound,
+    #[error("Vault is locked")]
+    Locked,
+    #[error("Vault is open read-only")]
+    ReadOnly,
+    #[error("Vault is password protected")]
+    PasswordRequired,
+    #[error("Wrong master password")]
+    WrongPassword,
+    #[error("Vault is keyed with a key file, not a password")]
+    KeyFileRequired,
+    #[error("Vault is keyed with a password, not a key file")]
+    PasswordKeyed,
+    #[error("Key file does not match this vault")]
+    WrongKeyFile,
+    #[error("Vault file is corrupt: {0}")]
+    Corrupt(String),
+    #[error("Vault I/O error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+pub const DEFAULT_VAULT_NAME: &str = "vault";
+/// Environment variable that points [`Vault::new`] at a vault file elsewhere.
+pub const VAULT_PATH_ENV: &str = "PORTKEY_VAULT_PATH";
+/// Environment variable holding the master password for unattended unlocks.
+/// Anything that can read the process environment can read it too.
+pub const PASSWORD_ENV: &str = "PORTKEY_PASSWORD";
+const VAULT_EXTENSION: &str = "dat";
+
+pub const EXPORT_FORMAT: &str = "portkey-export";
+pub const EXPORT_FORMAT_VERSION: u32 = 1;
+
+/// Portable backup written by `portkey export`: a sealed copy of the vault
+/// data under its own password, independent of the local master password.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportFile {
+    pub format: String,
+    pub format_version: u32,
+    pub encrypted: bool,
+    pub vault: VaultFile,
+}
+
+impl ExportFile {
+    pub fn read(path: &Path) -> Result<Self> {
+        let content =
+            fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let export: ExportFile = serde_json::from_slice(&content)
+            .with_context(|| format!("{} is not a portkey export", path.display()))?;
+
+        if export.format != EXPORT_FORMAT {
+            return Err(anyhow::anyhow!(
+                "{} is not a portkey export",
+                path.display()
+            ));
+        }
+        if export.format_version > EXPORT_FORMAT_VERSION {
+            return Err(anyhow::anyhow!(
+                "Export format version {} is newer than this portkey supports ({EXPORT_FORMAT_VERSION}); please upgrade",
+                export.format_version
+            ));
+        }
+        Ok(export)
+    }
+
+    /// Decrypts the export with its own password and brings the data up to
+    /// the current schema.
+    pub fn open(&self, password: Option<&str>) -> Result<VaultData> {
+        let mut data: VaultData = if self.encrypted {
+            let password =
+                password.ok_or_else(|| anyhow::anyhow!("This export is password protected"))?;
+            let key = MasterKey::from_password(password, &self.vault.salt, self.vault.kdf)?;
+            let decrypted = key
+                .decrypt(&self.vault.ciphertext, &self.vault.nonce)
+                .context("Wrong export password")?;
+            parse_plaintext(decrypted).context("Failed to deserialize exported data")?
+        } else {
+            serde_json::from_slice(&self.vault.ciphertext)
+                .context("Failed to deserialize exported data")?
+        };
+
+        data.migrate()?;
+        Ok(data)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct UndoSnapshot {
+    label: String,
+    data: VaultData,
+}
+
+/// Deserializes decrypted bytes, keeping them locked in memory while parsing
+/// and wiping them afterwards.
+fn parse_plaintext<T: DeserializeOwned>(mut plaintext: Vec<u8>) -> serde_json::Result<T> {
+    let locked = lock_memory(&mut plaintext);
+    let parsed = serde_json::from_slice(&plaintext);
+    if locked {
+        // munlock wipes the region before releasing it
+        unlock_memory(&mut plaintext);
+    } else {
+        plaintext.zeroize();
+    }
+    parsed
+}
+
+fn open_server_file(file: &ServerFile, key: Option<&MasterKey>) -> Result<Vec<u8>> {
+    match key {
+        Some(key) => key.decrypt(&file.ciphertext, &file.nonce),
+        None => Ok(file.ciphertext.clone()),
+    }
+}
+
+// The servers `ids` from their files in `dir`, failing on the first that is
+// missing or not sealed with `key`.
+fn read_server_files(
+    dir: &Path,
+    ids: &[Uuid],
+    key: Option<&MasterKey>,
+) -> Result<Vec<Server>, VaultError> {
+    ids.iter()
+        .map(|id| {
+            let path = dir.join(format!("{id}.{VAULT_EXTENSION}"));
+            let corrupt = |reason: String| {
+                VaultError::Corrupt(format!("server file {}: {reason}", path.display()))
+            };
+            let content = fs::read(&path).map_err(|e| corrupt(e.to_string()))?;
+            let file: ServerFile =
+                serde_json::from_slice(&content).map_err(|e| corrupt(e.to_string()))?;
+            let plaintext = open_server_file(&file, key)
+                .map_err(|_| corrupt("not sealed with this vault's key".to_string()))?;
+            parse_plaintext(plaintext).map_err(|e| corrupt(e.to_string()))
+        })
+        .collect()
+}
+
+/// Writes `content` to a private temp file beside `path` and renames it into
+/// place, so a crash leaves either the old file or the new one, never a mix.
+pub(crate) fn write_atomic(path: &Path, content: &[u8]) -> Result<()> {
+    let parent = path
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("Path has no parent directory"))?;
+    fs::create_dir_all(parent)?;
+
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("Path has no file name"))?
+        .to_string_lossy();
+    let temp_path = parent.join(format!(".{file_name}.{}.tmp", Uuid::new_v4()));
+
+    let mut file = fs::OpenOptions::new()
+        .create_new(true)
+        .write(true)
+        .open(&temp_path)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mut perms = file.metadata()?.permissions();
+        perms.set_mode(0o600); // Read/write for owner only
+        file.set_permissions(perms)?;
+    }
+    // Windows has no mode bits to set. The file inherits the ACL of the data
+    // directory, which under the user profile (%APPDATA%) only grants access
+    // to the owner, SYSTEM and administrators; vaults kept elsewhere get
+    // whatever that folder allows.
+
+    // A full disk fails the write or the sync; either way the partial temp
+    // file goes, and so does one that can't be renamed into place
+    let written = file
+        .write_all(content)
+        .and_then(|()| file.sync_all())
+        .and_then(|()| {
+            drop(file);
+            fs::rename(&temp_path, path)
+        });
+    if let Err(error) = written {
+        let _ = fs::remove_file(&temp_path);
+        return Err(error.into());
+    }
+
+    Ok(())
+}
+
+/// How an unencrypted vault is stored on disk. Encrypted vaults are always
+/// written as JSON, whatever the format was when they were created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VaultFormat {
+    #[default]
+    Json,
+    /// The vault data as a plain TOML document, meant for editing by hand.
+    Toml,
+}
+
+impl VaultFormat {
+    /// JSON vault files are always an object, so anything else is TOML.
+    fn detect(content: &[u8]) -> Self {
+        match content.iter().find(|byte| !byte.is_ascii_whitespace()) {
+            Some(b'{') | None => VaultFormat::Json,
+            Some(_) => VaultFormat::Toml,
+        }
+    }
+}
+
+impl FromStr for VaultFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "json" => Ok(VaultFormat::Json),
+            "toml" => Ok(VaultFormat::Toml),
+            _ => Err(format!(
+                "unknown vault format '{value}' (expected json or toml)"
+            )),
+        }
+    }
+}
+
+/// Whether the vault is one file, or an index plus a file per server so a
+/// vault synced with git changes one file when one server changes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StoreLayout {
+    #[default]
+    Single,
+    /// The vault file holds everything but the servers, which are sealed one
+    /// per file in [`Vault::servers_dir`].
+    Split,
+}
+
+impl FromStr for StoreLayout {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "single" => Ok(StoreLayout::Single),
+            "split" => Ok(StoreLayout::Split),
+            _ => Err(format!(
+                "unknown vault layout '{value}' (expected single or split)"
+            )),
+        }
+    }
 }
 
 pub struct Vault {
     data_path: PathBuf,
+    format: VaultFormat,
+    layout: StoreLayout,
+    read_only: bool,
     master_key: Option<MasterKey>,
     data: Option<VaultData>,
+    // Modification time of the vault file as of our last read or write, used
+    // to tell external edits apart from our own saves.
+    last_seen_modified: Option<SystemTime>,
 }
 
 impl Vault {
+    /// The default vault: the file named by `PORTKEY_VAULT_PATH` when set,
+    /// otherwise `vault.dat` in the data directory.
     pub fn new() -> Result<Self> {
-        let data_dir = dirs::data_dir()
-            .context("Failed to find data directory")?
-            .join("portkey");
-        let data_path = data_dir.join("vault.dat");
-        Self::new_at(data_path)
+        match std::env::var_os(VAULT_PATH_ENV).filter(|path| !path.is_empty()) {
+            Some(path) => Self::with_path(PathBuf::from(path)),
+            None => Self::named(DEFAULT_VAULT_NAME),
+        }
     }
 
-    pub fn new_at(data_path: PathBuf) -> Result<Self> {
+    /// A named vault stored as `<name>.dat` in the data directory.
+    pub fn named(name: &str) -> Result<Self> {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(anyhow::anyhow!(
+                "Invalid vault name '{name}': use letters, digits, '-' or '_'"
+            ));
+        }
+        Self::with_path(Self::data_dir()?.join(format!("{name}.{VAULT_EXTENSION}")))
+    }
+
+    pub fn with_path(data_path: PathBuf) -> Result<Self> {
         if let Some(parent) = data_path.parent() {
             fs::create_dir_all(parent)?;
         }
 
         Ok(Self {
             data_path,
+            format: VaultFormat::default(),
+            layout: StoreLayout::default(),
+            read_only: false,
             master_key: None,
             data: None,
+            last_seen_modified: None,
         })
     }
 
+    pub fn data_dir() -> Result<PathBuf> {
+        Ok(dirs::data_dir()
+            .context("Failed to find data directory")?
+            .join("portkey"))
+    }
+
+    /// Vault files in the data directory, sorted by path.
+    pub fn list_vaults() -> Result<Vec<PathBuf>> {
+        let data_dir = Self::data_dir()?;
+        if !data_dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut vaults: Vec<PathBuf> = fs::read_dir(&data_dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.is_file() && path.extension().is_some_and(|ext| ext == VAULT_EXTENSION)
+            })
+            .collect();
+        vaults.sort();
+        Ok(vaults)
+    }
+
     pub fn exists(&self) -> bool {
         self.data_path.exists()
     }
 
+    /// Decrypts and loads the vault. Failures carry a [`VaultError`] so
+    /// callers can tell a wrong password apart from a damaged file.
     pub fn unlock(&mut self, password: Option<&str>) -> Result<()> {
         if !self.exists() {
-            return Err(anyhow::anyhow!("Vault does not exist"));
+            return Err(VaultError::NotFound.into());
         }
 
         let vault_file = self.load_vault_file()?;
+        if vault_file.key_source == KeySource::KeyFile {
+            return Err(VaultError::KeyFileRequired.into());
+        }
 
         // Try to decrypt with password if provided
         if let Some(password) = password {
-            let master_key = MasterKey::from_password(password, &vault_file.salt)?;
+            let master_key = MasterKey::from_password(password, &vault_file.salt, vault_file.kdf)?;
+            return self.open_with_key(&vault_file, master_key, VaultError::WrongPassword);
+        }
 
-            // Check if this looks like encrypted data by attempting decryption
-            let decrypted_data = master_key.decrypt(&vault_file.ciphertext, &vault_file.nonce)?;
-            let vault_data: VaultData = serde_json::from_slice(&decrypted_data)
-                .context("Failed to deserialize vault data")?;
+        // No password provided, assume unencrypted vault (JSON or TOML)
+        let mut vault_data: VaultData = serde_json::from_slice(&vault_file.ciphertext)
+            .map_err(|_| VaultError::PasswordRequired)?;
+        self.layout = self.join_servers(&vault_file, &mut vault_data, None)?;
+        self.format = self.stored_format();
+        self.master_key = None;
+        self.data = Some(vault_data);
 
-            self.master_key = Some(master_key);
-            self.data = Some(vault_data);
-        } else {
-            // No password provided, assume unencrypted vault
-            let vault_data: VaultData = serde_json::from_slice(&vault_file.ciphertext)
-                .context("Failed to deserialize vault data - try providing a password")?;
+        self.last_seen_modified = self.modified_on_disk();
+        self.apply_migrations()?;
+        Ok(())
+    }
+
+    /// The salt and Argon2 cost the master key of a password-protected vault
+    /// is derived with, so [`MasterKey::from_password`] can run somewhere
+    /// else (it takes seconds at the higher costs) before
+    /// [`Vault::unlock_with_key`].
+    pub fn key_derivation(&self) -> Result<(argon2id13::Salt, KdfParams)> {
+        if !self.exists() {
+            return Err(VaultError::NotFound.into());
+        }
+
+        let vault_file = self.load_vault_file()?;
+        if vault_file.key_source == KeySource::KeyFile {
+            return Err(VaultError::KeyFileRequired.into());
+        }
+        Ok((vault_file.salt, vault_file.kdf))
+    }
+
+    /// [`Vault::unlock`] with a master key already derived from the password.
+    pub fn unlock_with_key(&mut self, master_key: MasterKey) -> Result<()> {
+        if !self.exists() {
+            return Err(VaultError::NotFound.into());
+        }
+
+        let vault_file = self.load_vault_file()?;
+        if vault_file.key_source == KeySource::KeyFile {
+            return Err(VaultError::KeyFileRequired.into());
+        }
+        self.open_with_key(&vault_file, master_key, VaultError::WrongPassword)
+    }
+
+    /// Unlocks a vault created with [`Vault::create_with_keyfile`], for
+    /// headless use where no password can be typed.
+    pub fn unlock_with_keyfile(&mut self, path: &Path) -> Result<()> {
+        if !self.exists() {
+            return Err(VaultError::NotFound.into());
+        }
 
-            self.master_key = None;
-            self.data = Some(vault_data);
+        let vault_file = self.load_vault_file()?;
+        if vault_file.key_source != KeySource::KeyFile {
+            return Err(VaultError::PasswordKeyed.into());
         }
 
+        let master_key = MasterKey::from_key_file(path)?;
+        self.open_with_key(&vault_file, master_key, VaultError::WrongKeyFile)
+    }
+
+    // Decrypts `vault_file` and keeps the key. The MAC check fails for a wrong
+    // key, reported as `wrong`; bad JSON after it passes is damage.
+    fn open_with_key(
+        &mut self,
+        vault_file: &VaultFile,
+        master_key: MasterKey,
+        wrong: VaultError,
+    ) -> Result<()> {
+        let decrypted_data = master_key
+            .decrypt(&vault_file.ciphertext, &vault_file.nonce)
+            .map_err(|_| wrong)?;
+        let mut vault_data: VaultData =
+            parse_plaintext(decrypted_data).map_err(|e| VaultError::Corrupt(e.to_string()))?;
+        self.layout = self.join_servers(vault_file, &mut vault_data, Some(&master_key))?;
+
+        self.master_key = Some(master_key);
+        self.data = Some(vault_data);
+        self.last_seen_modified = self.modified_on_disk();
+        self.apply_migrations()?;
+        Ok(())
+    }
+
+    /// Whether another process has written the vault file since we last read
+    /// or saved it.
+    pub fn changed_on_disk(&self) -> bool {
+        self.is_unlocked() && self.modified_on_disk() != self.last_seen_modified
+    }
+
+    /// Re-reads the vault file with the key already in memory, picking up
+    /// changes made by another portkey process.
+    pub fn reload(&mut self) -> Result<()> {
+        self.ensure_unlocked()?;
+
+        // Record the new mtime up front so a vault we can't read isn't retried every tick
+        self.last_seen_modified = self.modified_on_disk();
+        let vault_file = self.load_vault_file()?;
+        let mut vault_data: VaultData = parse_plaintext(self.open_sealed(&vault_file)?)
+            .context("Failed to deserialize vault data")?;
+        self.layout = self.join_servers(&vault_file, &mut vault_data, self.master_key.as_ref())?;
+
+        self.format = self.stored_format();
+        self.data = Some(vault_data);
+        self.apply_migrations()?;
         Ok(())
     }
 
     pub fn create(&mut self, password: Option<&str>) -> Result<()> {
+        self.create_with_kdf(password, KdfParams::default())
+    }
+
+    /// Creates an unencrypted vault stored in `format`. TOML vaults can be
+    /// edited by hand and stay TOML until a password is set.
+    pub fn create_with_format(&mut self, format: VaultFormat) -> Result<()> {
+        self.format = format;
+        self.create_with_kdf(None, KdfParams::default())
+    }
+
+    /// Creates the vault, deriving the master key with the given Argon2id
+    /// cost. The parameters are stored in the vault file for later unlocks.
+    pub fn create_with_kdf(&mut self, password: Option<&str>, kdf: KdfParams) -> Result<()> {
         if self.exists() {
             return Err(anyhow::anyhow!("Vault already exists"));
         }
@@ -93,7 +519,7 @@ impl Vault {
         let vault_file = if let Some(password) = password {
             // Password-protected vault
             let salt = generate_salt();
-            let master_key = MasterKey::from_password(password, &salt)?;
+            let master_key = MasterKey::from_password(password, &salt, kdf)?;
             let (nonce, ciphertext) = master_key.encrypt(&serialized);
 
             VaultFile {
@@ -102,6 +528,9 @@ impl Vault {
                 ciphertext,
                 created_at: Utc::now(),
                 updated_at: Utc::now(),
+                kdf,
+                key_source: KeySource::Password,
+                split: self.empty_split(),
             }
         } else {
             // Unencrypted vault (no password)
@@ -114,13 +543,18 @@ impl Vault {
                 ciphertext: serialized, // Store data unencrypted
                 created_at: Utc::now(),
                 updated_at: Utc::now(),
+                kdf: KdfParams::default(),
+                key_source: KeySource::Password,
+                split: self.empty_split(),
             }
         };
 
         self.save_vault_file(&vault_file)?;
+        // A snapshot left over from a previous vault at this path can't be restored
+        let _ = fs::remove_file(self.undo_path());
 
         if let Some(password) = password {
-            let master_key = MasterKey::from_password(password, &vault_file.salt)?;
+            let master_key = MasterKey::from_password(password, &vault_file.salt, kdf)?;
             self.master_key = Some(master_key);
         }
         self.data = Some(vault_data);
@@ -128,33 +562,262 @@ impl Vault {
         Ok(())
     }
 
+    /// Creates a vault encrypted with the raw key in `key_file` instead of a
+    /// password-derived one; see [`MasterKey::from_key_file`].
+    pub fn create_with_keyfile(&mut self, key_file: &Path) -> Result<()> {
+        if self.exists() {
+            return Err(anyhow::anyhow!("Vault already exists"));
+        }
+
+        let master_key = MasterKey::from_key_file(key_file)?;
+        let vault_data = VaultData::new();
+        let (nonce, ciphertext) = master_key.encrypt(&serde_json::to_vec(&vault_data)?);
+        let vault_file = VaultFile {
+            salt: generate_salt(),
+            nonce,
+            ciphertext,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            kdf: KdfParams::default(),
+            key_source: KeySource::KeyFile,
+            split: self.empty_split(),
+        };
+
+        self.save_vault_file(&vault_file)?;
+        let _ = fs::remove_file(self.undo_path());
+
+        self.master_key = Some(master_key);
+        self.data = Some(vault_data);
+        Ok(())
+    }
+
+    /// Re-encrypts the vault under a new master password, or removes or adds
+    /// password protection when `old` or `new` is `None`. The current file is
+    /// only replaced once the re-encrypted copy has been fully written. A
+    /// split vault's server files are first written to
+    /// [`Self::pending_servers_dir`] and swapped in after the index, so a
+    /// failure never leaves files sealed with two different keys.
+    pub fn change_password(&mut self, old: Option<&str>, new: Option<&str>) -> Result<()> {
+        if !self.exists() {
+            return Err(VaultError::NotFound.into());
+        }
+        if self.read_only {
+            return Err(VaultError::ReadOnly.into());
+        }
+
+        let current = self.load_vault_file()?;
+        if current.key_source == KeySource::KeyFile {
+            return Err(VaultError::KeyFileRequired.into());
+        }
+        let (mut vault_data, old_key): (VaultData, _) = match old {
+            Some(old) => {
+                let old_key = MasterKey::from_password(old, &current.salt, current.kdf)?;
+                let decrypted = old_key
+                    .decrypt(&current.ciphertext, &current.nonce)
+                    .map_err(|_| VaultError::WrongPassword)?;
+                let data =
+                    parse_plaintext(decrypted).map_err(|e| VaultError::Corrupt(e.to_string()))?;
+                (data, Some(old_key))
+            }
+            None => (
+                serde_json::from_slice(&current.ciphertext)
+                    .map_err(|_| VaultError::PasswordRequired)?,
+                None,
+            ),
+        };
+        self.layout = self.join_servers(&current, &mut vault_data, old_key.as_ref())?;
+        let pending = self.pending_servers_dir();
+        if pending.exists() {
+            fs::remove_dir_all(&pending)?;
+        }
+        // Per-server passwords are sealed with the old key too
+        if let Some(old_key) = &old_key {
+            for server in &mut vault_data.servers {
+                let password = server.decrypt_password(old_key)?;
+                server.set_password(password);
+                let sudo_password = server.decrypt_sudo_password(old_key)?;
+                server.set_sudo_password(sudo_password);
+            }
+        }
+
+        let (master_key, vault_file) = match new {
+            Some(new) => {
+                // Keep the vault's KDF cost; only the password and salt change
+                let salt = generate_salt();
+                let master_key = MasterKey::from_password(new, &salt, current.kdf)?;
+                for server in &mut vault_data.servers {
+                    server.seal_password(&master_key);
+                }
+                let (serialized, split) =
+                    self.split_servers(&vault_data, Some(&master_key), &pending)?;
+                let (nonce, ciphertext) = master_key.encrypt(&serialized);
+                let vault_file = VaultFile {
+                    salt,
+                    nonce,
+                    ciphertext,
+                    created_at: current.created_at,
+                    updated_at: Utc::now(),
+                    kdf: current.kdf,
+                    key_source: KeySource::Password,
+                    split,
+                };
+                (Some(master_key), vault_file)
+            }
+            None => {
+                let (ciphertext, split) = self.split_servers(&vault_data, None, &pending)?;
+                let vault_file = VaultFile {
+                    salt: generate_salt(),
+                    nonce: secretbox::gen_nonce(),
+                    ciphertext,
+                    created_at: current.created_at,
+                    updated_at: Utc::now(),
+                    kdf: current.kdf,
+                    key_source: KeySource::Password,
+                    split,
+                };
+                (None, vault_file)
+            }
+        };
+
+        self.save_vault_file(&vault_file)?;
+        if vault_file.split.is_some() {
+            self.swap_in_pending_servers()?;
+        }
+        // The undo snapshot is sealed with the old key and can no longer be opened
+        let _ = fs::remove_file(self.undo_path());
+
+        self.master_key = master_key;
+        self.data = Some(vault_data);
+        Ok(())
+    }
+
+    /// Forgets the master key and decrypted data, wiping login and sudo
+    /// passwords first. Afterwards [`Self::is_unlocked`] is false and every
+    /// operation fails with [`VaultError::Locked`] until the vault is
+    /// unlocked again.
+    pub fn lock(&mut self) {
+        if let Some(data) = self.data.as_mut() {
+            for server in &mut data.servers {
+                server.wipe_secrets();
+            }
+        }
+        self.data = None;
+        self.master_key = None;
+    }
+
+    /// Whether the vault is unlocked with a master password, as opposed to
+    /// being stored unencrypted.
+    pub fn is_encrypted(&self) -> bool {
+        self.master_key.is_some()
+    }
+
     pub fn is_unlocked(&self) -> bool {
         self.data.is_some()
     }
 
+    /// Refuses every change to the servers with [`VaultError::ReadOnly`],
+    /// unlike [`Self::lock`] leaving them readable. Connecting still works
+    /// but no longer updates the history or last-connected times.
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Adds `server`, failing with [`VaultError::DuplicateServer`] when one
+    /// with the same host, port and username is already stored.
     pub fn add_server(&mut self, server: Server) -> Result<()> {
-        self.ensure_unlocked()?;
+        self.ensure_writable()?;
 
+        let data = self.data.as_ref().unwrap();
+        if let Some(existing) = data.find_duplicate(&server.host, server.port, &server.username) {
+            return Err(VaultError::DuplicateServer(existing.name.clone()).into());
+        }
+        self.add_server_allowing_duplicate(server)
+    }
+
+    pub fn add_server_allowing_duplicate(&mut self, server: Server) -> Result<()> {
+        self.ensure_writable()?;
+
+        server.validate()?;
+        server.validate_ssh_options()?;
         let data = self.data.as_mut().unwrap();
+        data.gateway_chain(&server)?;
+        if let Some(existing) = data.find_alias_clash(&server) {
+            return Err(
+                VaultError::DuplicateAlias(server.ssh_alias(), existing.name.clone()).into(),
+            );
+        }
+        let snapshot = data.clone();
+        let label = format!("added '{}'", server.name);
         data.add_server(server);
 
-        self.save()?;
+        self.save_with_undo(&snapshot, &label)?;
         Ok(())
     }
 
+    /// Saves a copy of server `id`; see [`VaultData::clone_server`].
+    pub fn clone_server(&mut self, id: &uuid::Uuid) -> Result<Option<uuid::Uuid>> {
+        self.ensure_writable()?;
+
+        let data = self.data.as_mut().unwrap();
+        let snapshot = data.clone();
+        let Some(new_id) = data.clone_server(id) else {
+            return Ok(None);
+        };
+
+        let name = snapshot
+            .find_server(id)
+            .map(|s| s.name.as_str())
+            .unwrap_or_default();
+        self.save_with_undo(&snapshot, &format!("cloned '{name}'"))?;
+        Ok(Some(new_id))
+    }
+
     pub fn remove_server(&mut self, id: &uuid::Uuid) -> Result<bool> {
-        self.ensure_unlocked()?;
+        self.ensure_writable()?;
 
         let data = self.data.as_mut().unwrap();
+        let snapshot = data.clone();
         let removed = data.remove_server(id);
 
         if removed {
-            self.save()?;
+            let name = snapshot
+                .find_server(id)
+                .map(|s| s.name.as_str())
+                .unwrap_or_default();
+            self.save_with_undo(&snapshot, &format!("removed '{name}'"))?;
         }
 
         Ok(removed)
     }
 
+    /// Removes every server in `ids` with a single save, so one undo brings
+    /// them all back. Returns the names of the servers that were removed.
+    pub fn remove_servers(&mut self, ids: &[uuid::Uuid]) -> Result<Vec<String>> {
+        self.ensure_writable()?;
+
+        let data = self.data.as_mut().unwrap();
+        let snapshot = data.clone();
+        let removed: Vec<String> = ids
+            .iter()
+            .filter_map(|id| {
+                let name = data.find_server(id)?.name.clone();
+                data.remove_server(id).then_some(name)
+            })
+            .collect();
+
+        let label = match removed.as_slice() {
+            [] => return Ok(removed),
+            [name] => format!("removed '{name}'"),
+            names => format!("removed {} servers", names.len()),
+        };
+        self.save_with_undo(&snapshot, &label)?;
+        Ok(removed)
+    }
+
     pub fn list_servers(&self) -> Result<&Vec<Server>> {
         self.ensure_unlocked()?;
 
@@ -168,107 +831,667 @@ impl Vault {
     }
 
     pub fn replace_server(&mut self, server: Server) -> Result<bool> {
-        self.ensure_unlocked()?;
+        self.ensure_writable()?;
+        server.validate()?;
+        server.validate_ssh_options()?;
         let data = self.data.as_mut().unwrap();
+        data.gateway_chain(&server)?;
+        if let Some(existing) = data.find_alias_clash(&server) {
+            return Err(
+                VaultError::DuplicateAlias(server.ssh_alias(), existing.name.clone()).into(),
+            );
+        }
+        let snapshot = data.clone();
+        let label = format!("edited '{}'", server.name);
         let replaced = data.replace_server(server);
         if replaced {
-            self.save()?;
+            self.save_with_undo(&snapshot, &label)?;
         }
         Ok(replaced)
     }
 
+    /// Stamps `last_connected` with the current time and saves using the key
+    /// already in memory. Not recorded as an undoable change, and skipped for
+    /// a read-only vault so connecting still works.
+    pub fn mark_connected(&mut self, id: &uuid::Uuid) -> Result<()> {
+        self.ensure_unlocked()?;
+        if self.read_only {
+            return Ok(());
+        }
+
+        let server = self
+            .data
+            .as_mut()
+            .unwrap()
+            .servers
+            .iter_mut()
+            .find(|s| &s.id == id)
+            .ok_or_else(|| anyhow::anyhow!("Server not found"))?;
+        server.last_connected = Some(Utc::now());
+        self.save()
+    }
+
+    /// Appends a connection event for `id` and saves. Called after ssh exits,
+    /// so changes another process made in the meantime are picked up first.
+    /// Nothing is recorded for a read-only vault.
+    pub fn record_connection(&mut self, id: &uuid::Uuid, success: bool) -> Result<()> {
+        self.ensure_unlocked()?;
+        if self.read_only {
+            return Ok(());
+        }
+        if self.changed_on_disk() {
+            self.reload()?;
+        }
+
+        self.data
+            .as_mut()
+            .unwrap()
+            .record_connection(ConnectionEvent {
+                server_id: *id,
+                at: Utc::now(),
+                success,
+            });
+        self.save()
+    }
+
+    /// Everything held in the vault, passwords still sealed where they are.
+    pub fn data(&self) -> Result<&VaultData> {
+        self.ensure_unlocked()?;
+
+        Ok(self.data.as_ref().unwrap())
+    }
+
+    pub fn history(&self) -> Result<&[ConnectionEvent]> {
+        self.ensure_unlocked()?;
+
+        Ok(&self.data.as_ref().unwrap().history)
+    }
+
+    /// Changes how many connection events are kept, trimming the oldest now.
+    pub fn set_history_limit(&mut self, limit: usize) -> Result<()> {
+        self.ensure_writable()?;
+
+        let data = self.data.as_mut().unwrap();
+        data.history_limit = limit;
+        data.trim_history();
+        self.save()
+    }
+
+    /// Restores the snapshot taken before the last mutating operation and
+    /// discards it, returning a description of what was undone. Only one level
+    /// is kept, and it survives restarts in an encrypted `vault.undo` file.
+    pub fn undo(&mut self) -> Result<Option<String>> {
+        self.ensure_writable()?;
+
+        let undo_path = self.undo_path();
+        if !undo_path.exists() {
+            return Ok(None);
+        }
+
+        let undo_file: VaultFile = serde_json::from_slice(&fs::read(&undo_path)?)
+            .context("Failed to read undo snapshot")?;
+        let snapshot: UndoSnapshot = parse_plaintext(self.open_sealed(&undo_file)?)
+            .context("Failed to deserialize undo snapshot")?;
+
+        // History is a log, not an edit; keep what was recorded since the snapshot
+        let mut restored = snapshot.data;
+        let current = self.data.take().unwrap();
+        restored.history = current.history;
+        restored.history_limit = current.history_limit;
+        self.data = Some(restored);
+        self.apply_migrations()?;
+        self.save()?;
+        fs::remove_file(&undo_path)?;
+
+        Ok(Some(snapshot.label))
+    }
+
+    /// Writes a portable backup of the unlocked vault to `path`, sealed with
+    /// `password` and a fresh salt rather than the local master key.
+    pub fn export(&self, path: &Path, password: Option<&str>) -> Result<()> {
+        self.ensure_unlocked()?;
+
+        // The backup has its own key, so passwords go in as cleartext inside it
+        let mut data = self.data.as_ref().unwrap().clone();
+        for server in &mut data.servers {
+            *server = self.with_password(server)?;
+        }
+        let serialized = serde_json::to_vec(&data)?;
+        for server in &mut data.servers {
+            server.clear_password();
+            server.set_sudo_password(None);
+        }
+        let salt = generate_salt();
+        let kdf = self
+            .master_key
+            .as_ref()
+            .map(MasterKey::kdf)
+            .unwrap_or_default();
+        let (nonce, ciphertext) = match password {
+            Some(password) => MasterKey::from_password(password, &salt, kdf)?.encrypt(&serialized),
+            None => (secretbox::gen_nonce(), serialized),
+        };
+
+        let now = Utc::now();
+        let export = ExportFile {
+            format: EXPORT_FORMAT.to_string(),
+            format_version: EXPORT_FORMAT_VERSION,
+            encrypted: password.is_some(),
+            vault: VaultFile {
+                salt,
+                nonce,
+                ciphertext,
+                created_at: now,
+                updated_at: now,
+                kdf,
+                key_source: KeySource::Password,
+                split: None,
+            },
+        };
+        write_atomic(path, &serde_json::to_vec(&export)?)
+    }
+
+    /// Writes the decrypted vault, passwords in cleartext, as pretty JSON in
+    /// the shape [`VaultData::schema`] describes. Only the file's owner-only
+    /// permissions protect it.
+    pub fn export_plain(&self, path: &Path) -> Result<()> {
+        self.ensure_unlocked()?;
+
+        let mut data = self.data.as_ref().unwrap().clone();
+        for server in &mut data.servers {
+            *server = self.with_password(server)?;
+        }
+        let mut serialized = serde_json::to_vec_pretty(&data)?;
+        let written = write_atomic(path, &serialized);
+        serialized.zeroize();
+        written
+    }
+
+    /// Merges `servers` into the vault, skipping duplicates by host, username
+    /// and port. With `dry_run` nothing is written and the summary describes
+    /// what would have been imported.
+    pub fn import_servers(&mut self, servers: Vec<Server>, dry_run: bool) -> Result<MergeSummary> {
+        self.import_servers_with(servers, dry_run, OnConflict::Skip)
+    }
+
+    /// [`import_servers`](Self::import_servers) that can merge into or
+    /// overwrite existing entries with the same endpoint; see
+    /// [`VaultData::merge_servers_with`].
+    pub fn import_servers_with(
+        &mut self,
+        servers: Vec<Server>,
+        dry_run: bool,
+        on_conflict: OnConflict,
+    ) -> Result<MergeSummary> {
+        if dry_run {
+            self.ensure_unlocked()?;
+        } else {
+            self.ensure_writable()?;
+        }
+
+        let snapshot = self.data.as_ref().unwrap().clone();
+        let mut merged = snapshot.clone();
+        let summary = merged.merge_servers_with(servers, on_conflict);
+
+        if !dry_run && summary.changed() {
+            self.data = Some(merged);
+            let label = format!(
+                "imported {} server(s)",
+                summary.added.len() + summary.merged.len() + summary.replaced.len()
+            );
+            self.save_with_undo(&snapshot, &label)?;
+        }
+        Ok(summary)
+    }
+
+    /// The cleartext password of `server`, opened with the master key when it
+    /// is sealed. Zeroize the result once it has been used.
+    pub fn decrypt_password(&self, server: &Server) -> Result<String> {
+        match &self.master_key {
+            Some(master_key) => server.decrypt_password(master_key),
+            None if server.is_password_sealed() => Err(VaultError::Locked.into()),
+            None => Ok(server.password.clone()),
+        }
+    }
+
+    /// The password sudo on `server` expects: its separate sudo password,
+    /// or the login password when it has none. Zeroize the result after use.
+    pub fn decrypt_sudo_password(&self, server: &Server) -> Result<String> {
+        match self.separate_sudo_password(server)? {
+            Some(sudo_password) => Ok(sudo_password),
+            None => self.decrypt_password(server),
+        }
+    }
+
+    fn separate_sudo_password(&self, server: &Server) -> Result<Option<String>> {
+        match &self.master_key {
+            Some(master_key) => server.decrypt_sudo_password(master_key),
+            None if !server.sudo_password_enc.is_empty() => Err(VaultError::Locked.into()),
+            None => Ok(server.sudo_password.clone()),
+        }
+    }
+
+    /// A copy of `server` with its passwords in cleartext, for handing to
+    /// `ssh::connect` and friends. Zeroize its password after use.
+    pub fn with_password(&self, server: &Server) -> Result<Server> {
+        let password = self.decrypt_password(server)?;
+        let sudo_password = self.separate_sudo_password(server)?;
+        let mut unsealed = server.clone();
+        unsealed.set_password(password);
+        unsealed.set_sudo_password(sudo_password);
+        Ok(unsealed)
+    }
+
+    pub fn undo_path(&self) -> PathBuf {
+        self.data_path.with_extension("undo")
+    }
+
+    /// Where a split vault keeps its server files: `vault.servers/` beside
+    /// `vault.dat`.
+    pub fn servers_dir(&self) -> PathBuf {
+        self.data_path.with_extension("servers")
+    }
+
+    /// Where [`Self::change_password`] writes the re-sealed server files of
+    /// a split vault before they replace [`Self::servers_dir`].
+    pub fn pending_servers_dir(&self) -> PathBuf {
+        self.data_path.with_extension("servers.new")
+    }
+
+    // Replaces the server files with the pending ones once the index sealed
+    // with the same key is on disk. A crash part way is picked up by
+    // `join_servers`, which falls back to the pending files.
+    fn swap_in_pending_servers(&self) -> Result<()> {
+        let current = self.servers_dir();
+        let old = self.data_path.with_extension("servers.old");
+        if old.exists() {
+            fs::remove_dir_all(&old)?;
+        }
+        if current.exists() {
+            fs::rename(&current, &old)?;
+        }
+        fs::rename(self.pending_servers_dir(), &current)?;
+        if old.exists() {
+            fs::remove_dir_all(&old)?;
+        }
+        Ok(())
+    }
+
+    pub fn layout(&self) -> StoreLayout {
+        self.layout
+    }
+
+    /// Chooses the layout of a vault about to be created, or rewrites an
+    /// unlocked vault in the new layout. Going back to a single file removes
+    /// the server files.
+    pub fn set_layout(&mut self, layout: StoreLayout) -> Result<()> {
+        if layout == StoreLayout::Split && self.format == VaultFormat::Toml {
+            return Err(anyhow::anyhow!(
+                "TOML vaults are always a single file and can't be split"
+            ));
+        }
+        if layout == self.layout {
+            return Ok(());
+        }
+        if self.read_only && self.is_unlocked() {
+            return Err(VaultError::ReadOnly.into());
+        }
+
+        self.layout = layout;
+        if self.is_unlocked() {
+            self.save()?;
+            if layout == StoreLayout::Single {
+                fs::remove_dir_all(self.servers_dir())?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn gateway_chain(&self, server: &Server) -> Result<Vec<Server>> {
+        self.ensure_unlocked()?;
+
+        let chain = self.data.as_ref().unwrap().gateway_chain(server)?;
+        Ok(chain.into_iter().cloned().collect())
+    }
+
+    pub fn tag_counts(&self) -> Result<Vec<(String, usize)>> {
+        self.ensure_unlocked()?;
+
+        Ok(self.data.as_ref().unwrap().tag_counts())
+    }
+
+    pub fn stats(&self) -> Result<VaultStats> {
+        self.ensure_unlocked()?;
+
+        Ok(self.data.as_ref().unwrap().stats())
+    }
+
+    pub fn search(&self, query: &str) -> Result<Vec<(i64, &Server)>> {
+        self.ensure_unlocked()?;
+
+        Ok(self.data.as_ref().unwrap().search(query))
+    }
+
+    pub fn dependents_of(&self, id: &uuid::Uuid) -> Result<Vec<&Server>> {
+        self.ensure_unlocked()?;
+
+        Ok(self.data.as_ref().unwrap().dependents_of(id))
+    }
+
     pub fn vault_path(&self) -> &PathBuf {
         &self.data_path
     }
 
+    /// Bytes the vault takes up on disk: the file itself plus, for a split
+    /// vault, every server file.
+    pub fn disk_size(&self) -> Result<u64> {
+        let mut size = fs::metadata(&self.data_path)?.len();
+        if self.layout == StoreLayout::Split {
+            match fs::read_dir(self.servers_dir()) {
+                Ok(entries) => {
+                    for entry in entries {
+                        size += entry?.metadata()?.len();
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(size)
+    }
+
     fn ensure_unlocked(&self) -> Result<()> {
         if !self.is_unlocked() {
-            return Err(anyhow::anyhow!("Vault is locked"));
+            return Err(VaultError::Locked.into());
+        }
+        Ok(())
+    }
+
+    fn ensure_writable(&self) -> Result<()> {
+        self.ensure_unlocked()?;
+        if self.read_only {
+            return Err(VaultError::ReadOnly.into());
         }
         Ok(())
     }
 
-    fn load_vault_file(&self) -> Result<VaultFile> {
+    /// Reads the vault file. A TOML vault is wrapped in an unencrypted
+    /// `VaultFile` holding its data as JSON, so callers need not care which
+    /// format is on disk.
+    fn load_vault_file(&self) -> Result<VaultFile, VaultError> {
         let content = fs::read(&self.data_path)?;
-        let vault_file: VaultFile = serde_json::from_slice(&content)?;
-        Ok(vault_file)
+        match VaultFormat::detect(&content) {
+            VaultFormat::Json => {
+                serde_json::from_slice(&content).map_err(|e| VaultError::Corrupt(e.to_string()))
+            }
+            VaultFormat::Toml => {
+                let text = std::str::from_utf8(&content)
+                    .map_err(|e| VaultError::Corrupt(e.to_string()))?;
+                let data: VaultData =
+                    toml::from_str(text).map_err(|e| VaultError::Corrupt(e.to_string()))?;
+                let modified = self
+                    .modified_on_disk()
+                    .map(DateTime::<Utc>::from)
+                    .unwrap_or_else(Utc::now);
+                Ok(VaultFile {
+                    salt: generate_salt(),
+                    nonce: secretbox::gen_nonce(),
+                    ciphertext: serde_json::to_vec(&data)
+                        .map_err(|e| VaultError::Corrupt(e.to_string()))?,
+                    created_at: modified,
+                    updated_at: modified,
+                    kdf: KdfParams::default(),
+                    key_source: KeySource::Password,
+                    split: None,
+                })
+            }
+        }
     }
 
-    fn save_vault_file(&self, vault_file: &VaultFile) -> Result<()> {
-        let content = serde_json::to_vec(vault_file)?;
+    fn stored_format(&self) -> VaultFormat {
+        fs::read(&self.data_path)
+            .map(|content| VaultFormat::detect(&content))
+            .unwrap_or_default()
+    }
 
-        let parent = self
-            .data_path
-            .parent()
-            .ok_or_else(|| anyhow::anyhow!("Vault path has no parent directory"))?;
-        fs::create_dir_all(parent)?;
+    fn save_vault_file(&mut self, vault_file: &VaultFile) -> Result<()> {
+        // A TOML vault stays TOML only while its data is unencrypted
+        let plaintext = match self.format {
+            VaultFormat::Toml if vault_file.split.is_none() => {
+                serde_json::from_slice::<VaultData>(&vault_file.ciphertext).ok()
+            }
+            _ => None,
+        };
+        match plaintext {
+            Some(data) => write_atomic(&self.data_path, toml::to_string_pretty(&data)?.as_bytes())?,
+            None => {
+                self.format = VaultFormat::Json;
+                self.write_sealed(&self.data_path, vault_file)?;
+            }
+        }
+        self.last_seen_modified = self.modified_on_disk();
+        Ok(())
+    }
 
-        let file_name = self
-            .data_path
-            .file_name()
-            .ok_or_else(|| anyhow::anyhow!("Vault path has no file name"))?
-            .to_string_lossy();
-        let temp_path = parent.join(format!(".{file_name}.{}.tmp", Uuid::new_v4()));
+    fn modified_on_disk(&self) -> Option<SystemTime> {
+        let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+        // Server files are renamed into place, which touches their directory
+        modified(&self.data_path).max(modified(&self.servers_dir()))
+    }
 
-        let mut file = fs::OpenOptions::new()
-            .create_new(true)
-            .write(true)
-            .open(&temp_path)?;
+    fn empty_split(&self) -> Option<Vec<Uuid>> {
+        (self.layout == StoreLayout::Split).then(Vec::new)
+    }
 
-        let mut perms = file.metadata()?.permissions();
-        perms.set_mode(0o600); // Read/write for owner only
-        file.set_permissions(perms)?;
+    /// Fills in the servers of a split vault from their files, in index
+    /// order, and returns the layout `vault_file` was stored in. When they
+    /// don't open, a password change interrupted before its swap left the
+    /// matching files in [`Self::pending_servers_dir`], so those are tried.
+    fn join_servers(
+        &self,
+        vault_file: &VaultFile,
+        data: &mut VaultData,
+        key: Option<&MasterKey>,
+    ) -> Result<StoreLayout, VaultError> {
+        let Some(ids) = &vault_file.split else {
+            return Ok(StoreLayout::Single);
+        };
 
-        file.write_all(&content)?;
-        file.sync_all()?;
-        drop(file);
+        let pending = self.pending_servers_dir();
+        data.servers = match read_server_files(&self.servers_dir(), ids, key) {
+            Err(_) if pending.exists() => read_server_files(&pending, ids, key)?,
+            servers => servers?,
+        };
+        Ok(StoreLayout::Split)
+    }
+
+    /// What goes in the vault file itself: all of `data` for a single-file
+    /// vault. A split vault gets everything but the servers, which are
+    /// written to their own files in `dir`, plus their ids for the index. Files
+    /// whose content is unchanged are left alone; see
+    /// [`Self::remove_stale_server_files`] for the ones no longer listed.
+    fn split_servers(
+        &self,
+        data: &VaultData,
+        key: Option<&MasterKey>,
+        dir: &Path,
+    ) -> Result<(Vec<u8>, Option<Vec<Uuid>>)> {
+        if self.layout == StoreLayout::Single {
+            return Ok((serde_json::to_vec(data)?, None));
+        }
 
-        if let Err(error) = fs::rename(&temp_path, &self.data_path) {
-            let _ = fs::remove_file(&temp_path);
-            return Err(error.into());
+        fs::create_dir_all(dir)?;
+        for server in &data.servers {
+            let path = dir.join(format!("{}.{VAULT_EXTENSION}", server.id));
+            let mut serialized = serde_json::to_vec(server)?;
+            let mut stored = fs::read(&path)
+                .ok()
+                .and_then(|content| serde_json::from_slice::<ServerFile>(&content).ok())
+                .and_then(|file| open_server_file(&file, key).ok())
+                .unwrap_or_default();
+            let unchanged = stored == serialized;
+            stored.zeroize();
+            if !unchanged {
+                let (nonce, ciphertext) = match key {
+                    Some(key) => key.encrypt(&serialized),
+                    None => (secretbox::gen_nonce(), serialized.clone()),
+                };
+                write_atomic(
+                    &path,
+                    &serde_json::to_vec(&ServerFile { nonce, ciphertext })?,
+                )?;
+            }
+            serialized.zeroize();
         }
 
+        let ids: Vec<Uuid> = data.servers.iter().map(|server| server.id).collect();
+        let index = VaultData {
+            servers: Vec::new(),
+            version: data.version.clone(),
+            history: data.history.clone(),
+            history_limit: data.history_limit,
+        };
+        Ok((serde_json::to_vec(&index)?, Some(ids)))
+    }
+
+    /// Removes the files of servers no longer among `ids`. Only called once
+    /// the index without them has been written, so a save that fails part
+    /// way never leaves the index naming a file that is gone.
+    fn remove_stale_server_files(&self, ids: &[Uuid]) -> Result<()> {
+        for entry in fs::read_dir(self.servers_dir())? {
+            let path = entry?.path();
+            let stale = path.extension().is_some_and(|ext| ext == VAULT_EXTENSION)
+                && path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str()?.parse::<Uuid>().ok())
+                    .is_some_and(|id| !ids.contains(&id));
+            if stale {
+                fs::remove_file(&path)?;
+            }
+        }
         Ok(())
     }
 
-    fn save(&mut self) -> Result<()> {
-        let data = self.data.as_ref().unwrap();
-        let serialized = serde_json::to_vec(data)?;
+    fn write_sealed(&self, path: &Path, vault_file: &VaultFile) -> Result<()> {
+        write_atomic(path, &serde_json::to_vec(vault_file)?)
+    }
+
+    /// Wraps `serialized` in a `VaultFile`, encrypting it when the vault has a
+    /// master key. The existing salt is reused to keep key derivation stable.
+    fn seal(&self, serialized: Vec<u8>) -> VaultFile {
+        let existing = self.load_vault_file().ok();
+        let created_at = existing
+            .as_ref()
+            .map(|f| f.created_at)
+            .unwrap_or_else(Utc::now);
 
-        let vault_file = if let Some(master_key) = &self.master_key {
+        if let Some(master_key) = &self.master_key {
             // Encrypted vault: reuse existing salt to keep key derivation stable
-            let existing = self.load_vault_file().ok();
-            let salt = existing
-                .as_ref()
-                .map(|f| f.salt)
-                .unwrap_or_else(generate_salt);
+            let salt = existing.map(|f| f.salt).unwrap_or_else(generate_salt);
 
             let (nonce, ciphertext) = master_key.encrypt(&serialized);
             VaultFile {
                 salt,
                 nonce,
                 ciphertext,
-                created_at: existing.map(|f| f.created_at).unwrap_or_else(Utc::now),
+                created_at,
                 updated_at: Utc::now(),
+                kdf: master_key.kdf(),
+                key_source: master_key.source(),
+                split: None,
             }
         } else {
             // Unencrypted vault
-            let salt = generate_salt();
-            let nonce = secretbox::gen_nonce();
-
             VaultFile {
-                salt,
-                nonce,
+                salt: generate_salt(),
+                nonce: secretbox::gen_nonce(),
                 ciphertext: serialized, // Store unencrypted
-                created_at: self
-                    .load_vault_file()
-                    .map(|f| f.created_at)
-                    .unwrap_or_else(|_| Utc::now()),
+                created_at,
                 updated_at: Utc::now(),
+                kdf: KdfParams::default(),
+                key_source: KeySource::Password,
+                split: None,
             }
+        }
+    }
+
+    fn open_sealed(&self, vault_file: &VaultFile) -> Result<Vec<u8>> {
+        match &self.master_key {
+            Some(master_key) => master_key.decrypt(&vault_file.ciphertext, &vault_file.nonce),
+            None => Ok(vault_file.ciphertext.clone()),
+        }
+    }
+
+    /// Brings freshly loaded data up to the current schema, persisting the
+    /// result so the upgrade only runs once. A read-only vault is upgraded in
+    /// memory alone, every time it is opened.
+    fn apply_migrations(&mut self) -> Result<()> {
+        let migrated = self.data.as_mut().unwrap().migrate()?;
+        // Vaults written before passwords were sealed individually
+        let sealed = self.seal_passwords();
+        if (migrated || sealed) && !self.read_only {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// Seals every cleartext server password with the master key; a no-op for
+    /// unencrypted vaults. Returns whether any password was sealed.
+    fn seal_passwords(&mut self) -> bool {
+        let (Some(master_key), Some(data)) = (&self.master_key, self.data.as_mut()) else {
+            return false;
         };
+        let mut sealed = false;
+        for server in &mut data.servers {
+            sealed |= server.seal_password(master_key);
+        }
+        sealed
+    }
+
+    fn save(&mut self) -> Result<()> {
+        self.seal_passwords();
+        let data = self.data.as_ref().unwrap();
+        let (serialized, split) =
+            self.split_servers(data, self.master_key.as_ref(), &self.servers_dir())?;
 
+        // A split vault's index is only rewritten when it changes, so editing
+        // a server touches that server's file alone
+        if split.is_some() {
+            let stored = self
+                .load_vault_file()
+                .ok()
+                .filter(|stored| stored.split == split)
+                .and_then(|stored| self.open_sealed(&stored).ok());
+            if stored.as_ref() == Some(&serialized) {
+                self.last_seen_modified = self.modified_on_disk();
+                return Ok(());
+            }
+        }
+
+        let mut vault_file = self.seal(serialized);
+        vault_file.split = split;
         self.save_vault_file(&vault_file)?;
+        if let Some(ids) = &vault_file.split {
+            self.remove_stale_server_files(ids)?;
+        }
         Ok(())
     }
+
+    fn save_with_undo(&mut self, snapshot: &VaultData, label: &str) -> Result<()> {
+        let undo = UndoSnapshot {
+            label: label.to_string(),
+            data: snapshot.clone(),
+        };
+        let undo_file = self.seal(serde_json::to_vec(&undo)?);
+        self.write_sealed(&self.undo_path(), &undo_file)?;
+
+        self.save()
+    }
+}
+
+impl Drop for Vault {
+    // Wipes cleartext server passwords instead of leaving them in freed memory
+    fn drop(&mut self) {
+        self.lock();
+    }
 }
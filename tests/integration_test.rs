@@ -1,8 +1,43 @@
-use portkey::cli::password_option_from_choice;
-use portkey::models::Server;
-use portkey::ssh::{build_ssh_args, manual_connection_help};
-use portkey::ssh_config::{render_managed_block, render_ssh_config, upsert_managed_block};
-use portkey::vault::Vault;
+use crossterm::event::KeyCode;
+use portkey::cli::{list_summary, password_option_from_choice, read_password_line, servers_json};
+use portkey::config::Config;
+use portkey::crypto::{
+    self, generate_password, Charset, KdfParams, KdfStrength, KeySource, MasterKey,
+    DEFAULT_PASSWORD_LENGTH,
+};
+use portkey::csv_import::parse_server_csv;
+use portkey::debug::{dump_vault, DumpOptions, REDACTED};
+use portkey::health::{Health, HealthMonitor};
+use portkey::migrations::{migrate, CURRENT_VERSION};
+use portkey::models::{
+    best_description_matches, compare_groups, is_ipv6_literal, match_servers, parse_age,
+    parse_bind_address, parse_field_assignment, parse_jump_host, parse_port, parse_port_forwards,
+    parse_search_query, parse_seconds, parse_ssh_option, parse_ssh_options, parse_tags,
+    parse_tmux_session, same_group, search_servers, stale_servers, strong_match, validate_host,
+    validate_name, validate_username, ConnectionEvent, PortForward, SearchQualifier,
+    ValidationError, VaultData, DEFAULT_HISTORY_LIMIT,
+};
+use portkey::models::{AuthMethod, HostKeyPolicy, ListSort, OnConflict, Server, SortOrder};
+use portkey::ssh::{
+    build_check_args, build_scp_args, build_sftp_args, build_ssh_args, build_ssh_command,
+    command_preview, find_in_path, first_hop, manual_connection_help, path_candidates, probe_tcp,
+    ssh_command_line, with_retries, ConnectOptions, Retry,
+};
+use portkey::ssh_config::{
+    managed_block_line, parse_ssh_config, render_host_block, render_managed_block,
+    render_ssh_config, upsert_managed_block, validate_ssh_config,
+};
+use portkey::theme::{Theme, ThemeName};
+use portkey::tui::{
+    centered, confirm_quit_seconds_left, confirms_quit, detail_fields, footer_hints,
+    is_editing_key, keybindings, page_down, reveal_seconds_left, unlock_again, visible_rows,
+    CONFIRM_QUIT_FOR, KEYBINDINGS, REVEAL_FOR,
+};
+use portkey::ui_state::UiState;
+use portkey::vault::{
+    ExportFile, StoreLayout, Vault, VaultError, VaultFile, VaultFormat, VAULT_PATH_ENV,
+};
+use ratatui::layout::Rect;
 use tempfile::tempdir;
 
 #[test]
@@ -39,6 +74,38 @@ fn test_ssh_command_generation() {
     assert_eq!(server.ssh_command(), expected);
 }
 
+#[test]
+fn ssh_uris_leave_out_the_default_port() {
+    let mut server = Server::new(
+        "web".to_string(),
+        "example.com".to_string(),
+        22,
+        "deploy".to_string(),
+        String::new(),
+        None,
+    );
+    assert_eq!(server.ssh_uri(), "ssh://deploy@example.com");
+    server.port = 2222;
+    assert_eq!(server.ssh_uri(), "ssh://deploy@example.com:2222");
+}
+
+#[test]
+fn ssh_uris_bracket_ipv6_hosts_and_escape_the_user() {
+    let mut server = Server::new(
+        "v6".to_string(),
+        "2001:db8::1".to_string(),
+        22,
+        "root".to_string(),
+        String::new(),
+        None,
+    );
+    assert_eq!(server.ssh_uri(), "ssh://root@[2001:db8::1]");
+    server.port = 2200;
+    assert_eq!(server.ssh_uri(), "ssh://root@[2001:db8::1]:2200");
+    server.username = "ops@corp".to_string();
+    assert_eq!(server.ssh_uri(), "ssh://ops%40corp@[2001:db8::1]:2200");
+}
+
 #[test]
 fn native_ssh_args_support_identity_file_and_agent_forwarding() {
     let mut server = Server::new(
@@ -52,7 +119,7 @@ fn native_ssh_args_support_identity_file_and_agent_forwarding() {
     server.identity_file = Some("~/.ssh/id_ed25519".to_string());
     server.forward_agent = true;
 
-    let args = build_ssh_args(&server);
+    let args = build_ssh_args(&server, &ConnectOptions::default());
 
     assert!(args.contains(&"-tt".to_string()));
     assert!(args.contains(&"-i".to_string()));
@@ -79,12 +146,143 @@ fn manual_connection_help_never_prints_password() {
     server.identity_file = Some("~/.ssh/id_ed25519".to_string());
     server.forward_agent = true;
 
-    let help = manual_connection_help(&server);
+    let help = manual_connection_help(&server, &ConnectOptions::default());
 
-    assert!(help.contains("ssh -tt -i ~/.ssh/id_ed25519 -A -p 22 deploy@example.com"));
+    assert!(help.contains(
+        "ssh -tt -i ~/.ssh/id_ed25519 -A -o StrictHostKeyChecking=accept-new \
+         -o ConnectTimeout=10 -o ServerAliveInterval=30 -o ServerAliveCountMax=3 \
+         -p 22 deploy@example.com"
+    ));
     assert!(!help.contains("super-secret"));
 }
 
+#[test]
+fn gateway_reference_resolves_to_jump_chain_and_rejects_dangling_ids() {
+    let mut outer = Server::new(
+        "edge".to_string(),
+        "edge.example.com".to_string(),
+        22,
+        "jump".to_string(),
+        String::new(),
+        None,
+    );
+    let mut bastion = Server::new(
+        "bastion".to_string(),
+        "10.0.0.1".to_string(),
+        2222,
+        "ops".to_string(),
+        String::new(),
+        None,
+    );
+    bastion.gateway_id = Some(outer.id);
+    let mut internal = Server::new(
+        "db".to_string(),
+        "10.0.1.5".to_string(),
+        22,
+        "deploy".to_string(),
+        String::new(),
+        None,
+    );
+    internal.gateway_id = Some(bastion.id);
+
+    let mut data = VaultData::new();
+    data.add_server(outer.clone());
+    data.add_server(bastion.clone());
+    data.add_server(internal.clone());
+
+    let gateways = data
+        .gateway_chain(&internal)
+        .unwrap()
+        .into_iter()
+        .cloned()
+        .collect::<Vec<_>>();
+    let args = build_ssh_args(
+        &internal,
+        &ConnectOptions {
+            gateways,
+            ..Default::default()
+        },
+    );
+    let jump = args.iter().position(|arg| arg == "-J").unwrap();
+    assert_eq!(args[jump + 1], "jump@edge.example.com:22,ops@10.0.0.1:2222");
+    assert_eq!(data.dependents_of(&bastion.id).len(), 1);
+
+    outer.gateway_id = Some(internal.id);
+    data.replace_server(outer.clone());
+    assert!(data.gateway_chain(&internal).is_err());
+
+    data.remove_server(&bastion.id);
+    assert!(data.gateway_chain(&internal).is_err());
+}
+
+#[test]
+fn copied_ssh_command_is_shell_quoted_and_omits_the_password() {
+    let gateway = Server::new(
+        "edge".to_string(),
+        "edge.example.com".to_string(),
+        22,
+        "jump".to_string(),
+        String::new(),
+        None,
+    );
+    let mut server = Server::new(
+        "db".to_string(),
+        "10.0.1.5".to_string(),
+        2222,
+        "deploy".to_string(),
+        "super-secret".to_string(),
+        None,
+    );
+    server.identity_file = Some("/home/ops/my keys/id_ed25519".to_string());
+
+    let command = ssh_command_line(
+        &server,
+        &ConnectOptions {
+            gateways: vec![gateway],
+            ..Default::default()
+        },
+    );
+
+    assert!(command.starts_with(
+        "ssh -tt -i '/home/ops/my keys/id_ed25519' -o StrictHostKeyChecking=accept-new "
+    ));
+    assert!(command.contains(" -o ConnectTimeout=10 "));
+    assert!(command.ends_with(" -J jump@edge.example.com:22 -p 2222 deploy@10.0.1.5"));
+    assert!(!command.contains("super-secret"));
+}
+
+#[test]
+fn description_keyword_picks_the_best_match_and_keeps_ties() {
+    let server = |name: &str, description: Option<&str>| {
+        Server::new(
+            name.to_string(),
+            "10.0.0.1".to_string(),
+            22,
+            "root".to_string(),
+            String::new(),
+            description.map(str::to_string),
+        )
+    };
+    let servers = vec![
+        server("db-01", Some("primary postgres")),
+        server("db-02", Some("replica postgres")),
+        server("web-01", Some("nginx frontend")),
+        server("bare", None),
+    ];
+
+    let matches = best_description_matches(&servers, "nginx");
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0].name, "web-01");
+
+    let names: Vec<&str> = best_description_matches(&servers, "postgres")
+        .iter()
+        .map(|s| s.name.as_str())
+        .collect();
+    assert_eq!(names, ["db-01", "db-02"]);
+
+    assert!(best_description_matches(&servers, "mysql").is_empty());
+}
+
 #[test]
 fn ssh_config_includes_session_options_and_rejects_unsafe_aliases() {
     let mut server = Server::new(
@@ -107,7 +305,7 @@ fn ssh_config_includes_session_options_and_rejects_unsafe_aliases() {
     assert!(config.contains("  IdentityFile ~/.ssh/id_ed25519"));
     assert!(config.contains("  ForwardAgent yes"));
 
-    let unsafe_server = Server::new(
+    let mut unsafe_server = Server::new(
         "prod\nHost attacker".to_string(),
         "example.com".to_string(),
         22,
@@ -115,7 +313,10 @@ fn ssh_config_includes_session_options_and_rejects_unsafe_aliases() {
         String::new(),
         None,
     );
-
+    // A name only feeds a sanitized alias; an explicit alias must be safe itself
+    let config = render_ssh_config(std::slice::from_ref(&unsafe_server)).unwrap();
+    assert!(config.starts_with("Host prod-Host-attacker\n"));
+    unsafe_server.alias = Some("prod\nHost attacker".to_string());
     assert!(render_ssh_config(&[unsafe_server]).is_err());
 }
 
@@ -152,6 +353,118 @@ fn ssh_config_managed_block_is_replaced_instead_of_appended() {
     );
 }
 
+#[test]
+fn ssh_config_validation_locates_block_and_rejects_broken_edits() {
+    let server = Server::new(
+        "prod".to_string(),
+        "example.com".to_string(),
+        22,
+        "deploy".to_string(),
+        String::new(),
+        None,
+    );
+    let block = render_managed_block(&[server]).unwrap();
+    let config = upsert_managed_block("Host github.com\n  HostName github.com\n", &block);
+
+    assert!(validate_ssh_config(&config).is_ok());
+    assert_eq!(managed_block_line(&config), Some(4));
+
+    assert!(validate_ssh_config(&config.replace("# END Portkey managed entries", "")).is_err());
+    assert!(validate_ssh_config("Host prod\n  HostName\n").is_err());
+    assert!(validate_ssh_config("Host prod\n  Host-Name example.com\n").is_err());
+    assert!(validate_ssh_config("Host prod\n  Port=2222\n").is_ok());
+}
+
+#[test]
+fn ssh_config_import_reads_concrete_hosts_and_skips_patterns() {
+    let config = "\
+Host *
+  ServerAliveInterval 60
+
+Host web-*
+  User www
+
+Host prod staging
+  HostName 10.0.0.5
+  User deploy
+  Port 2222
+  IdentityFile ~/.ssh/id_ed25519
+
+# plain alias
+host=bastion
+  Port=bad
+
+Host db
+  HostName \"db.internal\"
+";
+    let parsed = parse_ssh_config(config, "alice");
+
+    let names: Vec<&str> = parsed.servers.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(names, ["prod", "staging", "db"]);
+    assert_eq!(parsed.warnings.len(), 3);
+    assert!(parsed.warnings[0].contains("Host *"));
+    assert!(parsed.warnings[1].contains("Host web-*"));
+    assert!(parsed.warnings[2].contains("invalid port"));
+
+    let prod = &parsed.servers[0];
+    assert_eq!(prod.host, "10.0.0.5");
+    assert_eq!(prod.port, 2222);
+    assert_eq!(prod.username, "deploy");
+    assert!(prod.password.is_empty());
+    assert_eq!(prod.identity_file.as_deref(), Some("~/.ssh/id_ed25519"));
+    assert_eq!(prod.auth_method, AuthMethod::PublicKey);
+
+    let db = &parsed.servers[2];
+    assert_eq!(db.host, "db.internal");
+    assert_eq!(db.port, 22);
+    assert_eq!(db.username, "alice");
+    assert_eq!(db.auth_method, AuthMethod::Password);
+
+    // Aliases sharing an endpoint are duplicates, and so is our own rendered config
+    let dir = tempdir().unwrap();
+    let mut vault = Vault::with_path(dir.path().join("vault.dat")).unwrap();
+    vault.create(None).unwrap();
+    let summary = vault.import_servers(parsed.servers, false).unwrap();
+    assert_eq!(summary.added, ["prod", "db"]);
+    assert_eq!(summary.skipped, ["staging"]);
+    let rendered = render_ssh_config(vault.list_servers().unwrap()).unwrap();
+    let summary = vault
+        .import_servers(parse_ssh_config(&rendered, "alice").servers, false)
+        .unwrap();
+    assert!(summary.added.is_empty());
+    assert_eq!(summary.skipped.len(), 2);
+}
+
+#[test]
+fn json_listing_omits_passwords_unless_requested() {
+    let mut server = Server::new(
+        "web".to_string(),
+        "10.0.0.1".to_string(),
+        2222,
+        "deploy".to_string(),
+        "hunter2".to_string(),
+        None,
+    );
+    server.tags = vec!["prod".to_string()];
+
+    let json: serde_json::Value =
+        serde_json::from_str(&servers_json(&[&server], false).unwrap()).unwrap();
+    let entry = &json.as_array().unwrap()[0];
+    assert_eq!(entry["id"], server.id.to_string());
+    assert_eq!(entry["name"], "web");
+    assert_eq!(entry["host"], "10.0.0.1");
+    assert_eq!(entry["port"], 2222);
+    assert_eq!(entry["username"], "deploy");
+    assert!(entry["description"].is_null());
+    assert_eq!(entry["tags"], serde_json::json!(["prod"]));
+    assert!(entry.get("password").is_none());
+
+    let json: serde_json::Value =
+        serde_json::from_str(&servers_json(&[&server], true).unwrap()).unwrap();
+    assert_eq!(json[0]["password"], "hunter2");
+    assert_eq!(servers_json(&[], false).unwrap(), "[]");
+}
+
 #[test]
 fn password_protected_vault_requires_non_empty_master_password() {
     assert!(password_option_from_choice(true, "").is_err());
@@ -166,7 +479,7 @@ fn password_protected_vault_requires_non_empty_master_password() {
 fn vault_round_trip_preserves_key_session_options_with_restrictive_permissions() {
     let temp = tempdir().unwrap();
     let vault_path = temp.path().join("vault.dat");
-    let mut vault = Vault::new_at(vault_path.clone()).unwrap();
+    let mut vault = Vault::with_path(vault_path.clone()).unwrap();
     vault.create(Some("master-password")).unwrap();
 
     let mut server = Server::new(
@@ -179,9 +492,10 @@ fn vault_round_trip_preserves_key_session_options_with_restrictive_permissions()
     );
     server.identity_file = Some("~/.ssh/id_ed25519".to_string());
     server.forward_agent = true;
+    server.protected = true;
     vault.add_server(server).unwrap();
 
-    let mut reopened = Vault::new_at(vault_path.clone()).unwrap();
+    let mut reopened = Vault::with_path(vault_path.clone()).unwrap();
     reopened.unlock(Some("master-password")).unwrap();
     let servers = reopened.list_servers().unwrap();
 
@@ -191,6 +505,7 @@ fn vault_round_trip_preserves_key_session_options_with_restrictive_permissions()
         Some("~/.ssh/id_ed25519")
     );
     assert!(servers[0].forward_agent);
+    assert!(servers[0].protected);
 
     #[cfg(unix)]
     {
@@ -200,3 +515,4777 @@ fn vault_round_trip_preserves_key_session_options_with_restrictive_permissions()
         assert_eq!(mode, 0o600);
     }
 }
+
+#[test]
+fn hardened_process_drops_core_dumps_and_still_opens_encrypted_vaults() {
+    crypto::harden_process(true);
+
+    #[cfg(unix)]
+    {
+        let mut limit = libc::rlimit {
+            rlim_cur: 1,
+            rlim_max: 1,
+        };
+        // SAFETY: getrlimit only writes into the struct passed by reference.
+        assert_eq!(unsafe { libc::getrlimit(libc::RLIMIT_CORE, &mut limit) }, 0);
+        assert_eq!(limit.rlim_cur, 0);
+    }
+
+    // Nothing to pin for an empty region
+    assert!(!crypto::lock_memory(&mut []));
+
+    let temp = tempdir().unwrap();
+    let vault_path = temp.path().join("vault.dat");
+    let mut vault = Vault::with_path(vault_path.clone()).unwrap();
+    vault.create(Some("master-password")).unwrap();
+    vault
+        .add_server(Server::new(
+            "prod".to_string(),
+            "example.com".to_string(),
+            22,
+            "deploy".to_string(),
+            "s3cret".to_string(),
+            None,
+        ))
+        .unwrap();
+
+    let mut reopened = Vault::with_path(vault_path).unwrap();
+    reopened.unlock(Some("master-password")).unwrap();
+    let server = &reopened.list_servers().unwrap()[0];
+    assert_eq!(reopened.decrypt_password(server).unwrap(), "s3cret");
+}
+
+const ENCRYPTED_V1_FIXTURE: &[u8] = include_bytes!("fixtures/vault_v1_encrypted.json");
+const PLAIN_V1_FIXTURE: &[u8] = include_bytes!("fixtures/vault_v1_plain.json");
+
+fn install_fixture(fixture: &[u8]) -> (tempfile::TempDir, std::path::PathBuf) {
+    let temp = tempdir().unwrap();
+    let path = temp.path().join("vault.dat");
+    std::fs::write(&path, fixture).unwrap();
+    (temp, path)
+}
+
+#[test]
+fn vault_file_fixtures_keep_their_on_disk_representation() {
+    for fixture in [ENCRYPTED_V1_FIXTURE, PLAIN_V1_FIXTURE] {
+        let vault_file: VaultFile = serde_json::from_slice(fixture)
+            .expect("VaultFile no longer deserializes a v1 fixture - existing vaults would break");
+        let reserialized = serde_json::to_vec(&vault_file).unwrap();
+        assert_eq!(
+            String::from_utf8(reserialized).unwrap(),
+            String::from_utf8(fixture.to_vec()).unwrap(),
+            "VaultFile serialization changed! A dependency bump altered the on-disk format of \
+             salt/nonce/ciphertext; existing vaults may not round-trip."
+        );
+
+        let raw: serde_json::Value = serde_json::from_slice(fixture).unwrap();
+        assert_eq!(raw["salt"].as_array().unwrap().len(), 16);
+        assert_eq!(raw["nonce"].as_array().unwrap().len(), 24);
+    }
+}
+
+#[test]
+fn v1_fixture_vaults_unlock_with_current_code() {
+    let (_encrypted_dir, encrypted_path) = install_fixture(ENCRYPTED_V1_FIXTURE);
+    let mut encrypted = Vault::with_path(encrypted_path).unwrap();
+    assert!(encrypted.unlock(Some("wrong-password")).is_err());
+    encrypted.unlock(Some("fixture-password")).unwrap();
+
+    let (_plain_dir, plain_path) = install_fixture(PLAIN_V1_FIXTURE);
+    let mut plain = Vault::with_path(plain_path).unwrap();
+    plain.unlock(None).unwrap();
+
+    for vault in [&encrypted, &plain] {
+        let servers = vault.list_servers().unwrap();
+        assert_eq!(servers.len(), 1);
+        let server = &servers[0];
+        assert_eq!(
+            server.id.to_string(),
+            "6f1c2a3e-8d4b-4c5a-9e7f-0a1b2c3d4e5f"
+        );
+        assert_eq!(server.name, "prod-web");
+        assert_eq!(server.host, "192.168.1.100");
+        assert_eq!(server.port, 2222);
+        assert_eq!(server.username, "deploy");
+        assert!(!server.protected);
+        assert_eq!(vault.decrypt_password(server).unwrap(), "s3cret");
+        assert_eq!(server.identity_file.as_deref(), Some("~/.ssh/id_ed25519"));
+        assert!(server.forward_agent);
+        assert_eq!(server.tags, vec!["prod".to_string(), "web".to_string()]);
+        assert_eq!(server.gateway_id, None);
+    }
+}
+
+#[test]
+fn count_flags_print_only_the_number() {
+    let temp = tempdir().unwrap();
+    let mut vault = Vault::with_path(temp.path().join("portkey").join("vault.dat")).unwrap();
+    vault.create(None).unwrap();
+    for (i, name) in ["web-01", "web-02", "db-01"].into_iter().enumerate() {
+        vault
+            .add_server(Server::new(
+                name.to_string(),
+                format!("10.0.0.{}", i + 1),
+                22,
+                "root".to_string(),
+                String::new(),
+                None,
+            ))
+            .unwrap();
+    }
+
+    let portkey = |args: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_portkey"))
+            .args(args)
+            .env("XDG_DATA_HOME", temp.path())
+            .stdin(std::process::Stdio::null())
+            .output()
+            .unwrap()
+    };
+
+    let output = portkey(&["list", "--count"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(output.stdout, b"3\n");
+
+    let output = portkey(&["search", "db", "--count"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(output.stdout, b"1\n");
+}
+
+#[test]
+fn named_vaults_are_selected_by_flag_and_listed() {
+    let temp = tempdir().unwrap();
+    let data_dir = temp.path().join("portkey");
+    for (name, servers) in [("vault", 1), ("work", 2)] {
+        let mut vault = Vault::with_path(data_dir.join(format!("{name}.dat"))).unwrap();
+        vault.create(None).unwrap();
+        for i in 0..servers {
+            vault
+                .add_server(Server::new(
+                    format!("{name}-{i}"),
+                    format!("10.0.{servers}.{i}"),
+                    22,
+                    "root".to_string(),
+                    String::new(),
+                    None,
+                ))
+                .unwrap();
+        }
+    }
+
+    let portkey = |args: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_portkey"))
+            .args(args)
+            .env("XDG_DATA_HOME", temp.path())
+            .stdin(std::process::Stdio::null())
+            .output()
+            .unwrap()
+    };
+
+    assert_eq!(portkey(&["list", "--count"]).stdout, b"1\n");
+    assert_eq!(
+        portkey(&["--vault", "work", "list", "--count"]).stdout,
+        b"2\n"
+    );
+    let work_file = data_dir.join("work.dat");
+    let output = portkey(&[
+        "--vault-file",
+        work_file.to_str().unwrap(),
+        "list",
+        "--count",
+    ]);
+    assert_eq!(output.stdout, b"2\n");
+
+    let output = portkey(&["--vault", "work", "vaults"]);
+    assert!(output.status.success(), "{output:?}");
+    let listing = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = listing.lines().collect();
+    assert_eq!(lines.len(), 2, "{listing}");
+    assert!(lines[0].starts_with("  vault "), "{listing}");
+    assert!(lines[1].starts_with("* work "), "{listing}");
+
+    // Names can't reach outside the data directory
+    assert!(Vault::named("../work").is_err());
+    assert!(!portkey(&["--vault", "../work", "list"]).status.success());
+}
+
+#[test]
+fn vault_file_round_trips_through_create_and_unlock() {
+    let temp = tempdir().unwrap();
+    let vault_path = temp.path().join("vault.dat");
+    let mut vault = Vault::with_path(vault_path.clone()).unwrap();
+    vault.create(Some("master-password")).unwrap();
+    vault
+        .add_server(Server::new(
+            "prod".to_string(),
+            "example.com".to_string(),
+            22,
+            "deploy".to_string(),
+            "secret".to_string(),
+            Some("Primary".to_string()),
+        ))
+        .unwrap();
+
+    let bytes = std::fs::read(&vault_path).unwrap();
+    let vault_file: VaultFile = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(serde_json::to_vec(&vault_file).unwrap(), bytes);
+
+    let mut reopened = Vault::with_path(vault_path).unwrap();
+    reopened.unlock(Some("master-password")).unwrap();
+    let servers = reopened.list_servers().unwrap();
+    assert_eq!(servers.len(), 1);
+    assert_eq!(reopened.decrypt_password(&servers[0]).unwrap(), "secret");
+    assert_eq!(servers[0].description.as_deref(), Some("Primary"));
+}
+
+#[test]
+fn clipboard_restore_helper_rejects_malformed_payloads() {
+    let err =
+        portkey::clipboard::restore_after(std::time::Duration::ZERO, &b"not json"[..]).unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("Invalid clipboard restore payload"));
+}
+
+#[test]
+fn connectivity_test_probes_tcp_and_checks_login_without_a_session() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let open_port = listener.local_addr().unwrap().port();
+    let mut server = Server::new(
+        "local".to_string(),
+        "127.0.0.1".to_string(),
+        open_port,
+        "me".to_string(),
+        String::new(),
+        None,
+    );
+    assert!(probe_tcp(&server, std::time::Duration::from_secs(1)).is_ok());
+
+    drop(listener);
+    assert!(probe_tcp(&server, std::time::Duration::from_secs(1)).is_err());
+
+    server.remote_tmux = Some("main".to_string());
+    let args = build_check_args(&server, &ConnectOptions::default());
+    assert!(!args.contains(&"-tt".to_string()));
+    assert_eq!(&args[..2], ["-o", "BatchMode=yes"]);
+    assert_eq!(args.last().unwrap(), "true");
+    assert!(!args.iter().any(|a| a.contains("tmux")));
+
+    server.password = "secret".to_string();
+    let args = build_check_args(&server, &ConnectOptions::default());
+    assert_eq!(&args[..2], ["-o", "NumberOfPasswordPrompts=1"]);
+}
+
+#[test]
+fn binary_lookup_scans_path_without_which() {
+    assert!(find_in_path("portkey-definitely-missing-binary").is_none());
+    assert!(find_in_path("/nonexistent/dir/ssh").is_none());
+
+    #[cfg(unix)]
+    assert_eq!(
+        find_in_path("sh").map(|path| path.file_name().unwrap().to_owned()),
+        Some("sh".into())
+    );
+}
+
+#[test]
+fn binary_lookup_tries_pathext_extensions_only_for_bare_names() {
+    let dir = std::path::Path::new("bin");
+    assert_eq!(path_candidates(&dir.join("ssh"), None), [dir.join("ssh")]);
+    assert_eq!(
+        path_candidates(&dir.join("ssh"), Some(".COM;.EXE;;")),
+        [dir.join("ssh"), dir.join("ssh.COM"), dir.join("ssh.EXE")]
+    );
+    assert_eq!(
+        path_candidates(&dir.join("ssh.exe"), Some(".COM;.EXE")),
+        [dir.join("ssh.exe")]
+    );
+}
+
+#[test]
+fn bind_address_is_validated_and_passed_to_ssh() {
+    assert_eq!(parse_bind_address("").unwrap(), None);
+    assert_eq!(
+        parse_bind_address(" 10.0.0.5 ").unwrap().as_deref(),
+        Some("10.0.0.5")
+    );
+    assert_eq!(
+        parse_bind_address("fe80::1").unwrap().as_deref(),
+        Some("fe80::1")
+    );
+    assert!(parse_bind_address("eth0").is_err());
+
+    let mut server = Server::new(
+        "prod".to_string(),
+        "example.com".to_string(),
+        22,
+        "deploy".to_string(),
+        String::new(),
+        None,
+    );
+    server.bind_address = Some("10.0.0.5".to_string());
+
+    let help = manual_connection_help(&server, &ConnectOptions::default());
+    assert!(help.contains("-b 10.0.0.5"));
+    assert!(render_ssh_config(&[server])
+        .unwrap()
+        .contains("  BindAddress 10.0.0.5"));
+}
+
+#[test]
+fn unlock_errors_distinguish_wrong_password_from_corruption() {
+    fn vault_error(result: anyhow::Result<()>) -> VaultError {
+        result.unwrap_err().downcast::<VaultError>().unwrap()
+    }
+
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    assert!(matches!(
+        vault_error(vault.unlock(None)),
+        VaultError::NotFound
+    ));
+    assert!(matches!(
+        vault
+            .list_servers()
+            .unwrap_err()
+            .downcast::<VaultError>()
+            .unwrap(),
+        VaultError::Locked
+    ));
+
+    vault.create(Some("pass")).unwrap();
+    let mut reopened = Vault::with_path(path.clone()).unwrap();
+    assert!(matches!(
+        vault_error(reopened.unlock(None)),
+        VaultError::PasswordRequired
+    ));
+    assert!(matches!(
+        vault_error(reopened.unlock(Some("wrong"))),
+        VaultError::WrongPassword
+    ));
+
+    // Decrypts fine with the right password, but the contents aren't vault data
+    let mut vault_file: VaultFile = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+    let key = MasterKey::from_password("pass", &vault_file.salt, vault_file.kdf).unwrap();
+    (vault_file.nonce, vault_file.ciphertext) = key.encrypt(b"not json");
+    std::fs::write(&path, serde_json::to_vec(&vault_file).unwrap()).unwrap();
+    assert!(matches!(
+        vault_error(reopened.unlock(Some("pass"))),
+        VaultError::Corrupt(_)
+    ));
+
+    std::fs::write(&path, b"{ truncated").unwrap();
+    assert!(matches!(
+        vault_error(reopened.unlock(Some("pass"))),
+        VaultError::Corrupt(_)
+    ));
+}
+
+#[test]
+#[cfg(unix)]
+fn key_file_vaults_unlock_headlessly_and_require_private_key_files() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempdir().unwrap();
+    let write_key = |name: &str, bytes: &[u8], mode: u32| {
+        let path = dir.path().join(name);
+        std::fs::write(&path, bytes).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+        path
+    };
+    let key = write_key("vault.key", &[7; 32], 0o600);
+    let other_key = write_key("other.key", &[8; 32], 0o600);
+    let readable_key = write_key("readable.key", &[7; 32], 0o644);
+    let short_key = write_key("short.key", &[7; 16], 0o600);
+
+    assert!(MasterKey::from_key_file(&readable_key).is_err());
+    assert!(MasterKey::from_key_file(&short_key).is_err());
+
+    let path = dir.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.create_with_keyfile(&key).unwrap();
+    vault
+        .add_server(Server::new(
+            "ci".to_string(),
+            "10.0.0.3".to_string(),
+            22,
+            "runner".to_string(),
+            "secret".to_string(),
+            None,
+        ))
+        .unwrap();
+    let stored: VaultFile = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+    assert_eq!(stored.key_source, KeySource::KeyFile);
+
+    let mut reopened = Vault::with_path(path).unwrap();
+    for result in [reopened.unlock(None), reopened.unlock(Some("secret"))] {
+        assert!(matches!(
+            result.unwrap_err().downcast::<VaultError>().unwrap(),
+            VaultError::KeyFileRequired
+        ));
+    }
+    assert!(matches!(
+        reopened
+            .unlock_with_keyfile(&other_key)
+            .unwrap_err()
+            .downcast::<VaultError>()
+            .unwrap(),
+        VaultError::WrongKeyFile
+    ));
+    reopened.unlock_with_keyfile(&key).unwrap();
+    let server = &reopened.list_servers().unwrap()[0];
+    assert_eq!(reopened.decrypt_password(server).unwrap(), "secret");
+
+    // Password vaults refuse key files
+    let (_fixture_dir, fixture_path) = install_fixture(ENCRYPTED_V1_FIXTURE);
+    let mut password_vault = Vault::with_path(fixture_path).unwrap();
+    assert!(matches!(
+        password_vault
+            .unlock_with_keyfile(&key)
+            .unwrap_err()
+            .downcast::<VaultError>()
+            .unwrap(),
+        VaultError::PasswordKeyed
+    ));
+}
+
+#[test]
+#[cfg(unix)]
+fn key_file_vaults_unlock_again_from_the_key_file_after_a_lock() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempdir().unwrap();
+    let key = dir.path().join("vault.key");
+    std::fs::write(&key, [7; 32]).unwrap();
+    std::fs::set_permissions(&key, std::fs::Permissions::from_mode(0o600)).unwrap();
+    let mut vault = Vault::with_path(dir.path().join("vault.dat")).unwrap();
+    vault.create_with_keyfile(&key).unwrap();
+    vault
+        .add_server(Server::new(
+            "ci".to_string(),
+            "10.0.0.3".to_string(),
+            22,
+            "runner".to_string(),
+            "secret".to_string(),
+            None,
+        ))
+        .unwrap();
+
+    // What the TUI does on an idle lock
+    assert!(vault.is_encrypted());
+    vault.lock();
+    assert!(matches!(
+        unlock_again(&mut vault, "", None)
+            .unwrap_err()
+            .downcast::<VaultError>()
+            .unwrap(),
+        VaultError::KeyFileRequired
+    ));
+    unlock_again(&mut vault, "", Some(&key)).unwrap();
+    let server = &vault.list_servers().unwrap()[0];
+    assert_eq!(vault.decrypt_password(server).unwrap(), "secret");
+}
+
+#[test]
+fn revealed_passwords_count_down_and_hide_after_the_reveal_window() {
+    assert_eq!(REVEAL_FOR, std::time::Duration::from_secs(10));
+    assert_eq!(reveal_seconds_left(std::time::Duration::ZERO), Some(10));
+    assert_eq!(
+        reveal_seconds_left(std::time::Duration::from_millis(500)),
+        Some(10)
+    );
+    assert_eq!(
+        reveal_seconds_left(std::time::Duration::from_millis(9_001)),
+        Some(1)
+    );
+    assert_eq!(reveal_seconds_left(REVEAL_FOR), None);
+    assert_eq!(
+        reveal_seconds_left(std::time::Duration::from_secs(60)),
+        None
+    );
+}
+
+#[test]
+fn quit_prompt_accepts_a_second_quit_key_within_its_window() {
+    assert_eq!(
+        confirm_quit_seconds_left(std::time::Duration::ZERO),
+        Some(3)
+    );
+    assert_eq!(
+        confirm_quit_seconds_left(std::time::Duration::from_millis(2_500)),
+        Some(1)
+    );
+    assert_eq!(confirm_quit_seconds_left(CONFIRM_QUIT_FOR), None);
+
+    for code in [
+        KeyCode::Char('q'),
+        KeyCode::Char('Q'),
+        KeyCode::Char('y'),
+        KeyCode::Esc,
+    ] {
+        assert!(confirms_quit(code), "{code:?}");
+    }
+    for code in [KeyCode::Char('n'), KeyCode::Char('j'), KeyCode::Enter] {
+        assert!(!confirms_quit(code), "{code:?}");
+    }
+}
+
+#[test]
+fn help_overlay_and_footer_share_one_key_list() {
+    let footer = footer_hints(false);
+    assert!(footer.starts_with("? help | Enter connect | "));
+    assert!(footer.ends_with(" | Ctrl+C force quit"));
+    for (key, description) in KEYBINDINGS {
+        assert!(footer.contains(&format!("{key} {description}")), "{key}");
+    }
+    let keys: Vec<&str> = KEYBINDINGS.iter().map(|(key, _)| *key).collect();
+    let mut unique = keys.clone();
+    unique.sort_unstable();
+    unique.dedup();
+    assert_eq!(unique.len(), keys.len());
+
+    // A read-only vault leaves out the keys that would change it
+    assert_eq!(keybindings(false).len(), KEYBINDINGS.len());
+    let read_only = footer_hints(true);
+    assert!(read_only.contains("/ filter | "));
+    assert!(!read_only.contains("a add") && !read_only.contains("X delete marked"));
+    assert!(keybindings(true).iter().all(|(key, _)| *key != "u"));
+
+    let screen = Rect::new(0, 0, 80, 24);
+    assert_eq!(centered(screen, 40, 10), Rect::new(20, 7, 40, 10));
+    // A box bigger than the screen is cut down to it
+    assert_eq!(centered(screen, 100, 40), screen);
+    let offset = Rect::new(10, 5, 20, 10);
+    assert_eq!(centered(offset, 10, 4), Rect::new(15, 8, 10, 4));
+}
+
+#[test]
+fn paging_moves_by_the_visible_rows_and_stops_at_the_ends() {
+    // A 12-row list area loses two rows to its borders
+    assert_eq!(visible_rows(12), 10);
+    assert_eq!(visible_rows(2), 1);
+    assert_eq!(visible_rows(0), 1);
+
+    assert_eq!(page_down(0, 10, 25), 10);
+    assert_eq!(page_down(20, 10, 25), 24);
+    assert_eq!(page_down(0, 10, 0), 0);
+}
+
+#[test]
+fn info_pane_lists_connection_settings_and_hides_the_password() {
+    let gateway = Server::new(
+        "bastion".to_string(),
+        "203.0.113.1".to_string(),
+        22,
+        "jump".to_string(),
+        String::new(),
+        None,
+    );
+    let mut server = Server::new(
+        "db".to_string(),
+        "10.0.0.9".to_string(),
+        2222,
+        "root".to_string(),
+        "hunter2".to_string(),
+        None,
+    );
+    server.gateway_id = Some(gateway.id);
+    server.forward_agent = true;
+    server.connect_timeout = Some(5);
+    server.port_forwards = parse_port_forwards("8080:localhost:80").unwrap();
+    server.protected = true;
+    let servers = vec![gateway.clone(), server.clone()];
+
+    let fields = detail_fields(&server, &servers, None);
+    let value = |label: &str| {
+        fields
+            .iter()
+            .find(|(name, _)| *name == label)
+            .map(|(_, value)| value.clone())
+    };
+    assert_eq!(value("Host").as_deref(), Some("10.0.0.9:2222"));
+    assert_eq!(value("Password").as_deref(), Some("•••••••• (p to reveal)"));
+    assert_eq!(value("Gateway").as_deref(), Some("bastion"));
+    assert_eq!(value("Forward agent").as_deref(), Some("yes"));
+    assert_eq!(value("Connect timeout").as_deref(), Some("5s"));
+    assert_eq!(
+        value("Forward").as_deref(),
+        Some("localhost:8080 -> localhost:80")
+    );
+    assert_eq!(value("Protected").as_deref(), Some("yes"));
+    assert_eq!(value("Last connected").as_deref(), Some("never"));
+    assert_eq!(value("Keepalive"), None);
+
+    let revealed = detail_fields(&server, &servers, Some("hunter2"));
+    assert!(revealed.contains(&("Password", "hunter2".to_string())));
+    let no_password = detail_fields(&gateway, &servers, None);
+    assert!(no_password.contains(&("Password", "(none)".to_string())));
+}
+
+#[test]
+fn kdf_parameters_are_stored_in_the_vault_file_and_used_to_unlock() {
+    let fixture: VaultFile = serde_json::from_slice(ENCRYPTED_V1_FIXTURE).unwrap();
+    assert_eq!(fixture.kdf, KdfParams::default());
+    assert_eq!(fixture.kdf, KdfStrength::Interactive.params());
+    assert_eq!(
+        "moderate".parse::<KdfStrength>().unwrap(),
+        KdfStrength::Moderate
+    );
+    assert!("extreme".parse::<KdfStrength>().is_err());
+
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("vault.dat");
+    let moderate = KdfStrength::Moderate.params();
+
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.create_with_kdf(Some("pass"), moderate).unwrap();
+    vault
+        .add_server(Server::new(
+            "db".to_string(),
+            "10.0.0.9".to_string(),
+            22,
+            "root".to_string(),
+            "pw".to_string(),
+            None,
+        ))
+        .unwrap();
+
+    // Saves keep the parameters the vault was created with
+    let stored: VaultFile = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+    assert_eq!(stored.kdf, moderate);
+
+    let mut reopened = Vault::with_path(path).unwrap();
+    assert!(reopened.unlock(Some("wrong")).is_err());
+    reopened.unlock(Some("pass")).unwrap();
+    assert_eq!(reopened.list_servers().unwrap().len(), 1);
+}
+
+#[test]
+fn change_password_rekeys_and_toggles_encryption_without_losing_servers() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("vault.dat");
+
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.create(Some("old-pass")).unwrap();
+    vault
+        .add_server(Server::new(
+            "db".to_string(),
+            "10.0.0.9".to_string(),
+            22,
+            "root".to_string(),
+            "pw".to_string(),
+            None,
+        ))
+        .unwrap();
+    let old_salt = serde_json::from_slice::<VaultFile>(&std::fs::read(&path).unwrap())
+        .unwrap()
+        .salt;
+
+    assert!(vault.change_password(Some("wrong"), Some("x")).is_err());
+    vault
+        .change_password(Some("old-pass"), Some("new-pass"))
+        .unwrap();
+    assert!(!vault.undo_path().exists());
+    let new_salt = serde_json::from_slice::<VaultFile>(&std::fs::read(&path).unwrap())
+        .unwrap()
+        .salt;
+    assert_ne!(old_salt, new_salt);
+
+    let mut reopened = Vault::with_path(path.clone()).unwrap();
+    assert!(reopened.unlock(Some("old-pass")).is_err());
+    reopened.unlock(Some("new-pass")).unwrap();
+    assert_eq!(reopened.list_servers().unwrap()[0].name, "db");
+
+    reopened.change_password(Some("new-pass"), None).unwrap();
+    let mut plain = Vault::with_path(path.clone()).unwrap();
+    plain.unlock(None).unwrap();
+    assert_eq!(plain.list_servers().unwrap().len(), 1);
+
+    plain.change_password(None, Some("again")).unwrap();
+    let mut encrypted = Vault::with_path(path).unwrap();
+    assert!(encrypted.unlock(None).is_err());
+    encrypted.unlock(Some("again")).unwrap();
+    let server = &encrypted.list_servers().unwrap()[0];
+    assert_eq!(encrypted.decrypt_password(server).unwrap(), "pw");
+}
+
+#[test]
+fn interrupted_save_leaves_the_previous_vault_intact() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("vault.dat");
+
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.create(None).unwrap();
+    vault
+        .add_server(Server::new(
+            "kept".to_string(),
+            "10.0.0.1".to_string(),
+            22,
+            "root".to_string(),
+            String::new(),
+            None,
+        ))
+        .unwrap();
+    let original = std::fs::read(&path).unwrap();
+
+    // A save killed mid-write only ever touches a temp file next to the vault
+    let partial = &original[..original.len() / 2];
+    let stale_temp = dir.path().join(".vault.dat.interrupted.tmp");
+    std::fs::write(&stale_temp, partial).unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), original);
+    let mut reopened = Vault::with_path(path.clone()).unwrap();
+    reopened.unlock(None).unwrap();
+    assert_eq!(reopened.list_servers().unwrap()[0].name, "kept");
+
+    // The next save still goes through a fresh temp file and leaves none behind
+    reopened
+        .remove_server(&reopened.list_servers().unwrap()[0].id.clone())
+        .unwrap();
+    let leftovers: Vec<_> = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name.ends_with(".tmp"))
+        .collect();
+    assert_eq!(leftovers, vec![".vault.dat.interrupted.tmp".to_string()]);
+    let mut saved = Vault::with_path(path.clone()).unwrap();
+    saved.unlock(None).unwrap();
+    assert!(saved.list_servers().unwrap().is_empty());
+    let original = std::fs::read(&path).unwrap();
+    let temp_files = || {
+        std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.ends_with(".tmp") && !name.contains("interrupted"))
+            .count()
+    };
+    let server = Server::new(
+        "new".to_string(),
+        "10.0.0.2".to_string(),
+        22,
+        "root".to_string(),
+        String::new(),
+        None,
+    );
+
+    // A vault whose temp file name is too long to create fails to save,
+    // even as root, and keeps its bytes
+    let long_path = dir.path().join(format!("{}.dat", "v".repeat(220)));
+    std::fs::copy(&path, &long_path).unwrap();
+    let mut long = Vault::with_path(long_path.clone()).unwrap();
+    long.unlock(None).unwrap();
+    assert!(long.add_server(server.clone()).is_err());
+    assert_eq!(std::fs::read(&long_path).unwrap(), original);
+    assert_eq!(temp_files(), 0);
+    std::fs::remove_file(&long_path).unwrap();
+
+    // A rename that fails after the temp file is written removes the temp file
+    let aside = dir.path().join("aside");
+    std::fs::rename(&path, &aside).unwrap();
+    std::fs::create_dir(&path).unwrap();
+    std::fs::write(path.join("blocker"), "").unwrap();
+    assert!(saved.add_server(server).is_err());
+    assert_eq!(temp_files(), 0);
+    std::fs::remove_dir_all(&path).unwrap();
+    std::fs::rename(&aside, &path).unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), original);
+
+    // A full disk: with the file size capped below the restored vault's, the
+    // write itself fails and the partial temp file is removed
+    #[cfg(unix)]
+    {
+        let data_home = tempdir().unwrap();
+        let vault_dir = data_home.path().join("portkey");
+        let vault_path = vault_dir.join("vault.dat");
+        let mut big = Vault::with_path(vault_path.clone()).unwrap();
+        big.create(None).unwrap();
+        let server = Server::new(
+            "big".to_string(),
+            "10.0.0.3".to_string(),
+            22,
+            "root".to_string(),
+            String::new(),
+            Some("x".repeat(8192)),
+        );
+        let id = server.id;
+        big.add_server(server).unwrap();
+        big.remove_server(&id).unwrap();
+        let small = std::fs::read(&vault_path).unwrap();
+
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg("trap '' XFSZ; ulimit -f 4; exec \"$@\"")
+            .arg("sh")
+            .args([env!("CARGO_BIN_EXE_portkey"), "undo"])
+            .env("XDG_DATA_HOME", data_home.path())
+            .stdin(std::process::Stdio::null())
+            .output()
+            .unwrap();
+        assert!(!output.status.success(), "{output:?}");
+        let leftovers = std::fs::read_dir(&vault_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.ends_with(".tmp"))
+            .count();
+        assert_eq!(leftovers, 0);
+        assert_eq!(std::fs::read(&vault_path).unwrap(), small);
+    }
+}
+
+#[test]
+fn export_imports_into_a_vault_with_a_different_password_skipping_duplicates() {
+    let dir = tempdir().unwrap();
+    let server = |name: &str, host: &str| {
+        Server::new(
+            name.to_string(),
+            host.to_string(),
+            22,
+            "root".to_string(),
+            "pw".to_string(),
+            None,
+        )
+    };
+
+    let mut source = Vault::with_path(dir.path().join("a/vault.dat")).unwrap();
+    source.create(Some("source-pass")).unwrap();
+    let bastion = server("bastion", "10.0.0.1");
+    let mut inner = server("inner", "10.0.0.2");
+    inner.gateway_id = Some(bastion.id);
+    source.add_server(bastion).unwrap();
+    source.add_server(inner).unwrap();
+    source.add_server(server("web", "10.0.0.3")).unwrap();
+
+    let backup = dir.path().join("backup.json");
+    source.export(&backup, Some("backup-pass")).unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = std::fs::metadata(&backup).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode, 0o600);
+    }
+
+    let mut target = Vault::with_path(dir.path().join("b/vault.dat")).unwrap();
+    target.create(Some("target-pass")).unwrap();
+    target.add_server(server("my-bastion", "10.0.0.1")).unwrap();
+    let local_bastion = target.list_servers().unwrap()[0].id;
+
+    let export = ExportFile::read(&backup).unwrap();
+    assert!(export.encrypted);
+    assert!(export.open(None).is_err());
+    assert!(export.open(Some("source-pass")).is_err());
+    let data = export.open(Some("backup-pass")).unwrap();
+
+    let preview = target.import_servers(data.servers.clone(), true).unwrap();
+    assert_eq!(preview.added, vec!["inner", "web"]);
+    assert_eq!(preview.skipped, vec!["bastion"]);
+    assert_eq!(target.list_servers().unwrap().len(), 1);
+
+    target.import_servers(data.servers, false).unwrap();
+    let servers = target.list_servers().unwrap();
+    assert_eq!(servers.len(), 3);
+    let inner = servers.iter().find(|s| s.name == "inner").unwrap();
+    assert_eq!(inner.gateway_id, Some(local_bastion));
+
+    std::fs::write(dir.path().join("junk.json"), b"{}").unwrap();
+    assert!(ExportFile::read(&dir.path().join("junk.json")).is_err());
+}
+
+#[test]
+fn adding_a_duplicate_endpoint_is_rejected_unless_allowed() {
+    let dir = tempdir().unwrap();
+    let mut vault = Vault::with_path(dir.path().join("vault.dat")).unwrap();
+    vault.create(None).unwrap();
+    let server = |name: &str, host: &str, user: &str, port: u16| {
+        Server::new(
+            name.to_string(),
+            host.to_string(),
+            port,
+            user.to_string(),
+            String::new(),
+            None,
+        )
+    };
+
+    vault
+        .add_server(server("web", "Web.example.com", "deploy", 22))
+        .unwrap();
+    let err = vault
+        .add_server(server("web-again", "web.EXAMPLE.com", "DEPLOY", 22))
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<VaultError>(),
+        Some(VaultError::DuplicateServer(name)) if name == "web"
+    ));
+
+    vault
+        .add_server(server("web-alt-port", "web.example.com", "deploy", 2222))
+        .unwrap();
+    vault
+        .add_server_allowing_duplicate(server("web-again", "web.example.com", "deploy", 22))
+        .unwrap();
+    assert_eq!(vault.list_servers().unwrap().len(), 3);
+}
+
+#[test]
+fn mark_connected_persists_and_recent_sort_puts_latest_first() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.create(Some("pass")).unwrap();
+    for (name, host) in [
+        ("beta", "b.example.com"),
+        ("alpha", "c.example.com"),
+        ("gamma", "a.example.com"),
+    ] {
+        vault
+            .add_server(Server::new(
+                name.to_string(),
+                host.to_string(),
+                22,
+                "ops".to_string(),
+                String::new(),
+                None,
+            ))
+            .unwrap();
+    }
+    let id_of = |vault: &Vault, name: &str| {
+        vault
+            .list_servers()
+            .unwrap()
+            .iter()
+            .find(|s| s.name == name)
+            .unwrap()
+            .id
+    };
+    let (gamma, beta) = (id_of(&vault, "gamma"), id_of(&vault, "beta"));
+    vault.mark_connected(&gamma).unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(5));
+    vault.mark_connected(&beta).unwrap();
+    assert!(!vault.changed_on_disk());
+
+    let mut reopened = Vault::with_path(path).unwrap();
+    reopened.unlock(Some("pass")).unwrap();
+    let sorted_names = |order: SortOrder| {
+        let mut servers: Vec<&Server> = reopened.list_servers().unwrap().iter().collect();
+        servers.sort_by(|a, b| order.compare(a, b));
+        servers.iter().map(|s| s.name.clone()).collect::<Vec<_>>()
+    };
+    assert_eq!(sorted_names(SortOrder::Recent), ["beta", "gamma", "alpha"]);
+    assert_eq!(sorted_names(SortOrder::Name), ["alpha", "beta", "gamma"]);
+    assert_eq!(sorted_names(SortOrder::Host), ["gamma", "beta", "alpha"]);
+
+    assert_eq!("RECENT".parse::<SortOrder>().unwrap(), SortOrder::Recent);
+    assert!("size".parse::<SortOrder>().is_err());
+}
+
+#[test]
+fn locking_forgets_decrypted_data_until_unlocked_again() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.create(Some("pass")).unwrap();
+    vault
+        .add_server(Server::new(
+            "db".to_string(),
+            "10.0.0.7".to_string(),
+            22,
+            "root".to_string(),
+            "secret".to_string(),
+            None,
+        ))
+        .unwrap();
+    assert!(vault.is_encrypted());
+
+    vault.lock();
+    assert!(!vault.is_unlocked());
+    assert!(!vault.is_encrypted());
+    assert!(vault.list_servers().is_err());
+    assert!(vault.unlock(Some("wrong")).is_err());
+    vault.unlock(Some("pass")).unwrap();
+    let server = &vault.list_servers().unwrap()[0];
+    assert_eq!(vault.decrypt_password(server).unwrap(), "secret");
+
+    let mut plain = Vault::with_path(dir.path().join("plain.dat")).unwrap();
+    plain.create(None).unwrap();
+    assert!(plain.is_unlocked());
+    assert!(!plain.is_encrypted());
+}
+
+#[test]
+fn undo_restores_the_last_removal_once_from_an_encrypted_snapshot() {
+    let temp = tempdir().unwrap();
+    let vault_path = temp.path().join("vault.dat");
+    let mut vault = Vault::with_path(vault_path.clone()).unwrap();
+    vault.create(Some("master-password")).unwrap();
+    assert_eq!(vault.undo().unwrap(), None);
+
+    let server = Server::new(
+        "web-01".to_string(),
+        "10.0.0.1".to_string(),
+        22,
+        "deploy".to_string(),
+        "hunter2".to_string(),
+        None,
+    );
+    let id = server.id;
+    vault.add_server(server).unwrap();
+    vault.remove_server(&id).unwrap();
+
+    let undo_path = vault.undo_path();
+    let snapshot = std::fs::read_to_string(&undo_path).unwrap();
+    assert!(!snapshot.contains("hunter2"));
+    assert!(!snapshot.contains("web-01"));
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = std::fs::metadata(&undo_path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode, 0o600);
+    }
+
+    // The snapshot survives a restart
+    let mut reopened = Vault::with_path(vault_path).unwrap();
+    reopened.unlock(Some("master-password")).unwrap();
+    assert_eq!(
+        reopened.undo().unwrap().as_deref(),
+        Some("removed 'web-01'")
+    );
+    assert_eq!(reopened.list_servers().unwrap()[0].id, id);
+    assert!(!undo_path.exists());
+    assert_eq!(reopened.undo().unwrap(), None);
+}
+
+#[test]
+fn connection_history_is_capped_persisted_and_kept_across_undo() {
+    let mut data = VaultData::new();
+    assert_eq!(data.history_limit, DEFAULT_HISTORY_LIMIT);
+    data.history_limit = 2;
+    let ids: Vec<uuid::Uuid> = (0..3).map(|_| uuid::Uuid::new_v4()).collect();
+    for id in &ids {
+        data.record_connection(ConnectionEvent {
+            server_id: *id,
+            at: chrono::Utc::now(),
+            success: true,
+        });
+    }
+    let kept: Vec<uuid::Uuid> = data.history.iter().map(|e| e.server_id).collect();
+    assert_eq!(kept, ids[1..]);
+
+    let temp = tempdir().unwrap();
+    let vault_path = temp.path().join("vault.dat");
+    let mut vault = Vault::with_path(vault_path.clone()).unwrap();
+    vault.create(Some("master-password")).unwrap();
+    let server = Server::new(
+        "web-01".to_string(),
+        "10.0.0.1".to_string(),
+        22,
+        "deploy".to_string(),
+        String::new(),
+        None,
+    );
+    let id = server.id;
+    vault.add_server(server).unwrap();
+    vault.record_connection(&id, true).unwrap();
+    vault.record_connection(&id, false).unwrap();
+
+    // Undoing the add must not roll back the log
+    vault.undo().unwrap();
+    assert!(vault.list_servers().unwrap().is_empty());
+    assert_eq!(vault.history().unwrap().len(), 2);
+
+    vault.set_history_limit(1).unwrap();
+    let mut reopened = Vault::with_path(vault_path).unwrap();
+    reopened.unlock(Some("master-password")).unwrap();
+    let history = reopened.history().unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].server_id, id);
+    assert!(!history[0].success);
+}
+
+#[test]
+fn vault_detects_and_reloads_external_changes_but_not_its_own() {
+    let temp = tempdir().unwrap();
+    let vault_path = temp.path().join("vault.dat");
+    let mut tui_vault = Vault::with_path(vault_path.clone()).unwrap();
+    tui_vault.create(Some("master-password")).unwrap();
+    assert!(!tui_vault.changed_on_disk());
+
+    let mut cli_vault = Vault::with_path(vault_path).unwrap();
+    cli_vault.unlock(Some("master-password")).unwrap();
+    cli_vault
+        .add_server(Server::new(
+            "external".to_string(),
+            "example.com".to_string(),
+            22,
+            "deploy".to_string(),
+            String::new(),
+            None,
+        ))
+        .unwrap();
+
+    assert!(tui_vault.changed_on_disk());
+    tui_vault.reload().unwrap();
+    assert!(!tui_vault.changed_on_disk());
+    assert_eq!(tui_vault.list_servers().unwrap()[0].name, "external");
+
+    tui_vault
+        .add_server(Server::new(
+            "local".to_string(),
+            "example.org".to_string(),
+            22,
+            "deploy".to_string(),
+            String::new(),
+            None,
+        ))
+        .unwrap();
+    assert!(!tui_vault.changed_on_disk());
+}
+
+#[test]
+fn quiet_login_suppresses_banners_and_defaults_off() {
+    let mut server = Server::new(
+        "noisy".to_string(),
+        "example.com".to_string(),
+        22,
+        "deploy".to_string(),
+        String::new(),
+        None,
+    );
+    let args = build_ssh_args(&server, &ConnectOptions::default());
+    assert!(!args.contains(&"-q".to_string()));
+
+    server.quiet_login = true;
+    let args = build_ssh_args(&server, &ConnectOptions::default());
+    assert!(args.contains(&"-q".to_string()));
+    assert!(args.contains(&"LogLevel=QUIET".to_string()));
+
+    let legacy: Server = serde_json::from_str(
+        r#"{"id":"6f1c2a3e-8d4b-4c5a-9e7f-0a1b2c3d4e5f","name":"old","host":"h","port":22,
+        "username":"u","password":"","description":null,
+        "created_at":"2024-01-02T03:04:05Z","updated_at":"2024-01-02T03:04:05Z"}"#,
+    )
+    .unwrap();
+    assert!(!legacy.quiet_login);
+}
+
+#[test]
+fn auth_method_defaults_to_password_and_gates_sshpass_and_identity() {
+    let legacy: Server = serde_json::from_str(
+        r#"{"id":"6f1c2a3e-8d4b-4c5a-9e7f-0a1b2c3d4e5f","name":"old","host":"h","port":22,
+        "username":"u","password":"secret","description":null,
+        "created_at":"2024-01-02T03:04:05Z","updated_at":"2024-01-02T03:04:05Z"}"#,
+    )
+    .unwrap();
+    assert_eq!(legacy.auth_method, AuthMethod::Password);
+    assert!(legacy.uses_sshpass());
+
+    let mut server = legacy.clone();
+    server.auth_method = AuthMethod::PublicKey;
+    assert!(!server.uses_sshpass());
+    assert!(server.validate_auth().is_err());
+
+    server.identity_file = Some("~/.ssh/id_ed25519".to_string());
+    server.validate_auth().unwrap();
+    let args = build_ssh_args(&server, &ConnectOptions::default());
+    assert!(args.windows(2).any(|w| w == ["-i", "~/.ssh/id_ed25519"]));
+
+    server.auth_method = AuthMethod::Agent;
+    assert!(!server.uses_sshpass());
+    let args = build_ssh_args(&server, &ConnectOptions::default());
+    assert!(!args.contains(&"-i".to_string()));
+
+    let json = serde_json::to_string(&server).unwrap();
+    assert!(json.contains(r#""auth_method":"agent""#));
+}
+
+#[cfg(unix)]
+#[test]
+fn password_servers_fall_back_to_plain_ssh_without_sshpass() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = tempdir().unwrap();
+    let mut vault = Vault::with_path(temp.path().join("portkey").join("vault.dat")).unwrap();
+    vault.create(None).unwrap();
+    vault
+        .add_server(Server::new(
+            "prod".to_string(),
+            "10.0.0.1".to_string(),
+            22,
+            "deploy".to_string(),
+            "s3cret".to_string(),
+            None,
+        ))
+        .unwrap();
+
+    // A PATH with a stand-in ssh but neither sshpass nor a clipboard tool
+    let bin = temp.path().join("bin");
+    std::fs::create_dir(&bin).unwrap();
+    let ssh_args = temp.path().join("ssh-args");
+    let fake_ssh = bin.join("ssh");
+    std::fs::write(
+        &fake_ssh,
+        format!("#!/bin/sh\necho \"$@\" > '{}'\n", ssh_args.display()),
+    )
+    .unwrap();
+    std::fs::set_permissions(&fake_ssh, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let portkey = |args: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_portkey"))
+            .args(args)
+            .env("XDG_DATA_HOME", temp.path())
+            .env("PATH", &bin)
+            .env_remove("DISPLAY")
+            .env_remove("WAYLAND_DISPLAY")
+            .stdin(std::process::Stdio::null())
+            .output()
+            .unwrap()
+    };
+
+    // With nowhere to put the password, the connection isn't attempted
+    let output = portkey(&["connect", "prod"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("sshpass is required"), "{stderr}");
+    assert!(!stderr.contains("s3cret"));
+    assert!(!ssh_args.exists());
+
+    // --no-sshpass runs ssh itself and leaves the password prompt to it
+    let output = portkey(&["connect", "prod", "--no-sshpass"]);
+    assert!(output.status.success(), "{output:?}");
+    let args = std::fs::read_to_string(&ssh_args).unwrap();
+    assert!(args.trim_end().ends_with("-p 22 deploy@10.0.0.1"), "{args}");
+    assert!(!args.contains("s3cret"));
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("s3cret"));
+}
+
+#[test]
+fn protected_servers_are_only_removed_by_their_exact_name() {
+    let mut server = Server::new(
+        "prod-db".to_string(),
+        "10.0.0.1".to_string(),
+        22,
+        "root".to_string(),
+        String::new(),
+        None,
+    );
+    assert!(!server.protected);
+    server.protected = true;
+
+    assert!(server.confirms_removal("prod-db"));
+    assert!(server.confirms_removal("  prod-db\n"));
+    assert!(!server.confirms_removal("Prod-DB"));
+    assert!(!server.confirms_removal("prod"));
+    assert!(!server.confirms_removal(""));
+}
+
+#[test]
+fn servers_are_validated_for_host_port_name_and_user() {
+    for host in [
+        "example.com",
+        "db-1.internal.",
+        "10.0.0.1",
+        "fd00::7",
+        "my_host",
+    ] {
+        assert!(validate_host(host).is_ok(), "{host} should be accepted");
+    }
+    for host in [
+        "",
+        "-oProxyCommand=x",
+        "bad host",
+        "a..b",
+        "web-.example.com",
+        "ex@mple",
+    ] {
+        assert_eq!(
+            validate_host(host),
+            Err(ValidationError::InvalidHost(host.to_string()))
+        );
+    }
+
+    assert_eq!(parse_port(" 2222 "), Ok(2222));
+    for port in ["0", "70000", "ssh", ""] {
+        let error = parse_port(port).unwrap_err();
+        assert!(error.to_string().contains("1 to 65535"));
+    }
+
+    let valid = Server::new(
+        "web".to_string(),
+        "10.0.0.1".to_string(),
+        22,
+        "deploy".to_string(),
+        String::new(),
+        None,
+    );
+    assert!(valid.validate().is_ok());
+    let invalid = |edit: fn(&mut Server)| {
+        let mut server = valid.clone();
+        edit(&mut server);
+        server.validate().unwrap_err()
+    };
+    assert_eq!(
+        invalid(|s| s.name = " ".to_string()),
+        ValidationError::EmptyName
+    );
+    assert_eq!(
+        invalid(|s| s.username.clear()),
+        ValidationError::EmptyUsername
+    );
+    assert_eq!(
+        invalid(|s| s.port = 0),
+        ValidationError::InvalidPort("0".to_string())
+    );
+
+    let dir = tempdir().unwrap();
+    let mut vault = Vault::with_path(dir.path().join("vault.dat")).unwrap();
+    vault.create(None).unwrap();
+    let mut bad = valid.clone();
+    bad.host = "not a host".to_string();
+    let error = vault.add_server(bad).unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<ValidationError>(),
+        Some(ValidationError::InvalidHost(_))
+    ));
+    vault.add_server(valid.clone()).unwrap();
+    let mut renamed = valid;
+    renamed.name.clear();
+    assert!(vault.replace_server(renamed).is_err());
+}
+
+#[test]
+fn tags_parse_from_comma_list_and_match_exactly_ignoring_case() {
+    assert_eq!(
+        parse_tags(" Prod, web,,prod ,DB "),
+        vec!["prod", "web", "db"]
+    );
+    assert!(parse_tags(" , ").is_empty());
+
+    let mut server = Server::new(
+        "api".to_string(),
+        "10.0.0.2".to_string(),
+        22,
+        "deploy".to_string(),
+        String::new(),
+        None,
+    );
+    assert_eq!(server.tag_label(), "");
+    server.tags = parse_tags("prod,web");
+    assert!(server.has_tag("PROD"));
+    assert!(server.has_tag(" web "));
+    assert!(!server.has_tag("pro"));
+    assert!(!server.has_tag("production"));
+    assert_eq!(server.tag_label(), "[prod,web]");
+}
+
+#[test]
+fn connect_timeout_and_keepalive_default_and_can_be_overridden() {
+    let mut server: Server = serde_json::from_str(
+        r#"{"id":"6f1c2a3e-8d4b-4c5a-9e7f-0a1b2c3d4e5f","name":"vpn","host":"h","port":22,
+        "username":"u","password":"","description":null,
+        "created_at":"2024-01-02T03:04:05Z","updated_at":"2024-01-02T03:04:05Z"}"#,
+    )
+    .unwrap();
+    assert_eq!(server.connect_timeout, None);
+    assert_eq!(server.keepalive, None);
+
+    let args = build_ssh_args(&server, &ConnectOptions::default());
+    assert!(args.contains(&"ConnectTimeout=10".to_string()));
+    assert!(args.contains(&"ServerAliveInterval=30".to_string()));
+    assert!(args.contains(&"ServerAliveCountMax=3".to_string()));
+
+    server.connect_timeout = parse_seconds("5").unwrap();
+    server.keepalive = parse_seconds(" 15 ").unwrap();
+    let args = build_ssh_args(&server, &ConnectOptions::default());
+    assert!(args.contains(&"ConnectTimeout=5".to_string()));
+    assert!(args.contains(&"ServerAliveInterval=15".to_string()));
+    assert!(parse_seconds("-1").is_err());
+    assert_eq!(parse_seconds("").unwrap(), None);
+
+    let config = render_ssh_config(&[server]).unwrap();
+    assert!(config.contains("  ConnectTimeout 5\n"));
+    assert!(config.contains("  ServerAliveInterval 15\n"));
+}
+
+#[test]
+fn custom_ssh_options_are_validated_and_passed_before_defaults() {
+    assert_eq!(
+        parse_ssh_option("IdentitiesOnly=yes").unwrap(),
+        ("IdentitiesOnly".to_string(), "yes".to_string())
+    );
+    assert_eq!(
+        parse_ssh_options("PubkeyAcceptedAlgorithms +ssh-rsa; ;ConnectTimeout=3").unwrap(),
+        vec![
+            (
+                "PubkeyAcceptedAlgorithms".to_string(),
+                "+ssh-rsa".to_string()
+            ),
+            ("ConnectTimeout".to_string(), "3".to_string()),
+        ]
+    );
+    assert!(parse_ssh_option("-oProxyCommand=sh").is_err());
+    assert!(parse_ssh_option("1Key=yes").is_err());
+    assert!(parse_ssh_option("Key=").is_err());
+    assert!(parse_ssh_option("NoValue").is_err());
+
+    let mut server = Server::new(
+        "legacy".to_string(),
+        "old.example.com".to_string(),
+        22,
+        "admin".to_string(),
+        String::new(),
+        None,
+    );
+    server.ssh_options = parse_ssh_options("ConnectTimeout=3;IdentitiesOnly=yes").unwrap();
+    let args = build_ssh_args(&server, &ConnectOptions::default());
+    let custom = args.iter().position(|a| a == "ConnectTimeout=3").unwrap();
+    let default = args.iter().position(|a| a == "ConnectTimeout=10").unwrap();
+    assert!(custom < default);
+    assert!(args.contains(&"IdentitiesOnly=yes".to_string()));
+
+    let config = render_ssh_config(std::slice::from_ref(&server)).unwrap();
+    assert!(config.contains("  IdentitiesOnly yes\n"));
+
+    server.ssh_options = vec![("Bad Key".to_string(), "x".to_string())];
+    assert!(render_ssh_config(&[server.clone()]).is_err());
+    let dir = tempdir().unwrap();
+    let mut vault = Vault::with_path(dir.path().join("vault.dat")).unwrap();
+    vault.create(None).unwrap();
+    assert!(vault.add_server(server).is_err());
+}
+
+#[test]
+fn sftp_and_scp_args_mirror_ssh_options_in_their_own_syntax() {
+    let mut server = Server::new(
+        "files".to_string(),
+        "fd00::7".to_string(),
+        2200,
+        "deploy".to_string(),
+        String::new(),
+        None,
+    );
+    server.identity_file = Some("~/.ssh/id_ed25519".to_string());
+    server.auth_method = AuthMethod::PublicKey;
+    server.forward_agent = true;
+    server.quiet_login = true;
+    server.bind_address = Some("10.0.0.2".to_string());
+    server.jump_host = Some("bastion".to_string());
+
+    let args = build_sftp_args(&server, &ConnectOptions::default());
+    let joined = args.join(" ");
+    assert!(joined.starts_with("-i ~/.ssh/id_ed25519 "));
+    assert!(joined.contains("-o LogLevel=QUIET"));
+    assert!(joined.contains("-o BindAddress=10.0.0.2"));
+    assert!(joined.contains("-o ProxyJump=bastion"));
+    assert!(joined.ends_with("-P 2200 deploy@[fd00::7]"));
+    assert!(joined.contains("-o ForwardAgent=yes"));
+    // sftp's -b is a batch file and -A doesn't exist
+    assert!(!args.iter().any(|a| a == "-b" || a == "-A" || a == "-p"));
+
+    // Verbosity works the same as for ssh and keeps the output
+    let verbose = ConnectOptions {
+        verbosity: 2,
+        ..ConnectOptions::default()
+    };
+    let args = build_sftp_args(&server, &verbose);
+    assert_eq!(args[0], "-vv");
+    assert!(!args.contains(&"LogLevel=QUIET".to_string()));
+
+    server.host = "files.internal".to_string();
+    let args = build_scp_args(&server, &ConnectOptions::default(), "./build.tar", "/tmp/");
+    assert_eq!(
+        args[args.len() - 3..],
+        ["--", "./build.tar", "deploy@files.internal:/tmp/"]
+    );
+}
+
+#[test]
+fn port_forwards_become_local_forward_args_and_config_lines() {
+    assert_eq!(parse_port_forwards(" ").unwrap(), Vec::new());
+    assert!("5432:localhost".parse::<PortForward>().is_err());
+    assert!("0:localhost:5432".parse::<PortForward>().is_err());
+    assert!("5432:bad host:5432".parse::<PortForward>().is_err());
+    let v6: PortForward = "8080:[fd00::1]:80".parse().unwrap();
+    assert_eq!(v6.remote_host, "fd00::1");
+    assert_eq!(v6.to_string(), "8080:[fd00::1]:80");
+
+    let mut server = Server::new(
+        "db-gw".to_string(),
+        "gw.example.com".to_string(),
+        22,
+        "admin".to_string(),
+        String::new(),
+        None,
+    );
+    server.port_forwards = parse_port_forwards("5432:db.internal:5432").unwrap();
+
+    // One-off forwards are appended after the stored ones, without repeats
+    let options = ConnectOptions {
+        port_forwards: parse_port_forwards("5432:db.internal:5432, 6379:localhost:6379").unwrap(),
+        ..ConnectOptions::default()
+    };
+    let args = build_ssh_args(&server, &options);
+    assert_eq!(
+        args[..5],
+        [
+            "-tt",
+            "-L",
+            "5432:db.internal:5432",
+            "-L",
+            "6379:localhost:6379"
+        ]
+    );
+    assert!(!build_check_args(&server, &options).contains(&"-L".to_string()));
+
+    let config = render_ssh_config(std::slice::from_ref(&server)).unwrap();
+    assert!(config.contains("  LocalForward 5432 db.internal:5432\n"));
+
+    let json = serde_json::to_string(&server).unwrap();
+    let restored: Server = serde_json::from_str(&json).unwrap();
+    assert_eq!(restored.port_forwards, server.port_forwards);
+}
+
+#[test]
+fn jump_host_is_validated_and_overrides_gateway_chain() {
+    assert_eq!(parse_jump_host(" ").unwrap(), None);
+    assert_eq!(
+        parse_jump_host("admin@bastion.example.com:2222").unwrap(),
+        Some("admin@bastion.example.com:2222".to_string())
+    );
+    assert!(parse_jump_host("edge,ops@10.0.0.1:22").unwrap().is_some());
+    assert!(parse_jump_host("-oProxyCommand=sh").is_err());
+    assert!(parse_jump_host("admin@bastion:port").is_err());
+    assert!(parse_jump_host("admin@bas tion").is_err());
+
+    let gateway = Server::new(
+        "gw".to_string(),
+        "gw.example.com".to_string(),
+        22,
+        "jump".to_string(),
+        String::new(),
+        None,
+    );
+    let mut server = Server::new(
+        "inner".to_string(),
+        "10.0.0.5".to_string(),
+        22,
+        "ops".to_string(),
+        String::new(),
+        None,
+    );
+    let options = ConnectOptions {
+        gateways: vec![gateway.clone()],
+        ..Default::default()
+    };
+    let args = build_ssh_args(&server, &options);
+    assert!(args
+        .windows(2)
+        .any(|w| w == ["-J", "jump@gw.example.com:22"]));
+
+    server.jump_host = Some("admin@bastion:2222".to_string());
+    let args = build_ssh_args(&server, &options);
+    assert!(args.windows(2).any(|w| w == ["-J", "admin@bastion:2222"]));
+
+    server.gateway_id = Some(gateway.id);
+    let config = render_ssh_config(&[gateway, server]).unwrap();
+    assert!(config.contains("  ProxyJump admin@bastion:2222\n"));
+    assert!(!config.contains("  ProxyJump gw\n"));
+}
+
+#[test]
+fn remote_tmux_session_is_validated_and_overridable_at_connect() {
+    assert_eq!(parse_tmux_session("  ").unwrap(), None);
+    assert!(parse_tmux_session("main; rm -rf /").is_err());
+    assert!(parse_tmux_session("a:b").is_err());
+
+    let mut server = Server::new(
+        "prod".to_string(),
+        "example.com".to_string(),
+        22,
+        "deploy".to_string(),
+        String::new(),
+        None,
+    );
+    server.remote_tmux = parse_tmux_session("main").unwrap();
+
+    let args = build_ssh_args(&server, &ConnectOptions::default());
+    assert_eq!(args.last().unwrap(), "tmux new -A -s main");
+    assert_eq!(args[args.len() - 2], "deploy@example.com");
+
+    let options = ConnectOptions {
+        tmux_session: Some("debug".to_string()),
+        ..Default::default()
+    };
+    let help = manual_connection_help(&server, &options);
+    assert!(help.contains("deploy@example.com 'tmux new -A -s debug'"));
+
+    let config = render_ssh_config(&[server]).unwrap();
+    assert!(config.contains("  RemoteCommand tmux new -A -s main"));
+}
+
+#[test]
+fn migrating_a_1_0_0_vault_normalizes_legacy_fields() {
+    let mut data: VaultData =
+        serde_json::from_str(include_str!("fixtures/vault_data_1.0.0.json")).unwrap();
+
+    assert!(migrate(&mut data, "1.0.0").unwrap());
+    assert_eq!(data.version, CURRENT_VERSION);
+
+    let web = &data.servers[0];
+    assert_eq!(web.description, None);
+    assert_eq!(web.identity_file, None);
+    assert_eq!(web.tags, vec!["prod".to_string(), "web".to_string()]);
+    assert_eq!(web.password, "hunter2");
+
+    let db = &data.servers[1];
+    assert_eq!(db.description.as_deref(), Some("Primary database"));
+    assert!(db.tags.is_empty());
+    assert_eq!(db.gateway_id, None);
+
+    // Already-current data is left alone
+    assert!(!migrate(&mut data, CURRENT_VERSION).unwrap());
+    assert!(migrate(&mut data, "9.0.0").is_err());
+    assert!(migrate(&mut data, "1.0.5").is_err());
+}
+
+#[test]
+fn migrating_a_0_x_vault_backfills_auth_methods_without_losing_data() {
+    let mut data: VaultData =
+        serde_json::from_str(include_str!("fixtures/vault_data_0.x.json")).unwrap();
+    assert_eq!(data.version, "0.0.0");
+
+    assert!(data.migrate().unwrap());
+    assert_eq!(data.version, CURRENT_VERSION);
+    assert_eq!(data.servers.len(), 2);
+
+    let bastion = &data.servers[0];
+    assert_eq!(bastion.auth_method, AuthMethod::PublicKey);
+    assert_eq!(bastion.identity_file.as_deref(), Some("~/.ssh/id_ed25519"));
+    assert_eq!(bastion.description.as_deref(), Some("Jump box"));
+    bastion.validate_auth().unwrap();
+
+    let app = &data.servers[1];
+    assert_eq!(app.auth_method, AuthMethod::Password);
+    assert_eq!(app.password, "correct horse");
+    assert_eq!((app.port, app.username.as_str()), (2200, "app"));
+    assert_eq!(app.updated_at.to_rfc3339(), "2023-07-15T12:30:00+00:00");
+
+    // Any 0.* release takes the same path
+    let mut data: VaultData =
+        serde_json::from_str(include_str!("fixtures/vault_data_0.x.json")).unwrap();
+    data.version = "0.4.2".to_string();
+    assert!(data.migrate().unwrap());
+    assert!(!data.migrate().unwrap());
+}
+
+#[test]
+fn unlocking_a_legacy_vault_persists_the_migration() {
+    let (_dir, path) = install_fixture(PLAIN_V1_FIXTURE);
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.unlock(None).unwrap();
+
+    let vault_file: VaultFile = serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap();
+    let on_disk: VaultData = serde_json::from_slice(&vault_file.ciphertext).unwrap();
+    assert_eq!(on_disk.version, CURRENT_VERSION);
+    assert_eq!(on_disk.servers.len(), 1);
+}
+
+#[test]
+fn ui_state_round_trips_and_falls_back_to_defaults() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("nested").join("ui_state.json");
+    assert_eq!(UiState::load_from(&path), UiState::default());
+
+    let state = UiState {
+        filter: "prod".to_string(),
+        selected: Some(uuid::Uuid::new_v4()),
+        sort: None,
+    };
+    state.save_to(&path).unwrap();
+    assert_eq!(UiState::load_from(&path), state);
+
+    std::fs::write(&path, "not json").unwrap();
+    assert_eq!(UiState::load_from(&path), UiState::default());
+}
+
+#[test]
+fn csv_import_skips_bad_rows_and_overwrites_only_on_request() {
+    let csv = "\
+name,host,port,username,password,description,tags
+web,web.example.com,2222,deploy,s3cret,\"Front end, EU\",prod;web
+db,,22,postgres,,,
+cache,cache.internal,99999,redis,,,
+bastion,bastion.example.com,,admin,,,
+";
+    let parsed = parse_server_csv(csv.as_bytes()).unwrap();
+    assert_eq!(parsed.servers.len(), 2);
+    assert_eq!(parsed.warnings.len(), 2);
+    assert!(parsed.warnings[0].starts_with("line 3: missing host"));
+    assert!(parsed.warnings[1].starts_with("line 4:"));
+
+    let web = &parsed.servers[0];
+    assert_eq!(web.port, 2222);
+    assert_eq!(web.password, "s3cret");
+    assert_eq!(web.description.as_deref(), Some("Front end, EU"));
+    assert_eq!(web.tags, ["prod", "web"]);
+    assert_eq!(parsed.servers[1].port, 22);
+
+    assert!(parse_server_csv("name,host\nweb,web.example.com\n".as_bytes()).is_err());
+
+    let dir = tempdir().unwrap();
+    let mut vault = Vault::with_path(dir.path().join("vault.dat")).unwrap();
+    vault.create(None).unwrap();
+    let summary = vault.import_servers(parsed.servers, false).unwrap();
+    assert_eq!(summary.added, ["web", "bastion"]);
+
+    let updated = "name,host,port,username,password\nweb-eu,web.example.com,2222,deploy,rotated\n";
+    let servers = parse_server_csv(updated.as_bytes()).unwrap().servers;
+    let summary = vault
+        .import_servers_with(servers.clone(), false, OnConflict::Skip)
+        .unwrap();
+    assert_eq!(summary.skipped, ["web-eu"]);
+    assert!(summary.replaced.is_empty());
+
+    let id = vault.list_servers().unwrap()[0].id;
+    let summary = vault
+        .import_servers_with(servers, false, OnConflict::Overwrite)
+        .unwrap();
+    assert_eq!(summary.replaced, ["web-eu"]);
+    let web = vault.find_server(&id).unwrap().unwrap();
+    assert_eq!(web.name, "web-eu");
+    assert_eq!(web.id, id);
+    assert_eq!(web.password, "rotated");
+    assert_eq!(vault.list_servers().unwrap().len(), 2);
+}
+
+#[test]
+fn vault_path_env_var_overrides_the_default_location() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("custom").join("team.dat");
+
+    // No other test goes through Vault::new, so the variable can't leak into them
+    std::env::set_var(VAULT_PATH_ENV, &path);
+    let vault = Vault::new();
+    std::env::remove_var(VAULT_PATH_ENV);
+    let mut vault = vault.unwrap();
+
+    assert_eq!(vault.vault_path(), &path);
+    assert!(path.parent().unwrap().is_dir());
+    assert!(!vault.exists());
+    vault.create(None).unwrap();
+    assert!(vault.exists());
+    assert!(path.is_file());
+}
+
+#[test]
+fn cloned_servers_get_a_new_identity_but_keep_their_settings() {
+    let dir = tempdir().unwrap();
+    let mut vault = Vault::with_path(dir.path().join("vault.dat")).unwrap();
+    vault.create(None).unwrap();
+
+    let mut server = Server::new(
+        "web-01".to_string(),
+        "web-01.example.com".to_string(),
+        22,
+        "deploy".to_string(),
+        "s3cret".to_string(),
+        Some("Front end".to_string()),
+    );
+    server.tags = vec!["prod".to_string()];
+    server.ssh_options = vec![("Compression".to_string(), "yes".to_string())];
+    server.last_connected = Some(chrono::Utc::now());
+    let id = server.id;
+    vault.add_server(server).unwrap();
+
+    let new_id = vault.clone_server(&id).unwrap().unwrap();
+    assert_ne!(new_id, id);
+    assert_eq!(vault.clone_server(&uuid::Uuid::new_v4()).unwrap(), None);
+
+    let original = vault.find_server(&id).unwrap().unwrap().clone();
+    let copy = vault.find_server(&new_id).unwrap().unwrap();
+    assert_eq!(copy.name, "web-01 (copy)");
+    assert_eq!(copy.host, original.host);
+    assert_eq!(copy.password, original.password);
+    assert_eq!(copy.tags, original.tags);
+    assert_eq!(copy.ssh_options, original.ssh_options);
+    assert_eq!(copy.description, original.description);
+    assert!(copy.created_at >= original.created_at);
+    assert!(copy.last_connected.is_none());
+    assert_eq!(vault.list_servers().unwrap().len(), 2);
+
+    assert_eq!(vault.undo().unwrap().as_deref(), Some("cloned 'web-01'"));
+    assert_eq!(vault.list_servers().unwrap().len(), 1);
+}
+
+#[test]
+fn host_key_policy_is_per_server_and_overridable_per_run() {
+    assert_eq!("accept-new".parse(), Ok(HostKeyPolicy::AcceptNew));
+    assert_eq!("STRICT".parse(), Ok(HostKeyPolicy::Strict));
+    assert!("ask".parse::<HostKeyPolicy>().is_err());
+
+    let mut server = Server::new(
+        "prod".to_string(),
+        "prod.example.com".to_string(),
+        22,
+        "deploy".to_string(),
+        String::new(),
+        None,
+    );
+    assert_eq!(server.host_key_checking, HostKeyPolicy::AcceptNew);
+    server.host_key_checking = HostKeyPolicy::Strict;
+
+    let args = build_ssh_args(&server, &ConnectOptions::default());
+    assert!(args.contains(&"StrictHostKeyChecking=yes".to_string()));
+    let sftp = build_sftp_args(&server, &ConnectOptions::default());
+    assert!(sftp.contains(&"StrictHostKeyChecking=yes".to_string()));
+    let config = render_ssh_config(std::slice::from_ref(&server)).unwrap();
+    assert!(config.contains("  StrictHostKeyChecking yes\n"));
+
+    // ssh keeps the first value, so the override has to come first
+    let options = ConnectOptions {
+        host_key_policy: Some(HostKeyPolicy::Off),
+        ..ConnectOptions::default()
+    };
+    let args = build_ssh_args(&server, &options);
+    let override_pos = args
+        .iter()
+        .position(|a| a == "StrictHostKeyChecking=no")
+        .unwrap();
+    let stored_pos = args
+        .iter()
+        .position(|a| a == "StrictHostKeyChecking=yes")
+        .unwrap();
+    assert!(override_pos < stored_pos);
+
+    // Servers saved before the field existed get the default
+    let mut value = serde_json::to_value(&server).unwrap();
+    value.as_object_mut().unwrap().remove("host_key_checking");
+    let restored: Server = serde_json::from_value(value).unwrap();
+    assert_eq!(restored.host_key_checking, HostKeyPolicy::AcceptNew);
+}
+
+#[test]
+fn search_ranks_name_matches_above_description_matches() {
+    let matcher = fuzzy_matcher::skim::SkimMatcherV2::default();
+    let by_name = Server::new(
+        "billing".to_string(),
+        "10.0.0.1".to_string(),
+        22,
+        "ops".to_string(),
+        String::new(),
+        Some("payments backend".to_string()),
+    );
+    let by_description = Server::new(
+        "api-02".to_string(),
+        "10.0.0.2".to_string(),
+        22,
+        "ops".to_string(),
+        String::new(),
+        Some("billing".to_string()),
+    );
+
+    let name_score = by_name.search_score(&matcher, "billing").unwrap();
+    let description_score = by_description.search_score(&matcher, "billing").unwrap();
+    assert!(name_score > description_score);
+
+    // Words spread over several fields still match as a whole
+    assert!(by_name.search_score(&matcher, "billing ops").is_some());
+    assert!(by_name.search_score(&matcher, "zzz").is_none());
+}
+
+#[test]
+fn encrypted_vaults_keep_passwords_sealed_while_unlocked() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.create(Some("master")).unwrap();
+    vault
+        .add_server(Server::new(
+            "db".to_string(),
+            "db.example.com".to_string(),
+            22,
+            "postgres".to_string(),
+            "hunter2".to_string(),
+            None,
+        ))
+        .unwrap();
+
+    let server = vault.list_servers().unwrap()[0].clone();
+    assert!(server.password.is_empty());
+    assert!(server.is_password_sealed());
+    assert!(server.has_password() && server.uses_sshpass());
+    assert_eq!(vault.decrypt_password(&server).unwrap(), "hunter2");
+
+    let unsealed = vault.with_password(&server).unwrap();
+    assert_eq!(unsealed.password, "hunter2");
+    assert!(!unsealed.is_password_sealed());
+
+    // Editing other fields keeps the sealed password; a new one replaces it
+    let mut edited = server.clone();
+    edited.update_fields(
+        "db-primary".to_string(),
+        edited.host.clone(),
+        edited.port,
+        edited.username.clone(),
+        None,
+        None,
+    );
+    vault.replace_server(edited.clone()).unwrap();
+    let stored = vault.find_server(&server.id).unwrap().unwrap();
+    assert_eq!(vault.decrypt_password(stored).unwrap(), "hunter2");
+    edited.set_password("rotated".to_string());
+    vault.replace_server(edited).unwrap();
+    let stored = vault.find_server(&server.id).unwrap().unwrap();
+    assert!(stored.password.is_empty());
+    assert_eq!(vault.decrypt_password(stored).unwrap(), "rotated");
+
+    // Re-keying re-seals passwords under the new master password
+    vault.change_password(Some("master"), Some("next")).unwrap();
+    let mut reopened = Vault::with_path(path).unwrap();
+    reopened.unlock(Some("next")).unwrap();
+    let server = &reopened.list_servers().unwrap()[0];
+    assert!(server.password.is_empty());
+    assert_eq!(reopened.decrypt_password(server).unwrap(), "rotated");
+}
+
+#[test]
+fn legacy_cleartext_passwords_are_sealed_on_first_unlock() {
+    let (_dir, path) = install_fixture(ENCRYPTED_V1_FIXTURE);
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.unlock(Some("fixture-password")).unwrap();
+    assert!(vault.list_servers().unwrap()[0].is_password_sealed());
+
+    // The converted vault was saved, so the upgrade only happens once
+    let file: VaultFile = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+    let master_key = MasterKey::from_password("fixture-password", &file.salt, file.kdf).unwrap();
+    let data: VaultData =
+        serde_json::from_slice(&master_key.decrypt(&file.ciphertext, &file.nonce).unwrap())
+            .unwrap();
+    assert!(data.servers[0].password.is_empty());
+    assert_eq!(
+        data.servers[0].decrypt_password(&master_key).unwrap(),
+        "s3cret"
+    );
+
+    // Backups carry cleartext passwords under their own key
+    let export_path = path.with_file_name("backup.json");
+    vault.export(&export_path, Some("backup")).unwrap();
+    let exported = ExportFile::read(&export_path)
+        .unwrap()
+        .open(Some("backup"))
+        .unwrap();
+    assert_eq!(exported.servers[0].password, "s3cret");
+    assert!(!exported.servers[0].is_password_sealed());
+}
+
+#[test]
+fn set_field_applies_text_assignments_to_a_server() {
+    let mut server = Server::new(
+        "web".to_string(),
+        "web.example.com".to_string(),
+        22,
+        "deploy".to_string(),
+        String::new(),
+        None,
+    );
+
+    for assignment in [
+        "host=10.0.0.5",
+        "port=2222",
+        "tags=Prod, web,prod",
+        "auth_method=public-key",
+        "identity-file=~/.ssh/id_ed25519",
+        "quiet_login=yes",
+        "ssh_options=Compression=yes",
+        "host_key_checking=strict",
+    ] {
+        let (field, value) = parse_field_assignment(assignment).unwrap();
+        server.set_field(&field, &value).unwrap();
+    }
+    assert_eq!(server.host, "10.0.0.5");
+    assert_eq!(server.port, 2222);
+    assert_eq!(server.tags, vec!["prod", "web"]);
+    assert_eq!(server.auth_method, AuthMethod::PublicKey);
+    assert_eq!(server.identity_file.as_deref(), Some("~/.ssh/id_ed25519"));
+    assert!(server.quiet_login);
+    assert_eq!(
+        server.ssh_options,
+        vec![("Compression".to_string(), "yes".to_string())]
+    );
+    assert_eq!(server.host_key_checking, HostKeyPolicy::Strict);
+    assert!(server.validate().is_ok());
+    assert!(server.validate_auth().is_ok());
+
+    // Blank values clear optional fields
+    server.set_field("identity_file", "").unwrap();
+    assert_eq!(server.identity_file, None);
+
+    let err = server.set_field("colour", "blue").unwrap_err().to_string();
+    assert!(err.contains("Unknown field 'colour'"));
+    assert!(err.contains("host, port, username"));
+    assert!(server.set_field("port", "0").is_err());
+    assert!(server.set_field("forward_agent", "maybe").is_err());
+    assert!(parse_field_assignment("host").is_err());
+    assert!(parse_field_assignment("=value").is_err());
+}
+
+#[test]
+fn generated_passwords_use_the_requested_length_and_charset() {
+    let password = generate_password(DEFAULT_PASSWORD_LENGTH, Charset::Alphanumeric);
+    assert_eq!(password.chars().count(), 20);
+    assert!(password.chars().all(|c| c.is_ascii_alphanumeric()));
+
+    let with_symbols = generate_password(200, Charset::AlphanumericSymbols);
+    assert_eq!(with_symbols.chars().count(), 200);
+    assert!(with_symbols
+        .chars()
+        .all(|c| c.is_ascii_graphic() && !matches!(c, '"' | '\'' | '\\' | '`')));
+    assert!(with_symbols.chars().any(|c| !c.is_ascii_alphanumeric()));
+
+    assert_ne!(
+        generate_password(DEFAULT_PASSWORD_LENGTH, Charset::AlphanumericSymbols),
+        generate_password(DEFAULT_PASSWORD_LENGTH, Charset::AlphanumericSymbols)
+    );
+    assert!(generate_password(0, Charset::Alphanumeric).is_empty());
+}
+
+#[test]
+fn servers_sort_and_filter_by_group() {
+    let server = |name: &str, group: Option<&str>| {
+        let mut server = Server::new(
+            name.to_string(),
+            "example.com".to_string(),
+            22,
+            "admin".to_string(),
+            String::new(),
+            None,
+        );
+        server.group = group.map(str::to_string);
+        server
+    };
+    let mut servers = [
+        server("loose", None),
+        server("web", Some("production")),
+        server("ci", Some("Acme")),
+        server("db", Some("production")),
+    ];
+
+    servers.sort_by(compare_groups);
+    let names: Vec<&str> = servers.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(names, ["ci", "web", "db", "loose"]);
+    assert_eq!(servers[0].group_label(), "Acme");
+    assert_eq!(servers[3].group_label(), "(no group)");
+
+    assert!(servers[0].in_group("acme"));
+    assert!(servers[1].in_group(" PRODUCTION "));
+    assert!(!servers[3].in_group("production"));
+
+    // Fuzzy search reaches the group too
+    let matcher = fuzzy_matcher::skim::SkimMatcherV2::default();
+    assert!(servers[1].search_score(&matcher, "produc").is_some());
+    assert!(servers[0].search_score(&matcher, "produc").is_none());
+
+    let mut edited = servers[3].clone();
+    edited.set_field("group", "staging").unwrap();
+    assert_eq!(edited.group.as_deref(), Some("staging"));
+    edited.set_field("group", " ").unwrap();
+    assert_eq!(edited.group, None);
+}
+
+#[test]
+fn groups_differing_only_in_case_share_one_heading() {
+    let server = |name: &str, group: &str| {
+        let mut server = Server::new(
+            name.to_string(),
+            format!("{name}.example.com"),
+            22,
+            "admin".to_string(),
+            String::new(),
+            None,
+        );
+        server.group = Some(group.to_string());
+        server
+    };
+    let servers = [
+        server("api", "Backend"),
+        server("db", "backend"),
+        server("web", "Frontend"),
+    ];
+    assert!(same_group(&servers[0], &servers[1]));
+    assert!(!same_group(&servers[1], &servers[2]));
+
+    let temp = tempdir().unwrap();
+    let path = temp.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.create(None).unwrap();
+    for server in servers {
+        vault.add_server(server).unwrap();
+    }
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_portkey"))
+        .args(["list", "--by-group"])
+        .env("XDG_DATA_HOME", temp.path())
+        .env(VAULT_PATH_ENV, &path)
+        .stdin(std::process::Stdio::null())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let headings: Vec<&str> = stdout
+        .lines()
+        .filter(|line| line.starts_with("== "))
+        .collect();
+    assert_eq!(headings, ["== Backend ==", "== Frontend =="], "{stdout}");
+}
+
+#[test]
+fn ssh_command_carries_jump_host_and_forwards_with_a_redacted_preview() {
+    let mut server = Server::new(
+        "db".to_string(),
+        "db.internal".to_string(),
+        2222,
+        "deploy".to_string(),
+        "hunter2".to_string(),
+        None,
+    );
+    server.jump_host = Some("ops@bastion.example.com:22".to_string());
+    server.port_forwards = parse_port_forwards("5432:localhost:5432").unwrap();
+    let options = ConnectOptions {
+        port_forwards: parse_port_forwards("8080:web.internal:80").unwrap(),
+        ..ConnectOptions::default()
+    };
+
+    let command = build_ssh_command(&server, &options, true);
+    assert_eq!(command.get_program(), "sshpass");
+    let args: Vec<String> = command
+        .get_args()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    assert_eq!(
+        args,
+        [
+            "-e",
+            "ssh",
+            "-tt",
+            "-L",
+            "5432:localhost:5432",
+            "-L",
+            "8080:web.internal:80",
+            "-o",
+            "StrictHostKeyChecking=accept-new",
+            "-o",
+            "ConnectTimeout=10",
+            "-o",
+            "ServerAliveInterval=30",
+            "-o",
+            "ServerAliveCountMax=3",
+            "-J",
+            "ops@bastion.example.com:22",
+            "-p",
+            "2222",
+            "deploy@db.internal",
+        ]
+    );
+
+    let preview = command_preview(&command);
+    assert!(preview.starts_with("SSHPASS=*** sshpass -e ssh -tt -L 5432:localhost:5432"));
+    assert!(preview.ends_with("-p 2222 deploy@db.internal"));
+    assert!(!preview.contains("hunter2"));
+
+    // Without sshpass the same arguments go straight to ssh
+    let plain = build_ssh_command(&server, &options, false);
+    assert_eq!(plain.get_program(), "ssh");
+    assert_eq!(plain.get_envs().count(), 0);
+    assert!(command_preview(&plain).starts_with("ssh -tt -L 5432:localhost:5432"));
+}
+
+#[test]
+fn password_stdin_reads_a_single_line() {
+    assert_eq!(
+        read_password_line(&b"correct horse \nsecond line\n"[..]).unwrap(),
+        "correct horse "
+    );
+    assert_eq!(read_password_line(&b"s3cret\r\n"[..]).unwrap(), "s3cret");
+    assert_eq!(
+        read_password_line(&b"no newline"[..]).unwrap(),
+        "no newline"
+    );
+    assert!(read_password_line(&b""[..]).is_err());
+    assert!(read_password_line(&b"\n"[..]).is_err());
+}
+
+#[test]
+fn undo_after_remove_restores_the_original_id_and_timestamps() {
+    let temp = tempdir().unwrap();
+    let mut vault = Vault::with_path(temp.path().join("vault.dat")).unwrap();
+    vault.create(None).unwrap();
+
+    let mut server = Server::new(
+        "web-01".to_string(),
+        "10.0.0.1".to_string(),
+        22,
+        "deploy".to_string(),
+        String::new(),
+        None,
+    );
+    server.created_at = chrono::Utc::now() - chrono::Duration::days(30);
+    server.updated_at = server.created_at + chrono::Duration::days(1);
+    let original = server.clone();
+    vault.add_server(server).unwrap();
+
+    let mut edited = original.clone();
+    edited.set_field("host", "10.0.0.2").unwrap();
+    vault.replace_server(edited).unwrap();
+    assert_eq!(vault.undo().unwrap().as_deref(), Some("edited 'web-01'"));
+    assert_eq!(vault.list_servers().unwrap()[0].host, "10.0.0.1");
+
+    vault.remove_server(&original.id).unwrap();
+    assert!(vault.list_servers().unwrap().is_empty());
+    assert_eq!(vault.undo().unwrap().as_deref(), Some("removed 'web-01'"));
+    let restored = &vault.list_servers().unwrap()[0];
+    assert_eq!(restored.id, original.id);
+    assert_eq!(restored.created_at, original.created_at);
+    assert_eq!(restored.updated_at, original.updated_at);
+
+    // Only one level is kept
+    assert_eq!(vault.undo().unwrap(), None);
+}
+
+#[test]
+fn pass_prints_only_the_password_to_a_pipe() {
+    let (dir, path) = install_fixture(ENCRYPTED_V1_FIXTURE);
+    let portkey = |args: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_portkey"))
+            .args(args)
+            .env("XDG_DATA_HOME", dir.path())
+            .env(VAULT_PATH_ENV, &path)
+            .env("PORTKEY_PASSWORD", "fixture-password")
+            .stdin(std::process::Stdio::null())
+            .output()
+            .unwrap()
+    };
+
+    let output = portkey(&["pass", "prod-web", "--yes"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(output.stdout, b"s3cret");
+
+    // Without --yes there is nobody to confirm
+    let output = portkey(&["pass", "prod-web"]);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--yes"));
+}
+
+#[test]
+fn batch_removal_saves_once_and_undoes_as_a_whole() {
+    let temp = tempdir().unwrap();
+    let mut vault = Vault::with_path(temp.path().join("vault.dat")).unwrap();
+    vault.create(None).unwrap();
+
+    let mut ids = Vec::new();
+    for name in ["web-01", "web-02", "db-01"] {
+        let server = Server::new(
+            name.to_string(),
+            format!("{name}.example.com"),
+            22,
+            "deploy".to_string(),
+            String::new(),
+            None,
+        );
+        ids.push(server.id);
+        vault.add_server(server).unwrap();
+    }
+
+    let unknown = uuid::Uuid::new_v4();
+    let removed = vault.remove_servers(&[ids[0], unknown, ids[1]]).unwrap();
+    assert_eq!(removed, ["web-01", "web-02"]);
+    let remaining: Vec<&str> = vault
+        .list_servers()
+        .unwrap()
+        .iter()
+        .map(|s| s.name.as_str())
+        .collect();
+    assert_eq!(remaining, ["db-01"]);
+
+    assert_eq!(vault.undo().unwrap().as_deref(), Some("removed 2 servers"));
+    assert_eq!(vault.list_servers().unwrap().len(), 3);
+
+    // Nothing to remove leaves the undo history alone
+    assert!(vault.remove_servers(&[unknown]).unwrap().is_empty());
+    assert_eq!(vault.undo().unwrap(), None);
+}
+
+#[test]
+fn toml_vaults_round_trip_and_pick_up_hand_edits() {
+    let mut server = Server::new(
+        "web".to_string(),
+        "web.example.com".to_string(),
+        2222,
+        "deploy".to_string(),
+        "pw".to_string(),
+        Some("Frontend \"blue\" pool".to_string()),
+    );
+    server.tags = vec!["prod".to_string(), "eu-west".to_string()];
+    server.group = Some("production".to_string());
+    server.ssh_options = vec![("Compression".to_string(), "yes".to_string())];
+    server.port_forwards = parse_port_forwards("8080:localhost:80").unwrap();
+    let mut data = VaultData::new();
+    data.servers.push(server);
+    data.servers.push(Server::new(
+        "bare".to_string(),
+        "10.0.0.2".to_string(),
+        22,
+        "root".to_string(),
+        String::new(),
+        None,
+    ));
+
+    let text = toml::to_string_pretty(&data).unwrap();
+    let parsed: VaultData = toml::from_str(&text).unwrap();
+    assert_eq!(
+        serde_json::to_value(&parsed).unwrap(),
+        serde_json::to_value(&data).unwrap()
+    );
+    assert_eq!(parsed.servers[0].tags, ["prod", "eu-west"]);
+    assert_eq!(
+        parsed.servers[0].description.as_deref(),
+        Some("Frontend \"blue\" pool")
+    );
+    assert_eq!(parsed.servers[1].description, None);
+
+    let temp = tempdir().unwrap();
+    let path = temp.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.create_with_format(VaultFormat::Toml).unwrap();
+    vault.add_server(data.servers[0].clone()).unwrap();
+    let on_disk = std::fs::read_to_string(&path).unwrap();
+    assert!(on_disk.contains("host = \"web.example.com\""), "{on_disk}");
+
+    // Edit the file by hand, then open it like any unencrypted vault
+    std::fs::write(&path, on_disk.replace("port = 2222", "port = 2200")).unwrap();
+    let mut reopened = Vault::with_path(path.clone()).unwrap();
+    reopened.unlock(None).unwrap();
+    assert_eq!(reopened.list_servers().unwrap()[0].port, 2200);
+
+    // Saving keeps it TOML until a password is set, which switches to JSON
+    reopened.remove_server(&data.servers[0].id).unwrap();
+    assert!(!std::fs::read_to_string(&path).unwrap().starts_with('{'));
+    reopened.change_password(None, Some("secret")).unwrap();
+    serde_json::from_slice::<VaultFile>(&std::fs::read(&path).unwrap()).unwrap();
+
+    assert_eq!("TOML".parse::<VaultFormat>().unwrap(), VaultFormat::Toml);
+    assert!("yaml".parse::<VaultFormat>().is_err());
+}
+
+#[test]
+fn ipv6_hosts_are_bracketed_for_display_but_passed_bare_to_ssh() {
+    let options = ConnectOptions::default();
+    for (port, shown) in [(22, "[fe80::1]:22"), (2222, "[fe80::1]:2222")] {
+        let server = Server::new(
+            "v6".to_string(),
+            "fe80::1".to_string(),
+            port,
+            "deploy".to_string(),
+            String::new(),
+            None,
+        );
+        assert_eq!(server.host_port(), shown);
+        assert_eq!(
+            server.ssh_command(),
+            format!("ssh deploy@[fe80::1] -p {port}")
+        );
+        assert_eq!(server.jump_spec(), format!("deploy@{shown}"));
+
+        let args = build_ssh_args(&server, &options);
+        assert_eq!(
+            args[args.len() - 3..],
+            [
+                "-p".to_string(),
+                port.to_string(),
+                "deploy@fe80::1".to_string()
+            ]
+        );
+        assert_eq!(
+            build_sftp_args(&server, &options).last().unwrap(),
+            "deploy@[fe80::1]"
+        );
+    }
+
+    assert!(is_ipv6_literal("::1"));
+    assert!(!is_ipv6_literal("10.0.0.1"));
+    assert!(!is_ipv6_literal("example.com"));
+}
+
+#[test]
+fn server_lookup_prefers_exact_names_over_prefixes_over_ids() {
+    let server = |name: &str| {
+        Server::new(
+            name.to_string(),
+            format!("{name}.example.com"),
+            22,
+            "deploy".to_string(),
+            String::new(),
+            None,
+        )
+    };
+    let servers = vec![
+        server("web-01"),
+        server("web-02"),
+        server("Web"),
+        server("db"),
+    ];
+    let names = |query: &str, exact: bool| -> Vec<String> {
+        match_servers(&servers, query, exact)
+            .iter()
+            .map(|s| s.name.clone())
+            .collect()
+    };
+
+    assert_eq!(names("web", false), ["Web"]);
+    assert_eq!(names("web-", false), ["web-01", "web-02"]);
+    assert_eq!(names("DB", false), ["db"]);
+    assert!(names("web-", true).is_empty());
+    assert!(names("nope", false).is_empty());
+
+    let id = servers[3].id.to_string();
+    assert_eq!(names(&id[..8], false), ["db"]);
+    assert!(names(&id[..8], true).is_empty());
+    assert_eq!(names(&id.to_uppercase(), true), ["db"]);
+}
+
+#[test]
+fn shared_search_ranks_best_first_and_keeps_order_for_empty_queries() {
+    let mut data = VaultData::new();
+    for (name, description) in [
+        ("ops-box", Some("runs billing jobs")),
+        ("billing", None),
+        ("db", None),
+        ("billing-replica", None),
+    ] {
+        data.servers.push(Server::new(
+            name.to_string(),
+            format!("{name}.example.com"),
+            22,
+            "deploy".to_string(),
+            String::new(),
+            description.map(str::to_string),
+        ));
+    }
+
+    let all: Vec<&str> = data
+        .search("")
+        .iter()
+        .map(|(score, s)| {
+            assert_eq!(*score, 0);
+            s.name.as_str()
+        })
+        .collect();
+    assert_eq!(all, ["ops-box", "billing", "db", "billing-replica"]);
+
+    let ranked = data.search("billing");
+    let names: Vec<&str> = ranked.iter().map(|(_, s)| s.name.as_str()).collect();
+    assert_eq!(names.len(), 3);
+    assert!(!names.contains(&"db"));
+    assert_eq!(*names.last().unwrap(), "ops-box");
+    assert!(ranked.windows(2).all(|pair| pair[0].0 >= pair[1].0));
+
+    // The index form the TUI uses agrees with the method
+    let indices: Vec<usize> = search_servers(&data.servers, "billing")
+        .into_iter()
+        .map(|(_, index)| index)
+        .collect();
+    let expected: Vec<usize> = ranked
+        .iter()
+        .map(|(_, s)| data.servers.iter().position(|o| o.id == s.id).unwrap())
+        .collect();
+    assert_eq!(indices, expected);
+    assert!(data.search("zzz").is_empty());
+}
+
+#[test]
+fn list_counts_respect_filters_and_summarize_tags() {
+    let temp = tempdir().unwrap();
+    let path = temp.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.create(None).unwrap();
+    for (name, tags) in [
+        ("web-01", "prod"),
+        ("web-02", "Prod,edge"),
+        ("stage", "staging"),
+        ("spare", ""),
+    ] {
+        let mut server = Server::new(
+            name.to_string(),
+            format!("{name}.example.com"),
+            22,
+            "deploy".to_string(),
+            String::new(),
+            None,
+        );
+        server.tags = parse_tags(tags);
+        vault.add_server(server).unwrap();
+    }
+
+    let servers: Vec<&Server> = vault.list_servers().unwrap().iter().collect();
+    assert_eq!(
+        list_summary(&servers),
+        "4 servers (2 prod, 1 edge, 1 staging)"
+    );
+    assert_eq!(list_summary(&servers[3..]), "1 server");
+    assert_eq!(list_summary(&[]), "0 servers");
+    // Counted like `portkey tags`: hand-edited tags normalized, ties alphabetical
+    let mut edited = servers[0].clone();
+    edited.tags = vec![" Zeta".to_string(), "PROD".to_string(), "alpha".to_string()];
+    assert_eq!(
+        list_summary(&[servers[0], &edited]),
+        "2 servers (2 prod, 1 alpha, 1 zeta)"
+    );
+
+    let portkey = |args: &[&str]| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_portkey"))
+            .args(args)
+            .env("XDG_DATA_HOME", temp.path())
+            .env(VAULT_PATH_ENV, &path)
+            .stdin(std::process::Stdio::null())
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        String::from_utf8(output.stdout).unwrap()
+    };
+    assert_eq!(portkey(&["list", "--count", "--tag", "prod"]), "2\n");
+    assert_eq!(
+        portkey(&["list", "--count", "--json"]).trim(),
+        r#"{"count":4}"#
+    );
+    assert!(portkey(&["list"]).ends_with("4 servers (2 prod, 1 edge, 1 staging)\n"));
+}
+
+#[test]
+fn health_monitor_probes_in_the_background_and_caches_results() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let closed_port = closed.local_addr().unwrap().port();
+    drop(closed);
+
+    let local = |name: &str, port: u16| {
+        Server::new(
+            name.to_string(),
+            "127.0.0.1".to_string(),
+            port,
+            "me".to_string(),
+            String::new(),
+            None,
+        )
+    };
+    let up = local("up", listener.local_addr().unwrap().port());
+    let down = local("down", closed_port);
+    let mut jumped = local("jumped", closed_port);
+    jumped.jump_host = Some("bastion".to_string());
+
+    let mut monitor = HealthMonitor::new(std::time::Duration::from_secs(1));
+    monitor.check([&up, &down, &jumped], false);
+    assert_eq!(monitor.status(&up), Health::Unknown);
+
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+    while monitor.status(&up) == Health::Unknown || monitor.status(&down) == Health::Unknown {
+        assert!(
+            std::time::Instant::now() < deadline,
+            "probes never finished"
+        );
+        monitor.poll();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    assert_eq!(monitor.status(&up), Health::Up);
+    assert_eq!(monitor.status(&down), Health::Down);
+    assert_eq!(monitor.status(&jumped), Health::Unknown);
+
+    // Fresh results aren't probed again unless forced
+    drop(listener);
+    monitor.check([&up], false);
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    assert!(!monitor.poll());
+    assert_eq!(monitor.status(&up), Health::Up);
+
+    monitor.check([&up], true);
+    while monitor.status(&up) == Health::Up {
+        assert!(
+            std::time::Instant::now() < deadline,
+            "forced probe never finished"
+        );
+        monitor.poll();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    assert_eq!(monitor.status(&up), Health::Down);
+}
+
+#[test]
+fn add_flags_create_a_server_without_prompts() {
+    let temp = tempdir().unwrap();
+    let path = temp.path().join("vault.dat");
+    Vault::with_path(path.clone())
+        .unwrap()
+        .create(None)
+        .unwrap();
+
+    let portkey = |args: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_portkey"))
+            .args(args)
+            .env("XDG_DATA_HOME", temp.path())
+            .env(VAULT_PATH_ENV, &path)
+            .stdin(std::process::Stdio::null())
+            .output()
+            .unwrap()
+    };
+    let add = [
+        "add",
+        "--name",
+        "web",
+        "--host",
+        "10.0.0.1",
+        "--user",
+        "root",
+        "--password",
+        "pw",
+        "--tags",
+        "prod, edge",
+        "--port",
+        "2222",
+    ];
+    let output = portkey(&add);
+    assert!(output.status.success(), "{output:?}");
+
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.unlock(None).unwrap();
+    let server = &vault.list_servers().unwrap()[0];
+    assert_eq!(
+        (server.name.as_str(), server.host.as_str(), server.port),
+        ("web", "10.0.0.1", 2222)
+    );
+    assert_eq!(server.username, "root");
+    assert_eq!(server.tags, ["prod", "edge"]);
+    assert_eq!(vault.decrypt_password(server).unwrap(), "pw");
+
+    // A duplicate can't be confirmed without a terminal
+    let output = portkey(&add);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("already exists"));
+
+    // A missing field is prompted for, which fails without a terminal
+    let output = portkey(&["add", "--name", "db", "--user", "root", "--password", ""]);
+    assert!(!output.status.success());
+    let output = portkey(&["add", "--host", "bad host", "--name", "x", "--user", "u"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid host 'bad host'"));
+    vault.reload().unwrap();
+    assert_eq!(vault.list_servers().unwrap().len(), 1);
+}
+
+#[test]
+fn locking_wipes_cleartext_passwords_without_touching_the_file() {
+    let temp = tempdir().unwrap();
+    let path = temp.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.create(None).unwrap();
+    vault
+        .add_server(Server::new(
+            "db".to_string(),
+            "10.0.0.9".to_string(),
+            22,
+            "root".to_string(),
+            "hunter2".to_string(),
+            None,
+        ))
+        .unwrap();
+    let on_disk = std::fs::read(&path).unwrap();
+
+    // What lock does to every loaded server before dropping it
+    let mut loaded = vault.list_servers().unwrap()[0].clone();
+    assert_eq!(loaded.password, "hunter2");
+    loaded.wipe_secrets();
+    assert_eq!(loaded.password, "");
+    assert!(!loaded.has_password());
+    assert_eq!(vault.list_servers().unwrap()[0].password, "hunter2");
+
+    vault.lock();
+    assert!(!vault.is_unlocked());
+    let err = vault.list_servers().unwrap_err();
+    assert!(matches!(err.downcast_ref(), Some(VaultError::Locked)));
+    assert!(matches!(
+        vault.find_server(&loaded.id).unwrap_err().downcast_ref(),
+        Some(VaultError::Locked)
+    ));
+    drop(vault);
+    assert_eq!(std::fs::read(&path).unwrap(), on_disk);
+
+    let mut reopened = Vault::with_path(path).unwrap();
+    reopened.unlock(None).unwrap();
+    assert_eq!(reopened.list_servers().unwrap()[0].password, "hunter2");
+}
+
+#[test]
+fn notes_are_kept_apart_from_the_description_and_survive_a_round_trip() {
+    let mut server = Server::new(
+        "db".to_string(),
+        "10.0.0.9".to_string(),
+        22,
+        "root".to_string(),
+        String::new(),
+        Some("Primary database".to_string()),
+    );
+    assert_eq!(server.notes, None);
+
+    server
+        .set_field("notes", "sudo via ops group\nrunbook: https://wiki/db\n\n")
+        .unwrap();
+    assert_eq!(
+        server.notes.as_deref(),
+        Some("sudo via ops group\nrunbook: https://wiki/db")
+    );
+    assert_eq!(server.description.as_deref(), Some("Primary database"));
+
+    let temp = tempdir().unwrap();
+    let path = temp.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.create(Some("pw")).unwrap();
+    vault.add_server(server.clone()).unwrap();
+    let mut reopened = Vault::with_path(path).unwrap();
+    reopened.unlock(Some("pw")).unwrap();
+    assert_eq!(reopened.list_servers().unwrap()[0].notes, server.notes);
+
+    server.set_field("notes", "  ").unwrap();
+    assert_eq!(server.notes, None);
+
+    // Servers saved before notes existed load without them
+    let mut json = serde_json::to_value(&server).unwrap();
+    json.as_object_mut().unwrap().remove("notes");
+    let old: Server = serde_json::from_value(json).unwrap();
+    assert_eq!(old.notes, None);
+}
+
+#[test]
+fn ssh_config_output_file_is_rewritten_in_place_on_every_run() {
+    let temp = tempdir().unwrap();
+    let path = temp.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.create(None).unwrap();
+    vault
+        .add_server(Server::new(
+            "web".to_string(),
+            "10.0.0.1".to_string(),
+            22,
+            "deploy".to_string(),
+            String::new(),
+            None,
+        ))
+        .unwrap();
+
+    let output = temp.path().join("config.d").join("portkey");
+    let export = || {
+        let result = std::process::Command::new(env!("CARGO_BIN_EXE_portkey"))
+            .args(["ssh-config", "--output"])
+            .arg(&output)
+            .env("XDG_DATA_HOME", temp.path())
+            .env(VAULT_PATH_ENV, &path)
+            .stdin(std::process::Stdio::null())
+            .output()
+            .unwrap();
+        assert!(result.status.success(), "{result:?}");
+        std::fs::read_to_string(&output).unwrap()
+    };
+
+    let first = export();
+    assert!(first.starts_with("# BEGIN Portkey managed entries\n"));
+    assert!(first.contains("Host web"));
+    assert_eq!(export(), first);
+
+    // Hand-written lines around the block survive, and the block isn't repeated
+    std::fs::write(&output, format!("Host mine\n    User me\n\n{first}")).unwrap();
+    let rewritten = export();
+    assert!(rewritten.starts_with("Host mine\n"));
+    assert_eq!(
+        rewritten.matches("# BEGIN Portkey managed entries").count(),
+        1
+    );
+    // Written through a temp file that is renamed into place
+    let entries: Vec<_> = std::fs::read_dir(output.parent().unwrap())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(entries, ["portkey"]);
+
+    // A symlinked config is updated where it points, and stays a symlink
+    #[cfg(unix)]
+    {
+        let target = temp.path().join("dotfiles-ssh-config");
+        std::fs::rename(&output, &target).unwrap();
+        std::os::unix::fs::symlink(&target, &output).unwrap();
+        std::fs::write(&target, "Host mine\n").unwrap();
+        assert!(export().contains("Host web"));
+        assert!(std::fs::symlink_metadata(&output)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert!(std::fs::read_to_string(&target)
+            .unwrap()
+            .contains("Host web"));
+    }
+}
+
+#[test]
+fn ssh_config_upsert_keeps_surrounding_hosts_untouched() {
+    let fixture = include_str!("fixtures/ssh_config_with_managed_block");
+    let block = render_managed_block(&[Server::new(
+        "fresh".to_string(),
+        "10.0.0.1".to_string(),
+        22,
+        "deploy".to_string(),
+        String::new(),
+        None,
+    )])
+    .unwrap();
+
+    let updated = upsert_managed_block(fixture, &block);
+    let begin = fixture.find("# BEGIN").unwrap();
+    assert_eq!(updated[..begin], fixture[..begin]);
+    assert!(updated.ends_with(
+        "\nHost *.internal\n  ProxyJump bastion\nMatch host legacy\n    KexAlgorithms +diffie-hellman-group1-sha1\n"
+    ));
+    assert_eq!(
+        updated.matches("# BEGIN Portkey managed entries").count(),
+        1
+    );
+    assert!(updated.contains("Host fresh"));
+    assert!(!updated.contains("Host stale"));
+    assert_eq!(upsert_managed_block(&updated, &block), updated);
+
+    // Without a block, the entries go at the end after a blank line
+    let plain = "Host github.com\n    User git";
+    assert_eq!(
+        upsert_managed_block(plain, &block),
+        format!("{plain}\n\n{block}")
+    );
+    assert_eq!(upsert_managed_block("\n", &block), block);
+}
+
+#[test]
+fn tags_are_normalized_and_counted_across_the_vault() {
+    let mut server = Server::new(
+        "web".into(),
+        "10.0.0.1".into(),
+        22,
+        "root".into(),
+        String::new(),
+        None,
+    );
+    server.set_tags([" Prod", "web", "prod ", "", "WEB"]);
+    assert_eq!(server.tags, ["prod", "web"]);
+    server.set_field("tags", "Edge,, edge , Prod").unwrap();
+    assert_eq!(server.tags, ["edge", "prod"]);
+
+    let temp = tempdir().unwrap();
+    let path = temp.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.create(None).unwrap();
+    vault.add_server(server).unwrap();
+    let mut db = Server::new(
+        "db".into(),
+        "10.0.0.2".into(),
+        22,
+        "root".into(),
+        String::new(),
+        None,
+    );
+    // A hand-edited entry that skipped normalization still counts as "prod"
+    db.tags = vec!["PROD".into(), "db".into()];
+    vault.add_server(db).unwrap();
+    assert_eq!(
+        vault.tag_counts().unwrap(),
+        [("prod".into(), 2), ("db".into(), 1), ("edge".into(), 1)]
+    );
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_portkey"))
+        .arg("tags")
+        .env("XDG_DATA_HOME", temp.path())
+        .env(VAULT_PATH_ENV, &path)
+        .stdin(std::process::Stdio::null())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "prod  2\ndb    1\nedge  1\n"
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn connect_exec_runs_the_command_without_a_tty_and_passes_its_exit_code_on() {
+    use portkey::ssh::RemoteCommand;
+    use std::os::unix::fs::PermissionsExt;
+
+    let server = Server::new(
+        "web".into(),
+        "10.0.0.1".into(),
+        22,
+        "root".into(),
+        String::new(),
+        None,
+    );
+    let exec = |tty| ConnectOptions {
+        remote_command: Some(RemoteCommand {
+            command: "uptime".into(),
+            tty,
+        }),
+        tmux_session: Some("main".into()),
+        ..ConnectOptions::default()
+    };
+    let args = build_ssh_args(&server, &exec(false));
+    assert!(!args.contains(&"-tt".to_string()));
+    assert_eq!(&args[args.len() - 2..], ["root@10.0.0.1", "uptime"]);
+    let args = build_ssh_args(&server, &exec(true));
+    assert_eq!(args[0], "-tt");
+    assert_eq!(args.last().unwrap(), "uptime");
+
+    // A stand-in ssh that prints its last argument and exits with status 3
+    let temp = tempdir().unwrap();
+    let bin = temp.path().join("bin");
+    std::fs::create_dir(&bin).unwrap();
+    let fake_ssh = bin.join("ssh");
+    std::fs::write(
+        &fake_ssh,
+        "#!/bin/sh\nfor arg; do last=$arg; done\necho \"ran $last\"\nexit 3\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(&fake_ssh, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let path = temp.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.create(None).unwrap();
+    vault.add_server(server).unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_portkey"))
+        .args(["connect", "web", "--exec", "uptime"])
+        .env("PATH", &bin)
+        .env("XDG_DATA_HOME", temp.path())
+        .env(VAULT_PATH_ENV, &path)
+        .stdin(std::process::Stdio::null())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3), "{output:?}");
+    // Only the remote command writes to stdout
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ran uptime\n");
+
+    vault.reload().unwrap();
+    assert!(vault.history().unwrap()[0].success);
+}
+
+#[test]
+#[cfg(unix)]
+fn exec_fans_out_to_tagged_servers_and_summarizes_failures() {
+    use std::os::unix::fs::PermissionsExt;
+
+    // A stand-in ssh that fails on 10.0.0.2 and succeeds everywhere else
+    let temp = tempdir().unwrap();
+    let bin = temp.path().join("bin");
+    std::fs::create_dir(&bin).unwrap();
+    let fake_ssh = bin.join("ssh");
+    std::fs::write(
+        &fake_ssh,
+        "#!/bin/sh\ncase \"$*\" in *10.0.0.2*) echo boom >&2; exit 2;; esac\necho up\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(&fake_ssh, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let path = temp.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.create(None).unwrap();
+    for (name, host, tags) in [
+        ("web1", "10.0.0.1", "web"),
+        ("web2", "10.0.0.2", "web"),
+        ("db", "10.0.0.3", "db"),
+    ] {
+        let mut server = Server::new(
+            name.into(),
+            host.into(),
+            22,
+            "root".into(),
+            String::new(),
+            None,
+        );
+        server.set_tags([tags]);
+        vault.add_server(server).unwrap();
+    }
+
+    let portkey = |args: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_portkey"))
+            .args(args)
+            .env("PATH", &bin)
+            .env("XDG_DATA_HOME", temp.path())
+            .env(VAULT_PATH_ENV, &path)
+            .stdin(std::process::Stdio::null())
+            .output()
+            .unwrap()
+    };
+    let output = portkey(&["exec", "--tag", "web", "--parallel", "2", "uptime"]);
+    assert!(!output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "[web1] up\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("[web2] boom"), "{stderr}");
+    assert!(stderr.contains("1 of 2 failed: web2 (exit 2)"), "{stderr}");
+
+    let output = portkey(&["exec", "--query", "db", "uptime"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "[db] up\n");
+
+    // A target has to be named explicitly
+    assert!(!portkey(&["exec", "uptime"]).status.success());
+    assert!(!portkey(&["exec", "--all", "--parallel", "0", "uptime"])
+        .status
+        .success());
+}
+
+#[test]
+fn list_sorts_reverse_and_are_remembered_between_runs() {
+    let servers: Vec<Server> = [("beta", 2222), ("alpha", 22), ("gamma", 80)]
+        .into_iter()
+        .map(|(name, port)| {
+            Server::new(
+                name.into(),
+                "10.0.0.1".into(),
+                port,
+                "root".into(),
+                String::new(),
+                None,
+            )
+        })
+        .collect();
+    let sorted_names = |sort: ListSort| {
+        let mut sorted: Vec<&Server> = servers.iter().collect();
+        sorted.sort_by(|a, b| sort.compare(a, b));
+        sorted.iter().map(|s| s.name.as_str()).collect::<Vec<_>>()
+    };
+    let by_port = ListSort::ascending(SortOrder::Port);
+    assert_eq!(sorted_names(by_port), ["alpha", "gamma", "beta"]);
+    assert_eq!(sorted_names(by_port.reversed()), ["beta", "gamma", "alpha"]);
+    assert_eq!(by_port.label(), "▲port");
+    assert_eq!(by_port.reversed().label(), "▼port");
+    assert_eq!(SortOrder::Host.next(), SortOrder::Port);
+    assert_eq!("port".parse::<SortOrder>().unwrap(), SortOrder::Port);
+
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("ui_state.json");
+    let state = UiState {
+        sort: Some(ListSort::ascending(SortOrder::Recent).reversed()),
+        ..UiState::default()
+    };
+    state.save_to(&path).unwrap();
+    assert_eq!(UiState::load_from(&path), state);
+
+    // State files written before sorting was remembered still load
+    std::fs::write(&path, r#"{"filter":"web"}"#).unwrap();
+    let loaded = UiState::load_from(&path);
+    assert_eq!((loaded.filter.as_str(), loaded.sort), ("web", None));
+}
+
+#[test]
+#[cfg(unix)]
+fn verbose_connects_pass_v_to_ssh_and_report_its_exit_status() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut server = Server::new(
+        "web".into(),
+        "10.0.0.1".into(),
+        22,
+        "root".into(),
+        String::new(),
+        None,
+    );
+    server.quiet_login = true;
+    let verbose = |verbosity| ConnectOptions {
+        verbosity,
+        ..ConnectOptions::default()
+    };
+    let args = build_ssh_args(&server, &verbose(0));
+    assert!(args.contains(&"-q".to_string()));
+    let args = build_ssh_args(&server, &verbose(2));
+    assert!(args.contains(&"-vv".to_string()));
+    assert!(!args.contains(&"-q".to_string()));
+    assert!(!args.contains(&"LogLevel=QUIET".to_string()));
+    assert!(build_ssh_args(&server, &verbose(7)).contains(&"-vvv".to_string()));
+
+    // A stand-in ssh that echoes its arguments and fails like ssh does
+    let temp = tempdir().unwrap();
+    let bin = temp.path().join("bin");
+    std::fs::create_dir(&bin).unwrap();
+    let fake_ssh = bin.join("ssh");
+    std::fs::write(&fake_ssh, "#!/bin/sh\necho \"$@\" >&2\nexit 255\n").unwrap();
+    std::fs::set_permissions(&fake_ssh, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let path = temp.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.create(None).unwrap();
+    vault.add_server(server).unwrap();
+
+    let connect = |args: &[&str]| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_portkey"))
+            .args(args)
+            .env("PATH", &bin)
+            .env("XDG_DATA_HOME", temp.path())
+            .env(VAULT_PATH_ENV, &path)
+            .stdin(std::process::Stdio::null())
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    };
+    let stderr = connect(&["connect", "web", "-vv"]);
+    assert!(stderr.contains("-tt -vv "), "{stderr}");
+    assert!(stderr.contains("SSH connection failed (exit status: 255)"));
+    let stderr = connect(&["connect", "web"]);
+    assert!(stderr.contains("run again with -v"), "{stderr}");
+}
+
+#[test]
+fn sudo_passwords_are_sealed_separately_and_fall_back_to_the_login_password() {
+    let temp = tempdir().unwrap();
+    let path = temp.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.create(Some("master")).unwrap();
+    let mut server = Server::new(
+        "web".into(),
+        "10.0.0.1".into(),
+        22,
+        "root".into(),
+        "login-secret".into(),
+        None,
+    );
+    let mut db = server.clone();
+    db.id = uuid::Uuid::new_v4();
+    db.host = "10.0.0.2".into();
+    db.name = "db".into();
+    vault.add_server(db).unwrap();
+    server.set_field("sudo_password", "sudo-secret").unwrap();
+    vault.add_server(server).unwrap();
+
+    let raw = std::fs::read(&path).unwrap();
+    assert!(!String::from_utf8_lossy(&raw).contains("sudo-secret"));
+
+    // Survives a master password change, still sealed on its own
+    vault.change_password(Some("master"), Some("new")).unwrap();
+    let mut reopened = Vault::with_path(path.clone()).unwrap();
+    reopened.unlock(Some("new")).unwrap();
+    let servers = reopened.list_servers().unwrap();
+    let web = servers.iter().find(|s| s.name == "web").unwrap();
+    let db = servers.iter().find(|s| s.name == "db").unwrap();
+    assert!(web.has_sudo_password() && web.sudo_password.is_none());
+    assert!(!db.has_sudo_password());
+    assert_eq!(reopened.decrypt_sudo_password(web).unwrap(), "sudo-secret");
+    assert_eq!(reopened.decrypt_sudo_password(db).unwrap(), "login-secret");
+    assert_eq!(reopened.decrypt_password(web).unwrap(), "login-secret");
+    let unsealed = reopened.with_password(web).unwrap();
+    assert_eq!(unsealed.sudo_password.as_deref(), Some("sudo-secret"));
+
+    let pass = |args: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_portkey"))
+            .args(args)
+            .env("XDG_DATA_HOME", temp.path())
+            .env(VAULT_PATH_ENV, &path)
+            .env("PORTKEY_PASSWORD", "new")
+            .stdin(std::process::Stdio::null())
+            .output()
+            .unwrap()
+    };
+    let output = pass(&["pass", "web", "--yes", "--sudo"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "sudo-secret");
+    let output = pass(&["pass", "web", "--yes"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "login-secret");
+
+    // A blank value goes back to reusing the login password
+    assert!(pass(&["edit", "web", "--set", "sudo_password="])
+        .status
+        .success());
+    let output = pass(&["pass", "web", "--yes", "--sudo"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "login-secret");
+}
+
+#[test]
+fn locking_an_encrypted_vault_wipes_sudo_passwords_and_requires_a_fresh_unlock() {
+    let temp = tempdir().unwrap();
+    let path = temp.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.create(Some("master")).unwrap();
+    let mut server = Server::new(
+        "db".to_string(),
+        "10.0.0.9".to_string(),
+        22,
+        "root".to_string(),
+        "hunter2".to_string(),
+        None,
+    );
+    server.set_sudo_password(Some("s3cret".to_string()));
+    vault.add_server(server).unwrap();
+
+    // A copy with the passwords still sealed, and one in cleartext
+    let sealed = vault.list_servers().unwrap()[0].clone();
+    assert!(sealed.has_sudo_password() && sealed.sudo_password.is_none());
+    // What lock does to each server before letting go of it
+    let mut loaded = sealed.clone();
+    loaded.set_password("hunter2".to_string());
+    loaded.set_sudo_password(Some("s3cret".to_string()));
+    loaded.wipe_secrets();
+    assert_eq!(loaded.password, "");
+    assert_eq!(loaded.sudo_password, None);
+    assert!(!loaded.has_password() && !loaded.has_sudo_password());
+
+    vault.lock();
+    assert!(!vault.is_unlocked());
+    // No master key is left to decrypt with
+    assert!(!vault.is_encrypted());
+    let err = vault.list_servers().unwrap_err();
+    assert!(matches!(err.downcast_ref(), Some(VaultError::Locked)));
+    assert!(matches!(
+        vault
+            .decrypt_sudo_password(&sealed)
+            .unwrap_err()
+            .downcast_ref(),
+        Some(VaultError::Locked)
+    ));
+    assert!(vault.unlock(None).is_err());
+
+    vault.unlock(Some("master")).unwrap();
+    let db = vault.list_servers().unwrap()[0].clone();
+    assert_eq!(vault.decrypt_password(&db).unwrap(), "hunter2");
+    assert_eq!(vault.decrypt_sudo_password(&db).unwrap(), "s3cret");
+
+    // Nothing outlives a CLI process, so the command only says so
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_portkey"))
+        .arg("lock")
+        .env("XDG_DATA_HOME", temp.path())
+        .env(VAULT_PATH_ENV, &path)
+        .stdin(std::process::Stdio::null())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Vault locked"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("keeps no key between commands"));
+}
+
+#[test]
+fn connect_as_another_user_leaves_the_stored_user_alone() {
+    let temp = tempdir().unwrap();
+    let path = temp.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.create(None).unwrap();
+    vault
+        .add_server(Server::new(
+            "web".into(),
+            "10.0.0.1".into(),
+            22,
+            "root".into(),
+            "hunter2".into(),
+            None,
+        ))
+        .unwrap();
+    drop(vault);
+
+    let connect = |args: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_portkey"))
+            .args(args)
+            .env("XDG_DATA_HOME", temp.path())
+            .env(VAULT_PATH_ENV, &path)
+            .stdin(std::process::Stdio::null())
+            .output()
+            .unwrap()
+    };
+    let output = connect(&["connect", "web", "--user", "ansible", "--dry-run"]);
+    assert!(output.status.success());
+    let preview = String::from_utf8_lossy(&output.stdout);
+    assert!(preview.contains("ansible@10.0.0.1"), "{preview}");
+    assert!(!preview.contains("SSHPASS"), "{preview}");
+
+    // The same user keeps the stored password and asks nothing
+    let output = connect(&["connect", "web", "--user", "root", "--dry-run"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("root@10.0.0.1"));
+    // Another user needs a password prompt, which a closed stdin can't answer
+    assert!(!connect(&["connect", "web", "--user", "ansible"])
+        .status
+        .success());
+    assert!(
+        connect(&["connect", "web", "--ask-pass", "--dry-run"])
+            .status
+            .code()
+            == Some(2)
+    );
+
+    let mut vault = Vault::with_path(path).unwrap();
+    vault.unlock(None).unwrap();
+    let web = &vault.list_servers().unwrap()[0];
+    assert_eq!(web.username, "root");
+    assert_eq!(vault.decrypt_password(web).unwrap(), "hunter2");
+}
+
+#[test]
+fn plain_json_exports_match_the_generated_schema() {
+    let schema = serde_json::to_value(VaultData::schema()).unwrap();
+    let server = &schema["definitions"]["Server"];
+    assert_eq!(server["properties"]["password"]["writeOnly"], true);
+    assert_eq!(server["properties"]["password"]["x-secret"], true);
+    assert_eq!(server["properties"]["sudo_password"]["x-secret"], true);
+    assert!(schema["properties"]["servers"].is_object());
+
+    let temp = tempdir().unwrap();
+    let path = temp.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.create(Some("master")).unwrap();
+    let mut web = Server::new(
+        "web".into(),
+        "10.0.0.1".into(),
+        22,
+        "root".into(),
+        "hunter2".into(),
+        None,
+    );
+    web.set_sudo_password(Some("s3cret".into()));
+    web.port_forwards = parse_port_forwards("5432:localhost:5432").unwrap();
+    vault.add_server(web).unwrap();
+    drop(vault);
+
+    let export = |args: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_portkey"))
+            .args(args)
+            .env("XDG_DATA_HOME", temp.path())
+            .env(VAULT_PATH_ENV, &path)
+            .env("PORTKEY_PASSWORD", "master")
+            .stdin(std::process::Stdio::null())
+            .output()
+            .unwrap()
+    };
+    let output_path = temp.path().join("plain.json");
+    let output = output_path.to_str().unwrap();
+    // Without --yes the confirmation can't be answered
+    assert!(!export(&["export", "-o", output, "--plain-json"])
+        .status
+        .success());
+    assert!(!output_path.exists());
+    assert!(export(&["export", "-o", output, "--plain-json", "--yes"])
+        .status
+        .success());
+
+    let exported: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&output_path).unwrap()).unwrap();
+    let entry = exported["servers"][0].as_object().unwrap();
+    assert_eq!(entry["password"], "hunter2");
+    assert_eq!(entry["sudo_password"], "s3cret");
+    assert!(!entry.contains_key("password_enc"));
+    // Every field written is one the schema knows about
+    let known = server["properties"].as_object().unwrap();
+    for field in entry.keys() {
+        assert!(known.contains_key(field), "{field} missing from the schema");
+    }
+    let data: VaultData = serde_json::from_value(exported).unwrap();
+    assert_eq!(data.servers[0].name, "web");
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&output_path)
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+    let printed = export(&["schema"]);
+    let printed: serde_json::Value = serde_json::from_slice(&printed.stdout).unwrap();
+    assert_eq!(printed, schema);
+}
+
+#[cfg(unix)]
+#[test]
+fn connection_failures_are_retried_but_remote_exits_are_not() {
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::process::ExitStatusExt;
+
+    let exit = |code: i32| std::process::ExitStatus::from_raw(code << 8);
+    let retry = Retry {
+        retries: 2,
+        delay: std::time::Duration::ZERO,
+    };
+    let mut codes = vec![255, 255, 255].into_iter();
+    let mut attempts = 0;
+    let status = with_retries(
+        retry,
+        || {
+            attempts += 1;
+            Ok(exit(codes.next().unwrap()))
+        },
+        || true,
+    )
+    .unwrap();
+    assert_eq!((attempts, status.code()), (3, Some(255)));
+
+    // A remote exit or a password sshpass rejected ends it at once, and so
+    // does a 255 from a server that answers, like a rejected key
+    for (code, unreachable) in [(1, true), (5, true), (255, false)] {
+        let mut attempts = 0;
+        let status = with_retries(
+            retry,
+            || {
+                attempts += 1;
+                Ok(exit(code))
+            },
+            || unreachable,
+        )
+        .unwrap();
+        assert_eq!((attempts, status.code()), (1, Some(code)));
+    }
+
+    // The first hop is what gets probed
+    let server = |host: &str, port| {
+        Server::new(
+            "web".into(),
+            host.into(),
+            port,
+            "root".into(),
+            String::new(),
+            None,
+        )
+    };
+    let mut gateway = server("fd00::1", 2200);
+    let target = server("10.0.0.1", 22);
+    let via = |gateway: &Server| ConnectOptions {
+        gateways: vec![gateway.clone()],
+        ..Default::default()
+    };
+    assert_eq!(
+        first_hop(&target, &ConnectOptions::default()),
+        Some(("10.0.0.1".to_string(), 22))
+    );
+    assert_eq!(
+        first_hop(&target, &via(&gateway)),
+        Some(("fd00::1".to_string(), 2200))
+    );
+    gateway.host = "gw.example.com".into();
+    assert_eq!(
+        first_hop(&target, &via(&gateway)),
+        Some(("gw.example.com".to_string(), 2200))
+    );
+    let mut proxied = target.clone();
+    proxied.ssh_options = vec![("ProxyCommand".into(), "nc %h %p".into())];
+    assert_eq!(first_hop(&proxied, &ConnectOptions::default()), None);
+
+    // A stand-in ssh that fails to connect once, then works, aimed at a
+    // port nothing listens on
+    let closed_port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let temp = tempdir().unwrap();
+    let bin = temp.path().join("bin");
+    std::fs::create_dir(&bin).unwrap();
+    let fake_ssh = bin.join("ssh");
+    std::fs::write(
+        &fake_ssh,
+        "#!/bin/sh\nn=0\n[ -f \"$ATTEMPTS\" ] && read n < \"$ATTEMPTS\"\necho $((n + 1)) > \"$ATTEMPTS\"\n[ \"$n\" -ge 1 ] && exit 0\nexit 255\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(&fake_ssh, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let path = temp.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.create(None).unwrap();
+    vault.add_server(server("127.0.0.1", closed_port)).unwrap();
+    let counter = temp.path().join("attempts");
+    let connect = || {
+        std::process::Command::new(env!("CARGO_BIN_EXE_portkey"))
+            .args(["connect", "web", "--retries", "3", "--retry-delay", "10"])
+            .env("PATH", &bin)
+            .env("ATTEMPTS", &counter)
+            .env("XDG_DATA_HOME", temp.path())
+            .env(VAULT_PATH_ENV, &path)
+            .stdin(std::process::Stdio::null())
+            .output()
+            .unwrap()
+    };
+    let output = connect();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert!(stderr.contains("Retrying (2/4)…"), "{stderr}");
+    assert!(!stderr.contains("(3/4)"));
+    assert_eq!(std::fs::read_to_string(&counter).unwrap().trim(), "2");
+
+    // With the port answering, the same 255 is a failed login and ends it
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut web = vault.list_servers().unwrap()[0].clone();
+    web.port = listener.local_addr().unwrap().port();
+    vault.replace_server(web).unwrap();
+    std::fs::remove_file(&counter).unwrap();
+    let output = connect();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(!stderr.contains("Retrying"), "{stderr}");
+    assert_eq!(std::fs::read_to_string(&counter).unwrap().trim(), "1");
+}
+
+#[test]
+fn a_single_server_renders_the_same_block_as_the_full_config() {
+    let mut gateway = Server::new(
+        "gw".into(),
+        "gw.example.com".into(),
+        22,
+        "jump".into(),
+        String::new(),
+        None,
+    );
+    gateway.auth_method = AuthMethod::Agent;
+    let mut web = Server::new(
+        "web".into(),
+        "10.0.0.1".into(),
+        2222,
+        "deploy".into(),
+        "hunter2".into(),
+        None,
+    );
+    web.gateway_id = Some(gateway.id);
+    web.ssh_options = parse_ssh_options("Compression=yes").unwrap();
+    web.port_forwards = parse_port_forwards("5432:localhost:5432").unwrap();
+    let servers = vec![gateway, web];
+
+    let block = render_host_block(&servers[1], &servers).unwrap();
+    assert!(block.starts_with("Host web\n"));
+    assert!(block.contains("  Compression yes\n"));
+    assert!(block.contains("  LocalForward 5432 localhost:5432\n"));
+    assert!(block.contains("  ProxyJump gw\n"));
+    assert!(!block.contains("hunter2"));
+    assert!(render_ssh_config(&servers).unwrap().contains(&block));
+
+    let temp = tempdir().unwrap();
+    let path = temp.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.create(None).unwrap();
+    for server in &servers {
+        vault.add_server(server.clone()).unwrap();
+    }
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_portkey"))
+        .args(["ssh-config", "web"])
+        .env("XDG_DATA_HOME", temp.path())
+        .env(VAULT_PATH_ENV, &path)
+        .stdin(std::process::Stdio::null())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), block);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("portkey ssh-config gw"));
+}
+
+#[test]
+fn themes_parse_by_name_and_plain_tells_health_apart_without_color() {
+    assert_eq!("Light".parse::<ThemeName>(), Ok(ThemeName::Light));
+    assert!("solarized".parse::<ThemeName>().is_err());
+    assert_eq!(Theme::named(ThemeName::Dark), Theme::dark());
+    assert_ne!(Theme::dark(), Theme::light());
+
+    let dark = Theme::dark();
+    assert_eq!(dark.health_dot(Health::Up).content, "● ");
+    assert_eq!(dark.health_dot(Health::Down).content, "● ");
+    assert_ne!(
+        dark.health_dot(Health::Up).style,
+        dark.health_dot(Health::Down).style
+    );
+
+    let plain = Theme::plain();
+    let dots: Vec<_> = [Health::Up, Health::Down, Health::Unknown]
+        .into_iter()
+        .map(|health| plain.health_dot(health))
+        .collect();
+    assert!(dots.iter().all(|dot| dot.style.fg.is_none()));
+    assert_ne!(dots[0].content, dots[1].content);
+    assert_ne!(dots[1].content, dots[2].content);
+    for style in [plain.header, plain.muted, plain.tags, plain.selected] {
+        assert!(style.fg.is_none() && style.bg.is_none());
+    }
+}
+
+#[test]
+fn prune_removes_stale_servers_but_keeps_protected_ones_and_needed_gateways() {
+    assert_eq!(parse_age("90d").unwrap(), chrono::Duration::days(90));
+    assert_eq!(parse_age("2W").unwrap(), chrono::Duration::days(14));
+    assert_eq!(parse_age(" 3m ").unwrap(), chrono::Duration::days(90));
+    for bad in ["", "d", "90", "0d", "-5d", "3y", "9999999999999m", "5é"] {
+        assert!(parse_age(bad).is_err(), "{bad}");
+    }
+
+    let server = |name: &str, days_ago: Option<i64>| {
+        let mut server = Server::new(
+            name.into(),
+            format!("{name}.example.com"),
+            22,
+            "root".into(),
+            String::new(),
+            None,
+        );
+        server.last_connected =
+            days_ago.map(|days| chrono::Utc::now() - chrono::Duration::days(days));
+        server
+    };
+    let gateway = server("gw", None);
+    let mut behind = server("behind", Some(1));
+    behind.gateway_id = Some(gateway.id);
+    let mut vault_server = server("vault", None);
+    vault_server.protected = true;
+    let servers = vec![
+        server("fresh", Some(3)),
+        server("old", Some(200)),
+        server("never", None),
+        gateway,
+        behind,
+        vault_server,
+    ];
+    let cutoff = chrono::Utc::now() - chrono::Duration::days(90);
+    let names: Vec<&str> = stale_servers(&servers, cutoff)
+        .iter()
+        .map(|server| server.name.as_str())
+        .collect();
+    assert_eq!(names, ["old", "never"]);
+
+    let temp = tempdir().unwrap();
+    let path = temp.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.create(None).unwrap();
+    for server in &servers {
+        vault.add_server(server.clone()).unwrap();
+    }
+    let prune = |args: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_portkey"))
+            .arg("prune")
+            .args(args)
+            .env("XDG_DATA_HOME", temp.path())
+            .env(VAULT_PATH_ENV, &path)
+            .stdin(std::process::Stdio::null())
+            .output()
+            .unwrap()
+    };
+    let output = prune(&["--older-than", "90d", "--dry-run"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("- never (root@never.example.com, never connected)"));
+    assert!(stdout.contains("Keeping 1 protected server(s)."));
+    assert!(stdout.contains("Would remove 2 server(s)."), "{stdout}");
+    assert!(!prune(&["--older-than", "soon"]).status.success());
+
+    let output = prune(&["--older-than", "90d", "--yes"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Removed 2 server(s)"));
+    let mut vault = Vault::with_path(path).unwrap();
+    vault.unlock(None).unwrap();
+    let left: Vec<&str> = vault
+        .list_servers()
+        .unwrap()
+        .iter()
+        .map(|server| server.name.as_str())
+        .collect();
+    assert_eq!(left, ["fresh", "gw", "behind", "vault"]);
+    assert_eq!(
+        vault.disk_size().unwrap(),
+        std::fs::metadata(vault.vault_path()).unwrap().len()
+    );
+}
+
+#[test]
+fn aliases_name_the_ssh_config_host_and_work_as_connect_targets() {
+    let mut web = Server::new(
+        "Web Box (EU)".into(),
+        "10.0.0.1".into(),
+        22,
+        "root".into(),
+        String::new(),
+        None,
+    );
+    assert_eq!(web.ssh_alias(), "Web-Box-EU");
+    let mut db = Server::new(
+        "db".into(),
+        "10.0.0.2".into(),
+        22,
+        "root".into(),
+        String::new(),
+        None,
+    );
+    db.set_field("alias", "eu-db").unwrap();
+    db.gateway_id = Some(web.id);
+    assert_eq!(db.ssh_alias(), "eu-db");
+    for bad in ["eu db", "web*", "a,b", "#x"] {
+        let mut server = db.clone();
+        server.set_field("alias", bad).unwrap();
+        assert!(matches!(
+            server.validate(),
+            Err(ValidationError::InvalidAlias(_))
+        ));
+    }
+    web.set_field("alias", " ").unwrap();
+    assert_eq!(web.alias, None);
+
+    let servers = vec![web, db];
+    let config = render_ssh_config(&servers).unwrap();
+    assert!(config.contains("Host Web-Box-EU\n"));
+    assert!(config.contains("Host eu-db\n"));
+    assert!(config.contains("  ProxyJump Web-Box-EU\n"));
+
+    let names = |query: &str| -> Vec<String> {
+        match_servers(&servers, query, true)
+            .iter()
+            .map(|server| server.name.clone())
+            .collect()
+    };
+    assert_eq!(names("EU-DB"), ["db"]);
+    assert_eq!(names("web-box-eu"), ["Web Box (EU)"]);
+    assert_eq!(names("Web Box (EU)"), ["Web Box (EU)"]);
+}
+
+#[test]
+fn aliases_must_be_unique_across_the_vault_ignoring_case() {
+    let temp = tempdir().unwrap();
+    let mut vault = Vault::with_path(temp.path().join("vault.dat")).unwrap();
+    vault.create(None).unwrap();
+    let mut db = Server::new(
+        "db".into(),
+        "10.0.0.2".into(),
+        22,
+        "root".into(),
+        String::new(),
+        None,
+    );
+    db.alias = Some("eu-db".into());
+    vault.add_server(db.clone()).unwrap();
+
+    let mut other = Server::new(
+        "replica".into(),
+        "10.0.0.3".into(),
+        22,
+        "root".into(),
+        String::new(),
+        None,
+    );
+    other.alias = Some("EU-DB".into());
+    let err = vault.add_server(other.clone()).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(VaultError::DuplicateAlias(alias, name)) if alias == "EU-DB" && name == "db"
+    ));
+    // A name whose derived alias clashes is caught too
+    let mut named = other.clone();
+    named.alias = None;
+    named.name = "Eu DB".into();
+    assert!(matches!(
+        vault
+            .add_server_allowing_duplicate(named)
+            .unwrap_err()
+            .downcast_ref(),
+        Some(VaultError::DuplicateAlias(..))
+    ));
+
+    other.alias = Some("eu-replica".into());
+    vault.add_server(other.clone()).unwrap();
+    other.alias = Some("Eu-Db".into());
+    assert!(matches!(
+        vault.replace_server(other).unwrap_err().downcast_ref(),
+        Some(VaultError::DuplicateAlias(..))
+    ));
+    // Saving a server under its own alias is not a clash
+    db.port = 2222;
+    assert!(vault.replace_server(db.clone()).unwrap());
+
+    // A copy drops the alias it would otherwise share
+    let copy_id = vault.clone_server(&db.id).unwrap().unwrap();
+    let copy = vault.find_server(&copy_id).unwrap().unwrap();
+    assert_eq!(copy.alias, None);
+    assert_eq!(copy.ssh_alias(), "db-copy");
+    let aliases: Vec<String> = vault
+        .list_servers()
+        .unwrap()
+        .iter()
+        .map(|server| server.ssh_alias())
+        .collect();
+    assert_eq!(aliases, ["eu-db", "eu-replica", "db-copy"]);
+}
+
+#[test]
+fn a_key_derived_elsewhere_unlocks_the_vault() {
+    let temp = tempdir().unwrap();
+    let path = temp.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.create(Some("master")).unwrap();
+    vault
+        .add_server(Server::new(
+            "web".into(),
+            "10.0.0.1".into(),
+            22,
+            "root".into(),
+            "hunter2".into(),
+            None,
+        ))
+        .unwrap();
+    drop(vault);
+
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    let (salt, kdf) = vault.key_derivation().unwrap();
+    let wrong = MasterKey::from_password("nope", &salt, kdf).unwrap();
+    let err = vault.unlock_with_key(wrong).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(VaultError::WrongPassword)
+    ));
+    assert!(!vault.is_unlocked());
+
+    let key = std::thread::spawn(move || MasterKey::from_password("master", &salt, kdf))
+        .join()
+        .unwrap()
+        .unwrap();
+    vault.unlock_with_key(key).unwrap();
+    assert!(vault.is_encrypted());
+    let web = &vault.list_servers().unwrap()[0];
+    assert_eq!(vault.decrypt_password(web).unwrap(), "hunter2");
+
+    // The CLI derives on a blocking thread; without a terminal there is no spinner
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_portkey"))
+        .args(["list", "--count"])
+        .env("XDG_DATA_HOME", temp.path())
+        .env(VAULT_PATH_ENV, &path)
+        .env("PORTKEY_PASSWORD", "master")
+        .stdin(std::process::Stdio::null())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "1");
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("Deriving key"));
+}
+
+#[cfg(unix)]
+#[test]
+fn ctrl_c_during_key_derivation_exits_without_waiting_for_it() {
+    let temp = tempdir().unwrap();
+    let path = temp.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.create(Some("master")).unwrap();
+    drop(vault);
+    // A cost that takes far longer to derive than the test waits
+    let mut vault_file: VaultFile = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+    vault_file.kdf = KdfParams {
+        opslimit: KdfStrength::Interactive.params().opslimit * 500,
+        ..KdfStrength::Interactive.params()
+    };
+    std::fs::write(&path, serde_json::to_vec(&vault_file).unwrap()).unwrap();
+
+    let child = std::process::Command::new(env!("CARGO_BIN_EXE_portkey"))
+        .args(["list", "--count"])
+        .env("XDG_DATA_HOME", temp.path())
+        .env(VAULT_PATH_ENV, &path)
+        .env("PORTKEY_PASSWORD", "master")
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(1000));
+    let started = std::time::Instant::now();
+    let killed = std::process::Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(killed.success());
+    let output = child.wait_with_output().unwrap();
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    assert_eq!(output.status.code(), Some(130));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unlock cancelled"));
+}
+
+#[test]
+fn dumps_redact_secrets_unless_asked_and_count_populated_fields() {
+    let temp = tempdir().unwrap();
+    let path = temp.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.create(Some("master")).unwrap();
+    let mut web = Server::new(
+        "web".into(),
+        "web.example.com".into(),
+        22,
+        "root".into(),
+        "hunter2".into(),
+        None,
+    );
+    web.notes = Some("vpn pin 4321".into());
+    web.set_tags(["prod"]);
+    let mut db = Server::new(
+        "db".into(),
+        "WEB.example.com".into(),
+        5432,
+        "postgres".into(),
+        String::new(),
+        Some("primary".into()),
+    );
+    db.set_sudo_password(Some("s3cret".into()));
+    db.set_tags(["prod", "db"]);
+    db.ssh_options = vec![
+        ("HostName".into(), "db.internal.example.com".into()),
+        ("ProxyJump".into(), "bastion.example.com".into()),
+        ("Compression".into(), "yes".into()),
+    ];
+    vault.add_server(web).unwrap();
+    vault.add_server(db).unwrap();
+
+    let dump = dump_vault(vault.data().unwrap(), DumpOptions::default()).unwrap();
+    let text = dump.to_string();
+    for secret in ["hunter2", "s3cret", "vpn pin", "password_enc", "nonce"] {
+        assert!(!text.contains(secret), "{secret} leaked: {text}");
+    }
+    assert_eq!(dump["version"], CURRENT_VERSION);
+    assert_eq!(dump["server_count"], 2);
+    assert_eq!(dump["secrets"], "redacted");
+    assert_eq!(dump["populated_fields"]["tags"], 2);
+    assert_eq!(dump["populated_fields"]["password"], 1);
+    assert_eq!(dump["populated_fields"]["description"], 1);
+    assert!(dump["populated_fields"].get("jump_host").is_none());
+    let servers = &dump["data"]["servers"];
+    assert_eq!(servers[0]["password"], REDACTED);
+    assert_eq!(servers[0]["notes"], REDACTED);
+    assert_eq!(servers[1]["password"], "");
+    assert_eq!(servers[1]["sudo_password"], REDACTED);
+    assert_eq!(servers[0]["host"], "web.example.com");
+
+    let hashed = dump_vault(
+        vault.data().unwrap(),
+        DumpOptions {
+            hash_hosts: true,
+            ..DumpOptions::default()
+        },
+    )
+    .unwrap();
+    let host = hashed["data"]["servers"][0]["host"].as_str().unwrap();
+    assert!(host.starts_with("host-") && !host.contains("example"));
+    assert_eq!(hashed["data"]["servers"][1]["host"], host);
+    let options = &hashed["data"]["servers"][1]["ssh_options"];
+    assert_eq!(options[0][0], "HostName");
+    assert!(options[0][1].as_str().unwrap().starts_with("host-"));
+    assert!(options[1][1].as_str().unwrap().starts_with("host-"));
+    assert_eq!(options[2][1], "yes");
+    assert!(!hashed.to_string().contains("example.com"));
+    // Salted per dump, so the same host hashes differently next time
+    let again = dump_vault(
+        vault.data().unwrap(),
+        DumpOptions {
+            hash_hosts: true,
+            ..DumpOptions::default()
+        },
+    )
+    .unwrap();
+    assert_ne!(again["data"]["servers"][0]["host"], host);
+    drop(vault);
+
+    let dump = |args: &[&str]| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_portkey"))
+            .arg("dump")
+            .args(args)
+            .env("XDG_DATA_HOME", temp.path())
+            .env(VAULT_PATH_ENV, &path)
+            .env("PORTKEY_PASSWORD", "master")
+            .stdin(std::process::Stdio::null())
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    assert!(!dump(&["--redacted"]).contains("hunter2"));
+    let full = dump(&["--include-secrets"]);
+    assert!(full.contains("hunter2") && full.contains("s3cret") && full.contains("vpn pin"));
+}
+
+#[test]
+fn form_field_checks_agree_with_server_validation() {
+    assert_eq!(validate_name("  "), Err(ValidationError::EmptyName));
+    assert_eq!(validate_username(""), Err(ValidationError::EmptyUsername));
+    assert!(validate_name("web").is_ok() && validate_username("deploy").is_ok());
+    for port in ["", "0", "65536", "22a"] {
+        assert!(parse_port(port).is_err(), "{port:?} should be rejected");
+    }
+
+    let mut server = Server::new(
+        "web".to_string(),
+        "web.example.com".to_string(),
+        22,
+        " ".to_string(),
+        String::new(),
+        None,
+    );
+    assert_eq!(server.validate(), validate_username(&server.username));
+    server.username = "deploy".to_string();
+    server.name = String::new();
+    assert_eq!(server.validate(), validate_name(&server.name));
+
+    assert!(Theme::dark().error.fg.is_some());
+    assert!(Theme::plain().error.fg.is_none());
+}
+
+#[test]
+fn split_vaults_keep_each_server_in_its_own_file() {
+    let temp = tempdir().unwrap();
+    let path = temp.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.set_layout(StoreLayout::Split).unwrap();
+    vault.create(Some("pw")).unwrap();
+
+    let web = Server::new(
+        "web".to_string(),
+        "web.example.com".to_string(),
+        22,
+        "deploy".to_string(),
+        "web-pw".to_string(),
+        None,
+    );
+    let mut db = Server::new(
+        "db".to_string(),
+        "db.example.com".to_string(),
+        22,
+        "postgres".to_string(),
+        "db-pw".to_string(),
+        None,
+    );
+    vault.add_server(web.clone()).unwrap();
+    vault.add_server(db.clone()).unwrap();
+
+    let dir = vault.servers_dir();
+    let server_file = |server: &Server| dir.join(format!("{}.dat", server.id));
+    let web_file = std::fs::read(server_file(&web)).unwrap();
+    let index = std::fs::read(&path).unwrap();
+    let index_text = String::from_utf8_lossy(&index);
+    assert!(!index_text.contains("web.example.com"), "{index_text}");
+
+    // Editing one server rewrites its file and nothing else
+    db.port = 5432;
+    vault.replace_server(db.clone()).unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), index);
+    assert_eq!(std::fs::read(server_file(&web)).unwrap(), web_file);
+
+    let mut reopened = Vault::with_path(path.clone()).unwrap();
+    reopened.unlock(Some("pw")).unwrap();
+    assert_eq!(reopened.layout(), StoreLayout::Split);
+    let servers = reopened.list_servers().unwrap().clone();
+    assert_eq!(
+        servers.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(),
+        ["web", "db"]
+    );
+    assert_eq!(servers[1].port, 5432);
+    assert_eq!(reopened.decrypt_password(&servers[0]).unwrap(), "web-pw");
+
+    // A removed server's file goes with it, and so do its bytes; a new
+    // password reseals the rest
+    let files_size = |ids: &[uuid::Uuid]| -> u64 {
+        ids.iter()
+            .map(|id| {
+                std::fs::metadata(dir.join(format!("{id}.dat")))
+                    .unwrap()
+                    .len()
+            })
+            .sum()
+    };
+    let index_size = || std::fs::metadata(&path).unwrap().len();
+    assert_eq!(
+        reopened.disk_size().unwrap(),
+        index_size() + files_size(&[web.id, db.id])
+    );
+    reopened.remove_server(&web.id).unwrap();
+    assert!(!server_file(&web).exists());
+    assert_eq!(
+        reopened.disk_size().unwrap(),
+        index_size() + files_size(&[db.id])
+    );
+    reopened.change_password(Some("pw"), Some("new")).unwrap();
+    let mut reopened = Vault::with_path(path.clone()).unwrap();
+    reopened.unlock(Some("new")).unwrap();
+    let db = reopened.list_servers().unwrap()[0].clone();
+    assert_eq!(reopened.decrypt_password(&db).unwrap(), "db-pw");
+
+    // Going back to one file takes the servers along and drops the directory
+    reopened.set_layout(StoreLayout::Single).unwrap();
+    assert!(!dir.exists());
+    let mut single = Vault::with_path(path).unwrap();
+    single.unlock(Some("new")).unwrap();
+    assert_eq!(single.layout(), StoreLayout::Single);
+    assert_eq!(single.list_servers().unwrap().len(), 1);
+
+    assert_eq!("Split".parse::<StoreLayout>(), Ok(StoreLayout::Split));
+    assert!("sharded".parse::<StoreLayout>().is_err());
+}
+
+#[test]
+fn split_vaults_keep_removed_servers_files_until_the_index_is_saved() {
+    let temp = tempdir().unwrap();
+    let path = temp.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.set_layout(StoreLayout::Split).unwrap();
+    vault.create(Some("pw")).unwrap();
+    let server = |name: &str| {
+        Server::new(
+            name.to_string(),
+            format!("{name}.example.com"),
+            22,
+            "deploy".to_string(),
+            String::new(),
+            None,
+        )
+    };
+    let web = server("web");
+    vault.add_server(web.clone()).unwrap();
+    vault.add_server(server("db")).unwrap();
+    let web_file = vault.servers_dir().join(format!("{}.dat", web.id));
+
+    // A non-empty directory in the index's place makes its rename fail
+    let saved_index = temp.path().join("saved-index");
+    std::fs::rename(&path, &saved_index).unwrap();
+    std::fs::create_dir(&path).unwrap();
+    std::fs::write(path.join("blocker"), "").unwrap();
+    assert!(vault.remove_server(&web.id).is_err());
+    assert!(web_file.exists());
+
+    // The old index still opens with every server it lists
+    std::fs::remove_dir_all(&path).unwrap();
+    std::fs::rename(&saved_index, &path).unwrap();
+    let mut reopened = Vault::with_path(path.clone()).unwrap();
+    reopened.unlock(Some("pw")).unwrap();
+    assert_eq!(reopened.list_servers().unwrap().len(), 2);
+
+    // Once the new index is written the file goes
+    reopened.remove_server(&web.id).unwrap();
+    assert!(!web_file.exists());
+}
+
+#[test]
+fn split_vault_password_changes_never_mix_keys_across_files() {
+    let temp = tempdir().unwrap();
+    let path = temp.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.set_layout(StoreLayout::Split).unwrap();
+    vault.create(Some("old")).unwrap();
+    for name in ["web", "db"] {
+        vault
+            .add_server(Server::new(
+                name.to_string(),
+                format!("{name}.example.com"),
+                22,
+                "deploy".to_string(),
+                format!("{name}-pw"),
+                None,
+            ))
+            .unwrap();
+    }
+    let servers_dir = vault.servers_dir();
+    let pending = vault.pending_servers_dir();
+
+    // The index can't be replaced, so the change fails after the new files
+    // are written; they stay out of the way and the old password still works
+    let saved_index = temp.path().join("saved-index");
+    std::fs::rename(&path, &saved_index).unwrap();
+    std::fs::create_dir(&path).unwrap();
+    std::fs::write(path.join("blocker"), "").unwrap();
+    assert!(vault.change_password(Some("old"), Some("new")).is_err());
+    std::fs::remove_dir_all(&path).unwrap();
+    std::fs::rename(&saved_index, &path).unwrap();
+    let mut reopened = Vault::with_path(path.clone()).unwrap();
+    reopened.unlock(Some("old")).unwrap();
+    assert_eq!(reopened.list_servers().unwrap().len(), 2);
+
+    // A retry succeeds and leaves a single directory behind
+    reopened.change_password(Some("old"), Some("new")).unwrap();
+    assert!(servers_dir.exists() && !pending.exists());
+
+    // Stopped between writing the index and swapping the directories, the
+    // vault opens from the pending files
+    std::fs::rename(&servers_dir, &pending).unwrap();
+    let mut interrupted = Vault::with_path(path.clone()).unwrap();
+    interrupted.unlock(Some("new")).unwrap();
+    let db = interrupted.list_servers().unwrap()[1].clone();
+    assert_eq!(interrupted.decrypt_password(&db).unwrap(), "db-pw");
+}
+
+#[test]
+fn configured_defaults_fill_in_the_port_of_new_servers() {
+    let temp = tempdir().unwrap();
+    let config_path = temp.path().join("portkey").join("config.toml");
+    assert_eq!(Config::load_from(&config_path).unwrap(), Config::default());
+    assert_eq!(Config::default().port(), 22);
+    assert_eq!(Config::default().username(), "");
+
+    let mut config = Config::default();
+    assert!(config.set("default_port", "0").is_err());
+    assert!(config.set("default_shell", "zsh").is_err());
+    config.set("default_username", "  ").unwrap();
+    assert_eq!(config.default_username, None);
+
+    let path = temp.path().join("vault.dat");
+    Vault::with_path(path.clone())
+        .unwrap()
+        .create(None)
+        .unwrap();
+    let portkey = |args: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_portkey"))
+            .args(args)
+            .env("XDG_DATA_HOME", temp.path())
+            .env(VAULT_PATH_ENV, &path)
+            .stdin(std::process::Stdio::null())
+            .output()
+            .unwrap()
+    };
+
+    assert!(portkey(&["config", "set", "default_port", "2222"])
+        .status
+        .success());
+    assert!(portkey(&["config", "set", "default_username", "admin"])
+        .status
+        .success());
+    let saved = Config::load_from(&config_path).unwrap();
+    assert_eq!(saved.port(), 2222);
+    assert_eq!(saved.username(), "admin");
+    let shown = portkey(&["config"]);
+    assert!(String::from_utf8_lossy(&shown.stdout).contains("default_port = 2222"));
+
+    let added = portkey(&[
+        "add",
+        "--name",
+        "web",
+        "--host",
+        "10.0.0.1",
+        "--user",
+        "deploy",
+        "--password",
+        "pw",
+    ]);
+    assert!(added.status.success(), "{added:?}");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.unlock(None).unwrap();
+    assert_eq!(vault.list_servers().unwrap()[0].port, 2222);
+
+    std::fs::write(&config_path, "default_port = \"ssh\"\n").unwrap();
+    let broken = portkey(&["config", "set", "default_username", "ops"]);
+    assert!(!broken.status.success());
+}
+
+#[test]
+#[cfg(unix)]
+fn a_clear_query_connects_without_opening_the_tui() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let server = |name: &str, host: &str| {
+        Server::new(
+            name.into(),
+            host.into(),
+            22,
+            "root".into(),
+            String::new(),
+            None,
+        )
+    };
+    let servers = vec![
+        server("prod-web", "10.0.0.1"),
+        server("prod-db", "10.0.0.2"),
+        server("staging", "10.0.1.1"),
+    ];
+    let name = |query: &str| strong_match(&servers, query).map(|s| s.name.as_str());
+    assert_eq!(name("prod-web"), Some("prod-web"));
+    assert_eq!(name("STAG"), Some("staging"));
+    // Two servers share the prefix, so neither is picked
+    assert_eq!(name("prod"), None);
+    assert_eq!(name("zzz"), None);
+
+    // A stand-in ssh that records where it was sent
+    let temp = tempdir().unwrap();
+    let bin = temp.path().join("bin");
+    std::fs::create_dir(&bin).unwrap();
+    let fake_ssh = bin.join("ssh");
+    std::fs::write(&fake_ssh, "#!/bin/sh\necho \"$@\" > \"$ARGS\"\n").unwrap();
+    std::fs::set_permissions(&fake_ssh, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let path = temp.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.create(None).unwrap();
+    for server in servers {
+        vault.add_server(server).unwrap();
+    }
+    let args = temp.path().join("args");
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_portkey"))
+        .arg("prod-db")
+        .env("PATH", &bin)
+        .env("ARGS", &args)
+        .env("XDG_DATA_HOME", temp.path())
+        .env(VAULT_PATH_ENV, &path)
+        .stdin(std::process::Stdio::null())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert!(std::fs::read_to_string(&args)
+        .unwrap()
+        .contains("root@10.0.0.2"));
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_portkey"))
+        .args(["prod-db", "list"])
+        .env("XDG_DATA_HOME", temp.path())
+        .env(VAULT_PATH_ENV, &path)
+        .stdin(std::process::Stdio::null())
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
+
+#[test]
+fn read_only_vaults_refuse_changes_but_still_list_and_connect() {
+    let temp = tempdir().unwrap();
+    let path = temp.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.create(None).unwrap();
+    let web = Server::new(
+        "web".into(),
+        "10.0.0.1".into(),
+        22,
+        "root".into(),
+        "pw".into(),
+        None,
+    );
+    vault.add_server(web.clone()).unwrap();
+    let before = std::fs::read(&path).unwrap();
+
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.set_read_only(true);
+    vault.unlock(None).unwrap();
+    assert!(vault.is_read_only());
+    let read_only = |result: anyhow::Result<()>| {
+        matches!(
+            result.unwrap_err().downcast_ref(),
+            Some(VaultError::ReadOnly)
+        )
+    };
+    let other = Server::new(
+        "db".into(),
+        "10.0.0.2".into(),
+        22,
+        "root".into(),
+        String::new(),
+        None,
+    );
+    assert!(read_only(vault.add_server(other)));
+    assert!(read_only(vault.remove_server(&web.id).map(|_| ())));
+    assert!(read_only(vault.replace_server(web.clone()).map(|_| ())));
+    assert!(read_only(vault.undo().map(|_| ())));
+    // The TUI refuses the same keys it leaves out of its hints
+    for key in ['a', 'e', 'd', 'x', 'X', 'u', 'c', 'N', 'P'] {
+        assert!(is_editing_key(key), "{key}");
+    }
+    for key in ['/', 'y', 'p', 'L', 'q'] {
+        assert!(!is_editing_key(key), "{key}");
+    }
+
+    // Reading and connecting still work; the visit just isn't written down
+    assert_eq!(vault.search("web").unwrap().len(), 1);
+    vault.mark_connected(&web.id).unwrap();
+    vault.record_connection(&web.id, true).unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), before);
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_portkey"))
+        .args(["--read-only", "remove", "web"])
+        .env("XDG_DATA_HOME", temp.path())
+        .env(VAULT_PATH_ENV, &path)
+        .stdin(std::process::Stdio::null())
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("read-only"));
+}
+
+#[test]
+fn merging_imports_folds_new_fields_into_matching_servers() {
+    let temp = tempdir().unwrap();
+    let path = temp.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.create(None).unwrap();
+    let mut web = Server::new(
+        "web".into(),
+        "web.example.com".into(),
+        22,
+        "deploy".into(),
+        "pw".into(),
+        Some("Old description".into()),
+    );
+    web.set_tags(["prod"]);
+    web.ssh_options = vec![("Compression".into(), "no".into())];
+    vault.add_server(web.clone()).unwrap();
+    vault.mark_connected(&web.id).unwrap();
+    vault.record_connection(&web.id, true).unwrap();
+    let created_at = vault.find_server(&web.id).unwrap().unwrap().created_at;
+
+    let incoming = || {
+        let mut server = Server::new(
+            "web-renamed".into(),
+            "WEB.example.com".into(),
+            22,
+            "deploy".into(),
+            "other".into(),
+            Some("Front end".into()),
+        );
+        server.set_tags(["prod", "eu"]);
+        server.ssh_options = vec![("compression".into(), "yes".into())];
+        server.port_forwards = parse_port_forwards("8080:localhost:80").unwrap();
+        server
+    };
+
+    let summary = vault
+        .import_servers_with(vec![incoming()], false, OnConflict::Merge)
+        .unwrap();
+    assert_eq!(summary.merged, ["web-renamed"]);
+    assert!(summary.added.is_empty() && summary.replaced.is_empty());
+    let merged = vault.find_server(&web.id).unwrap().unwrap().clone();
+    assert_eq!(merged.name, "web");
+    assert_eq!(merged.created_at, created_at);
+    assert!(merged.last_connected.is_some());
+    assert_eq!(merged.description.as_deref(), Some("Front end"));
+    assert_eq!(merged.tags, ["prod", "eu"]);
+    assert_eq!(merged.ssh_options, [("Compression".into(), "yes".into())]);
+    assert_eq!(merged.port_forwards.len(), 1);
+    assert_eq!(vault.decrypt_password(&merged).unwrap(), "pw");
+    assert_eq!(vault.history().unwrap().len(), 1);
+
+    // Running the same import again changes nothing
+    let summary = vault
+        .import_servers_with(vec![incoming()], false, OnConflict::Merge)
+        .unwrap();
+    assert_eq!(summary.skipped, ["web-renamed"]);
+    assert!(!summary.changed());
+
+    assert_eq!("Merge".parse::<OnConflict>(), Ok(OnConflict::Merge));
+    assert!("replace".parse::<OnConflict>().is_err());
+}
+
+#[test]
+fn overwriting_imports_keep_passwords_and_descriptions_the_record_lacks() {
+    let temp = tempdir().unwrap();
+    let mut vault = Vault::with_path(temp.path().join("vault.dat")).unwrap();
+    vault.create(Some("master")).unwrap();
+    let web = Server::new(
+        "web".into(),
+        "web.example.com".into(),
+        22,
+        "deploy".into(),
+        "hunter2".into(),
+        Some("Front end".into()),
+    );
+    vault.add_server(web.clone()).unwrap();
+    assert!(vault
+        .find_server(&web.id)
+        .unwrap()
+        .unwrap()
+        .is_password_sealed());
+
+    let config = "Host web-eu\n  HostName web.example.com\n  User deploy\n";
+    let servers = parse_ssh_config(config, "deploy").servers;
+    let summary = vault
+        .import_servers_with(servers, false, OnConflict::Overwrite)
+        .unwrap();
+    assert_eq!(summary.replaced, ["web-eu"]);
+    let overwritten = vault.find_server(&web.id).unwrap().unwrap().clone();
+    assert_eq!(overwritten.name, "web-eu");
+    assert_eq!(overwritten.description.as_deref(), Some("Front end"));
+    assert_eq!(vault.decrypt_password(&overwritten).unwrap(), "hunter2");
+}
+
+#[test]
+#[cfg(unix)]
+fn open_hands_the_sftp_uri_to_the_desktop_opener() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut server = Server::new(
+        "files".into(),
+        "files.example.com".into(),
+        2222,
+        "deploy".into(),
+        "secret".into(),
+        None,
+    );
+    assert_eq!(server.sftp_uri(), "sftp://deploy@files.example.com:2222");
+    server.port = 22;
+    assert_eq!(server.sftp_uri(), "sftp://deploy@files.example.com");
+    server.port = 2222;
+
+    let temp = tempdir().unwrap();
+    let bin = temp.path().join("bin");
+    std::fs::create_dir(&bin).unwrap();
+    let path = temp.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.create(None).unwrap();
+    vault.add_server(server).unwrap();
+
+    let open = || {
+        std::process::Command::new(env!("CARGO_BIN_EXE_portkey"))
+            .args(["open", "files"])
+            .env("PATH", &bin)
+            .env("ARGS", temp.path().join("args"))
+            .env("XDG_DATA_HOME", temp.path())
+            .env(VAULT_PATH_ENV, &path)
+            .stdin(std::process::Stdio::null())
+            .output()
+            .unwrap()
+    };
+
+    // Without an opener on PATH the command explains instead of panicking
+    let output = open();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("not installed"), "{stderr}");
+    assert!(!stderr.contains("panicked"), "{stderr}");
+
+    let opener = bin.join(portkey::ssh::uri_opener());
+    std::fs::write(&opener, "#!/bin/sh\necho \"$@\" > \"$ARGS\"\n").unwrap();
+    std::fs::set_permissions(&opener, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let output = open();
+    assert!(output.status.success(), "{output:?}");
+    let args = std::fs::read_to_string(temp.path().join("args")).unwrap();
+    assert_eq!(args.trim(), "sftp://deploy@files.example.com:2222");
+    // The password stays out of the URI
+    assert!(!args.contains("secret"));
+}
+
+#[test]
+fn stats_summarize_the_vault() {
+    let now = chrono::Utc::now();
+    let server = |name: &str, days_old: i64| {
+        let mut server = Server::new(
+            name.into(),
+            format!("{name}.example.com"),
+            22,
+            "root".into(),
+            String::new(),
+            None,
+        );
+        server.created_at = now - chrono::Duration::days(days_old);
+        server
+    };
+    let mut web = server("web", 30);
+    web.set_tags(["prod", "web"]);
+    web.group = Some("Frontend".into());
+    web.description = Some("Public site".into());
+    let mut db = server("db", 90);
+    db.set_tags(["prod"]);
+    db.group = Some("backend".into());
+    db.auth_method = AuthMethod::PublicKey;
+    db.last_connected = Some(now - chrono::Duration::hours(1));
+    let mut cache = server("cache", 1);
+    cache.group = Some("Backend".into());
+    cache.description = Some("  ".into());
+    cache.last_connected = Some(now - chrono::Duration::days(3));
+
+    let temp = tempdir().unwrap();
+    let path = temp.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.create(None).unwrap();
+    for server in [web, db, cache] {
+        vault.add_server(server).unwrap();
+    }
+
+    let stats = vault.stats().unwrap();
+    assert_eq!(stats.servers, 3);
+    assert_eq!(stats.tags, vec![("prod".into(), 2), ("web".into(), 1)]);
+    // Groups differing only in case are counted together
+    assert_eq!(
+        stats.groups,
+        vec![("backend".into(), 2), ("Frontend".into(), 1)]
+    );
+    assert_eq!(
+        stats.auth_methods,
+        vec![
+            (AuthMethod::Password, 2),
+            (AuthMethod::PublicKey, 1),
+            (AuthMethod::Agent, 0)
+        ]
+    );
+    assert_eq!(stats.with_description, 1);
+    assert_eq!(stats.oldest.as_ref().unwrap().0, "db");
+    assert_eq!(stats.newest.as_ref().unwrap().0, "cache");
+    assert_eq!(stats.last_connected.as_ref().unwrap().0, "db");
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_portkey"))
+        .args(["stats", "--json"])
+        .env("XDG_DATA_HOME", temp.path())
+        .env(VAULT_PATH_ENV, &path)
+        .stdin(std::process::Stdio::null())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["servers"], 3);
+    assert_eq!(json["tags"][0]["tag"], "prod");
+    assert_eq!(json["groups"][0]["count"], 2);
+    assert_eq!(json["auth_methods"]["public_key"], 1);
+    assert_eq!(json["newest"]["name"], "cache");
+    assert_eq!(json["last_connected"]["name"], "db");
+
+    let empty = VaultData::default().stats();
+    assert_eq!(empty.servers, 0);
+    assert!(empty.oldest.is_none() && empty.last_connected.is_none());
+}
+
+#[test]
+fn search_qualifiers_narrow_the_fuzzy_match() {
+    let server = |name: &str, user: &str, port: u16, tags: &[&str]| {
+        let mut server = Server::new(
+            name.into(),
+            format!("{name}.example.com"),
+            port,
+            user.into(),
+            String::new(),
+            None,
+        );
+        server.set_tags(tags.iter().copied());
+        server
+    };
+    let mut servers = vec![
+        server("web-1", "root", 22, &["prod"]),
+        server("web-2", "deploy", 22, &["prod"]),
+        server("web-3", "root", 2222, &["staging"]),
+        server("db", "root", 22, &["prod"]),
+    ];
+    servers[3].group = Some("Data".into());
+    let names = |query: &str| -> Vec<&str> {
+        let mut names: Vec<&str> = search_servers(&servers, query)
+            .into_iter()
+            .map(|(_, index)| servers[index].name.as_str())
+            .collect();
+        names.sort();
+        names
+    };
+
+    assert_eq!(names("tag:prod user:root web"), ["web-1"]);
+    assert_eq!(names("TAG:PROD User:Root"), ["db", "web-1"]);
+    assert_eq!(names("port:2222"), ["web-3"]);
+    assert_eq!(names("group:data"), ["db"]);
+    assert_eq!(names("host:web-2.example.com"), ["web-2"]);
+    // Qualifiers are exact, so a partial tag matches nothing
+    assert!(names("tag:pro").is_empty());
+    // Unknown keys, unparsable ports and bare keys are plain text
+    assert_eq!(
+        parse_search_query("os:linux port:ssh tag: user:root"),
+        (
+            vec![SearchQualifier::User("root".into())],
+            "os:linux port:ssh tag:".to_string()
+        )
+    );
+
+    // The CLI goes through the same search
+    let data = VaultData {
+        servers: servers.clone(),
+        ..Default::default()
+    };
+    let found: Vec<&str> = data
+        .search("user:deploy")
+        .into_iter()
+        .map(|(_, s)| s.name.as_str())
+        .collect();
+    assert_eq!(found, ["web-2"]);
+    // Several servers matching only qualifiers is not a clear pick
+    assert!(strong_match(&servers, "tag:prod").is_none());
+    assert_eq!(
+        strong_match(&servers, "port:2222").map(|s| s.name.as_str()),
+        Some("web-3")
+    );
+}
+
+#[test]
+fn first_run_without_a_terminal_only_points_at_init() {
+    let temp = tempdir().unwrap();
+    let home = temp.path().join("home");
+    std::fs::create_dir_all(home.join(".ssh")).unwrap();
+    std::fs::write(
+        home.join(".ssh").join("config"),
+        "Host web\n    HostName web.example.com\n    User deploy\n",
+    )
+    .unwrap();
+    let path = temp.path().join("vault.dat");
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_portkey"))
+        .env("HOME", &home)
+        .env("XDG_DATA_HOME", temp.path())
+        .env(VAULT_PATH_ENV, &path)
+        .stdin(std::process::Stdio::null())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Run 'portkey init'"), "{stdout}");
+    assert!(!stdout.contains("Welcome"), "{stdout}");
+    // Nothing is created or imported without someone to confirm it
+    assert!(!path.exists());
+}
+
+#[test]
+fn history_since_filters_and_exports_csv_newest_first() {
+    let now = chrono::Utc::now();
+    let web = Server::new(
+        "web, east".into(),
+        "web.example.com".into(),
+        22,
+        "deploy".into(),
+        String::new(),
+        None,
+    );
+    let event = |server_id, days_ago, success| ConnectionEvent {
+        server_id,
+        at: now - chrono::Duration::days(days_ago),
+        success,
+    };
+    let mut data = VaultData::new();
+    data.history = vec![
+        event(web.id, 30, true),
+        event(uuid::Uuid::new_v4(), 3, false),
+        event(web.id, 1, true),
+    ];
+    data.servers.push(web);
+
+    // An unencrypted TOML vault is plain VaultData, so old events can be written directly
+    let temp = tempdir().unwrap();
+    let path = temp.path().join("vault.dat");
+    let mut vault = Vault::with_path(path.clone()).unwrap();
+    vault.create_with_format(VaultFormat::Toml).unwrap();
+    std::fs::write(&path, toml::to_string_pretty(&data).unwrap()).unwrap();
+
+    let history = |args: &[&str]| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_portkey"))
+            .arg("history")
+            .args(args)
+            .env("XDG_DATA_HOME", temp.path())
+            .env(VAULT_PATH_ENV, &path)
+            .stdin(std::process::Stdio::null())
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let printed = history(&["--since", "7d"]);
+    assert_eq!(printed.lines().count(), 2, "{printed}");
+    assert!(printed.lines().next().unwrap().ends_with("web, east"));
+    assert_eq!(history(&[]).lines().count(), 3);
+
+    let csv_path = temp.path().join("audit.csv");
+    history(&["--since", "1w", "--csv", csv_path.to_str().unwrap()]);
+    let csv = std::fs::read_to_string(&csv_path).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), 3, "{csv}");
+    assert_eq!(lines[0], "server_name,host,timestamp,success");
+    let day = |days_ago| {
+        (now - chrono::Duration::days(days_ago)).to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+    };
+    assert_eq!(
+        lines[1],
+        format!("\"web, east\",web.example.com,{},true", day(1))
+    );
+    assert_eq!(lines[2], format!("(deleted),,{},false", day(3)));
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_portkey"))
+        .args(["history", "--since", "soon"])
+        .env("XDG_DATA_HOME", temp.path())
+        .env(VAULT_PATH_ENV, &path)
+        .stdin(std::process::Stdio::null())
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}